@@ -0,0 +1,49 @@
+use std::{sync::Arc, time::Instant};
+
+use arc_metrics::{IntCounter, PromMetricRegistry};
+
+const SERIES: usize = 10_000;
+const SCRAPES: u32 = 100;
+
+fn run<F: FnMut()>(name: &str, mut scrape: F) {
+    let start = Instant::now();
+    for _ in 0..SCRAPES {
+        scrape();
+    }
+    println!(
+        "{:<12} {} series: {:?} per scrape",
+        name,
+        SERIES,
+        start.elapsed() / SCRAPES
+    );
+}
+
+fn main() {
+    let counters = Arc::new(
+        (0..SERIES)
+            .map(|i| {
+                let counter = IntCounter::new();
+                counter.inc_by(i as u64 * 104_729);
+                counter
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    let mut reg = PromMetricRegistry::empty().with_base_attrs([("program", "bench")]);
+    reg.register_fn(&counters, |counters, reg| {
+        for (i, counter) in counters.iter().enumerate() {
+            reg.count(format!("family_{}_total", i / 100), counter)
+                .attr("series", (i % 100).to_string());
+        }
+    });
+
+    run("to_string", || {
+        std::hint::black_box(reg.to_string());
+    });
+
+    let mut buf = String::new();
+    run("encode_into", || {
+        reg.encode_into(&mut buf);
+        std::hint::black_box(&buf);
+    });
+}
@@ -0,0 +1,54 @@
+use std::{sync::Arc, time::Instant};
+
+use arc_metrics::{helpers::LocalCounter, IntCounter};
+
+const THREADS: usize = 8;
+const INCREMENTS: u64 = 10_000_000;
+
+#[derive(Default)]
+struct Met {
+    requests: IntCounter,
+}
+
+fn run<F: Fn(Arc<Met>) + Send + Sync + Copy + 'static>(name: &str, work: F) {
+    let met = Arc::new(Met::default());
+    let start = Instant::now();
+
+    let threads = (0..THREADS)
+        .map(|_| {
+            let met = met.clone();
+            std::thread::spawn(move || work(met))
+        })
+        .collect::<Vec<_>>();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let elapsed = start.elapsed();
+    assert_eq!(met.requests.load(), THREADS as u64 * INCREMENTS);
+
+    println!(
+        "{:<12} {} threads x {} incs: {:?} ({:.2} ns/inc)",
+        name,
+        THREADS,
+        INCREMENTS,
+        elapsed,
+        elapsed.as_nanos() as f64 / (THREADS as u64 * INCREMENTS) as f64
+    );
+}
+
+fn main() {
+    run("shared_inc", |met| {
+        for _ in 0..INCREMENTS {
+            std::hint::black_box(&met.requests).shared_inc();
+        }
+    });
+
+    run("local", |met| {
+        let local = LocalCounter::new(&met, |m| &m.requests);
+        for _ in 0..INCREMENTS {
+            std::hint::black_box(&local).inc();
+        }
+    });
+}
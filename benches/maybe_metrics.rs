@@ -0,0 +1,61 @@
+use std::time::Instant;
+
+use arc_metrics::{
+    helpers::{ActiveGauge, DurationIncMs, MaybeMetrics},
+    IntCounter, IntGauge,
+};
+
+const ITERATIONS: u64 = 100_000_000;
+
+#[derive(Default)]
+struct Met {
+    requests: IntCounter,
+    active: IntGauge,
+    latency_ms: IntCounter,
+}
+
+fn run<F: Fn()>(name: &str, work: F) {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        work();
+    }
+
+    let elapsed = start.elapsed();
+    println!(
+        "{:<16} {} iterations: {:?} ({:.2} ns/iter)",
+        name,
+        ITERATIONS,
+        elapsed,
+        elapsed.as_nanos() as f64 / ITERATIONS as f64
+    );
+}
+
+fn main() {
+    /* disabled rows are within a few ns of the empty loop, enabled ones pay for the atomics */
+    let disabled = std::hint::black_box(MaybeMetrics::<Met>::Disabled);
+    let enabled = std::hint::black_box(MaybeMetrics::new(Met::default()));
+
+    run("empty", || {
+        std::hint::black_box(&disabled);
+    });
+
+    run("disabled_inc", || {
+        std::hint::black_box(&disabled).inc(|m| &m.requests);
+    });
+
+    run("disabled_guards", || {
+        let metrics = std::hint::black_box(&disabled);
+        let _active = ActiveGauge::maybe(metrics, |m| &m.active);
+        let _timer = DurationIncMs::maybe(metrics, |m| &m.latency_ms);
+    });
+
+    run("enabled_inc", || {
+        std::hint::black_box(&enabled).inc(|m| &m.requests);
+    });
+
+    run("enabled_guards", || {
+        let metrics = std::hint::black_box(&enabled);
+        let _active = ActiveGauge::maybe(metrics, |m| &m.active);
+        let _timer = DurationIncMs::maybe(metrics, |m| &m.latency_ms);
+    });
+}
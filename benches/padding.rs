@@ -0,0 +1,55 @@
+use std::{sync::Arc, time::Instant};
+
+use arc_metrics::{IntCounter, PaddedIntCounter};
+
+const THREADS: usize = 8;
+const INCREMENTS: u64 = 10_000_000;
+
+/* each thread increments only its own counter, any slowdown is false sharing */
+fn run<C: Send + Sync + 'static>(
+    name: &str,
+    threads: usize,
+    counters: &Arc<[C; THREADS]>,
+    inc: fn(&C),
+) {
+    let start = Instant::now();
+
+    let handles = (0..threads)
+        .map(|i| {
+            let counters = counters.clone();
+            std::thread::spawn(move || {
+                for _ in 0..INCREMENTS {
+                    inc(std::hint::black_box(&counters[i]));
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let elapsed = start.elapsed();
+    println!(
+        "{:<20} {} threads x {} incs: {:?} ({:.2} ns/inc), {} bytes",
+        name,
+        threads,
+        INCREMENTS,
+        elapsed,
+        elapsed.as_nanos() as f64 / (threads as u64 * INCREMENTS) as f64,
+        std::mem::size_of::<[C; THREADS]>()
+    );
+}
+
+fn main() {
+    let plain = Arc::new(<[IntCounter; THREADS]>::default());
+    let padded = Arc::new(<[PaddedIntCounter; THREADS]>::default());
+
+    run("uncontended", 1, &plain, |c| c.inc());
+    run("uncontended_padded", 1, &padded, |c| c.inc());
+    run("adjacent", THREADS, &plain, |c| c.inc());
+    run("adjacent_padded", THREADS, &padded, |c| c.inc());
+
+    assert_eq!(plain[1].load(), INCREMENTS);
+    assert_eq!(padded[1].load(), INCREMENTS);
+}
@@ -0,0 +1,43 @@
+use std::{sync::Arc, time::Instant};
+
+use arc_metrics::{IntCounter, PromMetricRegistry};
+
+const FAMILIES: usize = 50;
+const SERIES: usize = 1000;
+const RENDERS: usize = 20;
+
+fn main() {
+    let counters = Arc::new(
+        (0..FAMILIES * SERIES)
+            .map(|i| {
+                let counter = IntCounter::new();
+                counter.inc_by(i as u64 * 104_729);
+                counter
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    let mut reg = PromMetricRegistry::empty().with_base_attrs([("program", "bench")]);
+    reg.register_fn(&counters, |counters, reg| {
+        for (i, counter) in counters.iter().enumerate() {
+            reg.count(format!("family_{}_total", i / SERIES), counter)
+                .attr("series", (i % SERIES).to_string())
+                .attr("zone", "eu-west");
+        }
+    });
+
+    let mut out = String::with_capacity(reg.to_string().len());
+    let start = Instant::now();
+    for _ in 0..RENDERS {
+        out.clear();
+        std::fmt::write(&mut out, format_args!("{}", reg)).unwrap();
+        std::hint::black_box(&out);
+    }
+
+    println!(
+        "render {} series: {:?} per render, {} bytes",
+        FAMILIES * SERIES,
+        start.elapsed() / RENDERS as u32,
+        out.len()
+    );
+}
@@ -0,0 +1,57 @@
+use std::{sync::Arc, time::Instant};
+
+use arc_metrics::{IntGauge, PromMetricRegistry};
+
+const FAMILIES: usize = 80;
+const SERIES: usize = 1000;
+const RENDERS: usize = 20;
+
+fn fixture() -> (Arc<Vec<IntGauge>>, PromMetricRegistry) {
+    let gauges = Arc::new(
+        (0..FAMILIES * SERIES)
+            .map(|i| {
+                let gauge = IntGauge::new();
+                gauge.set(i as u64 * 7919);
+                gauge
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    let mut reg = PromMetricRegistry::empty();
+    reg.register_fn(&gauges, |gauges, reg| {
+        for (i, gauge) in gauges.iter().enumerate() {
+            reg.gauge(format!("family_{}", i / SERIES), gauge)
+                .attr("series", (i % SERIES).to_string());
+        }
+    });
+
+    (gauges, reg)
+}
+
+fn run(name: &str, gauges: &[IntGauge], reg: &PromMetricRegistry) {
+    reg.to_string();
+
+    let start = Instant::now();
+    for (i, gauge) in gauges.iter().take(RENDERS).enumerate() {
+        /* mostly static, a single family changes between scrapes */
+        gauge.set(i as u64);
+        std::hint::black_box(reg.to_string());
+    }
+
+    let elapsed = start.elapsed();
+    println!(
+        "{:<10} {} series: {:?} per render",
+        name,
+        FAMILIES * SERIES,
+        elapsed / RENDERS as u32
+    );
+}
+
+fn main() {
+    let (gauges, reg) = fixture();
+    run("uncached", &gauges, &reg);
+
+    let (gauges, mut reg) = fixture();
+    reg.render_cache(true);
+    run("cached", &gauges, &reg);
+}
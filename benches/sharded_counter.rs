@@ -0,0 +1,57 @@
+use std::{sync::Arc, time::Instant};
+
+use arc_metrics::{IntCounter, ShardedCounter};
+
+const THREADS: usize = 8;
+const INCREMENTS: u64 = 10_000_000;
+
+struct Met {
+    shared: IntCounter,
+    sharded: ShardedCounter,
+}
+
+fn run<F: Fn(&Met) + Send + Sync + Copy + 'static>(name: &str, met: &Arc<Met>, work: F) {
+    let start = Instant::now();
+
+    let threads = (0..THREADS)
+        .map(|_| {
+            let met = met.clone();
+            std::thread::spawn(move || work(&met))
+        })
+        .collect::<Vec<_>>();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let elapsed = start.elapsed();
+    println!(
+        "{:<12} {} threads x {} incs: {:?} ({:.2} ns/inc)",
+        name,
+        THREADS,
+        INCREMENTS,
+        elapsed,
+        elapsed.as_nanos() as f64 / (THREADS as u64 * INCREMENTS) as f64
+    );
+}
+
+fn main() {
+    let met = Arc::new(Met {
+        shared: IntCounter::new(),
+        sharded: ShardedCounter::new(THREADS),
+    });
+
+    run("shared_inc", &met, |met| {
+        for _ in 0..INCREMENTS {
+            std::hint::black_box(&met.shared).shared_inc();
+        }
+    });
+    assert_eq!(met.shared.load(), THREADS as u64 * INCREMENTS);
+
+    run("sharded", &met, |met| {
+        for _ in 0..INCREMENTS {
+            std::hint::black_box(&met.sharded).inc();
+        }
+    });
+    assert_eq!(met.sharded.load(), THREADS as u64 * INCREMENTS);
+}
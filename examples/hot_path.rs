@@ -0,0 +1,41 @@
+/* functions inspected by tests/codegen.rs, each should be a single locked instruction */
+use std::{hint::black_box, sync::Arc};
+
+use arc_metrics::{ChildMetric, IntCounter, IntGauge};
+
+#[derive(Default)]
+pub struct Metrics {
+    pub requests: IntCounter,
+    pub active: IntGauge,
+}
+
+#[no_mangle]
+#[inline(never)]
+pub fn hot_path_counter_inc(counter: &IntCounter) {
+    counter.inc();
+}
+
+#[no_mangle]
+#[inline(never)]
+pub fn hot_path_gauge_dec(gauge: &IntGauge) {
+    gauge.dec();
+}
+
+#[no_mangle]
+#[inline(never)]
+pub fn hot_path_child_inc(child: &ChildMetric<Metrics, IntCounter>) {
+    child.inc();
+}
+
+fn main() {
+    let metrics = Arc::new(Metrics::default());
+    let child = ChildMetric::create(&metrics, |m| &m.requests);
+
+    for _ in 0..black_box(1000) {
+        hot_path_counter_inc(black_box(&metrics.requests));
+        hot_path_gauge_dec(black_box(&metrics.active));
+        hot_path_child_inc(black_box(&child));
+    }
+
+    println!("{}", metrics.requests.load());
+}
@@ -0,0 +1,157 @@
+/*
+ * embedding the registry in an existing HTTP stack through as_scrape_fn. The handler below
+ * only sees what any framework hands it: the Accept header, the query string and
+ * Prometheus' scrape timeout header. Run with `cargo run --example scrape_fn`.
+ */
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use arc_metrics::{
+    helpers::RegisterableMetric,
+    scrape::{RenderError, ScrapeOptions, ScrapeOutput},
+    IntCounter, IntGauge, PromMetricRegistry, RegisterAction,
+};
+
+#[derive(Default)]
+struct HttpMetrics {
+    requests: IntCounter,
+    in_flight: IntGauge,
+}
+
+impl RegisterableMetric for HttpMetrics {
+    fn register(&'static self, register: &mut RegisterAction) {
+        register.count("http_requests", &self.requests);
+        register.gauge("http_in_flight", &self.in_flight);
+    }
+}
+
+#[derive(Default)]
+struct DbMetrics {
+    queries: IntCounter,
+}
+
+impl RegisterableMetric for DbMetrics {
+    fn register(&'static self, register: &mut RegisterAction) {
+        register.count("db_queries", &self.queries);
+    }
+}
+
+/* what a framework passes a handler, reduced to the parts a scrape looks at */
+struct Request<'a> {
+    accept: Option<&'a str>,
+    /* ex. module=http_&module=db_ */
+    query: &'a str,
+    /* X-Prometheus-Scrape-Timeout-Seconds */
+    scrape_timeout: Option<&'a str>,
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+/* leaves some of Prometheus' timeout for the response to travel back */
+const TIMEOUT_MARGIN: Duration = Duration::from_millis(250);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn options(request: &Request) -> ScrapeOptions {
+    let mut options = ScrapeOptions::from_accept(request.accept);
+    for (key, value) in request
+        .query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+    {
+        if key == "module" {
+            options = options.module(value);
+        }
+    }
+
+    let timeout = request
+        .scrape_timeout
+        .and_then(|secs| secs.parse::<f64>().ok())
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .unwrap_or(DEFAULT_TIMEOUT);
+    options.timeout(timeout.saturating_sub(TIMEOUT_MARGIN))
+}
+
+fn metrics_handler(
+    scrape: &impl Fn(ScrapeOptions) -> Result<ScrapeOutput, RenderError>,
+    request: &Request,
+) -> Response {
+    match scrape(options(request)) {
+        Ok(output) => Response {
+            status: 200,
+            content_type: output.content_type,
+            body: output.body,
+        },
+        Err(error) => Response {
+            status: 503,
+            content_type: "text/plain; charset=utf-8",
+            body: format!("{}\n", error),
+        },
+    }
+}
+
+fn print(title: &str, response: &Response) {
+    println!(
+        "--- {}: {} {}",
+        title, response.status, response.content_type
+    );
+    print!("{}", response.body);
+}
+
+fn main() {
+    let http = Arc::new(HttpMetrics::default());
+    let db = Arc::new(DbMetrics::default());
+    let registry = Arc::new(RwLock::new(PromMetricRegistry::empty()));
+
+    /* the scrape fn is cloned into the server, registrations made later still show up */
+    let scrape = PromMetricRegistry::as_scrape_fn(registry.clone());
+    registry.write().unwrap().register(&http);
+
+    http.requests.inc_by(3);
+    http.in_flight.set(1);
+
+    let text = metrics_handler(
+        &scrape,
+        &Request {
+            accept: Some("text/plain;version=0.0.4"),
+            query: "",
+            scrape_timeout: Some("10"),
+        },
+    );
+    print("text", &text);
+    assert_eq!(text.status, 200);
+
+    registry.write().unwrap().register(&db);
+    db.queries.inc();
+
+    let openmetrics = metrics_handler(
+        &scrape,
+        &Request {
+            accept: Some("application/openmetrics-text;version=1.0.0,text/plain;q=0.5"),
+            query: "module=db_",
+            scrape_timeout: Some("10"),
+        },
+    );
+    print("openmetrics, db_ only", &openmetrics);
+    assert!(openmetrics.body.contains("db_queries_total 1\n"));
+    assert!(!openmetrics.body.contains("http_requests"));
+
+    /* a writer holding the registry past the deadline turns the scrape into a 503 */
+    let writer = registry.write().unwrap();
+    let timed_out = metrics_handler(
+        &scrape,
+        &Request {
+            accept: None,
+            query: "module=http_",
+            scrape_timeout: Some("0.3"),
+        },
+    );
+    drop(writer);
+    print("deadline", &timed_out);
+    assert_eq!(timed_out.status, 503);
+}
@@ -0,0 +1,70 @@
+use std::{borrow::Cow, ops::Deref};
+
+pub(crate) type Attribute = [Cow<'static, str>; 2];
+
+const INLINE: usize = 4;
+const EMPTY: Attribute = [Cow::Borrowed(""), Cow::Borrowed("")];
+
+/* attribute storage for registered series, up to 4 labels are kept inline without allocating */
+#[derive(Clone)]
+pub(crate) enum Attributes {
+    Inline { len: u8, items: [Attribute; INLINE] },
+    Heap(Box<[Attribute]>),
+}
+
+impl Default for Attributes {
+    fn default() -> Self {
+        Attributes::Inline {
+            len: 0,
+            items: [EMPTY; INLINE],
+        }
+    }
+}
+
+impl From<&[Attribute]> for Attributes {
+    fn from(attributes: &[Attribute]) -> Self {
+        if INLINE < attributes.len() {
+            return Attributes::Heap(attributes.into());
+        }
+
+        let mut items = [EMPTY; INLINE];
+        items[..attributes.len()].clone_from_slice(attributes);
+
+        Attributes::Inline {
+            len: attributes.len() as u8,
+            items,
+        }
+    }
+}
+
+impl Deref for Attributes {
+    type Target = [Attribute];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Inline { len, items } => &items[..*len as usize],
+            Self::Heap(items) => items,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use super::{Attribute, Attributes};
+
+    #[test]
+    fn representations_test() {
+        let attrs = (0..6)
+            .map(|i| [Cow::Owned(format!("k{}", i)), Cow::Borrowed("v")])
+            .collect::<Vec<Attribute>>();
+
+        for len in 0..=6 {
+            let stored = Attributes::from(&attrs[..len]);
+            assert_eq!(&stored[..], &attrs[..len]);
+            assert_eq!(&stored.clone()[..], &attrs[..len]);
+            assert_eq!(matches!(stored, Attributes::Inline { .. }), len <= 4);
+        }
+    }
+}
@@ -0,0 +1,455 @@
+/*
+ * Crash forensics: (timestamp, metric id, value) records in a memory-mapped ring file.
+ * The mapping is shared with the page cache so records survive the process dying. Any
+ * thread may write: a slot is reserved with fetch_add on the header cursor, filled, and
+ * published by storing its sequence number last. Every record carries a checksum so the
+ * reader drops records torn by a crash mid-write.
+ *
+ * Layout, little endian: a 64 byte header (magic, version, record size, slot count,
+ * cursor) followed by slots of 40 bytes (sequence, timestamp ns, metric id, value, check).
+ */
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    io,
+    os::fd::AsRawFd,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, RwLock,
+    },
+    thread::JoinHandle,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{PromMetricRegistry, Reading};
+
+pub const MAGIC: [u8; 8] = *b"ARCMBBX\0";
+pub const VERSION: u32 = 1;
+
+const HEADER: usize = 64;
+const RECORD: usize = 40;
+const CURSOR: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    /* 1 based order of the write, gaps are torn or overwritten records */
+    pub sequence: u64,
+    pub timestamp: SystemTime,
+    pub metric_id: u64,
+    pub value: u64,
+}
+
+/* FNV-1a of the series as rendered, ex. requests_total{method="GET"}, stable across runs */
+pub fn metric_id(series: &str) -> u64 {
+    series.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn check(sequence: u64, timestamp: u64, metric_id: u64, value: u64) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for word in [sequence, timestamp, metric_id, value] {
+        hash = (hash ^ word).wrapping_mul(0x100000001b3);
+        hash ^= hash >> 29;
+    }
+    hash
+}
+
+pub struct BlackBox {
+    map: *mut u8,
+    len: usize,
+    slots: u64,
+}
+
+/* only touched through atomics */
+unsafe impl Send for BlackBox {}
+unsafe impl Sync for BlackBox {}
+
+impl BlackBox {
+    /* creates or truncates path with room for slots records */
+    pub fn create<P: AsRef<Path>>(path: P, slots: u64) -> io::Result<Self> {
+        if slots == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no slots"));
+        }
+
+        let len = HEADER + slots as usize * RECORD;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(len as u64)?;
+
+        let map = unsafe {
+            sys::mmap(
+                std::ptr::null_mut(),
+                len,
+                sys::PROT_READ | sys::PROT_WRITE,
+                sys::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if map == sys::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        let blackbox = BlackBox {
+            map: map as *mut u8,
+            len,
+            slots,
+        };
+
+        let mut header = [0u8; CURSOR];
+        header[..8].copy_from_slice(&MAGIC);
+        header[8..12].copy_from_slice(&VERSION.to_le_bytes());
+        header[12..16].copy_from_slice(&(RECORD as u32).to_le_bytes());
+        header[16..24].copy_from_slice(&slots.to_le_bytes());
+        unsafe { std::ptr::copy_nonoverlapping(header.as_ptr(), blackbox.map, CURSOR) };
+
+        Ok(blackbox)
+    }
+
+    fn word(&self, offset: usize) -> &AtomicU64 {
+        debug_assert!(offset + 8 <= self.len && offset % 8 == 0);
+        unsafe { &*(self.map.add(offset) as *const AtomicU64) }
+    }
+
+    /* wait-free, safe to call from any thread */
+    pub fn record(&self, metric_id: u64, value: u64) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos() as u64);
+
+        let sequence = self.word(CURSOR).fetch_add(1, Ordering::Relaxed) + 1;
+        let slot = HEADER + ((sequence - 1) % self.slots) as usize * RECORD;
+
+        /* unpublish first so a crash while filling leaves a record that fails its check */
+        self.word(slot).store(0, Ordering::Release);
+        self.word(slot + 8)
+            .store(timestamp.to_le(), Ordering::Release);
+        self.word(slot + 16)
+            .store(metric_id.to_le(), Ordering::Release);
+        self.word(slot + 24).store(value.to_le(), Ordering::Release);
+        self.word(slot + 32).store(
+            check(sequence, timestamp, metric_id, value).to_le(),
+            Ordering::Release,
+        );
+        self.word(slot).store(sequence.to_le(), Ordering::Release);
+    }
+}
+
+impl Drop for BlackBox {
+    fn drop(&mut self) {
+        unsafe { sys::munmap(self.map as *mut _, self.len) };
+    }
+}
+
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    /* not a blackbox file or cut short */
+    Format,
+    UnsupportedVersion(u32),
+}
+
+impl std::fmt::Display for ReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "reading blackbox failed: {}", error),
+            Self::Format => write!(f, "not a blackbox file"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported blackbox version {}", version)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+/* complete records oldest first, torn records are skipped */
+pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<Event>, ReadError> {
+    let data = std::fs::read(path).map_err(ReadError::Io)?;
+    let word = |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+
+    if data.len() < HEADER || data[..8] != MAGIC {
+        return Err(ReadError::Format);
+    }
+    let version = u32::from_le_bytes(data[8..12].try_into().unwrap());
+    if version != VERSION {
+        return Err(ReadError::UnsupportedVersion(version));
+    }
+    let record = u32::from_le_bytes(data[12..16].try_into().unwrap()) as usize;
+    let slots = word(16) as usize;
+    if record != RECORD || data.len() < HEADER + slots * RECORD {
+        return Err(ReadError::Format);
+    }
+
+    let mut events = Vec::new();
+    for slot in 0..slots {
+        let offset = HEADER + slot * RECORD;
+        let [sequence, timestamp, metric_id, value, checked] =
+            [0, 8, 16, 24, 32].map(|field| word(offset + field));
+
+        if sequence == 0 || checked != check(sequence, timestamp, metric_id, value) {
+            continue;
+        }
+        events.push(Event {
+            sequence,
+            timestamp: UNIX_EPOCH + Duration::from_nanos(timestamp),
+            metric_id,
+            value,
+        });
+    }
+
+    events.sort_by_key(|event| event.sequence);
+    Ok(events)
+}
+
+/* records the series marked with RegisterHelper::blackbox() whenever their value changed */
+pub struct Recorder {
+    blackbox: Arc<BlackBox>,
+    last: HashMap<u64, u64>,
+}
+
+impl Recorder {
+    pub fn new(blackbox: Arc<BlackBox>) -> Self {
+        Recorder {
+            blackbox,
+            last: HashMap::new(),
+        }
+    }
+
+    /* histograms record their count, float metrics their f64 bits */
+    pub fn sample(&mut self, registry: &PromMetricRegistry) {
+        for metric in registry.metrics.iter().filter(|metric| metric.blackbox) {
+            let value = match registry.hold(metric) {
+                Some(_holder) => match metric.read(false) {
+                    Reading::Value(value) => value,
+                    Reading::Float(value) => value.get().to_bits(),
+                    Reading::Histogram { counts, .. } => counts.last().copied().unwrap_or(0),
+                    Reading::Summary { count, .. } => count,
+                    Reading::Skipped => continue,
+                },
+                None => continue,
+            };
+
+            let id = metric_id(&format!("{}{}", metric.name, metric.labels()));
+            if self.last.insert(id, value) != Some(value) {
+                self.blackbox.record(id, value);
+            }
+        }
+    }
+
+    pub fn spawn_interval(
+        mut self,
+        registry: Arc<RwLock<PromMetricRegistry>>,
+        period: Duration,
+    ) -> RecorderHandle {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = std::thread::spawn(move || loop {
+            self.sample(&registry.read().unwrap());
+            if !matches!(
+                stopped.recv_timeout(period),
+                Err(mpsc::RecvTimeoutError::Timeout)
+            ) {
+                break;
+            }
+        });
+
+        RecorderHandle { stop, thread }
+    }
+}
+
+pub struct RecorderHandle {
+    stop: mpsc::Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl RecorderHandle {
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.thread.join();
+    }
+}
+
+impl PromMetricRegistry {
+    /* (metric id, series) of every series marked for the blackbox, to decode events */
+    pub fn blackbox_series(&self) -> Vec<(u64, String)> {
+        self.metrics
+            .iter()
+            .filter(|metric| metric.blackbox)
+            .map(|metric| {
+                let series = format!("{}{}", metric.name, metric.labels());
+                (metric_id(&series), series)
+            })
+            .collect()
+    }
+}
+
+/* std has no mmap, libc is linked anyway */
+mod sys {
+    use std::ffi::{c_int, c_long, c_void};
+
+    pub const PROT_READ: c_int = 1;
+    pub const PROT_WRITE: c_int = 2;
+    pub const MAP_SHARED: c_int = 1;
+    pub const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+    extern "C" {
+        pub fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: c_long,
+        ) -> *mut c_void;
+
+        pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::OpenOptions,
+        io::{Seek, SeekFrom, Write},
+        path::PathBuf,
+        process::{Command, Stdio},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use crate::{IntCounter, PromMetricRegistry};
+
+    use super::{metric_id, read, BlackBox, ReadError, Recorder, HEADER, RECORD};
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("arc-metrics-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn ring_test() {
+        let path = path("ring");
+        let blackbox = BlackBox::create(&path, 4).unwrap();
+        for value in 1..=6 {
+            blackbox.record(7, value);
+        }
+
+        let events = read(&path).unwrap();
+        assert_eq!(
+            events
+                .iter()
+                .map(|e| (e.sequence, e.value))
+                .collect::<Vec<_>>(),
+            [(3, 3), (4, 4), (5, 5), (6, 6)]
+        );
+        assert!(events.iter().all(|e| e.metric_id == 7));
+        drop(blackbox);
+
+        /* flip a byte of the value in the newest record */
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        let newest = HEADER + RECORD + 24;
+        file.seek(SeekFrom::Start(newest as u64)).unwrap();
+        file.write_all(&[0xff]).unwrap();
+        assert_eq!(read(&path).unwrap().len(), 3);
+
+        file.seek(SeekFrom::Start(8)).unwrap();
+        file.write_all(&2u32.to_le_bytes()).unwrap();
+        assert!(matches!(read(&path), Err(ReadError::UnsupportedVersion(2))));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn recorder_test() {
+        let path = path("recorder");
+        let blackbox = Arc::new(BlackBox::create(&path, 64).unwrap());
+
+        let counters = Arc::new([IntCounter::new(), IntCounter::new()]);
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&counters, |c, reg| {
+            reg.count("tracked", &c[0]).attr("a", "b").blackbox();
+            reg.count("untracked", &c[1]);
+        });
+
+        let mut recorder = Recorder::new(blackbox);
+        counters[0].inc();
+        counters[1].inc();
+        recorder.sample(&reg);
+        recorder.sample(&reg);
+        counters[0].inc();
+        recorder.sample(&reg);
+
+        let id = metric_id("tracked{a=\"b\"}");
+        assert_eq!(
+            reg.blackbox_series(),
+            [(id, "tracked{a=\"b\"}".to_string())]
+        );
+
+        let events = read(&path).unwrap();
+        assert_eq!(
+            events
+                .iter()
+                .map(|e| (e.metric_id, e.value))
+                .collect::<Vec<_>>(),
+            [(id, 1), (id, 2)]
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    /* the writer side of killed_writer_test, runs until killed */
+    #[test]
+    #[ignore]
+    fn blackbox_writer_process() {
+        let Some(path) = std::env::var_os("ARC_METRICS_BLACKBOX") else {
+            return;
+        };
+
+        let blackbox = BlackBox::create(path, 1 << 16).unwrap();
+        for value in 1.. {
+            blackbox.record(value % 3, value);
+        }
+    }
+
+    #[test]
+    fn killed_writer_test() {
+        let path = path("killed");
+        let mut child = Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--ignored",
+                "--exact",
+                "blackbox::test::blackbox_writer_process",
+            ])
+            .env("ARC_METRICS_BLACKBOX", &path)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        /* wait for the ring to wrap so the kill lands in the middle of overwriting */
+        let started = std::time::Instant::now();
+        while read(&path).map_or(true, |events| {
+            events.last().map_or(0, |event| event.sequence) < 1 << 17
+        }) {
+            assert!(started.elapsed() < Duration::from_secs(30));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        let events = read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        /* everything that survived is a complete record of the writer's pattern */
+        assert!((1 << 16) - 2 <= events.len());
+        for event in &events {
+            assert_eq!(event.value, event.sequence);
+            assert_eq!(event.metric_id, event.value % 3);
+        }
+        assert!(events
+            .windows(2)
+            .all(|pair| pair[0].sequence < pair[1].sequence));
+    }
+}
@@ -0,0 +1,164 @@
+/*
+ * Appends text exposition from another metrics library to every render, ex. the output of
+ * the prometheus crate's TextEncoder over its Registry::gather(). Sources are rendered after
+ * the registered families and before the arc_metrics self-metrics. validate() reports
+ * families both sides define, a scrape with both would have duplicate HELP / TYPE lines.
+ */
+use std::{borrow::Cow, collections::HashSet};
+
+use crate::{
+    policy::{Violation, ViolationKind},
+    PromMetricRegistry,
+};
+
+type Source = Box<dyn Fn() -> String + Send + Sync>;
+
+#[derive(Default)]
+pub(crate) struct TextSources {
+    sources: Vec<Source>,
+}
+
+impl TextSources {
+    pub(crate) fn encode(
+        &self,
+        f: &mut dyn std::fmt::Write,
+        filter: &dyn Fn(&str) -> bool,
+    ) -> std::fmt::Result {
+        for source in &self.sources {
+            let text = source();
+            for line in text.lines().filter(|line| !line.is_empty()) {
+                if line_name(line).map_or(true, filter) {
+                    f.write_str(line)?;
+                    f.write_str("\n")?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /* families defined by a source and the registry, or by two sources */
+    pub(crate) fn conflicts(&self, registry: &PromMetricRegistry) -> Vec<Violation> {
+        let mut seen = registry
+            .metrics
+            .iter()
+            .map(|metric| metric.name.to_string())
+            .collect::<HashSet<_>>();
+
+        let mut conflicts = Vec::new();
+        for source in &self.sources {
+            let text = source();
+            for name in text.lines().filter_map(type_name) {
+                if !seen.insert(name.to_string()) {
+                    conflicts.push(Violation {
+                        kind: ViolationKind::Duplicate,
+                        name: Cow::Owned(name.to_string()),
+                        location: None,
+                    });
+                }
+            }
+        }
+        conflicts
+    }
+}
+
+/* family name of a TYPE line */
+fn type_name(line: &str) -> Option<&str> {
+    line.strip_prefix("# TYPE ")?.split(' ').next()
+}
+
+/* metric name of a sample, HELP or TYPE line, None for other comments */
+fn line_name(line: &str) -> Option<&str> {
+    let line = match line.strip_prefix('#') {
+        Some(comment) => comment
+            .trim_start()
+            .strip_prefix("HELP ")
+            .or_else(|| comment.trim_start().strip_prefix("TYPE "))?,
+        None => line,
+    };
+    line.split(['{', ' ']).next()
+}
+
+impl PromMetricRegistry {
+    /* source is called on every render, it must return complete text exposition families */
+    pub fn append_text_source<F>(&mut self, source: F) -> &mut Self
+    where
+        F: Fn() -> String + Send + Sync + 'static,
+    {
+        self.text_sources.sources.push(Box::new(source));
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    };
+
+    use crate::{policy::ViolationKind, scrape::ScrapeOptions, IntCounter, PromMetricRegistry};
+
+    /* what prometheus::TextEncoder writes for a single counter */
+    fn foreign(name: &'static str, value: Arc<AtomicU64>) -> impl Fn() -> String {
+        move || {
+            format!(
+                "# HELP {0} Handled by the legacy router.\n# TYPE {0} counter\n{0}{{route=\"/\"}} {1}\n",
+                name,
+                value.load(Ordering::Relaxed)
+            )
+        }
+    }
+
+    #[test]
+    fn append_text_source_test() {
+        let counter = Arc::new(IntCounter::new());
+        let legacy = Arc::new(AtomicU64::new(0));
+
+        let mut reg = PromMetricRegistry::empty();
+        reg.track_dropped();
+        reg.register_fn(&counter, |c, reg| {
+            reg.count("arc_requests", c);
+        });
+        reg.append_text_source(foreign("legacy_requests", legacy.clone()));
+
+        counter.inc();
+        legacy.store(4, Ordering::Relaxed);
+
+        let out = reg.to_string();
+        assert!(out.starts_with(
+            "# HELP arc_requests\n\
+             # TYPE arc_requests counter\n\
+             arc_requests 1\n\
+             # HELP legacy_requests Handled by the legacy router.\n\
+             # TYPE legacy_requests counter\n\
+             legacy_requests{route=\"/\"} 4\n\
+             # HELP arc_metrics_dropped_samples_total\n"
+        ));
+        assert!(reg.validate().is_ok());
+
+        let legacy_only = reg.scrape(&ScrapeOptions::default().module("legacy_"));
+        assert!(legacy_only.unwrap().body.starts_with(
+            "# HELP legacy_requests Handled by the legacy router.\n\
+             # TYPE legacy_requests counter\n\
+             legacy_requests{route=\"/\"} 4\n\
+             # HELP arc_metrics_dropped_samples_total\n"
+        ));
+    }
+
+    #[test]
+    fn conflict_test() {
+        let counter = Arc::new(IntCounter::new());
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&counter, |c, reg| {
+            reg.count("requests", c);
+        });
+        reg.append_text_source(foreign("requests", Arc::default()));
+        reg.append_text_source(foreign("other", Arc::default()));
+        reg.append_text_source(foreign("other", Arc::default()));
+
+        let conflicts = reg.validate().unwrap_err();
+        let names = conflicts.iter().map(|c| &*c.name).collect::<Vec<_>>();
+        assert_eq!(names, ["requests", "other"]);
+        assert!(conflicts.iter().all(|c| c.kind == ViolationKind::Duplicate));
+    }
+}
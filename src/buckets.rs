@@ -0,0 +1,162 @@
+/* histogram bound builders, invalid arguments panic like IntHistogram::new */
+use alloc::{vec, vec::Vec};
+use core::fmt::Display;
+
+/* count bounds start, start + width, start + 2 * width, ... */
+pub fn linear(start: u64, width: u64, count: usize) -> Vec<u64> {
+    assert!(0 < width, "linear buckets need a width of at least 1");
+    assert!(0 < count, "buckets need at least one bound");
+
+    (0..count as u64)
+        .map(|i| {
+            i.checked_mul(width)
+                .and_then(|offset| start.checked_add(offset))
+                .expect("linear buckets overflow u64")
+        })
+        .collect()
+}
+
+/*
+ * count bounds start, start * factor, start * factor^2, ... rounded up, each bound is at
+ * least one above the previous so small starts stay strictly increasing
+ */
+pub fn exponential(start: u64, factor: f64, count: usize) -> Vec<u64> {
+    assert!(0 < start, "exponential buckets need a start of at least 1");
+    assert!(1.0 < factor, "exponential buckets need a factor above 1");
+    assert!(0 < count, "buckets need at least one bound");
+
+    let mut bounds = Vec::with_capacity(count);
+    let mut exact = start as f64;
+    let mut last = start;
+    bounds.push(start);
+
+    for _ in 1..count {
+        exact *= factor;
+        assert!(exact < u64::MAX as f64, "exponential buckets overflow u64");
+        /* ceil without std, exact is positive and below u64::MAX */
+        let mut bound = exact as u64;
+        if (bound as f64) < exact {
+            bound += 1;
+        }
+        last = bound.max(last + 1);
+        bounds.push(last);
+    }
+
+    bounds
+}
+
+/* 1ms to 30s */
+pub fn latency_ms_default() -> Vec<u64> {
+    vec![
+        1, 2, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000, 30000,
+    ]
+}
+
+/* 100us to 30s */
+pub fn latency_us_default() -> Vec<u64> {
+    latency_ms_default().into_iter().map(|ms| ms * 1000).fold(
+        vec![100, 250, 500],
+        |mut bounds, us| {
+            bounds.push(us);
+            bounds
+        },
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BucketError {
+    Empty,
+    NotIncreasing { index: usize },
+}
+
+impl Display for BucketError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "histogram needs at least one bound"),
+            Self::NotIncreasing { index } => {
+                write!(f, "histogram bound {} is not above the previous", index)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BucketError {}
+
+pub(crate) fn validate(bounds: &[u64]) -> Result<(), BucketError> {
+    if bounds.is_empty() {
+        return Err(BucketError::Empty);
+    }
+
+    match bounds.windows(2).position(|w| w[1] <= w[0]) {
+        Some(index) => Err(BucketError::NotIncreasing { index: index + 1 }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::IntHistogram;
+
+    use super::{exponential, latency_ms_default, latency_us_default, linear, BucketError};
+
+    #[test]
+    fn builders_test() {
+        assert_eq!(linear(10, 5, 4), [10, 15, 20, 25]);
+        assert_eq!(exponential(1, 2.0, 5), [1, 2, 4, 8, 16]);
+        assert_eq!(exponential(1, 1.5, 5), [1, 2, 3, 4, 6]);
+        assert_eq!(exponential(100, 1.5, 3), [100, 150, 225]);
+        assert_eq!(latency_us_default()[..4], [100, 250, 500, 1000]);
+        assert_eq!(latency_us_default().last(), Some(&30_000_000));
+
+        for bounds in [latency_ms_default(), latency_us_default()] {
+            assert!(IntHistogram::try_new(bounds).is_ok());
+        }
+    }
+
+    #[test]
+    fn property_test() {
+        let mut state = 0x853c_49e6_748f_ea9bu64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..1000 {
+            /* 10_000 * 4.01^19 stays well inside u64 */
+            let count = 1 + (next() % 20) as usize;
+            let start = next() % 10_000;
+            let width = 1 + next() % 1000;
+            let factor = 1.01 + (next() % 300) as f64 / 100.0;
+
+            for bounds in [
+                linear(start, width, count),
+                exponential(start.max(1), factor, count),
+            ] {
+                assert_eq!(bounds.len(), count);
+                assert!(bounds.windows(2).all(|w| w[0] < w[1]), "{:?}", bounds);
+                assert!(IntHistogram::try_new(bounds).is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn validate_test() {
+        assert_eq!(
+            IntHistogram::try_new(Vec::new()).err(),
+            Some(BucketError::Empty)
+        );
+        assert_eq!(
+            IntHistogram::try_new([1, 5, 5]).err(),
+            Some(BucketError::NotIncreasing { index: 2 })
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_width_test() {
+        linear(0, 0, 3);
+    }
+}
@@ -0,0 +1,321 @@
+use std::borrow::Cow;
+
+use crate::{
+    config::{self, ConfigError},
+    escape, set_attr, PromMetricRegistry,
+};
+
+/*
+ * Registry with configurable default base attributes. Without any settings it builds
+ * the same registry as PromMetricRegistry::new().
+ */
+pub struct PromMetricRegistryBuilder {
+    program_attr_key: Cow<'static, str>,
+    version_attr_key: Cow<'static, str>,
+    /* name and version, from pkg_details unless set with program() */
+    program: Option<(Cow<'static, str>, Cow<'static, str>)>,
+    default_attrs: bool,
+    extra_base_attrs: Vec<[Cow<'static, str>; 2]>,
+    namespace: Option<Cow<'static, str>>,
+    max_series: Option<usize>,
+}
+
+impl Default for PromMetricRegistryBuilder {
+    fn default() -> Self {
+        PromMetricRegistryBuilder {
+            program_attr_key: Cow::Borrowed("program"),
+            version_attr_key: Cow::Borrowed("pkg_version"),
+            program: pkg_details::try_get().map(|details| {
+                (
+                    Cow::Borrowed(details.pkg_name),
+                    Cow::Borrowed(details.pkg_version),
+                )
+            }),
+            default_attrs: true,
+            extra_base_attrs: Vec::new(),
+            namespace: None,
+            max_series: None,
+        }
+    }
+}
+
+impl PromMetricRegistryBuilder {
+    pub fn program_attr_key<K: Into<Cow<'static, str>>>(mut self, key: K) -> Self {
+        self.program_attr_key = key.into();
+        self
+    }
+
+    pub fn version_attr_key<K: Into<Cow<'static, str>>>(mut self, key: K) -> Self {
+        self.version_attr_key = key.into();
+        self
+    }
+
+    /* overrides the values from pkg_details */
+    pub fn program<N, V>(mut self, name: N, version: V) -> Self
+    where
+        N: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+    {
+        self.program = Some((name.into(), version.into()));
+        self
+    }
+
+    /* drops the program / version attributes, extra attributes are still added */
+    pub fn no_default_attrs(mut self) -> Self {
+        self.default_attrs = false;
+        self
+    }
+
+    /* added after the program / version attributes, replacing them on the same key */
+    pub fn extra_base_attrs<K, V, I>(mut self, attrs: I) -> Self
+    where
+        K: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        for (key, value) in attrs {
+            set_attr(&mut self.extra_base_attrs, key.into(), value.into());
+        }
+        self
+    }
+
+    /* commit hash captured by the caller, ex. from a build script */
+    pub fn git_sha<V: Into<Cow<'static, str>>>(self, sha: V) -> Self {
+        self.extra_base_attrs([("git_sha", sha)])
+    }
+
+    /* debug or release, following debug_assertions */
+    pub fn build_profile(self) -> Self {
+        let profile = if cfg!(debug_assertions) {
+            "debug"
+        } else {
+            "release"
+        };
+        self.extra_base_attrs([("build_profile", profile)])
+    }
+
+    /* see PromMetricRegistry::name_prefix */
+    pub fn namespace<N: Into<Cow<'static, str>>>(mut self, namespace: N) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    pub fn max_series(mut self, limit: usize) -> Self {
+        self.max_series = Some(limit);
+        self
+    }
+
+    /*
+     * Reads METRICS_NAMESPACE, METRICS_BASE_LABELS (see config::parse_labels),
+     * METRICS_DISABLE_IDENTITY and METRICS_MAX_SERIES. Settings made on the builder, before
+     * or after this call, take precedence. Every invalid variable is reported at once.
+     */
+    pub fn env(mut self) -> Result<Self, ConfigError> {
+        fn check<T>(errors: &mut Vec<ConfigError>, result: Result<T, ConfigError>) -> Option<T> {
+            result.map_err(|error| errors.push(error)).ok()
+        }
+
+        let mut errors = Vec::new();
+
+        let namespace = check(
+            &mut errors,
+            config::optional_env("METRICS_NAMESPACE", |value| {
+                match escape::is_legacy_name(value) {
+                    true => Ok(value.to_string()),
+                    false => Err(ConfigError::InvalidValue {
+                        input: value.to_string(),
+                        reason: "not a valid metric name",
+                    }),
+                }
+            }),
+        );
+        let labels = check(
+            &mut errors,
+            config::optional_env("METRICS_BASE_LABELS", config::parse_labels),
+        );
+        let disable_identity = check(
+            &mut errors,
+            config::optional_env("METRICS_DISABLE_IDENTITY", config::parse_bool),
+        );
+        let max_series = check(
+            &mut errors,
+            config::optional_env("METRICS_MAX_SERIES", config::parse_usize),
+        );
+
+        match errors.len() {
+            0 => {}
+            1 => return Err(errors.remove(0)),
+            _ => return Err(ConfigError::Multiple(errors)),
+        }
+
+        if self.namespace.is_none() {
+            self.namespace = namespace.flatten().map(Cow::Owned);
+        }
+        if self.max_series.is_none() {
+            self.max_series = max_series.flatten();
+        }
+        if disable_identity.flatten() == Some(true) {
+            self.default_attrs = false;
+        }
+        if let Some(labels) = labels.flatten() {
+            let mut attrs = Vec::with_capacity(labels.len() + self.extra_base_attrs.len());
+            for (key, value) in labels {
+                set_attr(&mut attrs, Cow::Owned(key), Cow::Owned(value));
+            }
+            for [key, value] in self.extra_base_attrs {
+                set_attr(&mut attrs, key, value);
+            }
+            self.extra_base_attrs = attrs;
+        }
+
+        Ok(self)
+    }
+
+    pub fn build(self) -> PromMetricRegistry {
+        let mut registry = PromMetricRegistry::empty();
+
+        if let (true, Some((name, version))) = (self.default_attrs, self.program) {
+            registry.base_attr(self.program_attr_key, name);
+            registry.base_attr(self.version_attr_key, version);
+        }
+        for [key, value] in self.extra_base_attrs {
+            registry.base_attr(key, value);
+        }
+
+        /* before the prefix so the registry's own metrics keep their names */
+        if let Some(limit) = self.max_series {
+            registry.max_series(limit);
+        }
+        if let Some(namespace) = self.namespace {
+            registry.name_prefix(namespace);
+        }
+
+        registry
+    }
+}
+
+impl PromMetricRegistry {
+    pub fn builder() -> PromMetricRegistryBuilder {
+        PromMetricRegistryBuilder::default()
+    }
+
+    /* builder settings read from the environment, see PromMetricRegistryBuilder::env */
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Ok(Self::builder().env()?.build())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{borrow::Cow, sync::Arc};
+
+    use crate::{IntCounter, PromMetricRegistry};
+
+    fn labels(mut reg: PromMetricRegistry) -> Vec<[Cow<'static, str>; 2]> {
+        let counter = Arc::new(IntCounter::new());
+        reg.register_fn(&counter, |counter, reg| {
+            reg.count("requests", counter);
+        });
+        reg.gather().remove(0).samples.remove(0).attributes
+    }
+
+    #[test]
+    fn builder_test() {
+        let reg = PromMetricRegistry::builder()
+            .program_attr_key("app")
+            .version_attr_key("app_version")
+            .program("server", "1.2.3")
+            .git_sha("abc123")
+            .extra_base_attrs([("region", "eu")])
+            .build();
+
+        assert_eq!(
+            labels(reg),
+            [
+                ["app", "server"],
+                ["app_version", "1.2.3"],
+                ["git_sha", "abc123"],
+                ["region", "eu"],
+            ]
+        );
+    }
+
+    #[test]
+    fn no_default_attrs_test() {
+        let reg = PromMetricRegistry::builder()
+            .no_default_attrs()
+            .build_profile()
+            .build();
+
+        let profile = if cfg!(debug_assertions) {
+            "debug"
+        } else {
+            "release"
+        };
+        assert_eq!(labels(reg), [["build_profile", profile]]);
+
+        let reg = PromMetricRegistry::builder().no_default_attrs().build();
+        assert!(labels(reg).is_empty());
+    }
+
+    #[test]
+    fn env_test() {
+        let vars = [
+            ("METRICS_NAMESPACE", "tool"),
+            ("METRICS_BASE_LABELS", "env=prod,region=\"eu, west\""),
+            ("METRICS_DISABLE_IDENTITY", "1"),
+            ("METRICS_MAX_SERIES", "50000"),
+        ];
+        for (key, value) in vars {
+            std::env::set_var(key, value);
+        }
+
+        let reg = PromMetricRegistry::from_env().unwrap();
+        assert_eq!(reg.series_limit.map(|limit| limit.max_series), Some(50000));
+        assert_eq!(labels(reg), [["env", "prod"], ["region", "eu, west"]]);
+
+        /* builder settings win over the environment */
+        let mut reg = PromMetricRegistry::builder()
+            .extra_base_attrs([("env", "dev")])
+            .max_series(10)
+            .env()
+            .unwrap()
+            .namespace("app")
+            .build();
+        assert_eq!(reg.series_limit.map(|limit| limit.max_series), Some(10));
+
+        let counter = Arc::new(IntCounter::new());
+        reg.register_fn(&counter, |counter, reg| {
+            reg.count("requests", counter);
+        });
+        assert!(reg
+            .to_string()
+            .contains("app_requests{env=\"dev\",region=\"eu, west\"} 0\n"));
+        assert!(reg
+            .to_string()
+            .contains("\narc_metrics_series_rejected_total{"));
+
+        std::env::set_var("METRICS_NAMESPACE", "bad-name");
+        std::env::set_var("METRICS_MAX_SERIES", "lots");
+        assert_eq!(
+            PromMetricRegistry::from_env().err().map(|e| e.to_string()),
+            Some(
+                "invalid env METRICS_NAMESPACE: invalid value \"bad-name\": not a valid metric name; \
+                 invalid env METRICS_MAX_SERIES: invalid value \"lots\": expected a non-negative integer"
+                    .to_string()
+            )
+        );
+
+        for (key, _) in vars {
+            std::env::remove_var(key);
+        }
+    }
+
+    #[test]
+    fn default_test() {
+        assert_eq!(
+            PromMetricRegistry::builder().build().base_attrs(),
+            PromMetricRegistry::new().base_attrs()
+        );
+    }
+}
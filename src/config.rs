@@ -0,0 +1,479 @@
+/*
+ * parsers for settings given as text, ex. environment variables. Used by
+ * PromMetricRegistryBuilder::env, ExportPolicy::from_env for the statsd and remote_write
+ * exporters, and Vec::<u64>::from_env for histogram bucket overrides.
+ */
+use std::{error::Error, fmt::Display, time::Duration};
+
+use crate::escape;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    InvalidDuration {
+        input: String,
+        reason: &'static str,
+    },
+    InvalidBuckets {
+        input: String,
+        reason: &'static str,
+    },
+    InvalidLabels {
+        input: String,
+        reason: &'static str,
+    },
+    InvalidValue {
+        input: String,
+        reason: &'static str,
+    },
+    InvalidEnv {
+        var: String,
+        error: Box<ConfigError>,
+    },
+    NotUnicodeEnv {
+        var: String,
+    },
+    /* every error found, ex. all bad variables in PromMetricRegistry::from_env */
+    Multiple(Vec<ConfigError>),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidDuration { input, reason } => {
+                write!(f, "invalid duration {:?}: {}", input, reason)
+            }
+            Self::InvalidBuckets { input, reason } => {
+                write!(f, "invalid buckets {:?}: {}", input, reason)
+            }
+            Self::InvalidLabels { input, reason } => {
+                write!(f, "invalid labels {:?}: {}", input, reason)
+            }
+            Self::InvalidValue { input, reason } => {
+                write!(f, "invalid value {:?}: {}", input, reason)
+            }
+            Self::InvalidEnv { var, error } => write!(f, "invalid env {}: {}", var, error),
+            Self::NotUnicodeEnv { var } => write!(f, "invalid env {}: not unicode", var),
+            Self::Multiple(errors) => {
+                for (i, error) in errors.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str("; ")?;
+                    }
+                    write!(f, "{}", error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/* accepts "<number><unit>" with units ns, us, ms, s, m, h and optional fraction, ex "1.5s" */
+pub fn parse_duration(input: &str) -> Result<Duration, ConfigError> {
+    let error = |reason| ConfigError::InvalidDuration {
+        input: input.to_string(),
+        reason,
+    };
+
+    let trimmed = input.trim();
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+
+    if number.is_empty() {
+        return Err(error("missing number"));
+    }
+    if number.starts_with('.') || number.ends_with('.') || 1 < number.matches('.').count() {
+        return Err(error("malformed number"));
+    }
+
+    let unit_nanos: u64 = match unit.trim() {
+        "" => return Err(error("missing unit (expected ns, us, ms, s, m or h)")),
+        "ns" => 1,
+        "us" => 1_000,
+        "ms" => 1_000_000,
+        "s" => 1_000_000_000,
+        "m" => 60_000_000_000,
+        "h" => 3_600_000_000_000,
+        _ => return Err(error("unknown unit (expected ns, us, ms, s, m or h)")),
+    };
+
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    let whole: u64 = whole.parse().map_err(|_| error("number too large"))?;
+
+    let mut nanos = whole
+        .checked_mul(unit_nanos)
+        .ok_or_else(|| error("duration too large"))?;
+
+    /* apply fraction digit by digit to avoid float rounding */
+    let mut scale = unit_nanos;
+    for digit in fraction.bytes() {
+        scale /= 10;
+        nanos = nanos
+            .checked_add((digit - b'0') as u64 * scale)
+            .ok_or_else(|| error("duration too large"))?;
+    }
+
+    Ok(Duration::from_nanos(nanos))
+}
+
+/* comma separated, strictly increasing bounds; a trailing +Inf is accepted and implicit */
+pub fn parse_buckets(input: &str) -> Result<Vec<u64>, ConfigError> {
+    let error = |reason| ConfigError::InvalidBuckets {
+        input: input.to_string(),
+        reason,
+    };
+
+    let mut parts = input.split(',').map(str::trim).peekable();
+    let mut buckets = Vec::new();
+
+    while let Some(part) = parts.next() {
+        if part == "+Inf" {
+            if parts.peek().is_some() {
+                return Err(error("+Inf must be the last bucket"));
+            }
+            break;
+        }
+
+        if part.is_empty() {
+            return Err(error("empty bucket"));
+        }
+
+        let bound: u64 = part
+            .parse()
+            .map_err(|_| error("bucket is not an integer"))?;
+        if buckets.last().is_some_and(|last| bound <= *last) {
+            return Err(error("buckets must be strictly increasing"));
+        }
+
+        buckets.push(bound);
+    }
+
+    if buckets.is_empty() {
+        return Err(error("no buckets"));
+    }
+
+    Ok(buckets)
+}
+
+/*
+ * comma separated key=value pairs, ex "env=prod,region=eu". Values containing commas or
+ * spaces can be double quoted with \" and \\ escapes inside, ex desc="a, b".
+ */
+pub fn parse_labels(input: &str) -> Result<Vec<(String, String)>, ConfigError> {
+    let error = |reason| ConfigError::InvalidLabels {
+        input: input.to_string(),
+        reason,
+    };
+
+    let mut labels: Vec<(String, String)> = Vec::new();
+    if input.trim().is_empty() {
+        return Ok(labels);
+    }
+
+    let mut chars = input.chars().peekable();
+    loop {
+        let mut key = String::new();
+        loop {
+            match chars.next() {
+                Some('=') => break,
+                Some(',') | None => return Err(error("expected key=value")),
+                Some(c) => key.push(c),
+            }
+        }
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(error("missing label name"));
+        }
+        if !escape::is_legacy_name(key) {
+            return Err(error("invalid label name"));
+        }
+
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(c @ ('"' | '\\')) => value.push(c),
+                        _ => return Err(error("invalid escape in quoted value")),
+                    },
+                    Some(c) => value.push(c),
+                    None => return Err(error("unterminated quoted value")),
+                }
+            }
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            if chars.peek().is_some_and(|c| *c != ',') {
+                return Err(error("text after quoted value"));
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ',') {
+                if c == '"' {
+                    return Err(error("quote inside unquoted value"));
+                }
+                value.push(c);
+            }
+            value.truncate(value.trim_end().len());
+        }
+
+        if labels.iter().any(|(existing, _)| existing == key) {
+            return Err(error("duplicate label name"));
+        }
+        labels.push((key.to_string(), value));
+
+        match chars.next() {
+            Some(',') => continue,
+            _ => return Ok(labels),
+        }
+    }
+}
+
+/* 1 / true / yes or 0 / false / no, case insensitive */
+pub fn parse_bool(input: &str) -> Result<bool, ConfigError> {
+    match input.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" => Ok(true),
+        "0" | "false" | "no" | "" => Ok(false),
+        _ => Err(ConfigError::InvalidValue {
+            input: input.to_string(),
+            reason: "expected 1, 0, true or false",
+        }),
+    }
+}
+
+pub fn parse_usize(input: &str) -> Result<usize, ConfigError> {
+    input.trim().parse().map_err(|_| ConfigError::InvalidValue {
+        input: input.to_string(),
+        reason: "expected a non-negative integer",
+    })
+}
+
+pub trait FromEnv: Sized {
+    /* returns default when the variable is not set */
+    fn from_env(var: &str, default: Self) -> Result<Self, ConfigError>;
+}
+
+fn parse_env<T>(
+    var: &str,
+    default: T,
+    parse: impl FnOnce(&str) -> Result<T, ConfigError>,
+) -> Result<T, ConfigError> {
+    match std::env::var(var) {
+        Ok(value) => parse(&value).map_err(|error| ConfigError::InvalidEnv {
+            var: var.to_string(),
+            error: Box::new(error),
+        }),
+        Err(std::env::VarError::NotPresent) => Ok(default),
+        Err(std::env::VarError::NotUnicode(_)) => Err(ConfigError::NotUnicodeEnv {
+            var: var.to_string(),
+        }),
+    }
+}
+
+impl FromEnv for Duration {
+    fn from_env(var: &str, default: Self) -> Result<Self, ConfigError> {
+        parse_env(var, default, parse_duration)
+    }
+}
+
+impl FromEnv for Vec<u64> {
+    fn from_env(var: &str, default: Self) -> Result<Self, ConfigError> {
+        parse_env(var, default, parse_buckets)
+    }
+}
+
+impl FromEnv for bool {
+    fn from_env(var: &str, default: Self) -> Result<Self, ConfigError> {
+        parse_env(var, default, parse_bool)
+    }
+}
+
+impl FromEnv for usize {
+    fn from_env(var: &str, default: Self) -> Result<Self, ConfigError> {
+        parse_env(var, default, parse_usize)
+    }
+}
+
+/* None when the variable is not set */
+pub(crate) fn optional_env<T>(
+    var: &str,
+    parse: impl FnOnce(&str) -> Result<T, ConfigError>,
+) -> Result<Option<T>, ConfigError> {
+    parse_env(var, None, |value| parse(value).map(Some))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{parse_bool, parse_buckets, parse_duration, parse_labels, ConfigError, FromEnv};
+
+    #[test]
+    fn parse_duration_test() {
+        let ok = [
+            ("250ms", Duration::from_millis(250)),
+            ("2s", Duration::from_secs(2)),
+            ("5m", Duration::from_secs(300)),
+            ("1h", Duration::from_secs(3600)),
+            ("10us", Duration::from_micros(10)),
+            ("7ns", Duration::from_nanos(7)),
+            ("0s", Duration::ZERO),
+            ("  3s\t", Duration::from_secs(3)),
+            ("3 s", Duration::from_secs(3)),
+            ("1.5s", Duration::from_millis(1500)),
+            ("0.25m", Duration::from_secs(15)),
+            ("1.0001s", Duration::from_micros(1_000_100)),
+        ];
+
+        for (input, expected) in ok {
+            assert_eq!(parse_duration(input), Ok(expected), "{}", input);
+        }
+
+        let err = [
+            ("", "missing number"),
+            ("ms", "missing number"),
+            ("10", "missing unit (expected ns, us, ms, s, m or h)"),
+            ("10 days", "unknown unit (expected ns, us, ms, s, m or h)"),
+            ("10S", "unknown unit (expected ns, us, ms, s, m or h)"),
+            ("-1s", "missing number"),
+            (".5s", "malformed number"),
+            ("5.s", "malformed number"),
+            ("1.2.3s", "malformed number"),
+            ("99999999999999999999s", "number too large"),
+            ("9999999999999h", "duration too large"),
+            ("18446744073.999999999s", "duration too large"),
+        ];
+
+        for (input, reason) in err {
+            assert_eq!(
+                parse_duration(input),
+                Err(ConfigError::InvalidDuration {
+                    input: input.to_string(),
+                    reason
+                }),
+                "{}",
+                input
+            );
+        }
+
+        assert_eq!(
+            parse_duration("5x").unwrap_err().to_string(),
+            "invalid duration \"5x\": unknown unit (expected ns, us, ms, s, m or h)"
+        );
+    }
+
+    #[test]
+    fn parse_buckets_test() {
+        assert_eq!(parse_buckets("1,5,10,50,+Inf"), Ok(vec![1, 5, 10, 50]));
+        assert_eq!(parse_buckets(" 1 , 5,10 "), Ok(vec![1, 5, 10]));
+        assert_eq!(parse_buckets("0"), Ok(vec![0]));
+
+        let err = [
+            ("", "empty bucket"),
+            ("+Inf", "no buckets"),
+            ("1,,5", "empty bucket"),
+            ("1,5,", "empty bucket"),
+            ("1,+Inf,5", "+Inf must be the last bucket"),
+            ("5,1", "buckets must be strictly increasing"),
+            ("5,5", "buckets must be strictly increasing"),
+            ("1.5,2", "bucket is not an integer"),
+            ("-1,2", "bucket is not an integer"),
+        ];
+
+        for (input, reason) in err {
+            assert_eq!(
+                parse_buckets(input),
+                Err(ConfigError::InvalidBuckets {
+                    input: input.to_string(),
+                    reason
+                }),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn parse_labels_test() {
+        let labels = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        let ok = [
+            ("", labels(&[])),
+            (
+                "env=prod,region=eu",
+                labels(&[("env", "prod"), ("region", "eu")]),
+            ),
+            (
+                " env = prod , region=eu ",
+                labels(&[("env", "prod"), ("region", "eu")]),
+            ),
+            ("desc=\"a, b\",x=y", labels(&[("desc", "a, b"), ("x", "y")])),
+            (
+                "q=\"say \\\"hi\\\" \\\\ bye\"",
+                labels(&[("q", "say \"hi\" \\ bye")]),
+            ),
+            ("empty=,other=\"\"", labels(&[("empty", ""), ("other", "")])),
+        ];
+
+        for (input, expected) in ok {
+            assert_eq!(parse_labels(input), Ok(expected), "{}", input);
+        }
+
+        let err = [
+            ("env", "expected key=value"),
+            ("=prod", "missing label name"),
+            ("env=prod,", "expected key=value"),
+            ("bad-key=1", "invalid label name"),
+            ("a=\"open", "unterminated quoted value"),
+            ("a=\"x\"y", "text after quoted value"),
+            ("a=x\"y\"", "quote inside unquoted value"),
+            ("a=\"\\n\"", "invalid escape in quoted value"),
+            ("a=1,a=2", "duplicate label name"),
+        ];
+
+        for (input, reason) in err {
+            assert_eq!(
+                parse_labels(input),
+                Err(ConfigError::InvalidLabels {
+                    input: input.to_string(),
+                    reason
+                }),
+                "{}",
+                input
+            );
+        }
+
+        assert_eq!(parse_bool(" TRUE "), Ok(true));
+        assert_eq!(parse_bool("0"), Ok(false));
+        assert!(parse_bool("maybe").is_err());
+    }
+
+    #[test]
+    fn from_env_test() {
+        std::env::set_var("ARC_METRICS_TEST_INTERVAL", "250ms");
+        assert_eq!(
+            Duration::from_env("ARC_METRICS_TEST_INTERVAL", Duration::ZERO),
+            Ok(Duration::from_millis(250))
+        );
+
+        assert_eq!(
+            Duration::from_env("ARC_METRICS_TEST_UNSET", Duration::from_secs(1)),
+            Ok(Duration::from_secs(1))
+        );
+
+        std::env::set_var("ARC_METRICS_TEST_BUCKETS", "1,2,x");
+        assert_eq!(
+            Vec::<u64>::from_env("ARC_METRICS_TEST_BUCKETS", vec![])
+                .unwrap_err()
+                .to_string(),
+            "invalid env ARC_METRICS_TEST_BUCKETS: invalid buckets \"1,2,x\": bucket is not an integer"
+        );
+    }
+}
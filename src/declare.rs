@@ -0,0 +1,191 @@
+/*
+ * metrics! declares a metric struct, its Default and its RegisterableMetric impl in one place:
+ *
+ * metrics! {
+ *     pub struct HttpMetrics prefix "http" {
+ *         requests_total: counter ["HTTP requests"],
+ *         in_flight: gauge,
+ *         latency_ms_total: counter attr(unit = "ms"),
+ *     }
+ * }
+ *
+ * Series are named <prefix>_<field> (the field alone without a prefix), kinds are counter,
+ * gauge, float_counter, float_gauge, histogram (default buckets) and summary (default
+ * quantiles). Help text isn't rendered yet, it is kept in the struct's HELP const by series
+ * name.
+ */
+
+#[macro_export]
+macro_rules! metrics {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident prefix $prefix:literal { $($fields:tt)* }
+    ) => {
+        $crate::metrics!(@struct [$(#[$meta])*] $vis $name [$prefix] { $($fields)* });
+    };
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident { $($fields:tt)* }
+    ) => {
+        $crate::metrics!(@struct [$(#[$meta])*] $vis $name [] { $($fields)* });
+    };
+    (@struct [$($meta:tt)*] $vis:vis $name:ident $prefix:tt {
+        $(
+            $field:ident : $kind:ident
+            $([$help:literal])?
+            $(attr($($key:ident = $value:literal),* $(,)?))?
+        ),* $(,)?
+    }) => {
+        $($meta)*
+        #[derive(Default)]
+        $vis struct $name {
+            $(pub $field: $crate::metrics!(@type $kind)),*
+        }
+
+        impl $name {
+            pub const HELP: &'static [(&'static str, &'static str)] = &[
+                $($(($crate::metrics!(@name $prefix $field), $help),)?)*
+            ];
+        }
+
+        impl $crate::helpers::RegisterableMetric for $name {
+            fn register(&'static self, register: &mut $crate::RegisterAction) {
+                $(
+                    $crate::metrics!(
+                        @register register $kind
+                        $crate::metrics!(@name $prefix $field), &self.$field
+                    )
+                    $($(.attr(stringify!($key), $value))*)?;
+                )*
+            }
+        }
+    };
+    (@type counter) => { $crate::IntCounter };
+    (@type gauge) => { $crate::IntGauge };
+    (@type float_counter) => { $crate::FloatCounter };
+    (@type float_gauge) => { $crate::FloatGauge };
+    (@type histogram) => { $crate::IntHistogram };
+    (@type summary) => { $crate::Summary };
+    (@type $other:ident) => {
+        ::std::compile_error!(::std::concat!(
+            "unknown metric kind `",
+            ::std::stringify!($other),
+            "`, expected counter, gauge, float_counter, float_gauge, histogram or summary"
+        ))
+    };
+    (@register $register:ident counter $name:expr, $metric:expr) => {
+        $register.count($name, $metric)
+    };
+    (@register $register:ident gauge $name:expr, $metric:expr) => {
+        $register.gauge($name, $metric)
+    };
+    (@register $register:ident float_counter $name:expr, $metric:expr) => {
+        $register.float_count($name, $metric)
+    };
+    (@register $register:ident float_gauge $name:expr, $metric:expr) => {
+        $register.float_gauge($name, $metric)
+    };
+    (@register $register:ident histogram $name:expr, $metric:expr) => {
+        $register.histogram($name, $metric)
+    };
+    (@register $register:ident summary $name:expr, $metric:expr) => {
+        $register.summary($name, $metric)
+    };
+    /* the unknown kind is reported by @type */
+    (@register $register:ident $other:ident $name:expr, $metric:expr) => {
+        $register.empty()
+    };
+    (@name [$prefix:literal] $field:ident) => {
+        ::std::concat!($prefix, "_", ::std::stringify!($field))
+    };
+    (@name [] $field:ident) => {
+        ::std::stringify!($field)
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::PromMetricRegistry;
+
+    crate::metrics! {
+        /* per listener */
+        #[derive(Debug)]
+        pub struct HttpMetrics prefix "http" {
+            requests_total: counter ["HTTP requests"],
+            in_flight: gauge,
+            latency_ms_total: counter attr(unit = "ms", kind = "wall"),
+            sizes: histogram ["Response sizes"] attr(unit = "bytes"),
+            ttfb: summary,
+        }
+    }
+
+    crate::metrics! {
+        struct Bare {
+            jobs_total: counter,
+            load: float_gauge,
+        }
+    }
+
+    #[test]
+    fn metrics_macro_test() {
+        let metrics = Arc::new(HttpMetrics::default());
+        let bare = Arc::new(Bare::default());
+        let mut reg = PromMetricRegistry::empty();
+        reg.register(&metrics);
+        reg.register(&bare);
+
+        metrics.requests_total.inc_by(3);
+        metrics.in_flight.set(2);
+        metrics.latency_ms_total.inc_by(40);
+        metrics.sizes.observe(20_000);
+        metrics.ttfb.observe(7);
+        bare.jobs_total.inc();
+        bare.load.set(0.5);
+
+        let text = reg.to_string();
+        assert_eq!(
+            text.lines()
+                .filter(|line| !line.contains("_bucket{"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            "# HELP http_in_flight\n\
+             # TYPE http_in_flight gauge\n\
+             http_in_flight 2\n\
+             # HELP http_latency_ms_total\n\
+             # TYPE http_latency_ms_total counter\n\
+             http_latency_ms_total{unit=\"ms\",kind=\"wall\"} 40\n\
+             # HELP http_requests_total\n\
+             # TYPE http_requests_total counter\n\
+             http_requests_total 3\n\
+             # HELP http_sizes\n\
+             # TYPE http_sizes histogram\n\
+             http_sizes_sum{unit=\"bytes\"} 20000\n\
+             http_sizes_count{unit=\"bytes\"} 1\n\
+             # HELP http_ttfb\n\
+             # TYPE http_ttfb summary\n\
+             http_ttfb{quantile=\"0.5\"} 7\n\
+             http_ttfb{quantile=\"0.9\"} 7\n\
+             http_ttfb{quantile=\"0.99\"} 7\n\
+             http_ttfb_sum 7\n\
+             http_ttfb_count 1\n\
+             # HELP jobs_total\n\
+             # TYPE jobs_total counter\n\
+             jobs_total 1\n\
+             # HELP load\n\
+             # TYPE load gauge\n\
+             load 0.5"
+        );
+        assert!(text.contains("http_sizes_bucket{unit=\"bytes\",le=\"+Inf\"} 1\n"));
+
+        assert_eq!(
+            HttpMetrics::HELP,
+            [
+                ("http_requests_total", "HTTP requests"),
+                ("http_sizes", "Response sizes")
+            ]
+        );
+        assert!(Bare::HELP.is_empty());
+    }
+}
@@ -0,0 +1,391 @@
+/*
+ * Escaping for the exposition formats. Input is &str so it is always valid UTF-8, byte
+ * data (ex. OsStr paths) has to be converted lossily by the caller before it gets here.
+ */
+use std::{borrow::Cow, error::Error, fmt::Display};
+
+/* HELP text: backslash and newline */
+pub fn help(text: &str) -> Escaped<'_> {
+    Escaped {
+        text,
+        quote: false,
+        wrap: false,
+    }
+}
+
+/* label values: backslash, double quote and newline */
+pub fn label_value(text: &str) -> Escaped<'_> {
+    Escaped {
+        text,
+        quote: true,
+        wrap: false,
+    }
+}
+
+/* metric or label names, quoted when outside the legacy [a-zA-Z_:][a-zA-Z0-9_:]* charset */
+pub fn name(text: &str) -> Escaped<'_> {
+    Escaped {
+        text,
+        quote: true,
+        wrap: !is_legacy_name(text),
+    }
+}
+
+/* const so metric_name! can check names at compile time */
+pub const fn is_legacy_name(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    if bytes.is_empty() || bytes[0].is_ascii_digit() {
+        return false;
+    }
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if !(b.is_ascii_alphanumeric() || b == b'_' || b == b':') {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/* metric_name! without the modern feature, only checked at compile time in const items */
+pub const fn checked_metric_name(name: &'static str) -> &'static str {
+    assert!(is_legacy_name(name), "invalid metric name");
+    name
+}
+
+/* a metric name literal, invalid names fail to compile with the modern feature */
+#[cfg(feature = "modern")]
+#[macro_export]
+macro_rules! metric_name {
+    ($name:expr) => {
+        const {
+            assert!($crate::escape::is_legacy_name($name), "invalid metric name");
+            $name
+        }
+    };
+}
+
+/* a metric name literal, invalid names panic where they are first used */
+#[cfg(not(feature = "modern"))]
+#[macro_export]
+macro_rules! metric_name {
+    ($name:expr) => {
+        $crate::escape::checked_metric_name($name)
+    };
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LabelNameError {
+    Empty,
+    LeadingDigit { name: String },
+    InvalidChar { name: String, position: usize },
+    /* names starting with __ are reserved for Prometheus internals, see also RESERVED_LABELS */
+    Reserved { name: String },
+}
+
+impl Display for LabelNameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty label name"),
+            Self::LeadingDigit { name } => write!(f, "label name {:?} starts with a digit", name),
+            Self::InvalidChar { name, position } => write!(
+                f,
+                "label name {:?} has an invalid character at byte {}",
+                name, position
+            ),
+            Self::Reserved { name } => write!(f, "label name {:?} is reserved", name),
+        }
+    }
+}
+
+impl Error for LabelNameError {}
+
+/* label names must match [a-zA-Z_][a-zA-Z0-9_]* and not start with __ */
+pub fn check_label_name(name: &str) -> Result<(), LabelNameError> {
+    let owned = || name.to_string();
+
+    match name.chars().next() {
+        None => return Err(LabelNameError::Empty),
+        Some(c) if c.is_ascii_digit() => {
+            return Err(LabelNameError::LeadingDigit { name: owned() })
+        }
+        _ => {}
+    }
+    if let Some(position) = name.find(|c: char| !c.is_ascii_alphanumeric() && c != '_') {
+        return Err(LabelNameError::InvalidChar {
+            name: owned(),
+            position,
+        });
+    }
+    if name.starts_with("__") {
+        return Err(LabelNameError::Reserved { name: owned() });
+    }
+    Ok(())
+}
+
+/* labels the exposition adds itself, setting them on a series breaks histograms and summaries */
+pub const RESERVED_LABELS: &[&str] = &["le", "quantile", "__name__"];
+
+pub fn reserved_label(name: &str) -> Option<&'static str> {
+    RESERVED_LABELS
+        .iter()
+        .copied()
+        .find(|reserved| *reserved == name)
+}
+
+/* check_label_name that also rejects RESERVED_LABELS, for label names fixed up front */
+pub fn check_series_label_name(name: &str) -> Result<(), LabelNameError> {
+    check_label_name(name)?;
+    match reserved_label(name) {
+        Some(_) => Err(LabelNameError::Reserved {
+            name: name.to_string(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/* replaces invalid characters with _, prefixes a leading digit and collapses a __ prefix */
+pub fn sanitize_label_name(name: Cow<'static, str>) -> Cow<'static, str> {
+    if check_label_name(&name).is_ok() {
+        return name;
+    }
+
+    let mut sanitized = name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect::<String>();
+
+    let rest = sanitized.trim_start_matches('_');
+    if rest.len() != sanitized.len() {
+        sanitized = format!("_{}", rest);
+    }
+    if sanitized.is_empty() || sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    Cow::Owned(sanitized)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Escaped<'a> {
+    text: &'a str,
+    quote: bool,
+    wrap: bool,
+}
+
+impl Display for Escaped<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.wrap {
+            f.write_str("\"")?;
+        }
+
+        let mut rest = self.text;
+        while let Some(pos) = rest.find(|c| c == '\\' || c == '\n' || (self.quote && c == '"')) {
+            f.write_str(&rest[..pos])?;
+            f.write_str(match rest.as_bytes()[pos] {
+                b'\\' => "\\\\",
+                b'\n' => "\\n",
+                _ => "\\\"",
+            })?;
+            rest = &rest[pos + 1..];
+        }
+        f.write_str(rest)?;
+
+        if self.wrap {
+            f.write_str("\"")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnescapeError {
+    pub position: usize,
+}
+
+impl Display for UnescapeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid escape sequence at byte {}", self.position)
+    }
+}
+
+impl Error for UnescapeError {}
+
+/* reverses help / label_value escaping */
+pub fn unescape(text: &str) -> Result<String, UnescapeError> {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.char_indices();
+
+    while let Some((position, c)) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some((_, '\\')) => out.push('\\'),
+            Some((_, 'n')) => out.push('\n'),
+            Some((_, '"')) => out.push('"'),
+            _ => return Err(UnescapeError { position }),
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use super::{
+        check_label_name, help, is_legacy_name, label_value, name, sanitize_label_name, unescape,
+        LabelNameError, UnescapeError,
+    };
+
+    #[test]
+    fn escape_test() {
+        let cases = [
+            ("plain", "plain", "plain"),
+            ("back\\slash", "back\\\\slash", "back\\\\slash"),
+            ("new\nline", "new\\nline", "new\\nline"),
+            ("\"quoted\"", "\"quoted\"", "\\\"quoted\\\""),
+            ("tab\tstays", "tab\tstays", "tab\tstays"),
+            (
+                "em—dash ünïcode 🚀",
+                "em—dash ünïcode 🚀",
+                "em—dash ünïcode 🚀",
+            ),
+            ("\\n", "\\\\n", "\\\\n"),
+            ("", "", ""),
+        ];
+
+        for (input, help_expected, label_expected) in cases {
+            assert_eq!(help(input).to_string(), help_expected);
+            assert_eq!(label_value(input).to_string(), label_expected);
+            assert_eq!(unescape(label_expected).as_deref(), Ok(input));
+        }
+    }
+
+    #[test]
+    fn name_test() {
+        assert!(is_legacy_name("http_requests_total"));
+        assert!(is_legacy_name(":recording:rule"));
+        assert!(!is_legacy_name("1st"));
+        assert!(!is_legacy_name(""));
+        assert!(!is_legacy_name("with-dash"));
+        assert!(!is_legacy_name("ünïcode"));
+
+        assert_eq!(name("http_requests").to_string(), "http_requests");
+        assert_eq!(name("http.requests").to_string(), "\"http.requests\"");
+        assert_eq!(name("say \"hi\"").to_string(), "\"say \\\"hi\\\"\"");
+    }
+
+    #[test]
+    fn metric_name_test() {
+        const NAME: &str = metric_name!("http_requests_total");
+        assert_eq!(NAME, "http_requests_total");
+        assert_eq!(metric_name!(":recording:rule"), ":recording:rule");
+    }
+
+    /* with the modern feature this would fail to compile instead */
+    #[cfg(not(feature = "modern"))]
+    #[test]
+    #[should_panic(expected = "invalid metric name")]
+    fn metric_name_runtime_test() {
+        metric_name!("with-dash");
+    }
+
+    #[test]
+    fn label_name_test() {
+        assert_eq!(check_label_name("method"), Ok(()));
+        assert_eq!(check_label_name("_private"), Ok(()));
+        assert_eq!(check_label_name("a1_b2"), Ok(()));
+
+        let err = [
+            ("", LabelNameError::Empty),
+            (
+                "my-key",
+                LabelNameError::InvalidChar {
+                    name: "my-key".to_string(),
+                    position: 2,
+                },
+            ),
+            (
+                "with:colon",
+                LabelNameError::InvalidChar {
+                    name: "with:colon".to_string(),
+                    position: 4,
+                },
+            ),
+            (
+                "1stkey",
+                LabelNameError::LeadingDigit {
+                    name: "1stkey".to_string(),
+                },
+            ),
+            (
+                "__name__",
+                LabelNameError::Reserved {
+                    name: "__name__".to_string(),
+                },
+            ),
+        ];
+        for (input, error) in err {
+            assert_eq!(check_label_name(input), Err(error), "{}", input);
+        }
+
+        let sanitized = [
+            ("method", "method"),
+            ("my-key", "my_key"),
+            ("1stkey", "_1stkey"),
+            ("", "_"),
+            ("__name__", "_name__"),
+            ("___", "_"),
+            ("a.b c", "a_b_c"),
+            ("ключ", "_"),
+        ];
+        for (input, expected) in sanitized {
+            let output = sanitize_label_name(Cow::Borrowed(input));
+            assert_eq!(output, expected, "{}", input);
+            assert_eq!(check_label_name(&output), Ok(()), "{}", input);
+        }
+    }
+
+    #[test]
+    fn unescape_error_test() {
+        assert_eq!(unescape("bad\\t"), Err(UnescapeError { position: 3 }));
+        assert_eq!(unescape("trailing\\"), Err(UnescapeError { position: 8 }));
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let alphabet = [
+            'a', 'Z', '0', ' ', '\\', '"', '\n', '\t', 'n', 'é', '漢', '🚀',
+        ];
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+
+        for _ in 0..2000 {
+            let len = (state % 24) as usize;
+            let input = (0..len)
+                .map(|_| {
+                    /* xorshift, deterministic across runs */
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    alphabet[(state % alphabet.len() as u64) as usize]
+                })
+                .collect::<String>();
+
+            assert_eq!(
+                unescape(&label_value(&input).to_string()),
+                Ok(input.clone())
+            );
+            assert_eq!(unescape(&help(&input).to_string()), Ok(input.clone()));
+            state = state.wrapping_add(1);
+        }
+    }
+}
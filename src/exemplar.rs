@@ -0,0 +1,379 @@
+/*
+ * OpenMetrics exemplars on counters. ExemplarCounter is an IntCounter with a slot for its
+ * latest exemplar, so plain counters carry nothing extra and inc() never looks at the slot.
+ * How many exemplars are kept is set per series with RegisterHelper::exemplars, the policy
+ * lives in atomics and is checked before the clock is read or the exemplar lock is taken.
+ * Only the OpenMetrics encoder renders exemplars, the 0.0.4 text format has no syntax for
+ * them.
+ */
+use std::{
+    borrow::Cow,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicU64, AtomicU8, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    lost, IntCounter, MetricType, MetricValue, Observe, RegisterAction, RegisterHelper,
+    RegisterScope,
+};
+
+/* OpenMetrics limit on the combined length of an exemplar's label names and values */
+pub const MAX_LABEL_CHARS: usize = 128;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ExemplarPolicy {
+    #[default]
+    Always,
+    /* the first of every n exemplars offered */
+    EveryN(u32),
+    /* at most one per interval */
+    PerInterval(Duration),
+    Never,
+}
+
+const ALWAYS: u8 = 0;
+const EVERY_N: u8 = 1;
+const PER_INTERVAL: u8 = 2;
+const NEVER: u8 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exemplar {
+    pub labels: Vec<(String, String)>,
+    /* the increment the exemplar was attached to */
+    pub value: u64,
+    pub timestamp: SystemTime,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Slot {
+    /* ExemplarPolicy as a mode and its n or interval in nanoseconds */
+    mode: AtomicU8,
+    param: AtomicU64,
+    offered: AtomicU64,
+    /* nanoseconds since the epoch before which PerInterval rejects */
+    next: AtomicU64,
+    /* stays None and unlocked with Never */
+    exemplar: Mutex<Option<Exemplar>>,
+}
+
+impl Slot {
+    fn set_policy(&self, policy: ExemplarPolicy) {
+        let (mode, param) = match policy {
+            ExemplarPolicy::Always => (ALWAYS, 0),
+            ExemplarPolicy::EveryN(n) => (EVERY_N, u64::from(n.max(1))),
+            ExemplarPolicy::PerInterval(interval) => (PER_INTERVAL, nanos_of(interval)),
+            ExemplarPolicy::Never => (NEVER, 0),
+        };
+        self.offered.store(0, Ordering::Relaxed);
+        self.next.store(0, Ordering::Relaxed);
+        self.param.store(param, Ordering::Relaxed);
+        self.mode.store(mode, Ordering::Release);
+    }
+
+    /*
+     * decided on atomics before the exemplar lock is taken, the clock is only read once
+     * accepted or when PerInterval needs it. Returns the exemplar's timestamp
+     */
+    fn accepts(&self, clock: impl FnOnce() -> SystemTime) -> Option<SystemTime> {
+        match self.mode.load(Ordering::Acquire) {
+            NEVER => None,
+            EVERY_N => {
+                let n = self.param.load(Ordering::Relaxed).max(1);
+                (self.offered.fetch_add(1, Ordering::Relaxed) % n == 0).then(clock)
+            }
+            PER_INTERVAL => {
+                let timestamp = clock();
+                let now = nanos(timestamp);
+                let next = self.next.load(Ordering::Acquire);
+                let interval = self.param.load(Ordering::Relaxed);
+                (next <= now
+                    && self
+                        .next
+                        .compare_exchange(
+                            next,
+                            now.saturating_add(interval),
+                            Ordering::AcqRel,
+                            Ordering::Relaxed,
+                        )
+                        .is_ok())
+                .then_some(timestamp)
+            }
+            _ => Some(clock()),
+        }
+    }
+
+    pub(crate) fn latest(&self) -> Option<Exemplar> {
+        match self.exemplar.lock() {
+            Ok(exemplar) => exemplar.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    fn store(&self, exemplar: Exemplar) {
+        match self.exemplar.lock() {
+            Ok(mut slot) => *slot = Some(exemplar),
+            Err(poisoned) => *poisoned.into_inner() = Some(exemplar),
+        }
+    }
+}
+
+/* an IntCounter keeping its latest exemplar, derefs to the counter for inc() and load() */
+#[derive(Debug, Default)]
+pub struct ExemplarCounter {
+    counter: IntCounter,
+    slot: Slot,
+}
+
+impl Deref for ExemplarCounter {
+    type Target = IntCounter;
+
+    #[inline(always)]
+    fn deref(&self) -> &IntCounter {
+        &self.counter
+    }
+}
+
+impl Observe for ExemplarCounter {
+    #[inline]
+    fn observe(&self, value: u64) {
+        self.counter.observe(value);
+    }
+}
+
+impl ExemplarCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /* inc() plus an exemplar, ex. &[("trace_id", id)], kept as the policy allows */
+    pub fn inc_with_exemplar(&self, labels: &[(&str, &str)]) {
+        self.counter.inc();
+        self.offer(labels, SystemTime::now);
+    }
+
+    pub fn inc_with_exemplar_at(&self, labels: &[(&str, &str)], timestamp: SystemTime) {
+        self.counter.inc();
+        self.offer(labels, || timestamp);
+    }
+
+    fn offer(&self, labels: &[(&str, &str)], clock: impl FnOnce() -> SystemTime) {
+        let Some(timestamp) = self.slot.accepts(clock) else {
+            return;
+        };
+
+        let chars = labels
+            .iter()
+            .map(|(key, value)| key.chars().count() + value.chars().count())
+            .sum::<usize>();
+        if MAX_LABEL_CHARS < chars {
+            lost::record(lost::DropReason::Truncation, 1);
+            return;
+        }
+
+        self.slot.store(Exemplar {
+            labels: labels
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            value: 1,
+            timestamp,
+        });
+    }
+
+    /* the most recent exemplar */
+    pub fn exemplar(&self) -> Option<Exemplar> {
+        self.slot.latest()
+    }
+}
+
+impl RegisterAction<'_> {
+    #[track_caller]
+    pub fn exemplar_count<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        count: &'static ExemplarCounter,
+    ) -> RegisterHelper<'_> {
+        let mut helper = self.empty();
+        helper.exemplar_count(name, count);
+        helper
+    }
+}
+
+impl RegisterScope<'_> {
+    #[track_caller]
+    pub fn exemplar_count<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        count: &'static ExemplarCounter,
+    ) -> &mut Self {
+        self.helper.exemplar_count(name, count);
+        self
+    }
+}
+
+impl RegisterHelper<'_> {
+    /* a counter whose samples carry its exemplar in OpenMetrics output */
+    #[track_caller]
+    pub fn exemplar_count<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        count: &'static ExemplarCounter,
+    ) -> &mut Self {
+        self.push(
+            name,
+            MetricValue::Atomic(&count.counter.0),
+            MetricType::IntCounter,
+            false,
+        );
+        if let Some(reg) = self.registered.last_mut() {
+            reg.exemplar = Some(&count.slot);
+        }
+        self
+    }
+
+    /* sampling for the exemplar counters registered so far by this helper */
+    pub fn exemplars(&mut self, policy: ExemplarPolicy) -> &mut Self {
+        for reg in &self.registered {
+            if let Some(slot) = reg.exemplar {
+                slot.set_policy(policy);
+            }
+        }
+        self
+    }
+}
+
+fn nanos(timestamp: SystemTime) -> u64 {
+    timestamp.duration_since(UNIX_EPOCH).map_or(0, nanos_of)
+}
+
+fn nanos_of(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{mpsc, Arc},
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use crate::{lost, PromMetricRegistry};
+
+    use super::{Exemplar, ExemplarCounter, ExemplarPolicy};
+
+    #[derive(Default)]
+    struct Met {
+        always: ExemplarCounter,
+        every: ExemplarCounter,
+        interval: ExemplarCounter,
+        never: ExemplarCounter,
+    }
+
+    fn trace(exemplar: Option<Exemplar>) -> Option<String> {
+        exemplar.map(|exemplar| exemplar.labels[0].1.clone())
+    }
+
+    #[test]
+    fn exemplar_counter_test() {
+        let counter = Arc::new(ExemplarCounter::new());
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&counter, |counter, reg| {
+            reg.exemplar_count("requests_total", counter);
+        });
+
+        let at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        counter.inc_with_exemplar_at(&[("trace_id", "a")], at);
+        counter.inc_with_exemplar_at(&[("trace_id", "b")], at + Duration::from_secs(1));
+        counter.inc();
+
+        assert_eq!(counter.load(), 3);
+        let exemplar = counter.exemplar().unwrap();
+        assert_eq!(trace(Some(exemplar.clone())), Some("b".into()));
+        assert_eq!(exemplar.value, 1);
+        assert_eq!(exemplar.timestamp, at + Duration::from_secs(1));
+        assert!(reg.to_string().contains("requests_total 3\n"));
+    }
+
+    #[test]
+    fn policy_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&met, |m, reg| {
+            reg.exemplar_count("always_total", &m.always);
+            reg.exemplar_count("every_total", &m.every)
+                .exemplars(ExemplarPolicy::EveryN(3));
+            reg.exemplar_count("interval_total", &m.interval)
+                .exemplars(ExemplarPolicy::PerInterval(Duration::from_secs(10)));
+            reg.exemplar_count("never_total", &m.never)
+                .exemplars(ExemplarPolicy::Never);
+        });
+
+        /* the timestamps passed in are the clock */
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        for i in 0..5u64 {
+            let id = i.to_string();
+            let at = start + Duration::from_secs(4 * i);
+            for counter in [&met.always, &met.every, &met.interval, &met.never] {
+                counter.inc_with_exemplar_at(&[("trace_id", &id)], at);
+            }
+        }
+
+        assert_eq!(met.always.load(), 5);
+        assert_eq!(met.never.load(), 5);
+        assert_eq!(trace(met.always.exemplar()), Some("4".into()));
+        /* offers 0 and 3 are kept */
+        assert_eq!(trace(met.every.exemplar()), Some("3".into()));
+        /* at 0s and 12s, not 4s, 8s or 16s */
+        assert_eq!(trace(met.interval.exemplar()), Some("3".into()));
+        assert_eq!(met.never.exemplar(), None);
+
+        let exemplar = met.always.exemplar().unwrap();
+        assert_eq!(exemplar.value, 1);
+        assert_eq!(exemplar.timestamp, start + Duration::from_secs(16));
+    }
+
+    #[test]
+    fn never_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&met, |m, reg| {
+            reg.exemplar_count("never_total", &m.never)
+                .exemplars(ExemplarPolicy::Never);
+        });
+
+        /* with the exemplar lock held elsewhere, Never increments never wait on it */
+        let held = met.never.slot.exemplar.lock().unwrap();
+        let (done, finished) = mpsc::channel();
+        let counter = met.clone();
+        std::thread::spawn(move || {
+            for _ in 0..1000 {
+                counter.never.inc_with_exemplar(&[("trace_id", "abc")]);
+            }
+            done.send(()).unwrap();
+        });
+        finished.recv_timeout(Duration::from_secs(10)).unwrap();
+        drop(held);
+
+        assert_eq!(met.never.load(), 1000);
+        assert_eq!(met.never.exemplar(), None);
+    }
+
+    #[test]
+    fn label_limit_test() {
+        let counter = ExemplarCounter::new();
+        let dropped = lost::dropped(lost::DropReason::Truncation);
+
+        let long = "x".repeat(super::MAX_LABEL_CHARS);
+        counter.inc_with_exemplar(&[("trace_id", &long)]);
+        assert_eq!(counter.load(), 1);
+        assert_eq!(counter.exemplar(), None);
+        assert!(dropped < lost::dropped(lost::DropReason::Truncation));
+
+        counter.inc_with_exemplar(&[("trace_id", "abc")]);
+        assert_eq!(trace(counter.exemplar()), Some("abc".into()));
+    }
+}
@@ -0,0 +1,254 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    config::{self, ConfigError},
+    PromMetricRegistry, Reading,
+};
+
+/*
+ * when periodic exporters push: never more often than min_interval, early once at least
+ * push_on_change_threshold series changed since the last export, otherwise after
+ * max_interval even when nothing changed
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportPolicy {
+    pub min_interval: Duration,
+    pub max_interval: Duration,
+    pub push_on_change_threshold: usize,
+}
+
+impl ExportPolicy {
+    /* the plain fixed period behavior */
+    pub fn every(period: Duration) -> Self {
+        ExportPolicy {
+            min_interval: period,
+            max_interval: period,
+            push_on_change_threshold: 0,
+        }
+    }
+}
+
+impl ExportPolicy {
+    /*
+     * {prefix}_MIN_INTERVAL, {prefix}_MAX_INTERVAL (see config::parse_duration) and
+     * {prefix}_PUSH_ON_CHANGE override default, ex. prefix METRICS_STATSD
+     */
+    pub fn from_env(prefix: &str, default: ExportPolicy) -> Result<Self, ConfigError> {
+        fn check<T>(
+            errors: &mut Vec<ConfigError>,
+            result: Result<Option<T>, ConfigError>,
+        ) -> Option<T> {
+            result.map_err(|error| errors.push(error)).ok().flatten()
+        }
+
+        let var = |suffix| format!("{}_{}", prefix, suffix);
+        let mut errors = Vec::new();
+        let min_interval = check(
+            &mut errors,
+            config::optional_env(&var("MIN_INTERVAL"), config::parse_duration),
+        );
+        let max_interval = check(
+            &mut errors,
+            config::optional_env(&var("MAX_INTERVAL"), config::parse_duration),
+        );
+        let threshold = check(
+            &mut errors,
+            config::optional_env(&var("PUSH_ON_CHANGE"), config::parse_usize),
+        );
+
+        match errors.len() {
+            0 => {}
+            1 => return Err(errors.remove(0)),
+            _ => return Err(ConfigError::Multiple(errors)),
+        }
+
+        Ok(ExportPolicy {
+            min_interval: min_interval.unwrap_or(default.min_interval),
+            max_interval: max_interval.unwrap_or(default.max_interval),
+            push_on_change_threshold: threshold.unwrap_or(default.push_on_change_threshold),
+        })
+    }
+}
+
+impl Default for ExportPolicy {
+    fn default() -> Self {
+        ExportPolicy {
+            min_interval: Duration::from_secs(10),
+            max_interval: Duration::from_secs(60),
+            push_on_change_threshold: 1,
+        }
+    }
+}
+
+/* policy state, time is passed in so the decisions can be driven by a manual clock */
+pub struct ExportSchedule {
+    policy: ExportPolicy,
+    last_export: Option<Instant>,
+    exported: Vec<Reading>,
+    current: Vec<Reading>,
+}
+
+impl ExportSchedule {
+    pub fn new(policy: ExportPolicy) -> Self {
+        ExportSchedule {
+            policy,
+            last_export: None,
+            exported: Vec::new(),
+            current: Vec::new(),
+        }
+    }
+
+    pub fn policy(&self) -> &ExportPolicy {
+        &self.policy
+    }
+
+    /* the first call always exports */
+    pub fn should_export(&self, now: Instant, changed_series: usize) -> bool {
+        let Some(last) = self.last_export else {
+            return true;
+        };
+
+        let elapsed = now.saturating_duration_since(last);
+        if elapsed < self.policy.min_interval {
+            return false;
+        }
+
+        let threshold = self.policy.push_on_change_threshold.max(1);
+        threshold <= changed_series || self.policy.max_interval <= elapsed
+    }
+
+    pub fn exported(&mut self, now: Instant) {
+        self.last_export = Some(now);
+        std::mem::swap(&mut self.exported, &mut self.current);
+    }
+
+    /* series whose value differs from the last export, any change to the series list counts all */
+    pub fn changed_series(&mut self, registry: &PromMetricRegistry) -> usize {
+        self.current.clear();
+        for metric in &registry.metrics {
+            self.current.push(match registry.hold(metric) {
+                Some(_holder) => metric.read(false),
+                None => Reading::Skipped,
+            });
+        }
+
+        if self.current.len() != self.exported.len() {
+            return self.current.len();
+        }
+
+        self.current
+            .iter()
+            .zip(&self.exported)
+            .filter(|(current, exported)| current != exported)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use crate::{IntGauge, PromMetricRegistry};
+
+    use super::{ExportPolicy, ExportSchedule};
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn quiet_and_storm_test() {
+        let mut schedule = ExportSchedule::new(ExportPolicy {
+            min_interval: secs(10),
+            max_interval: secs(60),
+            push_on_change_threshold: 5,
+        });
+
+        /* (seconds since start, changed series) ticking every 5s */
+        let start = Instant::now();
+        let mut exports = Vec::new();
+        for tick in 0..=40u64 {
+            let changed = match tick {
+                /* storm between 100s and 140s */
+                20..=28 => 50,
+                /* trickle of changes below the threshold */
+                30..=40 => 2,
+                _ => 0,
+            };
+
+            let now = start + secs(tick * 5);
+            if schedule.should_export(now, changed) {
+                schedule.exported(now);
+                exports.push(tick * 5);
+            }
+        }
+
+        assert_eq!(exports, [0, 60, 100, 110, 120, 130, 140, 200]);
+    }
+
+    #[test]
+    fn every_test() {
+        let mut schedule = ExportSchedule::new(ExportPolicy::every(secs(10)));
+        let start = Instant::now();
+
+        schedule.exported(start);
+        assert!(!schedule.should_export(start + secs(9), 100));
+        assert!(schedule.should_export(start + secs(10), 0));
+    }
+
+    #[test]
+    fn from_env_test() {
+        std::env::set_var("ARC_METRICS_TEST_EXPORT_MIN_INTERVAL", "250ms");
+        std::env::set_var("ARC_METRICS_TEST_EXPORT_PUSH_ON_CHANGE", "3");
+        assert_eq!(
+            ExportPolicy::from_env("ARC_METRICS_TEST_EXPORT", ExportPolicy::default()),
+            Ok(ExportPolicy {
+                min_interval: Duration::from_millis(250),
+                max_interval: secs(60),
+                push_on_change_threshold: 3,
+            })
+        );
+
+        std::env::set_var("ARC_METRICS_TEST_BAD_EXPORT_MIN_INTERVAL", "5x");
+        std::env::set_var("ARC_METRICS_TEST_BAD_EXPORT_MAX_INTERVAL", "1");
+        assert_eq!(
+            ExportPolicy::from_env("ARC_METRICS_TEST_BAD_EXPORT", ExportPolicy::default())
+                .unwrap_err()
+                .to_string(),
+            "invalid env ARC_METRICS_TEST_BAD_EXPORT_MIN_INTERVAL: invalid duration \"5x\": \
+             unknown unit (expected ns, us, ms, s, m or h); \
+             invalid env ARC_METRICS_TEST_BAD_EXPORT_MAX_INTERVAL: invalid duration \"1\": \
+             missing unit (expected ns, us, ms, s, m or h)"
+        );
+    }
+
+    #[test]
+    fn changed_series_test() {
+        let gauges = Arc::new([IntGauge::new(), IntGauge::new(), IntGauge::new()]);
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&gauges, |gauges, reg| {
+            for (i, gauge) in gauges.iter().enumerate() {
+                reg.gauge("queue", gauge).attr("shard", i.to_string());
+            }
+        });
+
+        let mut schedule = ExportSchedule::new(ExportPolicy::default());
+        let now = Instant::now();
+        assert_eq!(schedule.changed_series(&reg), 3);
+        schedule.exported(now);
+        assert_eq!(schedule.changed_series(&reg), 0);
+
+        gauges[0].set(4);
+        gauges[2].set(1);
+        assert_eq!(schedule.changed_series(&reg), 2);
+
+        /* not exported, still compared against the last export */
+        gauges[2].set(0);
+        assert_eq!(schedule.changed_series(&reg), 1);
+        schedule.exported(now);
+        assert_eq!(schedule.changed_series(&reg), 0);
+    }
+}
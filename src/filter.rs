@@ -0,0 +1,186 @@
+/*
+ * per series filtering at render time, ex. a debug endpoint showing only application
+ * metrics from the registry Prometheus scrapes in full:
+ *
+ * write!(body, "{}", registry.filtered(FilterByPrefix("http_")))
+ *
+ * Families without a matching series are left out entirely, HELP / TYPE come from the
+ * first series that passes. Only registered series are written, text sources and the
+ * registry's own families (deprecations, scrape clients, dropped series) are left out.
+ */
+use std::{borrow::Cow, fmt::Display};
+
+use crate::{write_family, FamilyView, MetricType, PromMetricRegistry, Reading};
+
+/* what a filter sees of a series, attributes include the registry's base attributes */
+#[derive(Debug, Clone, Copy)]
+pub struct SampleMeta<'a> {
+    pub name: &'a str,
+    pub metric_type: MetricType,
+    pub attributes: &'a [[Cow<'static, str>; 2]],
+}
+
+impl SampleMeta<'_> {
+    pub fn attr(&self, key: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|[k, _]| k == key)
+            .map(|[_, value]| value.as_ref())
+    }
+}
+
+/* true keeps the series. Closures need their argument typed, |meta: &SampleMeta| .. */
+pub trait SeriesFilter {
+    fn keep(&self, meta: &SampleMeta) -> bool;
+}
+
+impl<F: Fn(&SampleMeta) -> bool> SeriesFilter for F {
+    fn keep(&self, meta: &SampleMeta) -> bool {
+        self(meta)
+    }
+}
+
+/* series whose name starts with the prefix */
+#[derive(Debug, Clone, Copy)]
+pub struct FilterByPrefix<'a>(pub &'a str);
+
+impl SeriesFilter for FilterByPrefix<'_> {
+    fn keep(&self, meta: &SampleMeta) -> bool {
+        meta.name.starts_with(self.0)
+    }
+}
+
+/* series without the label, ex. leaving out per tenant series on a lightweight target */
+#[derive(Debug, Clone, Copy)]
+pub struct ExcludeLabel<'a>(pub &'a str);
+
+impl SeriesFilter for ExcludeLabel<'_> {
+    fn keep(&self, meta: &SampleMeta) -> bool {
+        meta.attr(self.0).is_none()
+    }
+}
+
+pub struct Filtered<'r, F> {
+    registry: &'r PromMetricRegistry,
+    filter: F,
+}
+
+impl PromMetricRegistry {
+    /* text exposition of the series passing filter, bypasses the render cache */
+    pub fn filtered<F: SeriesFilter>(&self, filter: F) -> Filtered<'_, F> {
+        Filtered {
+            registry: self,
+            filter,
+        }
+    }
+}
+
+impl<F: SeriesFilter> Display for Filtered<'_, F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut readings = Vec::new();
+        self.registry.for_each_family(&|_| true, false, |family| {
+            readings.clear();
+            readings.extend(
+                family
+                    .metrics
+                    .iter()
+                    .zip(family.readings)
+                    .map(|(metric, reading)| {
+                        let meta = SampleMeta {
+                            name: &metric.name,
+                            metric_type: metric.metric_type,
+                            attributes: &metric.attributes,
+                        };
+                        match self.filter.keep(&meta) {
+                            true => reading.clone(),
+                            false => Reading::Skipped,
+                        }
+                    }),
+            );
+
+            let Some(visible) = readings.iter().position(|r| *r != Reading::Skipped) else {
+                return Ok(());
+            };
+
+            let family = FamilyView {
+                readings: &readings,
+                visible,
+                ..family
+            };
+            write_family(f, &family, self.registry.render_created)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{IntCounter, IntGauge, MetricType, PromMetricRegistry};
+
+    use super::{ExcludeLabel, FilterByPrefix, SampleMeta};
+
+    #[derive(Default)]
+    struct Met {
+        http_requests: IntCounter,
+        tenant_a: IntCounter,
+        tenant_b: IntCounter,
+        queue_depth: IntGauge,
+    }
+
+    #[test]
+    fn filtered_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&met, |met, reg| {
+            reg.count("http_requests", &met.http_requests)
+                .attr("method", "get");
+            reg.count("http_tenant_requests", &met.tenant_a)
+                .attr("tenant", "a");
+            reg.count("http_tenant_requests", &met.tenant_b)
+                .attr("tenant", "b");
+            reg.gauge("queue_depth", &met.queue_depth);
+        });
+        met.http_requests.inc_by(3);
+        met.tenant_a.inc();
+        met.tenant_b.inc_by(2);
+        met.queue_depth.set(4);
+
+        assert_eq!(
+            reg.filtered(FilterByPrefix("http_")).to_string(),
+            "# HELP http_requests\n\
+             # TYPE http_requests counter\n\
+             http_requests{method=\"get\"} 3\n\
+             # HELP http_tenant_requests\n\
+             # TYPE http_tenant_requests counter\n\
+             http_tenant_requests{tenant=\"a\"} 1\n\
+             http_tenant_requests{tenant=\"b\"} 2\n"
+        );
+
+        /* the fully filtered tenant family leaves no HELP / TYPE behind */
+        assert_eq!(
+            reg.filtered(ExcludeLabel("tenant")).to_string(),
+            "# HELP http_requests\n\
+             # TYPE http_requests counter\n\
+             http_requests{method=\"get\"} 3\n\
+             # HELP queue_depth\n\
+             # TYPE queue_depth gauge\n\
+             queue_depth 4\n"
+        );
+
+        let only_b = |meta: &SampleMeta| {
+            meta.metric_type == MetricType::IntCounter && meta.attr("tenant") == Some("b")
+        };
+        assert_eq!(
+            reg.filtered(only_b).to_string(),
+            "# HELP http_tenant_requests\n\
+             # TYPE http_tenant_requests counter\n\
+             http_tenant_requests{tenant=\"b\"} 2\n"
+        );
+
+        assert_eq!(
+            reg.filtered(|_: &SampleMeta| true).to_string(),
+            reg.to_string()
+        );
+    }
+}
@@ -0,0 +1,310 @@
+use std::fmt::Write as _;
+
+use crate::{escape, PromMetricRegistry, Sample, SampleValue};
+
+/* {:#} on the registry, {:+#} also groups digits */
+pub(crate) fn fmt_table(
+    registry: &PromMetricRegistry,
+    f: &mut std::fmt::Formatter<'_>,
+    group: bool,
+) -> std::fmt::Result {
+    let value = |value: u64| match group {
+        true => group_digits(value),
+        false => value.to_string(),
+    };
+
+    /* rows per family, histograms are shown as their _sum and _count */
+    let mut families = Vec::new();
+    for family in registry.gather() {
+        let mut rows = Vec::new();
+        for sample in &family.samples {
+            let labels = sample
+                .attributes
+                .iter()
+                .map(|[key, value]| format!("{}={}", key, value))
+                .collect::<Vec<_>>()
+                .join(",");
+
+            match sample.value {
+                SampleValue::Value(v) => rows.push([
+                    family.name.to_string(),
+                    family.metric_type.to_string(),
+                    labels,
+                    value(v),
+                ]),
+                SampleValue::Float(v) => rows.push([
+                    family.name.to_string(),
+                    family.metric_type.to_string(),
+                    labels,
+                    v.to_string(),
+                ]),
+                SampleValue::Histogram { sum, count, .. }
+                | SampleValue::Summary { sum, count, .. } => {
+                    for (suffix, v) in [("_sum", sum), ("_count", count)] {
+                        rows.push([
+                            format!("{}{}", family.name, suffix),
+                            family.metric_type.to_string(),
+                            labels.clone(),
+                            value(v),
+                        ]);
+                    }
+                }
+            }
+        }
+        families.push(rows);
+    }
+
+    let header = ["NAME", "TYPE", "LABELS", "VALUE"].map(String::from);
+    let mut widths = header.clone().map(|column| column.len());
+    for row in families.iter().flatten() {
+        for (width, column) in widths.iter_mut().zip(row) {
+            *width = (*width).max(column.chars().count());
+        }
+    }
+
+    let write_row = |f: &mut std::fmt::Formatter<'_>, row: &[String; 4]| {
+        writeln!(
+            f,
+            "{:<w0$}  {:<w1$}  {:<w2$}  {:>w3$}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+        )
+    };
+
+    write_row(f, &header)?;
+    for rows in &families {
+        writeln!(f)?;
+        for row in rows {
+            write_row(f, row)?;
+        }
+    }
+
+    Ok(())
+}
+
+impl PromMetricRegistry {
+    /* one aligned `name{labels} = value` line per series for humans and grep, sorted by series */
+    pub fn render_flat(&self) -> String {
+        self.render_flat_filtered("")
+    }
+
+    /* only series whose name contains filter */
+    pub fn render_flat_filtered(&self, filter: &str) -> String {
+        let mut lines = Vec::new();
+        for family in self.gather() {
+            for sample in &family.samples {
+                /* histograms and summaries are shown as their _sum and _count */
+                let values = match sample.value {
+                    SampleValue::Value(value) => vec![(family.name.to_string(), value)],
+                    /* floats are shown as rendered, without grouping or units */
+                    SampleValue::Float(value) => {
+                        if family.name.contains(filter) {
+                            lines.push((series(&family.name, sample), value.to_string()));
+                        }
+                        continue;
+                    }
+                    SampleValue::Histogram { sum, count, .. }
+                    | SampleValue::Summary { sum, count, .. } => vec![
+                        (format!("{}_sum", family.name), sum),
+                        (format!("{}_count", family.name), count),
+                    ],
+                };
+
+                for (name, value) in values {
+                    if name.contains(filter) {
+                        lines.push((series(&name, sample), humanize(&name, value)));
+                    }
+                }
+            }
+        }
+        lines.sort();
+
+        let width = lines
+            .iter()
+            .map(|(series, _)| series.chars().count())
+            .max()
+            .unwrap_or(0);
+
+        let mut out = String::new();
+        for (series, value) in lines {
+            let _ = writeln!(out, "{:<width$} = {}", series, value, width = width);
+        }
+        out
+    }
+}
+
+fn series(name: &str, sample: &Sample) -> String {
+    let mut out = name.to_string();
+
+    let mut sep = '{';
+    for [key, value] in &sample.attributes {
+        let _ = write!(out, "{}{}=\"{}\"", sep, key, escape::label_value(value));
+        sep = ',';
+    }
+    if sep == ',' {
+        out.push('}');
+    }
+
+    out
+}
+
+/* byte sized by the _bytes naming convention, everything else gets digit grouping */
+fn humanize(name: &str, value: u64) -> String {
+    let base = name
+        .strip_suffix("_total")
+        .or_else(|| name.strip_suffix("_sum"))
+        .unwrap_or(name);
+
+    if base.ends_with("_bytes") {
+        bytes(value)
+    } else {
+        group_digits(value)
+    }
+}
+
+fn group_digits(value: u64) -> String {
+    let digits = value.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+
+    for (i, digit) in digits.chars().enumerate() {
+        if i != 0 && (digits.len() - i) % 3 == 0 {
+            out.push('_');
+        }
+        out.push(digit);
+    }
+
+    out
+}
+
+fn bytes(value: u64) -> String {
+    const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+    if value < 1024 {
+        return format!("{} B", value);
+    }
+
+    let mut scaled = value as f64 / 1024.0;
+    let mut unit = 0;
+    while 1024.0 <= scaled && unit + 1 < UNITS.len() {
+        scaled /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", scaled, UNITS[unit])
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{IntCounter, IntGauge, IntHistogram, PromMetricRegistry};
+
+    use super::{bytes, group_digits};
+
+    #[derive(Default)]
+    struct Met {
+        requests: IntCounter,
+        errors: IntCounter,
+        heap: IntGauge,
+        sent: IntCounter,
+        size: IntHistogram,
+    }
+
+    #[test]
+    fn render_flat_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+
+        reg.register_fn(&met, |m, reg| {
+            reg.count("http_requests_total", &m.requests)
+                .attr("method", "get");
+            reg.count("http_errors_total", &m.errors);
+            reg.gauge("heap_bytes", &m.heap);
+            reg.count("net_sent_bytes_total", &m.sent);
+            reg.histogram("body_bytes", &m.size);
+        });
+
+        met.requests.inc_by(1_234_567);
+        met.heap.set(1_288_490_189);
+        met.sent.inc_by(512);
+        met.size.observe(2048);
+
+        assert_eq!(
+            reg.render_flat(),
+            "body_bytes_count                  = 1\n\
+             body_bytes_sum                    = 2.0 KiB\n\
+             heap_bytes                        = 1.2 GiB\n\
+             http_errors_total                 = 0\n\
+             http_requests_total{method=\"get\"} = 1_234_567\n\
+             net_sent_bytes_total              = 512 B\n"
+        );
+
+        assert_eq!(
+            reg.render_flat_filtered("http"),
+            "http_errors_total                 = 0\n\
+             http_requests_total{method=\"get\"} = 1_234_567\n"
+        );
+        assert_eq!(reg.render_flat_filtered("missing"), "");
+    }
+
+    #[test]
+    fn table_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+
+        reg.register_fn(&met, |m, reg| {
+            reg.count("http_requests_total", &m.requests)
+                .attr("method", "get")
+                .attr("code", "200");
+            reg.count("http_requests_total", &m.errors)
+                .attr("method", "get")
+                .attr("code", "500");
+            reg.gauge("heap_bytes", &m.heap);
+            reg.histogram("body_bytes", &m.size);
+        });
+
+        met.requests.inc_by(1_234_567);
+        met.errors.inc_by(12);
+        met.heap.set(1_288_490_189);
+        met.size.observe(2048);
+
+        /* {} stays the exposition format */
+        assert_eq!(format!("{}", reg), reg.to_string());
+        assert!(format!("{}", reg).starts_with("# HELP body_bytes\n"));
+
+        assert_eq!(
+            format!("{:#}", reg),
+            "NAME                 TYPE       LABELS                    VALUE\n\
+             \n\
+             body_bytes_sum       histogram                             2048\n\
+             body_bytes_count     histogram                                1\n\
+             \n\
+             heap_bytes           gauge                           1288490189\n\
+             \n\
+             http_requests_total  counter    method=get,code=200     1234567\n\
+             http_requests_total  counter    method=get,code=500          12\n"
+        );
+
+        assert!(format!("{:+#}", reg)
+            .contains("\nhttp_requests_total  counter    method=get,code=200      1_234_567\n"));
+    }
+
+    #[test]
+    fn humanize_test() {
+        assert_eq!(group_digits(0), "0");
+        assert_eq!(group_digits(999), "999");
+        assert_eq!(group_digits(1000), "1_000");
+        assert_eq!(group_digits(u64::MAX), "18_446_744_073_709_551_615");
+
+        assert_eq!(bytes(1023), "1023 B");
+        assert_eq!(bytes(1024), "1.0 KiB");
+        assert_eq!(bytes(1536 * 1024), "1.5 MiB");
+        assert_eq!(bytes(u64::MAX), "16.0 EiB");
+    }
+}
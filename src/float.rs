@@ -0,0 +1,286 @@
+/*
+ * f64 metrics stored as their bits in an AtomicU64. Setting is a single store, adding is a
+ * CAS loop as there is no atomic float add, so prefer IntCounter on contended hot paths.
+ */
+use core::{
+    fmt::Display,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+#[derive(Debug, Default)]
+pub struct FloatCounter(pub(crate) AtomicU64);
+
+#[derive(Debug, Default)]
+pub struct FloatGauge(pub(crate) AtomicU64);
+
+/*
+ * a sample value compared by its bits, so samples holding one stay Eq. Rendered the way
+ * Prometheus parses it: NaN, +Inf, -Inf, integral values without a fraction.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FloatValue(u64);
+
+impl FloatValue {
+    #[inline]
+    pub fn new(value: f64) -> Self {
+        FloatValue(value.to_bits())
+    }
+
+    #[cfg(feature = "std")]
+    #[inline]
+    pub(crate) fn from_bits(bits: u64) -> Self {
+        FloatValue(bits)
+    }
+
+    #[inline]
+    pub fn get(self) -> f64 {
+        f64::from_bits(self.0)
+    }
+}
+
+impl From<f64> for FloatValue {
+    fn from(value: f64) -> Self {
+        Self::new(value)
+    }
+}
+
+impl Display for FloatValue {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let value = self.get();
+        if value.is_nan() {
+            f.write_str("NaN")
+        } else if value.is_infinite() {
+            f.write_str(if 0.0 < value { "+Inf" } else { "-Inf" })
+        } else if -1e15 < value && value < 1e15 && value == value as i64 as f64 {
+            write!(f, "{}", value as i64)
+        } else {
+            /* shortest round trip, with an exponent for very large or small values */
+            write!(f, "{:?}", value)
+        }
+    }
+}
+
+/* a plain number, NaN and the infinities serialize however the format writes them */
+#[cfg(feature = "serde")]
+impl serde::Serialize for FloatValue {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.get())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for FloatValue {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        f64::deserialize(deserializer).map(FloatValue::new)
+    }
+}
+
+/* atomic f64 add, returns the previous value */
+#[inline]
+fn fetch_add(bits: &AtomicU64, amount: f64) -> f64 {
+    let previous = bits
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+            Some((f64::from_bits(current) + amount).to_bits())
+        })
+        .unwrap_or_else(|current| current);
+    f64::from_bits(previous)
+}
+
+impl FloatCounter {
+    #[inline]
+    pub const fn new() -> Self {
+        FloatCounter(AtomicU64::new(0))
+    }
+
+    pub fn with_value(value: f64) -> Self {
+        FloatCounter(AtomicU64::new(value.to_bits()))
+    }
+
+    #[inline]
+    pub fn inc(&self) {
+        self.inc_by(1.0);
+    }
+
+    /* counters only go up, panics on negative or NaN amounts in debug builds or with strict */
+    #[inline]
+    #[track_caller]
+    pub fn inc_by(&self, amount: f64) {
+        if cfg!(any(debug_assertions, feature = "strict")) && (amount < 0.0 || amount.is_nan()) {
+            invalid_increment(amount);
+        }
+        fetch_add(&self.0, amount);
+    }
+
+    #[inline]
+    pub fn load(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Acquire))
+    }
+
+    /* zeroes the counter returning the previous value */
+    #[inline]
+    pub fn take(&self) -> f64 {
+        f64::from_bits(self.0.swap(0, Ordering::AcqRel))
+    }
+
+    #[inline]
+    pub fn reset(&self) -> f64 {
+        self.take()
+    }
+}
+
+#[cold]
+#[inline(never)]
+#[track_caller]
+fn invalid_increment(amount: f64) -> ! {
+    panic!("counter incremented by {}", amount);
+}
+
+impl FloatGauge {
+    #[inline]
+    pub const fn new() -> Self {
+        FloatGauge(AtomicU64::new(0))
+    }
+
+    pub fn with_value(value: f64) -> Self {
+        FloatGauge(AtomicU64::new(value.to_bits()))
+    }
+
+    #[inline]
+    pub fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Release);
+    }
+
+    #[inline]
+    pub fn inc(&self) {
+        self.add(1.0);
+    }
+
+    #[inline]
+    pub fn dec(&self) {
+        self.add(-1.0);
+    }
+
+    /* negative deltas decrement, float gauges may go below zero */
+    #[inline]
+    pub fn add(&self, delta: f64) {
+        fetch_add(&self.0, delta);
+    }
+
+    #[inline]
+    pub fn sub(&self, delta: f64) {
+        fetch_add(&self.0, -delta);
+    }
+
+    #[inline]
+    pub fn load(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Acquire))
+    }
+
+    #[inline]
+    pub fn swap(&self, value: f64) -> f64 {
+        f64::from_bits(self.0.swap(value.to_bits(), Ordering::AcqRel))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{PromMetricRegistry, SampleValue};
+
+    use super::{FloatCounter, FloatGauge, FloatValue};
+
+    #[test]
+    fn float_value_test() {
+        let rendered = |value: f64| FloatValue::new(value).to_string();
+        assert_eq!(rendered(0.0), "0");
+        assert_eq!(rendered(3.0), "3");
+        assert_eq!(rendered(-2.0), "-2");
+        assert_eq!(rendered(0.25), "0.25");
+        assert_eq!(rendered(1e21), "1e21");
+        assert_eq!(rendered(1.5e-9), "1.5e-9");
+        assert_eq!(rendered(f64::NAN), "NaN");
+        assert_eq!(rendered(f64::INFINITY), "+Inf");
+        assert_eq!(rendered(f64::NEG_INFINITY), "-Inf");
+    }
+
+    #[test]
+    fn float_metrics_test() {
+        let counter = FloatCounter::new();
+        counter.inc();
+        counter.inc_by(0.5);
+        assert_eq!(counter.load(), 1.5);
+        assert_eq!(counter.take(), 1.5);
+        assert_eq!(counter.load(), 0.0);
+
+        let gauge = FloatGauge::with_value(2.0);
+        gauge.sub(2.5);
+        assert_eq!(gauge.load(), -0.5);
+        gauge.set(0.75);
+        assert_eq!(gauge.swap(1.0), 0.75);
+
+        let counter = Arc::new(FloatCounter::new());
+        let threads = (0..4)
+            .map(|_| {
+                let counter = counter.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        counter.inc_by(0.5);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(counter.load(), 2000.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "counter incremented by -1")]
+    fn negative_increment_test() {
+        FloatCounter::new().inc_by(-1.0);
+    }
+
+    #[derive(Default)]
+    struct Met {
+        cpu_seconds: FloatCounter,
+        load: FloatGauge,
+    }
+
+    #[test]
+    fn register_float_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&met, |m, reg| {
+            reg.float_count("cpu_seconds_total", &m.cpu_seconds);
+            reg.float_gauge("load", &m.load).attr("cpu", "0");
+        });
+        met.cpu_seconds.inc_by(1.25);
+        met.load.set(f64::NAN);
+
+        assert_eq!(
+            reg.to_string(),
+            "# HELP cpu_seconds_total\n\
+             # TYPE cpu_seconds_total counter\n\
+             cpu_seconds_total 1.25\n\
+             # HELP load\n\
+             # TYPE load gauge\n\
+             load{cpu=\"0\"} NaN\n"
+        );
+        assert_eq!(
+            reg.find("cpu_seconds_total")[0].value,
+            SampleValue::Float(FloatValue::new(1.25))
+        );
+
+        let mut open = String::new();
+        reg.encode_openmetrics(&mut open).unwrap();
+        assert!(open.contains("cpu_seconds_total 1.25\n"));
+
+        /* counters reset as they are read, gauges keep their value */
+        let snapshot = reg.snapshot_and_reset();
+        assert_eq!(met.cpu_seconds.load(), 0.0);
+        assert!(met.load.load().is_nan());
+        assert_eq!(snapshot.len(), 2);
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::{Arc, OnceLock, RwLock};
+
+use crate::{helpers::RegisterableMetric, PromMetricRegistry};
+
+/* opt-in process wide registry, created on first use */
+pub fn default_registry() -> &'static RwLock<PromMetricRegistry> {
+    static REGISTRY: OnceLock<RwLock<PromMetricRegistry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(PromMetricRegistry::new()))
+}
+
+pub fn register_default<M: RegisterableMetric + 'static>(metrics: &Arc<M>) {
+    default_registry().write().unwrap().register(metrics);
+}
+
+pub fn render_default() -> String {
+    default_registry().read().unwrap().to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{helpers::RegisterableMetric, IntCounter, RegisterAction};
+
+    use super::{register_default, render_default};
+
+    struct Numbered {
+        id: usize,
+        count: IntCounter,
+    }
+
+    impl RegisterableMetric for Numbered {
+        fn register(&'static self, register: &mut RegisterAction) {
+            register.count(format!("global_test_{}", self.id), &self.count);
+        }
+    }
+
+    #[test]
+    fn concurrent_register_test() {
+        let threads = (0..8)
+            .map(|id| {
+                std::thread::spawn(move || {
+                    let metrics = Arc::new(Numbered {
+                        id,
+                        count: IntCounter::default(),
+                    });
+                    register_default(&metrics);
+                    metrics.count.inc_by(id as u64);
+                    render_default()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let output = render_default();
+        for id in 0..8 {
+            let name = format!("global_test_{}", id);
+            let samples = output
+                .lines()
+                .filter(|line| {
+                    line.strip_prefix(name.as_str())
+                        .is_some_and(|rest| rest.starts_with([' ', '{']))
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(samples.len(), 1);
+            assert!(samples[0].ends_with(&format!(" {}", id)));
+        }
+    }
+}
@@ -0,0 +1,362 @@
+/*
+ * Graphite plaintext protocol, `path value timestamp` per line. The path comes from a
+ * template of dot separated segments where {prefix}, {name} and {<attribute key>} are
+ * substituted, attributes the template doesn't use are appended as values sorted by key.
+ * Dots and whitespace in attribute values become underscores, empty segments are dropped.
+ */
+use std::{
+    borrow::Cow,
+    io::Write as _,
+    net::TcpStream,
+    sync::{mpsc, Arc, RwLock},
+    thread::JoinHandle,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{PromMetricRegistry, SampleValue};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphiteFormat {
+    template: Cow<'static, str>,
+    prefix: Cow<'static, str>,
+}
+
+impl Default for GraphiteFormat {
+    fn default() -> Self {
+        Self::new("{prefix}.{name}")
+    }
+}
+
+impl GraphiteFormat {
+    /* ex. "{prefix}.{program}.{name}" */
+    pub fn new<T: Into<Cow<'static, str>>>(template: T) -> Self {
+        GraphiteFormat {
+            template: template.into(),
+            prefix: Cow::Borrowed(""),
+        }
+    }
+
+    pub fn prefix<P: Into<Cow<'static, str>>>(mut self, prefix: P) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /* now is seconds since the unix epoch */
+    pub fn encode(
+        &self,
+        registry: &PromMetricRegistry,
+        f: &mut dyn std::fmt::Write,
+        now: u64,
+    ) -> std::fmt::Result {
+        for family in registry.gather() {
+            for sample in &family.samples {
+                let attributes = sample
+                    .attributes
+                    .iter()
+                    .map(|[key, value]| (key.as_ref(), value.as_ref()))
+                    .collect::<Vec<_>>();
+
+                match &sample.value {
+                    SampleValue::Value(value) => {
+                        let path = self.path(&family.name, &attributes);
+                        writeln!(f, "{} {} {}", path, value, now)?;
+                    }
+                    SampleValue::Float(value) => {
+                        let path = self.path(&family.name, &attributes);
+                        writeln!(f, "{} {} {}", path, value, now)?;
+                    }
+                    SampleValue::Histogram {
+                        buckets,
+                        sum,
+                        count,
+                    } => {
+                        let bounds = buckets
+                            .iter()
+                            .map(|(le, count)| (le.to_string(), *count))
+                            .chain(std::iter::once(("+Inf".to_string(), *count)));
+                        let name = format!("{}_bucket", family.name);
+                        for (le, count) in bounds {
+                            let mut attributes = attributes.clone();
+                            attributes.push(("le", &le));
+                            writeln!(f, "{} {} {}", self.path(&name, &attributes), count, now)?;
+                        }
+
+                        let name = format!("{}_sum", family.name);
+                        writeln!(f, "{} {} {}", self.path(&name, &attributes), sum, now)?;
+                        let name = format!("{}_count", family.name);
+                        writeln!(f, "{} {} {}", self.path(&name, &attributes), count, now)?;
+                    }
+                    SampleValue::Summary {
+                        quantiles,
+                        sum,
+                        count,
+                    } => {
+                        for (quantile, value) in quantiles {
+                            let quantile = quantile.to_string();
+                            let mut attributes = attributes.clone();
+                            attributes.push(("quantile", &quantile));
+                            let path = self.path(&family.name, &attributes);
+                            writeln!(f, "{} {} {}", path, value, now)?;
+                        }
+
+                        let name = format!("{}_sum", family.name);
+                        writeln!(f, "{} {} {}", self.path(&name, &attributes), sum, now)?;
+                        let name = format!("{}_count", family.name);
+                        writeln!(f, "{} {} {}", self.path(&name, &attributes), count, now)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn path(&self, name: &str, attributes: &[(&str, &str)]) -> String {
+        let mut used = Vec::new();
+        let mut segments = Vec::new();
+
+        for segment in self.template.split('.') {
+            let mut out = String::new();
+            let mut rest = segment;
+            while let Some(open) = rest.find('{') {
+                let Some(close) = rest[open..].find('}') else {
+                    break;
+                };
+                out.push_str(&rest[..open]);
+
+                let key = &rest[open + 1..open + close];
+                match key {
+                    "prefix" => out.push_str(&self.prefix),
+                    "name" => out.push_str(name),
+                    key => {
+                        if let Some((_, value)) = attributes.iter().find(|(k, _)| *k == key) {
+                            out.push_str(&sanitize(value));
+                        }
+                        used.push(key);
+                    }
+                }
+                rest = &rest[open + close + 1..];
+            }
+            out.push_str(rest);
+            segments.push(out);
+        }
+
+        let mut rest = attributes
+            .iter()
+            .filter(|(key, _)| !used.contains(key))
+            .collect::<Vec<_>>();
+        rest.sort_by_key(|(key, _)| *key);
+        segments.extend(rest.into_iter().map(|(_, value)| sanitize(value)));
+
+        segments.retain(|segment| !segment.is_empty());
+        segments.join(".")
+    }
+}
+
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '.' => '_',
+            c if c.is_whitespace() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+impl PromMetricRegistry {
+    /* GraphiteFormat::default(), the metric name followed by attribute values */
+    pub fn encode_graphite(&self, f: &mut dyn std::fmt::Write, now: u64) -> std::fmt::Result {
+        GraphiteFormat::default().encode(self, f, now)
+    }
+}
+
+/* keeps one connection open, reconnecting on the next push after a failure */
+pub struct GraphitePusher {
+    /* host:port, resolved on every connect */
+    addr: String,
+    format: GraphiteFormat,
+    stream: Option<TcpStream>,
+}
+
+impl GraphitePusher {
+    pub fn new<A: Into<String>>(addr: A, format: GraphiteFormat) -> Self {
+        GraphitePusher {
+            addr: addr.into(),
+            format,
+            stream: None,
+        }
+    }
+
+    pub fn push(&mut self, registry: &PromMetricRegistry) -> std::io::Result<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+
+        let mut body = String::new();
+        let _ = self.format.encode(registry, &mut body, now);
+        self.send(body.as_bytes())
+    }
+
+    pub fn spawn_interval(
+        mut self,
+        registry: Arc<RwLock<PromMetricRegistry>>,
+        period: Duration,
+    ) -> GraphiteHandle {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = std::thread::spawn(move || loop {
+            let stop = !matches!(
+                stopped.recv_timeout(period),
+                Err(mpsc::RecvTimeoutError::Timeout)
+            );
+
+            /* the next interval reconnects and sends current values */
+            let _ = self.push(&registry.read().unwrap());
+
+            if stop {
+                break;
+            }
+        });
+
+        GraphiteHandle { stop, thread }
+    }
+
+    fn send(&mut self, payload: &[u8]) -> std::io::Result<()> {
+        let mut stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => {
+                let stream = TcpStream::connect(self.addr.as_str())?;
+                stream.set_write_timeout(Some(TIMEOUT))?;
+                stream
+            }
+        };
+
+        stream.write_all(payload)?;
+        stream.flush()?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+}
+
+pub struct GraphiteHandle {
+    stop: mpsc::Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl GraphiteHandle {
+    /* performs a final push before returning */
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.thread.join();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+        sync::{Arc, RwLock},
+        time::Duration,
+    };
+
+    use crate::{IntCounter, IntGauge, IntHistogram, PromMetricRegistry};
+
+    use super::{GraphiteFormat, GraphitePusher};
+
+    #[derive(Default)]
+    struct Met {
+        requests: IntCounter,
+        queue: IntGauge,
+        latency: IntHistogram,
+    }
+
+    fn registry() -> (Arc<Met>, PromMetricRegistry) {
+        let met = Arc::new(Met {
+            latency: IntHistogram::new([10]),
+            ..Default::default()
+        });
+        let mut reg = PromMetricRegistry::empty().with_base_attrs([("program", "api.v2")]);
+        reg.register_fn(&met, |m, reg| {
+            reg.count("requests", &m.requests)
+                .attr("status", "200")
+                .attr("method", "get all");
+            reg.gauge("queue", &m.queue);
+            reg.histogram("latency", &m.latency);
+        });
+
+        met.requests.inc_by(3);
+        met.queue.set(7);
+        met.latency.observe(5);
+        (met, reg)
+    }
+
+    fn encode(reg: &PromMetricRegistry, format: &GraphiteFormat) -> String {
+        let mut out = String::new();
+        format.encode(reg, &mut out, 1_700_000_000).unwrap();
+        out
+    }
+
+    #[test]
+    fn default_path_test() {
+        let (_met, reg) = registry();
+        let mut out = String::new();
+        reg.encode_graphite(&mut out, 1_700_000_000).unwrap();
+
+        assert_eq!(
+            out,
+            "latency_bucket.10.api_v2 1 1700000000\n\
+             latency_bucket.+Inf.api_v2 1 1700000000\n\
+             latency_sum.api_v2 5 1700000000\n\
+             latency_count.api_v2 1 1700000000\n\
+             queue.api_v2 7 1700000000\n\
+             requests.get_all.api_v2.200 3 1700000000\n"
+        );
+    }
+
+    #[test]
+    fn template_path_test() {
+        let (_met, reg) = registry();
+        let format = GraphiteFormat::new("{prefix}.{program}.{name}").prefix("ops.dc1");
+
+        assert_eq!(
+            encode(&reg, &format).lines().skip(4).collect::<Vec<_>>(),
+            [
+                "ops.dc1.api_v2.queue 7 1700000000",
+                "ops.dc1.api_v2.requests.get_all.200 3 1700000000",
+            ]
+        );
+
+        /* attributes can be placed inside a segment, missing ones leave it empty */
+        let format = GraphiteFormat::new("{name}.by_{method}.{zone}");
+        assert!(encode(&reg, &format).contains("\nrequests.by_get_all.api_v2.200 3 1700000000\n"));
+        assert!(encode(&reg, &format).contains("\nqueue.by_.api_v2 7 1700000000\n"));
+    }
+
+    #[test]
+    fn pusher_test() {
+        let (met, reg) = registry();
+        let registry = Arc::new(RwLock::new(reg));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let pusher = GraphitePusher::new(
+            listener.local_addr().unwrap().to_string(),
+            GraphiteFormat::new("{name}"),
+        )
+        .spawn_interval(registry, Duration::from_millis(10));
+
+        /* pushes reuse the connection */
+        let (stream, _) = listener.accept().unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        let first = lines.next().unwrap().unwrap();
+        assert!(first.starts_with("latency_bucket.10.api_v2 1 "));
+
+        met.queue.set(9);
+        assert!(lines
+            .map(|line| line.unwrap())
+            .any(|line| line.starts_with("queue.api_v2 9 ")));
+        pusher.stop();
+    }
+}
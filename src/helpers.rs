@@ -1,72 +1,1449 @@
-use std::{sync::Arc, time::Instant};
+use alloc::sync::Arc;
+use core::{cell::Cell, sync::atomic::Ordering, time::Duration};
+#[cfg(feature = "std")]
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::AtomicI32,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
-use crate::{ChildMetric, IntCounter, IntGauge, RegisterAction};
+#[cfg(feature = "std")]
+use crate::{lost, PromMetricRegistry, RegisterAction};
+use crate::{
+    ChildMetric, ChildMetrics2, FloatCounter, IntCounter, IntGauge, IntHistogram, Observe,
+};
 
-pub struct ActiveGauge<M>(ChildMetric<M, IntGauge>);
+pub struct ActiveGauge<M> {
+    gauge: Option<ChildMetric<M, IntGauge>>,
+    amount: u64,
+}
 
 impl<M: 'static> ActiveGauge<M> {
     pub fn new<F: Fn(&'static M) -> &'static IntGauge>(metrics: &Arc<M>, get: F) -> Self {
-        let metric = ChildMetric::create(metrics, get);
-        metric.inc();
-        ActiveGauge(metric)
+        Self::new_by(metrics, get, 1)
+    }
+
+    /* adds amount now and removes the same amount on drop */
+    pub fn new_by<F: Fn(&'static M) -> &'static IntGauge>(
+        metrics: &Arc<M>,
+        get: F,
+        amount: u64,
+    ) -> Self {
+        let gauge = ChildMetric::create(metrics, get);
+        gauge.shared_inc_by(amount);
+        ActiveGauge {
+            gauge: Some(gauge),
+            amount,
+        }
+    }
+
+    /* a disarmed guard when get returns None */
+    pub fn try_new<F: Fn(&'static M) -> Option<&'static IntGauge>>(
+        metrics: &Arc<M>,
+        get: F,
+    ) -> Self {
+        let gauge = ChildMetric::try_create(metrics, get);
+        if let Some(gauge) = &gauge {
+            gauge.shared_inc();
+        }
+        ActiveGauge { gauge, amount: 1 }
+    }
+
+    /* a disarmed guard without touching the gauge when metrics are disabled */
+    #[inline]
+    pub fn maybe<F: Fn(&'static M) -> &'static IntGauge>(
+        metrics: &MaybeMetrics<M>,
+        get: F,
+    ) -> Self {
+        match metrics {
+            MaybeMetrics::Enabled(metrics) => Self::new(metrics, get),
+            MaybeMetrics::Disabled => ActiveGauge {
+                gauge: None,
+                amount: 1,
+            },
+        }
+    }
+
+    /* decrements now instead of on drop */
+    pub fn release(mut self) {
+        self.dec();
+    }
+}
+
+impl<M> ActiveGauge<M> {
+    pub fn amount(&self) -> u64 {
+        self.amount
+    }
+
+    /* None when disabled or released */
+    pub fn metric(&self) -> Option<&ChildMetric<M, IntGauge>> {
+        self.gauge.as_ref()
+    }
+
+    /* applies the difference to the gauge immediately, drop then removes new_amount */
+    pub fn adjust(&mut self, new_amount: u64) {
+        if let Some(gauge) = &self.gauge {
+            if self.amount < new_amount {
+                gauge.shared_inc_by(new_amount - self.amount);
+            } else {
+                gauge.shared_dec_by(self.amount - new_amount);
+            }
+        }
+        self.amount = new_amount;
+    }
+
+    fn dec(&mut self) {
+        if let Some(gauge) = self.gauge.take() {
+            gauge.shared_dec_by(self.amount);
+        }
     }
 }
 
 impl<M> Drop for ActiveGauge<M> {
+    #[inline]
     fn drop(&mut self) {
-        self.0.dec();
+        self.dec();
     }
 }
 
-pub struct DurationIncMs<M> {
-    start: Instant,
-    count: ChildMetric<M, IntCounter>,
+/* ActiveGauge that also raises a peak gauge to the highest in flight value seen */
+pub struct HighWaterGauge<M> {
+    gauges: Option<ChildMetrics2<M, IntGauge, IntGauge>>,
 }
 
-impl<M: 'static> DurationIncMs<M> {
-    pub fn new<F: Fn(&'static M) -> &'static IntCounter>(metrics: &Arc<M>, get: F) -> Self {
-        DurationIncMs {
-            start: Instant::now(),
-            count: ChildMetric::create(metrics, get),
+impl<M: 'static> HighWaterGauge<M> {
+    pub fn new<F, P>(metrics: &Arc<M>, in_flight: F, peak: P) -> Self
+    where
+        F: Fn(&'static M) -> &'static IntGauge,
+        P: Fn(&'static M) -> &'static IntGauge,
+    {
+        let gauges = ChildMetrics2::create(metrics, |m| (in_flight(m), peak(m)));
+        let current = gauges.first().0.fetch_add(1, Ordering::AcqRel) + 1;
+        gauges.second().set_max(current);
+
+        HighWaterGauge {
+            gauges: Some(gauges),
         }
     }
+
+    /* decrements now instead of on drop */
+    pub fn release(mut self) {
+        self.dec();
+    }
 }
 
-impl<M> Drop for DurationIncMs<M> {
+impl<M> HighWaterGauge<M> {
+    fn dec(&mut self) {
+        if let Some(gauges) = self.gauges.take() {
+            gauges.first().dec();
+        }
+    }
+}
+
+impl<M> Drop for HighWaterGauge<M> {
     fn drop(&mut self) {
-        let elapsed = self.start.elapsed().as_millis() as u64;
-        self.count.shared_inc_by(elapsed as _);
+        self.dec();
     }
 }
 
-pub struct DurationIncUs<M> {
-    start: Instant,
-    count: ChildMetric<M, IntCounter>,
+/* per thread counter, increments stay local until flush() or drop */
+pub struct LocalCounter<M> {
+    counter: ChildMetric<M, IntCounter>,
+    pending: Cell<u64>,
 }
 
-impl<M: 'static> DurationIncUs<M> {
+impl<M: 'static> LocalCounter<M> {
     pub fn new<F: Fn(&'static M) -> &'static IntCounter>(metrics: &Arc<M>, get: F) -> Self {
-        DurationIncUs {
-            start: Instant::now(),
-            count: ChildMetric::create(metrics, get),
+        LocalCounter {
+            counter: ChildMetric::create(metrics, get),
+            pending: Cell::new(0),
+        }
+    }
+}
+
+impl<M> LocalCounter<M> {
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    pub fn inc_by(&self, amount: u64) {
+        self.pending.set(self.pending.get() + amount);
+    }
+
+    pub fn pending(&self) -> u64 {
+        self.pending.get()
+    }
+
+    pub fn metric(&self) -> &ChildMetric<M, IntCounter> {
+        &self.counter
+    }
+
+    pub fn flush(&self) {
+        let pending = self.pending.replace(0);
+        if pending != 0 {
+            self.counter.shared_inc_by(pending);
+        }
+    }
+}
+
+impl<M> Drop for LocalCounter<M> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/*
+ * monotonic time for the duration guards. StdClock reads Instant, without std implement it
+ * over the platform's timer and start guards with with_clock
+ */
+pub trait Clock {
+    type Instant;
+
+    fn now(&self) -> Self::Instant;
+    fn elapsed(&self, since: &Self::Instant) -> Duration;
+}
+
+#[cfg(feature = "std")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StdClock;
+
+#[cfg(feature = "std")]
+impl Clock for StdClock {
+    type Instant = Instant;
+
+    #[inline]
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    #[inline]
+    fn elapsed(&self, since: &Instant) -> Duration {
+        since.elapsed()
+    }
+}
+
+/* uninhabited, the default clock without std so guards can only be made with with_clock */
+#[derive(Debug, Clone, Copy)]
+pub enum NoClock {}
+
+impl Clock for NoClock {
+    type Instant = NoClock;
+
+    fn now(&self) -> NoClock {
+        match *self {}
+    }
+
+    fn elapsed(&self, _since: &NoClock) -> Duration {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "std")]
+pub type DefaultClock = StdClock;
+#[cfg(not(feature = "std"))]
+pub type DefaultClock = NoClock;
+
+pub struct DurationIncMs<M, O: Observe + 'static = IntCounter, C: Clock = DefaultClock> {
+    /* None once recorded, cancelled or when metrics are disabled */
+    timer: Option<(C::Instant, ChildMetric<M, O>)>,
+    clock: C,
+}
+
+#[cfg(feature = "std")]
+impl<M: 'static, O: Observe> DurationIncMs<M, O> {
+    pub fn new<F: Fn(&'static M) -> &'static O>(metrics: &Arc<M>, get: F) -> Self {
+        Self::with_clock(metrics, get, StdClock)
+    }
+
+    /* a disarmed guard that doesn't read the clock when metrics are disabled */
+    #[inline]
+    pub fn maybe<F: Fn(&'static M) -> &'static O>(metrics: &MaybeMetrics<M>, get: F) -> Self {
+        match metrics {
+            MaybeMetrics::Enabled(metrics) => Self::new(metrics, get),
+            MaybeMetrics::Disabled => DurationIncMs {
+                timer: None,
+                clock: StdClock,
+            },
+        }
+    }
+
+    /* a disarmed guard when get returns None, ex. |m| m.optional.as_ref() */
+    pub fn try_new<F: Fn(&'static M) -> Option<&'static O>>(metrics: &Arc<M>, get: F) -> Self {
+        Self::try_with_clock(metrics, get, StdClock)
+    }
+}
+
+impl<M: 'static, O: Observe, C: Clock> DurationIncMs<M, O, C> {
+    pub fn with_clock<F: Fn(&'static M) -> &'static O>(metrics: &Arc<M>, get: F, clock: C) -> Self {
+        Self::try_with_clock(metrics, |m| Some(get(m)), clock)
+    }
+
+    pub fn try_with_clock<F: Fn(&'static M) -> Option<&'static O>>(
+        metrics: &Arc<M>,
+        get: F,
+        clock: C,
+    ) -> Self {
+        let timer = ChildMetric::try_create(metrics, get).map(|metric| (clock.now(), metric));
+        DurationIncMs { timer, clock }
+    }
+}
+
+impl<M, O: Observe, C: Clock> DurationIncMs<M, O, C> {
+    /* None when disabled, ex. metric().map(|m| m.owner()) to reach sibling metrics */
+    pub fn metric(&self) -> Option<&ChildMetric<M, O>> {
+        self.timer.as_ref().map(|(_, metric)| metric)
+    }
+
+    /* drops the guard without recording */
+    pub fn cancel(mut self) {
+        self.timer = None;
+    }
+
+    /* records now instead of on drop, returns the recorded value (0 when disabled) */
+    pub fn finish(mut self) -> u64 {
+        self.record().unwrap_or_default()
+    }
+
+    fn record(&mut self) -> Option<u64> {
+        let (start, count) = self.timer.take()?;
+        let elapsed = DurationUnit::Millis.convert(self.clock.elapsed(&start));
+        count.observe(elapsed);
+        Some(elapsed)
+    }
+}
+
+impl<M, O: Observe, C: Clock> Drop for DurationIncMs<M, O, C> {
+    /* the check stays inline so disarmed guards don't cost a call */
+    #[inline]
+    fn drop(&mut self) {
+        if self.timer.is_some() {
+            self.record();
+        }
+    }
+}
+
+pub struct DurationIncUs<M, O: Observe + 'static = IntCounter, C: Clock = DefaultClock> {
+    /* None once recorded, cancelled or when the metric is absent */
+    timer: Option<(C::Instant, ChildMetric<M, O>)>,
+    clock: C,
+}
+
+#[cfg(feature = "std")]
+impl<M: 'static, O: Observe> DurationIncUs<M, O> {
+    pub fn new<F: Fn(&'static M) -> &'static O>(metrics: &Arc<M>, get: F) -> Self {
+        Self::with_clock(metrics, get, StdClock)
+    }
+
+    /* a disarmed guard when get returns None */
+    pub fn try_new<F: Fn(&'static M) -> Option<&'static O>>(metrics: &Arc<M>, get: F) -> Self {
+        Self::try_with_clock(metrics, get, StdClock)
+    }
+}
+
+impl<M: 'static, O: Observe, C: Clock> DurationIncUs<M, O, C> {
+    pub fn with_clock<F: Fn(&'static M) -> &'static O>(metrics: &Arc<M>, get: F, clock: C) -> Self {
+        Self::try_with_clock(metrics, |m| Some(get(m)), clock)
+    }
+
+    pub fn try_with_clock<F: Fn(&'static M) -> Option<&'static O>>(
+        metrics: &Arc<M>,
+        get: F,
+        clock: C,
+    ) -> Self {
+        let timer = ChildMetric::try_create(metrics, get).map(|metric| (clock.now(), metric));
+        DurationIncUs { timer, clock }
+    }
+}
+
+impl<M, O: Observe, C: Clock> DurationIncUs<M, O, C> {
+    pub fn metric(&self) -> Option<&ChildMetric<M, O>> {
+        self.timer.as_ref().map(|(_, metric)| metric)
+    }
+
+    /* drops the guard without recording */
+    pub fn cancel(mut self) {
+        self.timer = None;
+    }
+
+    /* records now instead of on drop, returns the recorded value */
+    pub fn finish(mut self) -> u64 {
+        self.record().unwrap_or_default()
+    }
+
+    fn record(&mut self) -> Option<u64> {
+        let (start, count) = self.timer.take()?;
+        let elapsed = DurationUnit::Micros.convert(self.clock.elapsed(&start));
+        count.observe(elapsed);
+        Some(elapsed)
+    }
+}
+
+impl<M, O: Observe, C: Clock> Drop for DurationIncUs<M, O, C> {
+    #[inline]
+    fn drop(&mut self) {
+        if self.timer.is_some() {
+            self.record();
+        }
+    }
+}
+
+/* seconds as f64, ex. for a _seconds_total FloatCounter */
+pub struct DurationIncSecs<M, C: Clock = DefaultClock> {
+    /* None once recorded, cancelled or when the metric is absent */
+    timer: Option<(C::Instant, ChildMetric<M, FloatCounter>)>,
+    clock: C,
+}
+
+#[cfg(feature = "std")]
+impl<M: 'static> DurationIncSecs<M> {
+    pub fn new<F: Fn(&'static M) -> &'static FloatCounter>(metrics: &Arc<M>, get: F) -> Self {
+        Self::with_clock(metrics, get, StdClock)
+    }
+
+    /* a disarmed guard when get returns None */
+    pub fn try_new<F: Fn(&'static M) -> Option<&'static FloatCounter>>(
+        metrics: &Arc<M>,
+        get: F,
+    ) -> Self {
+        Self::try_with_clock(metrics, get, StdClock)
+    }
+}
+
+impl<M: 'static, C: Clock> DurationIncSecs<M, C> {
+    pub fn with_clock<F: Fn(&'static M) -> &'static FloatCounter>(
+        metrics: &Arc<M>,
+        get: F,
+        clock: C,
+    ) -> Self {
+        Self::try_with_clock(metrics, |m| Some(get(m)), clock)
+    }
+
+    pub fn try_with_clock<F: Fn(&'static M) -> Option<&'static FloatCounter>>(
+        metrics: &Arc<M>,
+        get: F,
+        clock: C,
+    ) -> Self {
+        let timer = ChildMetric::try_create(metrics, get).map(|metric| (clock.now(), metric));
+        DurationIncSecs { timer, clock }
+    }
+}
+
+impl<M, C: Clock> DurationIncSecs<M, C> {
+    pub fn metric(&self) -> Option<&ChildMetric<M, FloatCounter>> {
+        self.timer.as_ref().map(|(_, metric)| metric)
+    }
+
+    /* drops the guard without recording */
+    pub fn cancel(mut self) {
+        self.timer = None;
+    }
+
+    /* records now instead of on drop, returns the recorded seconds */
+    pub fn finish(mut self) -> f64 {
+        self.record().unwrap_or_default()
+    }
+
+    fn record(&mut self) -> Option<f64> {
+        let (start, counter) = self.timer.take()?;
+        let elapsed = self.clock.elapsed(&start).as_secs_f64();
+        counter.inc_by(elapsed);
+        Some(elapsed)
+    }
+}
+
+impl<M, C: Clock> Drop for DurationIncSecs<M, C> {
+    #[inline]
+    fn drop(&mut self) {
+        if self.timer.is_some() {
+            self.record();
+        }
+    }
+}
+
+/* total duration (any Observe) and number of calls */
+pub struct DurationWithCount<M, O: Observe + 'static = IntCounter, C: Clock = DefaultClock> {
+    start: C::Instant,
+    unit: DurationUnit,
+    counters: ChildMetrics2<M, O, IntCounter>,
+    clock: C,
+}
+
+pub type Timed<M, O = IntCounter> = DurationWithCount<M, O>;
+
+#[cfg(feature = "std")]
+impl<M: 'static, O: Observe> DurationWithCount<M, O> {
+    pub fn new<F>(metrics: &Arc<M>, get: F) -> Self
+    where
+        F: Fn(&'static M) -> (&'static O, &'static IntCounter),
+    {
+        Self::with_unit(metrics, get, DurationUnit::Millis)
+    }
+
+    pub fn with_unit<F>(metrics: &Arc<M>, get: F, unit: DurationUnit) -> Self
+    where
+        F: Fn(&'static M) -> (&'static O, &'static IntCounter),
+    {
+        Self::with_clock(metrics, get, unit, StdClock)
+    }
+}
+
+impl<M: 'static, O: Observe, C: Clock> DurationWithCount<M, O, C> {
+    pub fn with_clock<F>(metrics: &Arc<M>, get: F, unit: DurationUnit, clock: C) -> Self
+    where
+        F: Fn(&'static M) -> (&'static O, &'static IntCounter),
+    {
+        DurationWithCount {
+            start: clock.now(),
+            unit,
+            counters: ChildMetrics2::create(metrics, get),
+            clock,
+        }
+    }
+}
+
+impl<M, O: Observe, C: Clock> DurationWithCount<M, O, C> {
+    pub fn metrics(&self) -> &ChildMetrics2<M, O, IntCounter> {
+        &self.counters
+    }
+}
+
+impl<M, O: Observe, C: Clock> Drop for DurationWithCount<M, O, C> {
+    fn drop(&mut self) {
+        let elapsed = self.unit.convert(self.clock.elapsed(&self.start));
+        self.counters.first().observe(elapsed);
+        self.counters.second().shared_inc();
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DurationUnit {
+    Secs,
+    #[default]
+    Millis,
+    Micros,
+}
+
+impl DurationUnit {
+    /* truncates the fraction, saturates at u64::MAX */
+    pub fn convert(&self, duration: Duration) -> u64 {
+        match self {
+            Self::Secs => duration.as_secs(),
+            Self::Millis => u64::try_from(duration.as_millis()).unwrap_or(u64::MAX),
+            Self::Micros => u64::try_from(duration.as_micros()).unwrap_or(u64::MAX),
         }
     }
 }
 
-impl<M> Drop for DurationIncUs<M> {
+/* works with any Observe, the name is from before that existed */
+pub struct DurationHistogram<M, O: Observe + 'static = IntHistogram, C: Clock = DefaultClock> {
+    start: C::Instant,
+    unit: DurationUnit,
+    histogram: Option<ChildMetric<M, O>>,
+    clock: C,
+}
+
+#[cfg(feature = "std")]
+impl<M: 'static, O: Observe> DurationHistogram<M, O> {
+    pub fn new<F: Fn(&'static M) -> &'static O>(metrics: &Arc<M>, get: F) -> Self {
+        Self::with_unit(metrics, get, DurationUnit::Millis)
+    }
+
+    pub fn with_unit<F: Fn(&'static M) -> &'static O>(
+        metrics: &Arc<M>,
+        get: F,
+        unit: DurationUnit,
+    ) -> Self {
+        Self::with_clock(metrics, get, unit, StdClock)
+    }
+}
+
+impl<M: 'static, O: Observe, C: Clock> DurationHistogram<M, O, C> {
+    pub fn with_clock<F: Fn(&'static M) -> &'static O>(
+        metrics: &Arc<M>,
+        get: F,
+        unit: DurationUnit,
+        clock: C,
+    ) -> Self {
+        DurationHistogram {
+            start: clock.now(),
+            unit,
+            histogram: Some(ChildMetric::create(metrics, get)),
+            clock,
+        }
+    }
+
+    /* records now instead of on drop, returns the observed value */
+    pub fn observe_now(mut self) -> u64 {
+        self.observe().unwrap_or_default()
+    }
+}
+
+impl<M, O: Observe, C: Clock> DurationHistogram<M, O, C> {
+    pub fn metric(&self) -> Option<&ChildMetric<M, O>> {
+        self.histogram.as_ref()
+    }
+
+    fn observe(&mut self) -> Option<u64> {
+        let histogram = self.histogram.take()?;
+        let elapsed = self.unit.convert(self.clock.elapsed(&self.start));
+        histogram.observe(elapsed);
+        Some(elapsed)
+    }
+}
+
+impl<M, O: Observe, C: Clock> Drop for DurationHistogram<M, O, C> {
     fn drop(&mut self) {
-        let elapsed = self.start.elapsed().as_micros() as u64;
-        self.count.shared_inc_by(elapsed as _);
+        self.observe();
+    }
+}
+
+/*
+ * Coarse time of day from a fixed UTC offset. There is no timezone database, so DST or
+ * other offset changes are not followed automatically: call set_utc_offset when they
+ * happen. Increments racing the change may land in the bucket of either offset.
+ */
+#[cfg(feature = "std")]
+pub struct TimeOfDayLabel {
+    utc_offset_secs: AtomicI32,
+}
+
+#[cfg(feature = "std")]
+impl TimeOfDayLabel {
+    pub const LABEL: &'static str = "time_of_day";
+    /* 6 hour buckets starting at local midnight */
+    pub const BUCKETS: [&'static str; 4] = ["night", "morning", "afternoon", "evening"];
+
+    pub const fn utc() -> Self {
+        Self::with_utc_offset(0)
+    }
+
+    pub const fn with_utc_offset(secs: i32) -> Self {
+        TimeOfDayLabel {
+            utc_offset_secs: AtomicI32::new(secs),
+        }
+    }
+
+    pub fn set_utc_offset(&self, secs: i32) {
+        self.utc_offset_secs.store(secs, Ordering::Relaxed);
+    }
+
+    pub fn utc_offset(&self) -> i32 {
+        self.utc_offset_secs.load(Ordering::Relaxed)
+    }
+
+    pub fn current(&self) -> &'static str {
+        self.at(SystemTime::now())
+    }
+
+    pub fn at(&self, time: SystemTime) -> &'static str {
+        Self::BUCKETS[self.index_at(time)]
+    }
+
+    fn index_at(&self, time: SystemTime) -> usize {
+        let unix_secs = match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            /* round down for times before the epoch */
+            Err(error) => {
+                let before = error.duration();
+                -(before.as_secs() as i64) - (before.subsec_nanos() != 0) as i64
+            }
+        };
+
+        let local = unix_secs + self.utc_offset() as i64;
+        (local.rem_euclid(86_400) / 21_600) as usize
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for TimeOfDayLabel {
+    fn default() -> Self {
+        Self::utc()
     }
 }
 
+/* counter split by TimeOfDayLabel, one series per bucket */
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub struct TimeBucketedCounter {
+    label: TimeOfDayLabel,
+    counters: [IntCounter; 4],
+}
+
+#[cfg(feature = "std")]
+impl TimeBucketedCounter {
+    pub const fn new(label: TimeOfDayLabel) -> Self {
+        TimeBucketedCounter {
+            label,
+            counters: [
+                IntCounter::new(),
+                IntCounter::new(),
+                IntCounter::new(),
+                IntCounter::new(),
+            ],
+        }
+    }
+
+    pub fn label(&self) -> &TimeOfDayLabel {
+        &self.label
+    }
+
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    pub fn inc_by(&self, amount: u64) {
+        self.inc_at(SystemTime::now(), amount);
+    }
+
+    pub fn inc_at(&self, time: SystemTime, amount: u64) {
+        self.counters[self.label.index_at(time)].inc_by(amount);
+    }
+
+    pub fn get(&self, bucket: &str) -> Option<&IntCounter> {
+        let index = TimeOfDayLabel::BUCKETS.iter().position(|b| *b == bucket)?;
+        Some(&self.counters[index])
+    }
+
+    pub fn register(&'static self, name: &'static str, register: &mut RegisterAction) {
+        for (bucket, counter) in TimeOfDayLabel::BUCKETS.iter().zip(&self.counters) {
+            register
+                .count(name, counter)
+                .attr(TimeOfDayLabel::LABEL, *bucket);
+        }
+    }
+}
+
+/*
+ * forwards roughly 1 in `factor` observations, registered with sampled_histogram the
+ * stored counts stay exact and are scaled by the factor when rendered
+ */
+#[cfg(feature = "std")]
+pub struct Sampled<H> {
+    inner: H,
+    factor: IntGauge,
+}
+
+#[cfg(feature = "std")]
+impl<H: Observe> Sampled<H> {
+    pub fn new(inner: H, factor: u64) -> Self {
+        assert!(0 < factor, "sampling factor must be at least 1");
+
+        let gauge = IntGauge::new();
+        gauge.set(factor);
+        Sampled {
+            inner,
+            factor: gauge,
+        }
+    }
+
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+
+    pub fn factor(&self) -> u64 {
+        self.factor.load()
+    }
+
+    pub(crate) fn factor_gauge(&self) -> &IntGauge {
+        &self.factor
+    }
+}
+
+#[cfg(feature = "std")]
+impl<H: Observe> Observe for Sampled<H> {
+    fn observe(&self, value: u64) {
+        let factor = self.factor();
+        if factor == 1 || sample_next() % factor == 0 {
+            self.inner.observe(value);
+        } else {
+            lost::record(lost::DropReason::Sampling, 1);
+        }
+    }
+}
+
+/* per thread xorshift so instances observed in lockstep don't always skip the same one */
+#[cfg(feature = "std")]
+fn sample_next() -> u64 {
+    thread_local! {
+        static STATE: Cell<u64> = Cell::new(RandomState::new().build_hasher().finish() | 1);
+    }
+
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        x
+    })
+}
+
+#[cfg(feature = "std")]
 pub trait RegisterableMetric: 'static {
     fn register(&'static self, register: &mut RegisterAction);
 }
 
+/* None registers nothing */
+#[cfg(feature = "std")]
+impl<T: RegisterableMetric> RegisterableMetric for Option<T> {
+    fn register(&'static self, register: &mut RegisterAction) {
+        if let Some(metrics) = self {
+            metrics.register(register);
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: RegisterableMetric> RegisterableMetric for Arc<T> {
+    fn register(&'static self, register: &mut RegisterAction) {
+        T::register(self, register);
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A: RegisterableMetric, B: RegisterableMetric> RegisterableMetric for (A, B) {
+    fn register(&'static self, register: &mut RegisterAction) {
+        self.0.register(register);
+        self.1.register(register);
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A: RegisterableMetric, B: RegisterableMetric, C: RegisterableMetric> RegisterableMetric
+    for (A, B, C)
+{
+    fn register(&'static self, register: &mut RegisterAction) {
+        self.0.register(register);
+        self.1.register(register);
+        self.2.register(register);
+    }
+}
+
 #[derive(Default, Copy, Clone)]
 pub struct NoMetrics;
 
+#[cfg(feature = "std")]
 impl RegisterableMetric for NoMetrics {
     fn register(&'static self, _register: &mut RegisterAction) {}
 }
+
+/*
+ * for libraries whose metrics are optional: the metric struct stays concrete and every
+ * access goes through a closure that isn't called when disabled. The disabled paths don't
+ * allocate, clone an Arc or read the clock.
+ */
+#[derive(Default)]
+pub enum MaybeMetrics<M> {
+    Enabled(Arc<M>),
+    #[default]
+    Disabled,
+}
+
+impl<M> Clone for MaybeMetrics<M> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Enabled(metrics) => Self::Enabled(metrics.clone()),
+            Self::Disabled => Self::Disabled,
+        }
+    }
+}
+
+impl<M> From<Arc<M>> for MaybeMetrics<M> {
+    fn from(metrics: Arc<M>) -> Self {
+        MaybeMetrics::Enabled(metrics)
+    }
+}
+
+impl<M> From<Option<Arc<M>>> for MaybeMetrics<M> {
+    fn from(metrics: Option<Arc<M>>) -> Self {
+        metrics.map_or(MaybeMetrics::Disabled, MaybeMetrics::Enabled)
+    }
+}
+
+impl<M> MaybeMetrics<M> {
+    pub fn new(metrics: M) -> Self {
+        MaybeMetrics::Enabled(Arc::new(metrics))
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        matches!(self, Self::Enabled(_))
+    }
+
+    #[inline]
+    pub fn get(&self) -> Option<&Arc<M>> {
+        match self {
+            Self::Enabled(metrics) => Some(metrics),
+            Self::Disabled => None,
+        }
+    }
+
+    #[inline]
+    pub fn inc<F: FnOnce(&M) -> &IntCounter>(&self, get: F) {
+        if let Self::Enabled(metrics) = self {
+            get(metrics).inc();
+        }
+    }
+
+    #[inline]
+    pub fn inc_by<F: FnOnce(&M) -> &IntCounter>(&self, get: F, amount: u64) {
+        if let Self::Enabled(metrics) = self {
+            get(metrics).inc_by(amount);
+        }
+    }
+
+    #[inline]
+    pub fn set<F: FnOnce(&M) -> &IntGauge>(&self, get: F, value: u64) {
+        if let Self::Enabled(metrics) = self {
+            get(metrics).set(value);
+        }
+    }
+
+    #[inline]
+    pub fn observe<O: Observe, F: FnOnce(&M) -> &O>(&self, get: F, value: u64) {
+        if let Self::Enabled(metrics) = self {
+            get(metrics).observe(value);
+        }
+    }
+
+    /* anything else, ex. several metrics at once */
+    #[inline]
+    pub fn with<F: FnOnce(&M)>(&self, f: F) {
+        if let Self::Enabled(metrics) = self {
+            f(metrics);
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<M: RegisterableMetric> MaybeMetrics<M> {
+    /* registers nothing when disabled */
+    pub fn register(&self, registry: &mut PromMetricRegistry) {
+        if let Self::Enabled(metrics) = self {
+            registry.register(metrics);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use std::{
+        sync::Arc,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use crate::{FloatCounter, IntCounter, IntGauge, IntHistogram, Observe, PromMetricRegistry};
+
+    use super::{
+        ActiveGauge, DurationHistogram, DurationIncMs, DurationIncSecs, DurationIncUs,
+        DurationUnit, HighWaterGauge, LocalCounter, MaybeMetrics, RegisterableMetric, Sampled,
+        TimeBucketedCounter, TimeOfDayLabel, Timed,
+    };
+
+    #[derive(Default)]
+    struct Met {
+        latency: IntHistogram,
+        latency_us: IntCounter,
+        calls: IntCounter,
+        active: IntGauge,
+        peak: IntGauge,
+    }
+
+    impl RegisterableMetric for Met {
+        fn register(&'static self, register: &mut crate::RegisterAction) {
+            register.count("calls", &self.calls);
+            register.gauge("active", &self.active);
+        }
+    }
+
+    #[test]
+    fn duration_histogram_test() {
+        let met = Arc::new(Met::default());
+
+        {
+            let _timer = DurationHistogram::new(&met, |m| &m.latency);
+        }
+        assert_eq!(met.latency.count(), 1);
+
+        let timer = DurationHistogram::with_unit(&met, |m| &m.latency, DurationUnit::Micros);
+        std::thread::sleep(Duration::from_millis(2));
+        let elapsed = timer.observe_now();
+        assert!(elapsed >= 2000);
+        assert_eq!(met.latency.count(), 2);
+        assert!(met.latency.sum() >= elapsed);
+    }
+
+    #[test]
+    fn try_new_test() {
+        #[derive(Default)]
+        struct Optional {
+            active: Option<IntGauge>,
+            latency_ms: Option<IntCounter>,
+            latency_us: Option<IntCounter>,
+        }
+
+        let absent = Arc::new(Optional::default());
+        {
+            let _active = ActiveGauge::try_new(&absent, |m| m.active.as_ref());
+            let ms = DurationIncMs::try_new(&absent, |m| m.latency_ms.as_ref());
+            let us = DurationIncUs::try_new(&absent, |m| m.latency_us.as_ref());
+            assert!(ms.metric().is_none() && us.metric().is_none());
+            /* nothing cloned the Arc, so no atomic was touched */
+            assert_eq!(Arc::strong_count(&absent), 1);
+        }
+        assert_eq!(Arc::strong_count(&absent), 1);
+
+        let present = Arc::new(Optional {
+            active: Some(IntGauge::new()),
+            latency_ms: Some(IntCounter::new()),
+            latency_us: Some(IntCounter::new()),
+        });
+        {
+            let _active = ActiveGauge::try_new(&present, |m| m.active.as_ref());
+            let _ms = DurationIncMs::try_new(&present, |m| m.latency_ms.as_ref());
+            let _us = DurationIncUs::try_new(&present, |m| m.latency_us.as_ref());
+            assert_eq!(Arc::strong_count(&present), 4);
+            assert_eq!(present.active.as_ref().unwrap().load(), 1);
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        assert_eq!(Arc::strong_count(&present), 1);
+        assert_eq!(present.active.as_ref().unwrap().load(), 0);
+        assert!(2 <= present.latency_ms.as_ref().unwrap().load());
+        assert!(2000 <= present.latency_us.as_ref().unwrap().load());
+    }
+
+    #[test]
+    fn duration_secs_test() {
+        #[derive(Default)]
+        struct Handler {
+            busy_seconds: FloatCounter,
+        }
+
+        let met = Arc::new(Handler::default());
+        {
+            let _timer = DurationIncSecs::new(&met, |m| &m.busy_seconds);
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        let first = met.busy_seconds.load();
+        assert!((0.002..1.0).contains(&first));
+
+        let timer = DurationIncSecs::new(&met, |m| &m.busy_seconds);
+        let elapsed = timer.finish();
+        assert_eq!(met.busy_seconds.load(), first + elapsed);
+
+        DurationIncSecs::new(&met, |m| &m.busy_seconds).cancel();
+        assert_eq!(met.busy_seconds.load(), first + elapsed);
+        assert_eq!(Arc::strong_count(&met), 1);
+    }
+
+    #[test]
+    fn guard_metric_test() {
+        let met = Arc::new(Met::default());
+
+        let timer = DurationIncMs::new(&met, |m| &m.latency_us);
+        if let Some(latency) = timer.metric() {
+            latency.owner().calls.inc();
+        }
+        timer.cancel();
+        assert_eq!(met.calls.load(), 1);
+
+        let timed = Timed::new(&met, |m| (&m.latency_us, &m.calls));
+        timed.metrics().owner().active.set(4);
+        drop(timed);
+        assert_eq!(met.calls.load(), 2);
+        assert_eq!(met.active.load(), 4);
+
+        let disabled = ActiveGauge::maybe(&MaybeMetrics::<Met>::Disabled, |m| &m.active);
+        assert!(disabled.metric().is_none());
+    }
+
+    #[test]
+    fn observe_swap_test() {
+        #[derive(Default)]
+        struct Handler<O> {
+            latency: O,
+        }
+
+        /* the same call site for every latency type */
+        fn handle<O: Observe + 'static>(met: &Arc<Handler<O>>) {
+            let _timer = DurationHistogram::with_unit(met, |m| &m.latency, DurationUnit::Micros);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        let counter = Arc::new(Handler::<IntCounter>::default());
+        let histogram = Arc::new(Handler::<IntHistogram>::default());
+        handle(&counter);
+        handle(&histogram);
+
+        assert!(counter.latency.load() >= 1000);
+        assert_eq!(histogram.latency.count(), 1);
+        assert!(histogram.latency.sum() >= 1000);
+
+        DurationIncMs::new(&histogram, |m| &m.latency).finish();
+        assert_eq!(histogram.latency.count(), 2);
+    }
+
+    #[test]
+    fn timed_test() {
+        let met = Arc::new(Met::default());
+
+        for i in 1..=3 {
+            let _timer =
+                Timed::with_unit(&met, |m| (&m.latency_us, &m.calls), DurationUnit::Micros);
+            std::thread::sleep(Duration::from_millis(1));
+            assert_eq!(met.calls.load(), i - 1);
+        }
+
+        assert_eq!(met.calls.load(), 3);
+        assert!(met.latency_us.load() >= 3000);
+    }
+
+    #[derive(Default)]
+    struct Pool {
+        open: IntGauge,
+    }
+
+    impl RegisterableMetric for Pool {
+        fn register(&'static self, register: &mut crate::RegisterAction) {
+            register.gauge("open", &self.open);
+        }
+    }
+
+    #[derive(Default)]
+    struct Db {
+        queries: IntCounter,
+        pool: Pool,
+        replica: Option<Pool>,
+    }
+
+    impl RegisterableMetric for Db {
+        fn register(&'static self, register: &mut crate::RegisterAction) {
+            register.count("queries", &self.queries);
+            register
+                .nested("pool", &self.pool)
+                .nested("replica", &self.replica);
+        }
+    }
+
+    #[derive(Default)]
+    struct App {
+        db: Db,
+        cache: Option<Arc<Met>>,
+    }
+
+    impl RegisterableMetric for App {
+        fn register(&'static self, register: &mut crate::RegisterAction) {
+            register.nested("db", &self.db).nested("cache", &self.cache);
+        }
+    }
+
+    #[test]
+    fn nested_register_test() {
+        let mut reg = PromMetricRegistry::empty();
+        reg.name_prefix("app");
+        let app = Arc::new((App::default(), Arc::new(Met::default())));
+        reg.register(&app);
+        assert_eq!(
+            reg.metric_names(),
+            [
+                "app_active",
+                "app_calls",
+                "app_db_pool_open",
+                "app_db_queries"
+            ]
+        );
+
+        let app = Arc::new(App {
+            db: Db {
+                replica: Some(Pool::default()),
+                ..Default::default()
+            },
+            cache: Some(Arc::new(Met::default())),
+        });
+        let mut reg = PromMetricRegistry::empty();
+        reg.register(&app);
+        app.db.replica.as_ref().unwrap().open.set(3);
+        assert_eq!(
+            reg.metric_names(),
+            [
+                "cache_active",
+                "cache_calls",
+                "db_pool_open",
+                "db_queries",
+                "db_replica_open"
+            ]
+        );
+        assert!(reg.to_string().contains("\ndb_replica_open 3\n"));
+    }
+
+    #[test]
+    fn maybe_metrics_test() {
+        /* a library written once against MaybeMetrics<Met> */
+        fn handle(metrics: &MaybeMetrics<Met>) -> u64 {
+            let _active = ActiveGauge::maybe(metrics, |m| &m.active);
+            let timer = DurationIncMs::maybe(metrics, |m| &m.latency_us);
+            metrics.inc(|m| &m.calls);
+            metrics.observe(|m| &m.latency, 5);
+            metrics.with(|m| assert_eq!(m.active.load(), 1));
+            timer.finish()
+        }
+
+        let enabled = MaybeMetrics::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+        enabled.register(&mut reg);
+        handle(&enabled);
+        handle(&enabled);
+
+        let met = enabled.get().unwrap();
+        assert_eq!(met.calls.load(), 2);
+        assert_eq!(met.active.load(), 0);
+        assert_eq!(met.latency.count(), 2);
+        assert_eq!(Arc::strong_count(met), 2);
+        assert!(reg.to_string().contains("\ncalls 2\n"));
+
+        let disabled = MaybeMetrics::<Met>::default();
+        let mut reg = PromMetricRegistry::empty();
+        disabled.register(&mut reg);
+        assert!(reg.is_empty());
+        assert!(!disabled.is_enabled());
+        assert_eq!(handle(&disabled), 0);
+
+        let from: MaybeMetrics<Met> = None.into();
+        assert!(from.get().is_none());
+    }
+
+    #[test]
+    fn local_counter_test() {
+        let met = Arc::new(Met::default());
+
+        let threads = (0..4)
+            .map(|_| {
+                let met = met.clone();
+                std::thread::spawn(move || {
+                    let local = LocalCounter::new(&met, |m| &m.calls);
+                    for i in 0..1000 {
+                        local.inc();
+                        if i == 499 {
+                            local.flush();
+                            assert_eq!(local.pending(), 0);
+                        }
+                    }
+                    assert_eq!(local.pending(), 500);
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(met.calls.load(), 4000);
+    }
+
+    #[test]
+    fn time_of_day_test() {
+        /* 2023-11-14 22:13:20 UTC */
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        assert_eq!(TimeOfDayLabel::utc().at(time), "evening");
+        assert_eq!(TimeOfDayLabel::with_utc_offset(2 * 3600).at(time), "night");
+        assert_eq!(
+            TimeOfDayLabel::with_utc_offset(-5 * 3600).at(time),
+            "afternoon"
+        );
+        assert_eq!(
+            TimeOfDayLabel::with_utc_offset(-10 * 3600).at(time),
+            "afternoon"
+        );
+        assert_eq!(
+            TimeOfDayLabel::with_utc_offset(-16 * 3600).at(time),
+            "morning"
+        );
+
+        /* bucket edges and times before the epoch */
+        let utc = TimeOfDayLabel::utc();
+        assert_eq!(utc.at(UNIX_EPOCH), "night");
+        assert_eq!(utc.at(UNIX_EPOCH + Duration::from_secs(21_599)), "night");
+        assert_eq!(utc.at(UNIX_EPOCH + Duration::from_secs(21_600)), "morning");
+        assert_eq!(utc.at(UNIX_EPOCH - Duration::from_millis(1)), "evening");
+        assert_eq!(utc.at(UNIX_EPOCH - Duration::from_secs(86_400)), "night");
+
+        /* 2024-03-10 10:30 UTC, US eastern moves from -5h to -4h that morning */
+        let time = UNIX_EPOCH + Duration::from_secs(1_710_066_600);
+        let label = TimeOfDayLabel::with_utc_offset(-5 * 3600);
+        assert_eq!(label.at(time), "night");
+        label.set_utc_offset(-4 * 3600);
+        assert_eq!(label.at(time), "morning");
+    }
+
+    #[test]
+    fn time_bucketed_counter_test() {
+        static REQUESTS: TimeBucketedCounter = TimeBucketedCounter::new(TimeOfDayLabel::utc());
+
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_static_fn(&REQUESTS, |m, reg| m.register("requests", reg));
+
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        REQUESTS.inc_at(time, 2);
+        REQUESTS.inc_at(time + Duration::from_secs(3 * 3600), 1);
+        assert_eq!(REQUESTS.get("evening").unwrap().load(), 2);
+        assert_eq!(REQUESTS.get("dusk").map(|c| c.load()), None);
+
+        assert_eq!(
+            reg.to_string(),
+            "# HELP requests\n\
+             # TYPE requests counter\n\
+             requests{time_of_day=\"afternoon\"} 0\n\
+             requests{time_of_day=\"evening\"} 2\n\
+             requests{time_of_day=\"morning\"} 0\n\
+             requests{time_of_day=\"night\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn sampled_convergence_test() {
+        const OBSERVATIONS: u64 = 1_000_000;
+
+        let exact = IntHistogram::new([10, 100, 1000]);
+        let sampled = Sampled::new(IntHistogram::new([10, 100, 1000]), 4);
+
+        /* skewed synthetic workload, most values small with a long tail */
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        for _ in 0..OBSERVATIONS {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let value = match state % 100 {
+                0..=59 => state % 10,
+                60..=89 => 10 + state % 90,
+                90..=98 => 100 + state % 900,
+                _ => 1000 + state % 9000,
+            };
+
+            exact.observe(value);
+            Observe::observe(&sampled, value);
+        }
+
+        let close = |estimate: u64, actual: u64| {
+            let error = (estimate as f64 - actual as f64).abs() / actual as f64;
+            assert!(error < 0.05, "estimate {} actual {}", estimate, actual);
+        };
+
+        let estimated = sampled.inner().cumulative_counts();
+        for (estimate, actual) in estimated.iter().zip(exact.cumulative_counts()) {
+            close(estimate * sampled.factor(), actual);
+        }
+        close(sampled.inner().sum() * 4, exact.sum());
+        close(sampled.inner().count() * 4, OBSERVATIONS);
+    }
+
+    #[test]
+    fn sampled_render_test() {
+        static SAMPLED: std::sync::OnceLock<Sampled<IntHistogram>> = std::sync::OnceLock::new();
+        let sampled = SAMPLED.get_or_init(|| Sampled::new(IntHistogram::new([10]), 4));
+
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_static_fn(sampled, |m, reg| {
+            reg.sampled_histogram("packet_size", m);
+        });
+
+        sampled.inner().observe(5);
+        sampled.inner().observe(50);
+
+        assert_eq!(
+            reg.to_string(),
+            "# HELP packet_size\n\
+             # TYPE packet_size histogram\n\
+             packet_size_bucket{le=\"10\"} 4\n\
+             packet_size_bucket{le=\"+Inf\"} 8\n\
+             packet_size_sum 220\n\
+             packet_size_count 8\n\
+             # HELP packet_size_sampling_factor\n\
+             # TYPE packet_size_sampling_factor gauge\n\
+             packet_size_sampling_factor 4\n"
+        );
+    }
+
+    #[test]
+    fn guard_cancel_test() {
+        let met = Arc::new(Met::default());
+
+        let guard = DurationIncUs::new(&met, |m| &m.latency_us);
+        std::thread::sleep(Duration::from_millis(2));
+        guard.cancel();
+        assert_eq!(met.latency_us.load(), 0);
+
+        DurationIncMs::new(&met, |m| &m.calls).cancel();
+        assert_eq!(met.calls.load(), 0);
+
+        let guard = DurationIncUs::new(&met, |m| &m.latency_us);
+        std::thread::sleep(Duration::from_millis(2));
+        let elapsed = guard.finish();
+        assert!(2000 <= elapsed);
+        assert_eq!(met.latency_us.load(), elapsed);
+
+        let first = ActiveGauge::new(&met, |m| &m.active);
+        let second = ActiveGauge::new(&met, |m| &m.active);
+        assert_eq!(met.active.load(), 2);
+        first.release();
+        assert_eq!(met.active.load(), 1);
+        drop(second);
+        assert_eq!(met.active.load(), 0);
+    }
+
+    #[test]
+    fn active_gauge_weight_test() {
+        let met = Arc::new(Met::default());
+
+        let mut buffer = ActiveGauge::new_by(&met, |m| &m.active, 4096);
+        let other = ActiveGauge::new_by(&met, |m| &m.active, 100);
+        assert_eq!(met.active.load(), 4196);
+
+        buffer.adjust(1024);
+        assert_eq!(buffer.amount(), 1024);
+        assert_eq!(met.active.load(), 1124);
+
+        buffer.adjust(2048);
+        assert_eq!(met.active.load(), 2148);
+
+        drop(buffer);
+        assert_eq!(met.active.load(), 100);
+        other.release();
+        assert_eq!(met.active.load(), 0);
+    }
+
+    #[test]
+    fn high_water_gauge_test() {
+        let met = Arc::new(Met::default());
+        let observed = Arc::new(IntGauge::new());
+
+        let threads = (0..8)
+            .map(|_| {
+                let met = met.clone();
+                let observed = observed.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..200 {
+                        let guard = HighWaterGauge::new(&met, |m| &m.active, |m| &m.peak);
+                        observed.set_max(met.active.load());
+                        drop(guard);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(met.active.load(), 0);
+        assert!(observed.load() <= met.peak.load());
+        assert!(met.peak.load() <= 8);
+
+        let first = HighWaterGauge::new(&met, |m| &m.active, |m| &m.peak);
+        met.peak.set_min(0);
+        let second = HighWaterGauge::new(&met, |m| &m.active, |m| &m.peak);
+        first.release();
+        drop(second);
+        assert_eq!(met.peak.load(), 2);
+    }
+}
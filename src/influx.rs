@@ -0,0 +1,254 @@
+/*
+ * InfluxDB line protocol for Telegraf / InfluxDB setups. One line per series with the
+ * attributes and a metric_type tag, values are integer fields (float fields for
+ * FloatCounter / FloatGauge, NaN and infinite values are left out as influx rejects them).
+ * Histograms become one line with sum, count and a field per bucket bound (cumulative,
+ * +Inf last).
+ */
+use std::{
+    borrow::Cow,
+    fmt::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{PromMetricRegistry, SampleValue};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InfluxNaming {
+    /* metric name as measurement, the value in a field named value */
+    PerMetric,
+    /* every series in this measurement, the value in a field named after the metric */
+    Shared(Cow<'static, str>),
+}
+
+impl PromMetricRegistry {
+    pub fn encode_influx<W: Write + ?Sized>(
+        &self,
+        f: &mut W,
+        naming: InfluxNaming,
+    ) -> std::fmt::Result {
+        self.encode_influx_at(f, naming, None)
+    }
+
+    /* every line stamped with timestamp in nanoseconds */
+    pub fn encode_influx_at<W: Write + ?Sized>(
+        &self,
+        f: &mut W,
+        naming: InfluxNaming,
+        timestamp: Option<SystemTime>,
+    ) -> std::fmt::Result {
+        let timestamp = timestamp.map(|timestamp| {
+            timestamp
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_nanos())
+        });
+
+        for family in self.gather() {
+            let (measurement, prefix, value_key) = match &naming {
+                InfluxNaming::PerMetric => (family.name.as_ref(), String::new(), "value"),
+                InfluxNaming::Shared(measurement) => (
+                    measurement.as_ref(),
+                    format!("{}_", family.name),
+                    family.name.as_ref(),
+                ),
+            };
+            let metric_type = family.metric_type.to_string();
+
+            for sample in &family.samples {
+                if let SampleValue::Float(value) = sample.value {
+                    if !value.get().is_finite() {
+                        continue;
+                    }
+                }
+
+                let mut tags = sample
+                    .attributes
+                    .iter()
+                    .map(|[key, value]| (key.as_ref(), value.as_ref()))
+                    .chain(std::iter::once(("metric_type", metric_type.as_str())))
+                    /* influx rejects empty tag values, an empty label is the same as none */
+                    .filter(|(_, value)| !value.is_empty())
+                    .collect::<Vec<_>>();
+                tags.sort_by_key(|(key, _)| *key);
+
+                escape(f, measurement, &[',', ' '])?;
+                for (key, value) in tags {
+                    f.write_char(',')?;
+                    escape(f, key, &[',', '=', ' '])?;
+                    f.write_char('=')?;
+                    escape(f, value, &[',', '=', ' '])?;
+                }
+
+                match &sample.value {
+                    SampleValue::Value(value) => {
+                        f.write_char(' ')?;
+                        field(f, "", value_key, *value)?;
+                    }
+                    SampleValue::Float(value) => {
+                        f.write_char(' ')?;
+                        escape(f, value_key, &[',', '=', ' '])?;
+                        write!(f, "={}", value)?;
+                    }
+                    SampleValue::Histogram {
+                        buckets,
+                        sum,
+                        count,
+                    } => {
+                        f.write_char(' ')?;
+                        field(f, &prefix, "count", *count)?;
+                        f.write_char(',')?;
+                        field(f, &prefix, "sum", *sum)?;
+                        for (le, count) in buckets {
+                            f.write_char(',')?;
+                            field(f, &prefix, &le.to_string(), *count)?;
+                        }
+                        f.write_char(',')?;
+                        field(f, &prefix, "+Inf", *count)?;
+                    }
+                    SampleValue::Summary {
+                        quantiles,
+                        sum,
+                        count,
+                    } => {
+                        f.write_char(' ')?;
+                        field(f, &prefix, "count", *count)?;
+                        f.write_char(',')?;
+                        field(f, &prefix, "sum", *sum)?;
+                        for (quantile, value) in quantiles {
+                            f.write_char(',')?;
+                            field(f, &prefix, &quantile.to_string(), *value)?;
+                        }
+                    }
+                }
+
+                if let Some(timestamp) = timestamp {
+                    write!(f, " {}", timestamp)?;
+                }
+                f.write_char('\n')?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/* integers over i64::MAX need the unsigned type */
+fn field<W: Write + ?Sized>(f: &mut W, prefix: &str, key: &str, value: u64) -> std::fmt::Result {
+    escape(f, prefix, &[',', '=', ' '])?;
+    escape(f, key, &[',', '=', ' '])?;
+    match i64::try_from(value) {
+        Ok(value) => write!(f, "={}i", value),
+        Err(_) => write!(f, "={}u", value),
+    }
+}
+
+fn escape<W: Write + ?Sized>(f: &mut W, value: &str, special: &[char]) -> std::fmt::Result {
+    for c in value.chars() {
+        match c {
+            '\n' => f.write_str("\\n")?,
+            c if special.contains(&c) => {
+                f.write_char('\\')?;
+                f.write_char(c)?;
+            }
+            c => f.write_char(c)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::Arc,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use crate::{IntCounter, IntGauge, IntHistogram, PromMetricRegistry};
+
+    use super::InfluxNaming;
+
+    #[derive(Default)]
+    struct Met {
+        requests: IntCounter,
+        queue: IntGauge,
+        latency: IntHistogram,
+        huge: IntCounter,
+    }
+
+    fn registry() -> (Arc<Met>, PromMetricRegistry) {
+        let met = Arc::new(Met {
+            latency: IntHistogram::new([10]),
+            ..Default::default()
+        });
+        let mut reg = PromMetricRegistry::empty().with_base_attrs([("host", "a")]);
+        reg.register_fn(&met, |m, reg| {
+            reg.count("requests", &m.requests).attr("path", "/a b,c=d");
+            reg.gauge("queue", &m.queue).attr("zone", "");
+            reg.histogram("latency", &m.latency);
+            reg.count("huge", &m.huge);
+        });
+
+        met.requests.inc_by(3);
+        met.queue.set(7);
+        met.latency.observe(5);
+        met.latency.observe(50);
+        met.huge.inc_by(u64::MAX);
+        (met, reg)
+    }
+
+    fn encode(reg: &PromMetricRegistry, naming: InfluxNaming) -> String {
+        let mut out = String::new();
+        reg.encode_influx(&mut out, naming).unwrap();
+        out
+    }
+
+    #[test]
+    fn per_metric_test() {
+        let (_met, reg) = registry();
+        assert_eq!(
+            encode(&reg, InfluxNaming::PerMetric),
+            "huge,host=a,metric_type=counter value=18446744073709551615u\n\
+             latency,host=a,metric_type=histogram count=2i,sum=55i,10=1i,+Inf=2i\n\
+             queue,host=a,metric_type=gauge value=7i\n\
+             requests,host=a,metric_type=counter,path=/a\\ b\\,c\\=d value=3i\n"
+        );
+    }
+
+    #[test]
+    fn shared_test() {
+        let (_met, reg) = registry();
+        let mut out = String::new();
+        let timestamp = UNIX_EPOCH + Duration::from_nanos(1_700_000_000_000_000_001);
+        reg.encode_influx_at(
+            &mut out,
+            InfluxNaming::Shared("app metrics,v1".into()),
+            Some(timestamp),
+        )
+        .unwrap();
+
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[1],
+            "app\\ metrics\\,v1,host=a,metric_type=histogram \
+             latency_count=2i,latency_sum=55i,latency_10=1i,latency_+Inf=2i \
+             1700000000000000001"
+        );
+        assert_eq!(
+            lines[3],
+            "app\\ metrics\\,v1,host=a,metric_type=counter,path=/a\\ b\\,c\\=d \
+             requests=3i 1700000000000000001"
+        );
+    }
+
+    #[test]
+    fn escape_test() {
+        let mut out = String::new();
+        super::escape(&mut out, "a b,c=d\ne", &[',', '=', ' ']).unwrap();
+        assert_eq!(out, "a\\ b\\,c\\=d\\ne");
+
+        /* measurements keep equals signs */
+        out.clear();
+        super::escape(&mut out, "a b,c=d", &[',', ' ']).unwrap();
+        assert_eq!(out, "a\\ b\\,c=d");
+    }
+}
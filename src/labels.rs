@@ -0,0 +1,476 @@
+/*
+ * typed label sets instead of attr("method", "GET") strings. EncodeLabels is accepted by
+ * RegisterHelper::attrs, label sets made of label_enum! fields (label_set!) also have a
+ * finite LabelSpace so CounterVec can pre-create every child and look them up by index.
+ */
+use std::{
+    borrow::Cow,
+    marker::PhantomData,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{escape, IntCounter, RegisterAction};
+
+pub trait EncodeLabels {
+    fn labels(&self) -> Vec<(&'static str, Cow<'static, str>)>;
+}
+
+/* every combination of label values, numbered 0..cardinality() */
+pub trait LabelSpace: EncodeLabels {
+    fn cardinality() -> usize;
+
+    fn index(&self) -> usize;
+
+    fn labels_at(index: usize) -> Vec<(&'static str, Cow<'static, str>)>;
+}
+
+/* struct of label_enum! fields, field names are the label keys */
+#[macro_export]
+macro_rules! label_set {
+    ($(#[$meta:meta])* $vis:vis struct $name:ident { $($field:ident: $ty:ty),+ $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        $vis struct $name {
+            $(pub $field: $ty),+
+        }
+
+        impl $crate::labels::EncodeLabels for $name {
+            fn labels(&self) -> Vec<(&'static str, ::std::borrow::Cow<'static, str>)> {
+                vec![$((
+                    stringify!($field),
+                    ::std::borrow::Cow::Borrowed(
+                        <$ty as $crate::matrix::LabelValues>::VALUES
+                            [$crate::matrix::LabelValues::index(self.$field)],
+                    ),
+                )),+]
+            }
+        }
+
+        impl $crate::labels::LabelSpace for $name {
+            fn cardinality() -> usize {
+                1 $(* <$ty as $crate::matrix::LabelValues>::VALUES.len())+
+            }
+
+            fn index(&self) -> usize {
+                let mut index = 0;
+                $(
+                    index = index * <$ty as $crate::matrix::LabelValues>::VALUES.len()
+                        + $crate::matrix::LabelValues::index(self.$field);
+                )+
+                index
+            }
+
+            fn labels_at(index: usize) -> Vec<(&'static str, ::std::borrow::Cow<'static, str>)> {
+                $crate::labels::decode(
+                    index,
+                    &[$(stringify!($field)),+],
+                    &[$(<$ty as $crate::matrix::LabelValues>::VALUES),+],
+                )
+            }
+        }
+    };
+}
+
+/* mixed radix, the last field varies fastest */
+#[doc(hidden)]
+pub fn decode(
+    mut index: usize,
+    keys: &[&'static str],
+    values: &[&'static [&'static str]],
+) -> Vec<(&'static str, Cow<'static, str>)> {
+    let mut labels = Vec::with_capacity(keys.len());
+    for (key, values) in keys.iter().zip(values).rev() {
+        labels.push((*key, Cow::Borrowed(values[index % values.len()])));
+        index /= values.len();
+    }
+    labels.reverse();
+    labels
+}
+
+/*
+ * a counter per label combination, created up front so with_labels is an index lookup.
+ * With set_ttl children that aren't written for longer than the ttl are zeroed and hidden
+ * until their next with_labels, so short lived label values stop being exported.
+ */
+pub struct CounterVec<L: LabelSpace> {
+    children: Children,
+    _labels: PhantomData<fn(L)>,
+}
+
+/* the untyped part of a CounterVec, referenced by its registered series through Expiry */
+pub(crate) struct Children {
+    counters: Box<[IntCounter]>,
+    /*
+     * seconds since UNIX_EPOCH of the first prune after the last with_labels, WRITTEN until
+     * that prune and 0 while expired. The prune pass is the clock, with_labels doesn't read it
+     */
+    touched: Box<[AtomicU64]>,
+    /* seconds, 0 keeps every child */
+    ttl: AtomicU64,
+}
+
+impl<L: LabelSpace> Default for CounterVec<L> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<L: LabelSpace> CounterVec<L> {
+    /* panics on label names try_new rejects */
+    #[track_caller]
+    pub fn new() -> Self {
+        match Self::try_new() {
+            Ok(vec) => vec,
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    /* label names must pass escape::check_series_label_name */
+    pub fn try_new() -> Result<Self, escape::LabelNameError> {
+        if 0 < L::cardinality() {
+            for (key, _) in L::labels_at(0) {
+                escape::check_series_label_name(key)?;
+            }
+        }
+
+        Ok(CounterVec {
+            children: Children {
+                counters: (0..L::cardinality()).map(|_| IntCounter::new()).collect(),
+                touched: (0..L::cardinality()).map(|_| AtomicU64::new(0)).collect(),
+                ttl: AtomicU64::new(0),
+            },
+            _labels: PhantomData,
+        })
+    }
+
+    pub fn with_labels(&self, labels: L) -> &IntCounter {
+        let index = labels.index();
+        if self.children.ttl.load(Ordering::Relaxed) != 0 {
+            self.children.touched[index].store(WRITTEN, Ordering::Release);
+        }
+        &self.children.counters[index]
+    }
+
+    /*
+     * rounded up to whole seconds, Duration::ZERO turns expiry off again. Children with a
+     * value count as touched now, the others stay hidden until written.
+     */
+    pub fn set_ttl(&self, ttl: Duration) {
+        let secs = ttl.as_secs() + (ttl.subsec_nanos() != 0) as u64;
+        let now = unix_secs(SystemTime::now());
+        for (counter, touched) in self.children.counters.iter().zip(&*self.children.touched) {
+            if counter.0.load(Ordering::Relaxed) != 0 {
+                touched.store(now, Ordering::Relaxed);
+            }
+        }
+        self.children.ttl.store(secs, Ordering::Relaxed);
+    }
+
+    /* zeroes and hides children idle for longer than the ttl, returns how many expired */
+    pub fn prune_expired(&self, now: SystemTime) -> usize {
+        let now = unix_secs(now);
+        (0..self.children.counters.len())
+            .filter(|index| self.children.expire(*index, now))
+            .count()
+    }
+
+    pub fn register(&'static self, name: &'static str, register: &mut RegisterAction) {
+        for (index, counter) in self.children.counters.iter().enumerate() {
+            register
+                .count(name, counter)
+                .attrs(&LabelsAt::<L>(index, PhantomData))
+                .expires(Expiry {
+                    children: &self.children,
+                    index,
+                });
+        }
+    }
+}
+
+/* written since the last prune, which replaces it with its time */
+const WRITTEN: u64 = u64::MAX;
+
+impl Children {
+    fn expire(&self, index: usize, now: u64) -> bool {
+        let ttl = self.ttl.load(Ordering::Relaxed);
+        let touched = self.touched[index].load(Ordering::Acquire);
+        if ttl == 0 || touched == 0 {
+            return false;
+        }
+
+        if touched == WRITTEN {
+            /* a with_labels racing this keeps WRITTEN for the next prune */
+            let _ = self.touched[index].compare_exchange(
+                WRITTEN,
+                now,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            );
+            return false;
+        }
+
+        if now.saturating_sub(touched) <= ttl {
+            return false;
+        }
+
+        /*
+         * zeroed before the child is hidden, so an increment after the swap lands on the new
+         * series. Lost to a concurrent with_labels the child stays and gets its value back
+         */
+        let value = self.counters[index].0.swap(0, Ordering::AcqRel);
+        if self.touched[index]
+            .compare_exchange(touched, 0, Ordering::AcqRel, Ordering::Relaxed)
+            .is_err()
+        {
+            self.counters[index].0.fetch_add(value, Ordering::AcqRel);
+            return false;
+        }
+        true
+    }
+}
+
+/* a registered CounterVec child, pruned and checked by the registry on every render */
+#[derive(Clone, Copy)]
+pub(crate) struct Expiry {
+    children: &'static Children,
+    index: usize,
+}
+
+impl Expiry {
+    pub(crate) fn prune(&self, now: u64) {
+        self.children.expire(self.index, now);
+    }
+
+    pub(crate) fn is_live(&self) -> bool {
+        self.children.ttl.load(Ordering::Relaxed) == 0
+            || self.children.touched[self.index].load(Ordering::Relaxed) != 0
+    }
+}
+
+/* never 0, that marks an expired child */
+pub(crate) fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+        .max(1)
+}
+
+struct LabelsAt<L>(usize, PhantomData<L>);
+
+impl<L: LabelSpace> EncodeLabels for LabelsAt<L> {
+    fn labels(&self) -> Vec<(&'static str, Cow<'static, str>)> {
+        L::labels_at(self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        borrow::Cow,
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use crate::{label_enum, PromMetricRegistry};
+
+    use super::{CounterVec, EncodeLabels, LabelSpace};
+
+    label_enum!(enum Method {
+        Get => "GET",
+        Post => "POST",
+    });
+
+    label_enum!(enum StatusClass {
+        Ok => "2xx",
+        ClientError => "4xx",
+        ServerError => "5xx",
+    });
+
+    label_set!(
+        struct RequestLabels {
+            method: Method,
+            status: StatusClass,
+        }
+    );
+
+    label_set!(
+        struct Bucketed {
+            le: Method,
+        }
+    );
+
+    /* label values that aren't a fixed set implement EncodeLabels by hand */
+    struct Upstream {
+        host: String,
+        status: u16,
+    }
+
+    impl EncodeLabels for Upstream {
+        fn labels(&self) -> Vec<(&'static str, Cow<'static, str>)> {
+            vec![
+                ("host", self.host.clone().into()),
+                ("status", self.status.to_string().into()),
+            ]
+        }
+    }
+
+    #[test]
+    fn label_space_test() {
+        assert_eq!(RequestLabels::cardinality(), 6);
+
+        for index in 0..RequestLabels::cardinality() {
+            let labels = RequestLabels::labels_at(index);
+            assert_eq!(labels.len(), 2);
+            assert_eq!(labels[0].0, "method");
+        }
+
+        let labels = RequestLabels {
+            method: Method::Post,
+            status: StatusClass::ClientError,
+        };
+        assert_eq!(labels.index(), 4);
+        assert_eq!(labels.labels(), RequestLabels::labels_at(4));
+        assert_eq!(
+            labels.labels(),
+            [("method", "POST".into()), ("status", "4xx".into())]
+        );
+    }
+
+    #[test]
+    fn reserved_label_test() {
+        assert_eq!(
+            CounterVec::<Bucketed>::try_new()
+                .err()
+                .map(|e| e.to_string()),
+            Some("label name \"le\" is reserved".to_string())
+        );
+    }
+
+    #[test]
+    fn max_series_test() {
+        let requests = Arc::new(CounterVec::<RequestLabels>::new());
+        let mut reg = PromMetricRegistry::empty();
+        reg.max_series(4);
+        reg.register_fn(&requests, |requests, reg| {
+            requests.register("requests", reg)
+        });
+
+        /* every child takes a slot */
+        let out = reg.to_string();
+        assert_eq!(out.matches("\nrequests{").count(), 4);
+        assert!(out.contains("arc_metrics_series_rejected_total 2\n"));
+    }
+
+    #[test]
+    fn counter_vec_test() {
+        let requests = Arc::new(CounterVec::<RequestLabels>::new());
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&requests, |requests, reg| {
+            requests.register("requests", reg)
+        });
+
+        requests
+            .with_labels(RequestLabels {
+                method: Method::Get,
+                status: StatusClass::Ok,
+            })
+            .inc_by(3);
+        requests
+            .with_labels(RequestLabels {
+                method: Method::Post,
+                status: StatusClass::ServerError,
+            })
+            .inc();
+
+        assert_eq!(
+            reg.to_string(),
+            "# HELP requests\n\
+             # TYPE requests counter\n\
+             requests{method=\"GET\",status=\"2xx\"} 3\n\
+             requests{method=\"GET\",status=\"4xx\"} 0\n\
+             requests{method=\"GET\",status=\"5xx\"} 0\n\
+             requests{method=\"POST\",status=\"2xx\"} 0\n\
+             requests{method=\"POST\",status=\"4xx\"} 0\n\
+             requests{method=\"POST\",status=\"5xx\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn ttl_test() {
+        let requests = Arc::new(CounterVec::<RequestLabels>::new());
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&requests, |requests, reg| {
+            requests.register("requests", reg)
+        });
+
+        let get = RequestLabels {
+            method: Method::Get,
+            status: StatusClass::Ok,
+        };
+        let post = RequestLabels {
+            method: Method::Post,
+            status: StatusClass::Ok,
+        };
+        requests.with_labels(get).inc_by(2);
+        requests.set_ttl(Duration::from_secs(60));
+        requests.with_labels(post).inc();
+
+        /* untouched children are hidden once a ttl is set */
+        assert_eq!(
+            reg.to_string(),
+            "# HELP requests\n\
+             # TYPE requests counter\n\
+             requests{method=\"GET\",status=\"2xx\"} 2\n\
+             requests{method=\"POST\",status=\"2xx\"} 1\n"
+        );
+
+        let now = SystemTime::now();
+        assert_eq!(requests.prune_expired(now + Duration::from_secs(30)), 0);
+        assert_eq!(requests.prune_expired(now + Duration::from_secs(120)), 2);
+        assert_eq!(requests.prune_expired(now + Duration::from_secs(180)), 0);
+        assert_eq!(reg.to_string(), "");
+
+        /* recreated from zero */
+        requests.with_labels(post).inc();
+        assert_eq!(
+            reg.to_string(),
+            "# HELP requests\n\
+             # TYPE requests counter\n\
+             requests{method=\"POST\",status=\"2xx\"} 1\n"
+        );
+
+        /* writes are stamped by the next prune, not by with_labels */
+        requests.with_labels(post).inc();
+        assert_eq!(requests.prune_expired(now + Duration::from_secs(200)), 0);
+        assert_eq!(requests.prune_expired(now + Duration::from_secs(250)), 0);
+        assert_eq!(requests.prune_expired(now + Duration::from_secs(300)), 1);
+
+        requests.set_ttl(Duration::ZERO);
+        assert_eq!(requests.prune_expired(now + Duration::from_secs(600)), 0);
+        assert_eq!(reg.to_string().lines().count(), 8);
+    }
+
+    #[test]
+    fn attrs_test() {
+        struct Met {
+            upstream: crate::IntCounter,
+        }
+
+        let met = Arc::new(Met {
+            upstream: crate::IntCounter::new(),
+        });
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&met, |met, reg| {
+            reg.count("upstream_requests", &met.upstream)
+                .attrs(&Upstream {
+                    host: "db".to_string(),
+                    status: 503,
+                });
+        });
+
+        met.upstream.inc();
+        assert!(reg
+            .to_string()
+            .contains("upstream_requests{host=\"db\",status=\"503\"} 1\n"));
+    }
+}
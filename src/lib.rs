@@ -1,23 +1,147 @@
-use std::{
-    any::Any,
-    borrow::Cow,
+/*
+ * the metric primitives and the helpers guards only need core and alloc, the registry and
+ * everything that renders, exports or registers is behind the default std feature
+ */
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::{
     fmt::Display,
     ops::Deref,
-    sync::{
-        atomic::{AtomicU64, Ordering},
-        Arc,
-    },
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
 };
 
-use helpers::RegisterableMetric;
-
-#[derive(Default, Debug)]
+#[cfg(feature = "std")]
+pub use builder::PromMetricRegistryBuilder;
+pub use float::{FloatCounter, FloatGauge, FloatValue};
+#[cfg(feature = "std")]
+pub use global::{default_registry, register_default, render_default};
+pub use padded::{CachePadded, PaddedIntCounter, PaddedIntGauge};
+#[cfg(feature = "std")]
+pub use registry::*;
+#[cfg(feature = "std")]
+pub use registry_set::RegistrySet;
+#[cfg(feature = "serve")]
+pub use serve::{serve_std, ServerHandle};
+#[cfg(feature = "std")]
+pub use sharded::ShardedCounter;
+#[cfg(feature = "std")]
+pub use summary::{Quantile, Summary};
+
+#[derive(Debug)]
 pub struct IntCounter(pub AtomicU64);
 
-#[derive(Default, Debug)]
+#[derive(Debug)]
 pub struct IntGauge(pub AtomicU64);
 
+#[derive(Debug)]
+pub struct IntHistogram {
+    /* inclusive upper bounds, final +Inf bucket is implicit */
+    bounds: Box<[u64]>,
+    buckets: Box<[AtomicU64]>,
+    /*
+     * integer sum updated with a single fetch_add, so it is wait-free and exact under
+     * contention (no CAS retry loop like an f64 sum would need). Precision is whatever
+     * unit the caller observes in (ex. DurationUnit), render converts nothing.
+     */
+    sum: AtomicU64,
+    count: AtomicU64,
+}
+
+#[cfg(feature = "std")]
+mod attributes;
+#[cfg(all(feature = "blackbox", unix, target_pointer_width = "64"))]
+pub mod blackbox;
+#[cfg(feature = "bridge")]
+mod bridge;
+pub mod buckets;
+#[cfg(feature = "std")]
+mod builder;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+mod declare;
+#[cfg(feature = "std")]
+pub mod escape;
+#[cfg(feature = "std")]
+pub mod exemplar;
+#[cfg(feature = "std")]
+pub mod export;
+#[cfg(feature = "std")]
+pub mod filter;
+#[cfg(feature = "std")]
+mod flat;
+mod float;
+#[cfg(feature = "std")]
+mod global;
+#[cfg(feature = "graphite")]
+pub mod graphite;
 pub mod helpers;
+#[cfg(feature = "std")]
+pub mod influx;
+#[cfg(feature = "std")]
+pub mod labels;
+#[cfg(feature = "std")]
+pub mod lost;
+#[cfg(feature = "std")]
+pub mod matrix;
+#[cfg(feature = "std")]
+pub mod namespace;
+#[cfg(feature = "std")]
+mod openmetrics;
+#[cfg(feature = "otel")]
+pub mod otel;
+#[cfg(feature = "otel-bridge")]
+pub mod otel_bridge;
+mod padded;
+#[cfg(feature = "std")]
+pub mod policy;
+#[cfg(feature = "push")]
+pub mod push;
+#[cfg(feature = "std")]
+mod registry;
+#[cfg(feature = "std")]
+mod registry_set;
+#[cfg(feature = "remote-write")]
+pub mod remote_write;
+#[cfg(feature = "std")]
+mod render_cache;
+#[cfg(feature = "std")]
+pub mod scrape;
+#[cfg(feature = "std")]
+mod self_metrics;
+#[cfg(feature = "serve")]
+mod serve;
+#[cfg(feature = "std")]
+mod sharded;
+#[cfg(feature = "std")]
+pub mod snapshot;
+#[cfg(feature = "statsd")]
+pub mod statsd;
+#[cfg(feature = "std")]
+mod summary;
+#[cfg(feature = "test-util")]
+pub mod testing;
+#[cfg(any(
+    feature = "push",
+    feature = "remote-write",
+    feature = "statsd",
+    feature = "test-util"
+))]
+pub mod transport;
+#[cfg(feature = "std")]
+pub mod units;
+
+/*
+ * anything recording a u64: counters add it, histograms bucket it. Duration helpers are
+ * generic over it so a metric can switch type without touching call sites.
+ */
+pub trait Observe {
+    fn observe(&self, value: u64);
+}
 
 pub struct ChildMetric<T, C: 'static> {
     arc: Arc<T>,
@@ -27,6 +151,7 @@ pub struct ChildMetric<T, C: 'static> {
 impl<T, C: 'static> Deref for ChildMetric<T, C> {
     type Target = C;
 
+    #[inline(always)]
     fn deref(&self) -> &Self::Target {
         self.child
     }
@@ -41,418 +166,504 @@ impl<T: 'static, C: 'static> Clone for ChildMetric<T, C> {
     }
 }
 
+/* references handed to get stay valid as long as the returned Arc is held */
+fn project<T: 'static, R, F: FnOnce(&'static T) -> R>(arc: &Arc<T>, get: F) -> (Arc<T>, R) {
+    let cloned = arc.clone();
+    let item = get(unsafe { core::mem::transmute::<&T, &'static T>(&cloned) });
+    (cloned, item)
+}
+
 impl<T: 'static, C: 'static> ChildMetric<T, C> {
     pub fn create<F: Fn(&'static T) -> &'static C>(arc: &Arc<T>, get: F) -> Self {
-        let cloned = arc.clone();
-        let item = get(unsafe { std::mem::transmute::<&T, &'static T>(&cloned) });
+        let (arc, child) = project(arc, get);
+        Self { arc, child }
+    }
+
+    /* None for an absent child, ex. |m| m.optional.as_ref(), the Arc is only cloned if present */
+    pub fn try_create<F: Fn(&'static T) -> Option<&'static C>>(
+        arc: &Arc<T>,
+        get: F,
+    ) -> Option<Self> {
+        /* valid while get runs as the caller holds arc, and after through the clone */
+        let child = get(unsafe { &*Arc::as_ptr(arc) })?;
+        Some(Self {
+            arc: arc.clone(),
+            child,
+        })
+    }
+
+    /* follows an inner Arc field, the child keeps the inner Arc alive */
+    pub fn create_nested<O, P, F>(outer: &O, inner: P, get: F) -> Self
+    where
+        P: Fn(&O) -> &Arc<T>,
+        F: Fn(&'static T) -> &'static C,
+    {
+        Self::create(inner(outer), get)
+    }
+
+    /* another child of the same owner, ex. a sibling counter inside a drop guard */
+    pub fn map<D: 'static, F: Fn(&'static T) -> &'static D>(&self, get: F) -> ChildMetric<T, D> {
+        ChildMetric::create(&self.arc, get)
+    }
+}
+
+impl<T, C: 'static> ChildMetric<T, C> {
+    /* the struct the child was projected from */
+    pub fn owner(&self) -> &T {
+        &self.arc
+    }
+
+    pub fn into_arc(self) -> Arc<T> {
+        self.arc
+    }
+}
+
+pub struct ChildMetrics2<T, A: 'static, B: 'static> {
+    arc: Arc<T>,
+    first: &'static A,
+    second: &'static B,
+}
+
+impl<T, A: 'static, B: 'static> Clone for ChildMetrics2<T, A, B> {
+    fn clone(&self) -> Self {
         Self {
-            arc: cloned,
-            child: item,
+            arc: self.arc.clone(),
+            first: self.first,
+            second: self.second,
         }
     }
 }
 
+impl<T: 'static, A: 'static, B: 'static> ChildMetrics2<T, A, B> {
+    pub fn create<F: Fn(&'static T) -> (&'static A, &'static B)>(arc: &Arc<T>, get: F) -> Self {
+        let (arc, (first, second)) = project(arc, get);
+        Self { arc, first, second }
+    }
+}
+
+impl<T, A: 'static, B: 'static> ChildMetrics2<T, A, B> {
+    pub fn owner(&self) -> &T {
+        &self.arc
+    }
+
+    pub fn first(&self) -> &A {
+        self.first
+    }
+
+    pub fn second(&self) -> &B {
+        self.second
+    }
+}
+
+impl Default for IntCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl IntCounter {
+    #[inline]
+    pub const fn new() -> Self {
+        IntCounter(AtomicU64::new(0))
+    }
+
+    #[inline]
+    pub const fn with_value(value: u64) -> Self {
+        IntCounter(AtomicU64::new(value))
+    }
+
+    #[inline]
     pub fn owned_inc(&self) {
         self.owned_inc_by(1);
     }
 
+    #[inline]
     pub fn inc(&self) {
         self.shared_inc();
     }
 
+    #[inline]
     pub fn inc_by(&self, amount: u64) {
         self.shared_inc_by(amount);
     }
 
+    #[inline]
     pub fn shared_inc(&self) {
         self.shared_inc_by(1);
     }
 
+    #[inline]
     pub fn owned_inc_by(&self, amount: u64) {
         self.0.fetch_add(amount, Ordering::Relaxed);
     }
 
+    #[inline]
     pub fn shared_inc_by(&self, amount: u64) {
         self.0.fetch_add(amount, Ordering::AcqRel);
     }
 
+    #[inline]
     pub fn load(&self) -> u64 {
         self.shared_load()
     }
 
+    #[inline]
     pub fn shared_load(&self) -> u64 {
         self.0.load(Ordering::Acquire)
     }
 
+    #[inline]
     pub fn owned_load(&self) -> u64 {
         self.0.load(Ordering::Relaxed)
     }
+
+    /* zeroes the counter returning the previous value, racing increments land in the next take */
+    #[inline]
+    pub fn take(&self) -> u64 {
+        self.0.swap(0, Ordering::AcqRel)
+    }
+
+    #[inline]
+    pub fn reset(&self) -> u64 {
+        self.take()
+    }
+
+    /* whole milliseconds, saturating instead of truncating the u128 */
+    #[inline]
+    pub fn inc_by_millis(&self, duration: Duration) {
+        self.inc_by(helpers::DurationUnit::Millis.convert(duration));
+    }
+
+    #[inline]
+    pub fn inc_by_micros(&self, duration: Duration) {
+        self.inc_by(helpers::DurationUnit::Micros.convert(duration));
+    }
+
+    /* a CAS loop instead of fetch_add, leaves the counter untouched if it would wrap */
+    #[inline]
+    pub fn checked_inc_by(&self, amount: u64) -> Result<(), CounterOverflow> {
+        self.0
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                current.checked_add(amount)
+            })
+            .map(|_| ())
+            .map_err(|current| CounterOverflow { current, amount })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CounterOverflow {
+    pub current: u64,
+    pub amount: u64,
+}
+
+impl Display for CounterOverflow {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "counter at {} would overflow adding {}",
+            self.current, self.amount
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CounterOverflow {}
+
+impl Default for IntGauge {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl IntGauge {
+    #[inline]
+    pub const fn new() -> Self {
+        IntGauge(AtomicU64::new(0))
+    }
+
+    /* ex. a capacity gauge starting at the configured size */
+    #[inline]
+    pub const fn with_value(value: u64) -> Self {
+        IntGauge(AtomicU64::new(value))
+    }
+
+    #[inline]
     pub fn set(&self, value: u64) {
         self.0.store(value, Ordering::Relaxed);
     }
 
+    #[inline]
+    #[track_caller]
     pub fn owned_dec(&self) {
         self.owned_dec_by(1);
     }
 
+    #[inline]
+    #[track_caller]
     pub fn dec(&self) {
         self.shared_dec();
     }
 
+    #[inline]
+    #[track_caller]
     pub fn shared_dec(&self) {
         self.shared_dec_by(1);
     }
 
+    /* panics below zero in debug builds or with the strict feature, wraps otherwise */
+    #[inline]
+    #[track_caller]
     pub fn owned_dec_by(&self, amount: u64) {
-        self.0.fetch_sub(amount, Ordering::Relaxed);
+        let previous = self.0.fetch_sub(amount, Ordering::Relaxed);
+        if cfg!(any(debug_assertions, feature = "strict")) && previous < amount {
+            gauge_underflow(previous, amount);
+        }
     }
 
+    #[inline]
+    #[track_caller]
     pub fn shared_dec_by(&self, amount: u64) {
-        self.0.fetch_sub(amount, Ordering::AcqRel);
+        let previous = self.0.fetch_sub(amount, Ordering::AcqRel);
+        if cfg!(any(debug_assertions, feature = "strict")) && previous < amount {
+            gauge_underflow(previous, amount);
+        }
+    }
+
+    /* a CAS loop that leaves the gauge untouched and returns false instead of going below 0 */
+    #[inline]
+    pub fn try_dec_by(&self, amount: u64) -> bool {
+        self.0
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                current.checked_sub(amount)
+            })
+            .is_ok()
     }
 
+    #[inline]
     pub fn inc(&self) {
         self.shared_inc();
     }
 
+    #[inline]
     pub fn shared_inc(&self) {
         self.shared_inc_by(1);
     }
 
+    #[inline]
     pub fn owned_inc_by(&self, amount: u64) {
         self.0.fetch_add(amount, Ordering::Relaxed);
     }
 
+    #[inline]
     pub fn shared_inc_by(&self, amount: u64) {
         self.0.fetch_add(amount, Ordering::AcqRel);
     }
 
+    #[inline]
     pub fn load(&self) -> u64 {
         self.shared_load()
     }
 
+    #[inline]
     pub fn shared_load(&self) -> u64 {
         self.0.load(Ordering::Acquire)
     }
 
+    #[inline]
     pub fn owned_load(&self) -> u64 {
         self.0.load(Ordering::Relaxed)
     }
-}
-
-pub struct PromMetricRegistry {
-    /* note: keep reference to Arc to ensure it doesn't drop */
-    metric_holders: Vec<Arc<dyn Any>>,
-    metrics: Vec<RegisteredMetric>,
-    base_attributes: Vec<[Cow<'static, str>; 2]>,
-}
 
-impl Default for PromMetricRegistry {
-    fn default() -> Self {
-        let base_attributes = if let Some(details) = pkg_details::try_get() {
-            vec![
-                [Cow::Borrowed("program"), Cow::Borrowed(details.pkg_name)],
-                [
-                    Cow::Borrowed("pkg_version"),
-                    Cow::Borrowed(details.pkg_version),
-                ],
-            ]
-        } else {
-            Vec::new()
-        };
-
-        PromMetricRegistry {
-            metric_holders: Vec::new(),
-            metrics: Vec::new(),
-            base_attributes,
-        }
+    #[inline]
+    pub fn swap(&self, value: u64) -> u64 {
+        self.0.swap(value, Ordering::AcqRel)
     }
-}
 
-unsafe impl Send for PromMetricRegistry {}
-unsafe impl Sync for PromMetricRegistry {}
-
-struct RegisteredMetric {
-    metric_type: MetricType,
-    name: Cow<'static, str>,
-    value: &'static AtomicU64,
-    attributes: Vec<[Cow<'static, str>; 2]>,
-    skip_zero: bool,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub enum MetricType {
-    IntCounter,
-    IntGauge,
-}
-
-impl Display for MetricType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::IntCounter => write!(f, "counter"),
-            Self::IntGauge => write!(f, "gauge"),
-        }
+    /* keeps the larger of the current and given value, returns the previous value */
+    #[inline]
+    pub fn set_max(&self, value: u64) -> u64 {
+        self.0.fetch_max(value, Ordering::AcqRel)
     }
-}
 
-impl Display for PromMetricRegistry {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut last = None;
-
-        for metric in &self.metrics {
-            let matches = if let Some((last, ty)) = &last {
-                last == &metric.name && *ty == metric.metric_type
-            } else {
-                false
-            };
-
-            if metric.skip_zero && metric.value.load(Ordering::Relaxed) == 0 {
-                continue;
-            }
-
-            if !matches {
-                writeln!(f, "# HELP {}", metric.name)?;
-                writeln!(f, "# TYPE {} {}", metric.name, metric.metric_type)?;
-                last = Some((metric.name.clone(), metric.metric_type));
-            }
-
-            write!(f, "{}", metric.name)?;
-            let end = metric.attributes.len();
-            for (i, [key, value]) in metric.attributes.iter().enumerate() {
-                if i == 0 {
-                    write!(f, "{{{}=\"{}\"", key, value)?;
-                    if end == 1 {
-                        write!(f, "}}")?;
-                    }
-                } else if i + 1 == end {
-                    write!(f, ",{}=\"{}\"}}", key, value)?;
-                } else {
-                    write!(f, ",{}=\"{}\"", key, value)?;
-                }
-            }
-
-            writeln!(f, " {}", metric.value.load(Ordering::Relaxed))?;
-        }
-
-        Ok(())
+    #[inline]
+    pub fn set_min(&self, value: u64) -> u64 {
+        self.0.fetch_min(value, Ordering::AcqRel)
     }
-}
 
-impl PromMetricRegistry {
-    pub fn new() -> Self {
-        Self::default()
+    /* single fetch_add of the two's complement delta, wraps at the u64 boundary */
+    #[inline]
+    pub fn add(&self, delta: i64) {
+        self.0.fetch_add(delta as u64, Ordering::AcqRel);
     }
 
-    pub fn register<M: RegisterableMetric + 'static>(&mut self, metrics: &Arc<M>) {
-        self.register_fn(metrics, |m, reg| {
-            m.register(reg);
-        });
+    /* leaves the gauge untouched if the result would go below 0 (or past u64::MAX) */
+    #[inline]
+    pub fn checked_add(&self, delta: i64) -> Result<(), GaugeUnderflow> {
+        self.0
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                current.checked_add_signed(delta)
+            })
+            .map(|_| ())
+            .map_err(|current| GaugeUnderflow { current, delta })
     }
+}
 
-    pub fn register_fn<'a, T: 'static>(
-        &'a mut self,
-        metrics: &Arc<T>,
-        register: impl FnOnce(&'static T, &mut RegisterAction<'a>),
-    ) {
-        /* allows us to keep static references as we own an Arc copy */
-        self.metric_holders
-            .push(Arc::clone(metrics) as Arc<dyn Any>);
+#[cold]
+#[inline(never)]
+#[track_caller]
+fn gauge_underflow(current: u64, amount: u64) -> ! {
+    panic!("gauge at {} decremented by {}", current, amount);
+}
 
-        let mut action = RegisterAction {
-            name_prefix: None,
-            metrics: &mut self.metrics,
-            base_attributes: self.base_attributes.clone(),
-        };
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GaugeUnderflow {
+    pub current: u64,
+    pub delta: i64,
+}
 
-        let metric_ref = unsafe { std::mem::transmute::<&T, &'static T>(metrics) };
-        register(metric_ref, &mut action);
+impl Display for GaugeUnderflow {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "gauge at {} can't change by {}",
+            self.current, self.delta
+        )
     }
 }
 
-pub struct RegisterAction<'a> {
-    metrics: &'a mut Vec<RegisteredMetric>,
-    name_prefix: Option<String>,
-    base_attributes: Vec<[Cow<'static, str>; 2]>,
-}
+#[cfg(feature = "std")]
+impl std::error::Error for GaugeUnderflow {}
+
+impl IntHistogram {
+    pub const DEFAULT_BUCKETS: &'static [u64] =
+        &[1, 2, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
 
-impl RegisterAction<'_> {
-    pub fn child(&mut self) -> RegisterAction<'_> {
-        RegisterAction {
-            metrics: self.metrics,
-            name_prefix: self.name_prefix.clone(),
-            base_attributes: self.base_attributes.clone(),
+    /* panics on bounds try_new rejects */
+    pub fn new<B: Into<Vec<u64>>>(bounds: B) -> Self {
+        match Self::try_new(bounds) {
+            Ok(histogram) => histogram,
+            Err(error) => invalid_bounds(error),
         }
     }
 
-    pub fn name_prefix<S: Into<String>>(&mut self, prefix: S) -> &mut Self {
-        self.name_prefix = Some(prefix.into());
-        self
-    }
+    /* bounds must be non-empty and strictly increasing, ex. from the buckets module */
+    pub fn try_new<B: Into<Vec<u64>>>(bounds: B) -> Result<Self, buckets::BucketError> {
+        let bounds = bounds.into();
+        buckets::validate(&bounds)?;
 
-    pub fn base_attr<K: Into<Cow<'static, str>>, V: Into<Cow<'static, str>>>(
-        &mut self,
-        key: K,
-        value: V,
-    ) -> &mut Self {
-        let key = key.into();
-        let value = value.into();
-        self.base_attributes.push([key, value]);
-        self
+        Ok(IntHistogram {
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            bounds: bounds.into_boxed_slice(),
+            sum: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        })
     }
 
-    pub fn count<N: Into<Cow<'static, str>>>(
-        &mut self,
-        name: N,
-        count: &'static IntCounter,
-    ) -> RegisterHelper<'_> {
-        self.metric(name, &count.0, MetricType::IntCounter)
+    #[inline]
+    pub fn observe(&self, value: u64) {
+        let bucket = self.bounds.partition_point(|bound| *bound < value);
+        self.buckets[bucket].fetch_add(1, Ordering::AcqRel);
+        self.sum.fetch_add(value, Ordering::AcqRel);
+        self.count.fetch_add(1, Ordering::AcqRel);
     }
 
-    pub fn gauge<N: Into<Cow<'static, str>>>(
-        &mut self,
-        name: N,
-        gauge: &'static IntGauge,
-    ) -> RegisterHelper<'_> {
-        self.metric(name, &gauge.0, MetricType::IntGauge)
+    pub fn bounds(&self) -> &[u64] {
+        &self.bounds
     }
 
-    fn metric<N: Into<Cow<'static, str>>>(
-        &mut self,
-        name: N,
-        value: &'static AtomicU64,
-        metric_type: MetricType,
-    ) -> RegisterHelper<'_> {
-        let mut helper = self.empty();
-        helper.metric(name, value, metric_type);
-        helper
+    /* cumulative count of observations <= each bound, last entry is the +Inf bucket */
+    pub fn cumulative_counts(&self) -> Vec<u64> {
+        let mut total = 0;
+        self.buckets
+            .iter()
+            .map(|bucket| {
+                total += bucket.load(Ordering::Acquire);
+                total
+            })
+            .collect()
     }
 
-    pub fn group<N: Into<Cow<'static, str>>>(&mut self, prefix: N) -> RegisterHelper<'_> {
-        self.start(Some(prefix))
+    pub fn sum(&self) -> u64 {
+        self.sum.load(Ordering::Acquire)
     }
 
-    pub fn empty(&mut self) -> RegisterHelper<'_> {
-        self.start::<String>(None)
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Acquire)
     }
 
-    fn start<N: Into<Cow<'static, str>>>(&mut self, prefix: Option<N>) -> RegisterHelper<'_> {
-        let attributes = self.base_attributes.clone();
-
-        let name_prefix = match (&self.name_prefix, prefix) {
-            (Some(prefix), None) => Some(Cow::Owned(prefix.clone())),
-            (None, Some(prefix)) => Some(prefix.into()),
-            (Some(a), Some(b)) => {
-                let b = b.into();
-                Some(Cow::Owned(format!("{}_{}", a, b)))
-            }
-            (None, None) => None,
-        };
+    /* like cumulative_counts while zeroing everything as take() does, returns (counts, sum) */
+    #[cfg(feature = "std")]
+    fn take_cumulative(&self) -> (Vec<u64>, u64) {
+        let mut total = 0;
+        let counts = self
+            .buckets
+            .iter()
+            .map(|bucket| {
+                total += bucket.swap(0, Ordering::AcqRel);
+                total
+            })
+            .collect();
+        self.count.swap(0, Ordering::AcqRel);
+        (counts, self.sum.swap(0, Ordering::AcqRel))
+    }
 
-        RegisterHelper {
-            metrics: self.metrics,
-            name_prefix,
-            attributes,
-            registered: Vec::new(),
+    /* zeroes buckets, sum and count returning (sum, count); not atomic as a whole */
+    pub fn take(&self) -> (u64, u64) {
+        for bucket in self.buckets.iter() {
+            bucket.swap(0, Ordering::AcqRel);
         }
+        let sum = self.sum.swap(0, Ordering::AcqRel);
+        let count = self.count.swap(0, Ordering::AcqRel);
+        (sum, count)
+    }
+}
+
+impl Observe for IntCounter {
+    #[inline]
+    fn observe(&self, value: u64) {
+        self.shared_inc_by(value);
     }
 }
 
-pub struct RegisterHelper<'a> {
-    name_prefix: Option<Cow<'static, str>>,
-    metrics: &'a mut Vec<RegisteredMetric>,
-    attributes: Vec<[Cow<'static, str>; 2]>,
-    registered: Vec<RegisteredMetric>,
+#[cfg(feature = "std")]
+impl Observe for ShardedCounter {
+    #[inline]
+    fn observe(&self, value: u64) {
+        self.inc_by(value);
+    }
 }
 
-impl RegisterHelper<'_> {
-    pub fn attr<K: Into<Cow<'static, str>>, V: Into<Cow<'static, str>>>(
-        &mut self,
-        key: K,
-        value: V,
-    ) -> &mut Self {
-        let key = key.into();
-        let value = value.into();
-        self.attributes.push([key, value]);
-        self
-    }
-
-    pub fn count<N: Into<Cow<'static, str>>>(
-        &mut self,
-        name: N,
-        count: &'static IntCounter,
-    ) -> &mut Self {
-        self.metric(name, &count.0, MetricType::IntCounter)
-    }
-
-    pub fn gauge<N: Into<Cow<'static, str>>>(
-        &mut self,
-        name: N,
-        gauge: &'static IntGauge,
-    ) -> &mut Self {
-        self.metric(name, &gauge.0, MetricType::IntGauge)
-    }
-
-    pub fn metric<N: Into<Cow<'static, str>>>(
-        &mut self,
-        name: N,
-        value: &'static AtomicU64,
-        metric_type: MetricType,
-    ) -> &mut Self {
-        self.metric_opt(name, value, metric_type, false)
-    }
-
-    pub fn metric_opt<N: Into<Cow<'static, str>>>(
-        &mut self,
-        name: N,
-        value: &'static AtomicU64,
-        metric_type: MetricType,
-        skip_zero: bool,
-    ) -> &mut Self {
-        let name = match &self.name_prefix {
-            Some(prefix) => Cow::Owned(format!("{}_{}", prefix, name.into())),
-            None => name.into(),
-        };
-
-        self.registered.push(RegisteredMetric {
-            metric_type,
-            name,
-            value,
-            attributes: Vec::new(),
-            skip_zero,
-        });
-
-        self
+impl<T, C: Observe + 'static> Observe for ChildMetric<T, C> {
+    #[inline]
+    fn observe(&self, value: u64) {
+        self.child.observe(value);
     }
 }
 
-impl Drop for RegisterHelper<'_> {
-    fn drop(&mut self) {
-        for mut reg in self.registered.drain(..) {
-            reg.attributes = self.attributes.clone();
-            self.metrics.push(reg);
-        }
-        self.metrics.sort_by_key(|item| SortKey {
-            name: item.name.clone(),
-            metric: item.metric_type,
-        });
+impl Observe for IntHistogram {
+    #[inline]
+    fn observe(&self, value: u64) {
+        IntHistogram::observe(self, value);
     }
 }
 
-#[derive(PartialEq, Eq, PartialOrd, Ord)]
-struct SortKey {
-    name: Cow<'static, str>,
-    metric: MetricType,
+#[cold]
+fn invalid_bounds(error: buckets::BucketError) -> ! {
+    panic!("invalid histogram bounds: {}", error)
 }
 
-#[cfg(test)]
+impl Default for IntHistogram {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_BUCKETS)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
 mod test {
     use std::sync::Arc;
 
-    use crate::{IntCounter, IntGauge, PromMetricRegistry};
+    use crate::{ChildMetric, ChildMetrics2, GaugeUnderflow, IntCounter, IntGauge, IntHistogram};
 
     #[derive(Debug, Default)]
     struct Met {
@@ -462,25 +673,200 @@ mod test {
     }
 
     #[test]
-    fn metrics_test() {
+    fn histogram_concurrent_sum_test() {
+        const THREADS: u64 = 16;
+        const OBSERVATIONS: u64 = 100_000;
+
+        let histogram = Arc::new(IntHistogram::new([10, 1000]));
+
+        let threads = (0..THREADS)
+            .map(|thread| {
+                let histogram = histogram.clone();
+                std::thread::spawn(move || {
+                    for i in 0..OBSERVATIONS {
+                        histogram.observe(thread * 100 + 1 + i % 7);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        /* each thread observes thread * 100 + (1..=7 repeating) */
+        let cycle_sum = (0..OBSERVATIONS).map(|i| 1 + i % 7).sum::<u64>();
+        let expected = (0..THREADS)
+            .map(|thread| thread * 100 * OBSERVATIONS + cycle_sum)
+            .sum::<u64>();
+
+        assert_eq!(histogram.sum(), expected);
+        assert_eq!(histogram.count(), THREADS * OBSERVATIONS);
+        assert_eq!(
+            histogram.cumulative_counts(),
+            vec![
+                /* thread 0 observes 1..=7 */
+                OBSERVATIONS,
+                /* threads 1..=9 observe 101..=907 */
+                10 * OBSERVATIONS,
+                THREADS * OBSERVATIONS,
+            ]
+        );
+    }
+
+    #[test]
+    fn child_metric_nested_test() {
+        #[derive(Default)]
+        struct Server {
+            http: Arc<Met>,
+        }
+
+        let server = Arc::new(Server::default());
+        let requests = ChildMetric::create_nested(&server, |s| &s.http, |m| &m.a);
+        let pair = ChildMetrics2::create(&server.http, |m| (&m.b, &m.c));
+
+        drop(server);
+        requests.inc();
+        pair.first().inc_by(2);
+        pair.clone().second().set(3);
+
+        assert_eq!(requests.load(), 1);
+        assert_eq!(pair.first().load(), 2);
+        assert_eq!(pair.second().load(), 3);
+    }
+
+    #[test]
+    fn child_metric_try_create_test() {
+        let met = Arc::new((Met::default(), None::<IntCounter>));
+        assert!(ChildMetric::try_create(&met, |m| m.1.as_ref()).is_none());
+        assert_eq!(Arc::strong_count(&met), 1);
+
+        let a = ChildMetric::try_create(&met, |m| Some(&m.0.a)).unwrap();
+        a.inc();
+        assert_eq!(met.0.a.load(), 1);
+        assert_eq!(Arc::strong_count(&met), 2);
+    }
+
+    #[test]
+    fn child_metric_accessors_test() {
         let met = Arc::new(Met::default());
-        let mut reg = PromMetricRegistry::new();
-        reg.base_attributes.push(["prefix".into(), "set".into()]);
+        let a = ChildMetric::create(&met, |m| &m.a);
+        let b = a.map(|m| &m.b);
+
+        a.inc();
+        b.inc_by(2);
+        a.owner().c.set(3);
+        assert_eq!(met.a.load(), 1);
+        assert_eq!(met.b.load(), 2);
+        assert_eq!(met.c.load(), 3);
+
+        drop(met);
+        let met = b.into_arc();
+        assert_eq!(Arc::strong_count(&met), 2);
+        drop(a);
+        assert_eq!(Arc::strong_count(&met), 1);
+    }
+
+    #[test]
+    fn gauge_signed_delta_test() {
+        let gauge = Arc::new(IntGauge::new());
+
+        gauge.add(5);
+        gauge.add(-2);
+        assert_eq!(gauge.load(), 3);
+        gauge.add(-4);
+        assert_eq!(gauge.load(), u64::MAX);
+        gauge.add(1);
+
+        assert_eq!(gauge.checked_add(7), Ok(()));
+        assert_eq!(
+            gauge.checked_add(-8),
+            Err(GaugeUnderflow {
+                current: 7,
+                delta: -8
+            })
+        );
+        assert_eq!(gauge.load(), 7);
+        gauge.set(u64::MAX);
+        assert!(gauge.checked_add(1).is_err());
+        gauge.set(1000);
+
+        /* each thread nets +100, the checked ones never take it below 0 */
+        let threads = (0..8)
+            .map(|i| {
+                let gauge = gauge.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        if i % 2 == 0 {
+                            gauge.add(3);
+                            gauge.add(-2);
+                        } else {
+                            gauge.checked_add(-2).unwrap();
+                            gauge.checked_add(3).unwrap();
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
 
-        reg.register_fn(&met, |m, reg| {
-            reg.name_prefix("base_prefix");
+        assert_eq!(gauge.load(), 1800);
+    }
 
-            reg.group("prefix")
-                .count("a", &m.a)
-                .metric_opt("b", &m.b.0, crate::MetricType::IntCounter, true)
-                .attr("test", "2");
+    #[test]
+    fn inc_by_duration_test() {
+        let counter = IntCounter::new();
+        counter.inc_by_millis(std::time::Duration::from_micros(2_500));
+        assert_eq!(counter.load(), 2);
+        counter.inc_by_micros(std::time::Duration::from_nanos(1_999));
+        assert_eq!(counter.load(), 3);
 
-            reg.gauge("c", &m.c);
-        });
+        /* as_micros() as u64 would truncate to a small number here */
+        let counter = IntCounter::new();
+        counter.inc_by_micros(std::time::Duration::MAX);
+        assert_eq!(counter.load(), u64::MAX);
+    }
 
-        println!("{}", reg);
+    #[test]
+    fn gauge_try_dec_test() {
+        let gauge = Arc::new(IntGauge::new());
+
+        /* every decrement follows its own increment, never a false underflow */
+        let threads = (0..8)
+            .map(|i| {
+                let gauge = gauge.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        if i % 2 == 0 {
+                            gauge.inc();
+                            gauge.dec();
+                        } else {
+                            gauge.owned_inc_by(3);
+                            assert!(gauge.try_dec_by(3));
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(gauge.load(), 0);
+
+        assert!(!gauge.try_dec_by(1));
+        gauge.set(2);
+        assert!(!gauge.try_dec_by(3));
+        assert!(gauge.try_dec_by(2));
+        assert_eq!(gauge.load(), 0);
+    }
 
-        met.b.inc();
-        println!("{}", reg);
+    #[test]
+    #[cfg(any(debug_assertions, feature = "strict"))]
+    #[should_panic(expected = "gauge at 1 decremented by 2")]
+    fn gauge_underflow_panic_test() {
+        let gauge = IntGauge::with_value(1);
+        gauge.owned_dec_by(2);
     }
 }
@@ -0,0 +1,164 @@
+/*
+ * process wide accounting of data dropped on purpose, every dropping site records through
+ * record() so arc_metrics_dropped_samples_total can explain gaps in exported data
+ */
+use std::{fmt::Display, sync::OnceLock};
+
+use crate::{escape, join_labels, write_sample, MetricType, PromMetricRegistry, ShardedCounter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /* series past max_series */
+    Cardinality,
+    /* series outside the registration's claimed namespaces */
+    Namespace,
+    /* observations skipped by a Sampled decorator */
+    Sampling,
+    /* values cut to fit a limit */
+    Truncation,
+}
+
+impl DropReason {
+    pub const ALL: [DropReason; 4] = [
+        DropReason::Cardinality,
+        DropReason::Namespace,
+        DropReason::Sampling,
+        DropReason::Truncation,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Cardinality => "cardinality",
+            Self::Namespace => "namespace",
+            Self::Sampling => "sampling",
+            Self::Truncation => "truncation",
+        }
+    }
+}
+
+impl Display for DropReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/* sharded as sampling records from hot paths */
+fn counters() -> &'static [ShardedCounter; 4] {
+    static COUNTERS: OnceLock<[ShardedCounter; 4]> = OnceLock::new();
+    COUNTERS.get_or_init(Default::default)
+}
+
+pub(crate) fn record(reason: DropReason, count: u64) {
+    counters()[reason as usize].inc_by(count);
+}
+
+pub fn dropped(reason: DropReason) -> u64 {
+    counters()[reason as usize].load()
+}
+
+impl PromMetricRegistry {
+    /* exports arc_metrics_dropped_samples_total with every reason, including zeros */
+    pub fn track_dropped(&mut self) -> &mut Self {
+        self.track_dropped = true;
+        self
+    }
+
+    pub(crate) fn encode_dropped(&self, f: &mut dyn std::fmt::Write) -> std::fmt::Result {
+        if !self.track_dropped {
+            return Ok(());
+        }
+
+        let name = "arc_metrics_dropped_samples_total";
+        writeln!(f, "# HELP {}", name)?;
+        writeln!(f, "# TYPE {} {}", name, MetricType::IntCounter)?;
+
+        let attrs = join_labels(&self.base_attributes);
+        for reason in DropReason::ALL {
+            let label = escape::label_value(reason.as_str()).to_string();
+            let reason_label = Some(("reason", label.as_str()));
+            write_sample(f, name, "", &attrs, reason_label, dropped(reason))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{helpers::Sampled, IntCounter, IntHistogram, Observe, PromMetricRegistry};
+
+    use super::{dropped, record, DropReason};
+
+    /* counters are process wide and tests run in parallel, so only lower bounds hold */
+    fn delta<F: FnOnce()>(reason: DropReason, run: F) -> u64 {
+        let before = dropped(reason);
+        run();
+        dropped(reason) - before
+    }
+
+    #[test]
+    fn cardinality_test() {
+        let counters = Arc::new([IntCounter::new(), IntCounter::new(), IntCounter::new()]);
+        let added = delta(DropReason::Cardinality, || {
+            let mut reg = PromMetricRegistry::empty();
+            reg.max_series(1);
+            reg.register_fn(&counters, |counters, reg| {
+                for (i, counter) in counters.iter().enumerate() {
+                    reg.count("requests", counter).attr("id", i.to_string());
+                }
+            });
+        });
+        assert!(2 <= added);
+    }
+
+    #[test]
+    fn namespace_test() {
+        let counter = Arc::new(IntCounter::new());
+        let added = delta(DropReason::Namespace, || {
+            let mut reg = PromMetricRegistry::empty();
+            reg.require_namespaces();
+            reg.register_fn(&counter, |counter, reg| {
+                reg.count("unclaimed_total", counter);
+            });
+        });
+        assert!(1 <= added);
+    }
+
+    #[test]
+    fn sampling_test() {
+        let sampled = Sampled::new(IntHistogram::new([10]), 4);
+        let added = delta(DropReason::Sampling, || {
+            for _ in 0..1000 {
+                sampled.observe(1);
+            }
+        });
+        assert!(1000 - sampled.inner().count() <= added);
+    }
+
+    #[test]
+    fn truncation_test() {
+        assert!(1 <= delta(DropReason::Truncation, || record(DropReason::Truncation, 1)));
+    }
+
+    #[test]
+    fn render_test() {
+        let mut reg = PromMetricRegistry::empty();
+        assert_eq!(reg.to_string(), "");
+
+        reg.track_dropped();
+        let rendered = reg.to_string();
+        assert!(rendered.starts_with(
+            "# HELP arc_metrics_dropped_samples_total\n\
+             # TYPE arc_metrics_dropped_samples_total counter\n"
+        ));
+        for reason in DropReason::ALL {
+            let line = format!(
+                "arc_metrics_dropped_samples_total{{reason=\"{}\"}} ",
+                reason
+            );
+            assert!(rendered.contains(&line), "{}", rendered);
+        }
+    }
+}
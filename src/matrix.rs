@@ -0,0 +1,300 @@
+/*
+ * counters pre-created for the cross product of two fixed label sets, incrementing is
+ * plain array indexing. Label enums come from label_enum! so an index can't be out of range.
+ * LabelMatrix sizes its arrays with const generics and needs the modern feature,
+ * DynLabelMatrix is the boxed fallback that works on the MSRV.
+ */
+use std::{borrow::Cow, marker::PhantomData};
+
+use crate::{escape, IntCounter, RegisterAction};
+
+pub trait LabelValues: Copy {
+    const VALUES: &'static [&'static str];
+
+    fn index(self) -> usize;
+}
+
+#[cfg(feature = "modern")]
+pub trait LabelIndex<const N: usize>: LabelValues {
+    const NAMES: [&'static str; N];
+}
+
+/* enum usable as a LabelMatrix index, each variant maps to its label value */
+#[cfg(feature = "modern")]
+#[macro_export]
+macro_rules! label_enum {
+    ($(#[$meta:meta])* $vis:vis enum $name:ident { $($variant:ident => $label:literal),+ $(,)? }) => {
+        $crate::label_enum!(@values $(#[$meta])* $vis enum $name { $($variant => $label),+ });
+
+        impl $crate::matrix::LabelIndex<{ [$($label),+].len() }> for $name {
+            const NAMES: [&'static str; { [$($label),+].len() }] = [$($label),+];
+        }
+    };
+    (@values $(#[$meta:meta])* $vis:vis enum $name:ident { $($variant:ident => $label:literal),+ }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        $vis enum $name {
+            $($variant),+
+        }
+
+        impl $crate::matrix::LabelValues for $name {
+            const VALUES: &'static [&'static str] = &[$($label),+];
+
+            fn index(self) -> usize {
+                self as usize
+            }
+        }
+    };
+}
+
+/* enum usable as a DynLabelMatrix index, each variant maps to its label value */
+#[cfg(not(feature = "modern"))]
+#[macro_export]
+macro_rules! label_enum {
+    ($(#[$meta:meta])* $vis:vis enum $name:ident { $($variant:ident => $label:literal),+ $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        $vis enum $name {
+            $($variant),+
+        }
+
+        impl $crate::matrix::LabelValues for $name {
+            const VALUES: &'static [&'static str] = &[$($label),+];
+
+            fn index(self) -> usize {
+                self as usize
+            }
+        }
+    };
+}
+
+#[cfg(feature = "modern")]
+pub struct LabelMatrix<const ROWS: usize, const COLS: usize> {
+    row_label: Cow<'static, str>,
+    row_names: [&'static str; ROWS],
+    col_label: Cow<'static, str>,
+    col_names: [&'static str; COLS],
+    counters: [[IntCounter; COLS]; ROWS],
+}
+
+#[cfg(feature = "modern")]
+impl<const ROWS: usize, const COLS: usize> LabelMatrix<ROWS, COLS> {
+    /* panics on label names escape::check_series_label_name rejects */
+    #[track_caller]
+    pub fn new<R: Into<Cow<'static, str>>, C: Into<Cow<'static, str>>>(
+        row_label: R,
+        row_names: [&'static str; ROWS],
+        col_label: C,
+        col_names: [&'static str; COLS],
+    ) -> Self {
+        let (row_label, col_label) = checked_labels(row_label.into(), col_label.into());
+        LabelMatrix {
+            row_label,
+            row_names,
+            col_label,
+            col_names,
+            counters: std::array::from_fn(|_| std::array::from_fn(|_| IntCounter::new())),
+        }
+    }
+
+    /* label values taken from the index enums */
+    pub fn for_enums<R, C>(row_label: &'static str, col_label: &'static str) -> Self
+    where
+        R: LabelIndex<ROWS>,
+        C: LabelIndex<COLS>,
+    {
+        Self::new(row_label, R::NAMES, col_label, C::NAMES)
+    }
+
+    pub fn at<R: LabelIndex<ROWS>, C: LabelIndex<COLS>>(&self, row: R, col: C) -> &IntCounter {
+        &self.counters[row.index()][col.index()]
+    }
+
+    /* panics when out of range */
+    pub fn at_index(&self, row: usize, col: usize) -> &IntCounter {
+        &self.counters[row][col]
+    }
+
+    pub fn register(&'static self, name: &'static str, register: &mut RegisterAction) {
+        let cells = self.counters.iter().flatten();
+        let labels = [
+            (&self.row_label, &self.row_names[..]),
+            (&self.col_label, &self.col_names[..]),
+        ];
+        register_cells(register, name, labels, cells);
+    }
+}
+
+/* same layout as LabelMatrix with the counters sized at runtime from the enums */
+pub struct DynLabelMatrix<R: LabelValues, C: LabelValues> {
+    row_label: Cow<'static, str>,
+    col_label: Cow<'static, str>,
+    /* row major, R::VALUES.len() * C::VALUES.len() */
+    counters: Box<[IntCounter]>,
+    _index: PhantomData<fn(R, C)>,
+}
+
+impl<R: LabelValues, C: LabelValues> DynLabelMatrix<R, C> {
+    /* panics on label names escape::check_series_label_name rejects */
+    #[track_caller]
+    pub fn new<RL: Into<Cow<'static, str>>, CL: Into<Cow<'static, str>>>(
+        row_label: RL,
+        col_label: CL,
+    ) -> Self {
+        let (row_label, col_label) = checked_labels(row_label.into(), col_label.into());
+        let cells = R::VALUES.len() * C::VALUES.len();
+        DynLabelMatrix {
+            row_label,
+            col_label,
+            counters: (0..cells).map(|_| IntCounter::new()).collect(),
+            _index: PhantomData,
+        }
+    }
+
+    pub fn at(&self, row: R, col: C) -> &IntCounter {
+        self.at_index(row.index(), col.index())
+    }
+
+    /* panics when out of range */
+    pub fn at_index(&self, row: usize, col: usize) -> &IntCounter {
+        let cols = C::VALUES.len();
+        assert!(
+            col < cols,
+            "column {} out of range for {} columns",
+            col,
+            cols
+        );
+        &self.counters[row * cols + col]
+    }
+
+    pub fn register(&'static self, name: &'static str, register: &mut RegisterAction) {
+        let labels = [(&self.row_label, R::VALUES), (&self.col_label, C::VALUES)];
+        register_cells(register, name, labels, self.counters.iter());
+    }
+}
+
+#[track_caller]
+fn checked_labels(
+    row_label: Cow<'static, str>,
+    col_label: Cow<'static, str>,
+) -> (Cow<'static, str>, Cow<'static, str>) {
+    for label in [&row_label, &col_label] {
+        if let Err(error) = escape::check_series_label_name(label) {
+            panic!("{}", error);
+        }
+    }
+    (row_label, col_label)
+}
+
+fn register_cells(
+    register: &mut RegisterAction,
+    name: &'static str,
+    [(row_label, row_names), (col_label, col_names)]: [(&Cow<'static, str>, &[&'static str]); 2],
+    mut cells: impl Iterator<Item = &'static IntCounter>,
+) {
+    for row_name in row_names {
+        for col_name in col_names {
+            let counter = cells.next().expect("matrix has a counter per cell");
+            register
+                .count(name, counter)
+                .attr(row_label.clone(), *row_name)
+                .attr(col_label.clone(), *col_name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::PromMetricRegistry;
+
+    use super::{DynLabelMatrix, LabelValues};
+    #[cfg(feature = "modern")]
+    use super::{LabelIndex, LabelMatrix};
+
+    label_enum!(enum Method {
+        Get => "GET",
+        Post => "POST",
+    });
+
+    label_enum!(pub(crate) enum StatusClass {
+        Ok => "2xx",
+        ClientError => "4xx",
+        ServerError => "5xx",
+    });
+
+    const RENDERED: &str = "# HELP requests\n\
+         # TYPE requests counter\n\
+         requests{method=\"GET\",status=\"2xx\"} 3\n\
+         requests{method=\"GET\",status=\"4xx\"} 0\n\
+         requests{method=\"GET\",status=\"5xx\"} 0\n\
+         requests{method=\"POST\",status=\"2xx\"} 0\n\
+         requests{method=\"POST\",status=\"4xx\"} 1\n\
+         requests{method=\"POST\",status=\"5xx\"} 1\n";
+
+    #[test]
+    fn label_enum_test() {
+        assert_eq!(Method::VALUES, ["GET", "POST"]);
+        assert_eq!(StatusClass::ServerError.index(), 2);
+
+        #[cfg(feature = "modern")]
+        assert_eq!(Method::NAMES, ["GET", "POST"]);
+    }
+
+    #[cfg(feature = "modern")]
+    #[test]
+    fn matrix_render_test() {
+        let matrix = Arc::new(LabelMatrix::for_enums::<Method, StatusClass>(
+            "method", "status",
+        ));
+
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&matrix, |matrix, reg| matrix.register("requests", reg));
+
+        matrix.at(Method::Get, StatusClass::Ok).inc_by(3);
+        matrix.at(Method::Post, StatusClass::ServerError).inc();
+        matrix.at(Method::Post, StatusClass::ClientError).inc();
+        assert_eq!(matrix.at_index(1, 1).load(), 1);
+
+        assert_eq!(reg.to_string(), RENDERED);
+    }
+
+    #[test]
+    fn dyn_matrix_render_test() {
+        let matrix = Arc::new(DynLabelMatrix::<Method, StatusClass>::new(
+            "method", "status",
+        ));
+
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&matrix, |matrix, reg| matrix.register("requests", reg));
+
+        matrix.at(Method::Get, StatusClass::Ok).inc_by(3);
+        matrix.at(Method::Post, StatusClass::ServerError).inc();
+        matrix.at(Method::Post, StatusClass::ClientError).inc();
+        assert_eq!(matrix.at_index(1, 1).load(), 1);
+
+        assert_eq!(reg.to_string(), RENDERED);
+    }
+
+    #[cfg(feature = "modern")]
+    #[test]
+    #[should_panic]
+    fn at_index_out_of_range_test() {
+        let matrix = LabelMatrix::new("a", ["x"], "b", ["y", "z"]);
+        matrix.at_index(1, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "label name \"quantile\" is reserved")]
+    fn reserved_label_test() {
+        DynLabelMatrix::<Method, StatusClass>::new("method", "quantile");
+    }
+
+    #[test]
+    #[should_panic]
+    fn dyn_at_index_out_of_range_test() {
+        let matrix = DynLabelMatrix::<Method, StatusClass>::new("a", "b");
+        matrix.at_index(0, 3);
+    }
+}
@@ -0,0 +1,338 @@
+use std::{borrow::Cow, collections::HashMap, error::Error, fmt::Display, sync::Arc};
+
+use crate::{escape, lost, IntCounter, PromMetricRegistry, RegisterAction};
+
+/*
+ * prefix of the registry's own metrics, nothing can claim it. Those are registered exempt
+ * from enforcement, see register_own_fn
+ */
+const RESERVED: &str = "arc_metrics";
+
+/* assigned by the registry to a holder on its first claim, see holder_id */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HolderId(u64);
+
+impl Display for HolderId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "holder #{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamespaceClaim {
+    pub namespace: Cow<'static, str>,
+    pub owner: HolderId,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NamespaceError {
+    Invalid { namespace: String },
+    Reserved { namespace: String },
+    AlreadyClaimed { namespace: String, owner: HolderId },
+}
+
+impl Display for NamespaceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Invalid { namespace } => write!(f, "invalid namespace {:?}", namespace),
+            Self::Reserved { namespace } => write!(f, "namespace {:?} is reserved", namespace),
+            Self::AlreadyClaimed { namespace, owner } => {
+                write!(f, "namespace {:?} already claimed by {}", namespace, owner)
+            }
+        }
+    }
+}
+
+impl Error for NamespaceError {}
+
+#[derive(Default)]
+pub(crate) struct Namespaces {
+    claims: Vec<NamespaceClaim>,
+    /*
+     * address of a claiming holder to its id. The registry keeps claiming holders until
+     * they are unregistered or pruned, which releases the address before it can be reused.
+     */
+    holders: HashMap<usize, HolderId>,
+    next_id: u64,
+    /* set once enforcement is enabled */
+    rejected: Option<&'static IntCounter>,
+}
+
+impl Namespaces {
+    /* false when the name isn't under a namespace claimed by the holder at address */
+    pub(crate) fn allows(&self, address: usize, name: &str) -> bool {
+        if self.rejected.is_none() {
+            return true;
+        }
+
+        let Some(owner) = self.holders.get(&address) else {
+            return false;
+        };
+        self.claims
+            .iter()
+            .any(|claim| claim.owner == *owner && in_namespace(name, &claim.namespace))
+    }
+
+    fn holder_id(&mut self, address: usize) -> HolderId {
+        let next_id = &mut self.next_id;
+        *self.holders.entry(address).or_insert_with(|| {
+            *next_id += 1;
+            HolderId(*next_id)
+        })
+    }
+
+    pub(crate) fn release(&mut self, address: usize) {
+        if let Some(owner) = self.holders.remove(&address) {
+            self.claims.retain(|claim| claim.owner != owner);
+        }
+    }
+
+    pub(crate) fn reject(&self) {
+        if let Some(rejected) = self.rejected {
+            rejected.inc();
+            lost::record(lost::DropReason::Namespace, 1);
+        }
+    }
+}
+
+fn in_namespace(name: &str, namespace: &str) -> bool {
+    match name.strip_prefix(namespace) {
+        Some(rest) => rest.is_empty() || rest.starts_with('_'),
+        None => false,
+    }
+}
+
+impl PromMetricRegistry {
+    /*
+     * every registration must claim_namespace for the names it registers, others are
+     * skipped and counted in arc_metrics_namespace_rejected_total
+     */
+    pub fn require_namespaces(&mut self) -> &mut Self {
+        if self.namespaces.rejected.is_some() {
+            return self;
+        }
+
+        let rejected = Arc::new(IntCounter::default());
+        self.register_own_fn(&rejected, |counter, reg| {
+            reg.count("arc_metrics_namespace_rejected_total", counter);
+        });
+
+        self.namespaces.rejected =
+            Some(unsafe { std::mem::transmute::<&IntCounter, &'static IntCounter>(&rejected) });
+        self
+    }
+
+    pub fn namespace_claims(&self) -> &[NamespaceClaim] {
+        &self.namespaces.claims
+    }
+
+    /* None until the holder claims a namespace */
+    pub fn holder_id<T: 'static>(&self, holder: &Arc<T>) -> Option<HolderId> {
+        let address = Arc::as_ptr(holder) as usize;
+        self.namespaces.holders.get(&address).copied()
+    }
+}
+
+impl RegisterAction<'_> {
+    /* claiming the same namespace again from the same holder is a no-op */
+    pub fn claim_namespace<N: Into<Cow<'static, str>>>(
+        &mut self,
+        namespace: N,
+    ) -> Result<&mut Self, NamespaceError> {
+        let namespace = namespace.into();
+
+        if !escape::is_legacy_name(&namespace) {
+            return Err(NamespaceError::Invalid {
+                namespace: namespace.into_owned(),
+            });
+        }
+        if in_namespace(&namespace, RESERVED) {
+            return Err(NamespaceError::Reserved {
+                namespace: namespace.into_owned(),
+            });
+        }
+
+        let claimed = self
+            .namespaces
+            .claims
+            .iter()
+            .find(|claim| claim.namespace == namespace)
+            .map(|claim| claim.owner);
+        let owner = match claimed {
+            Some(owner) if Some(&owner) != self.namespaces.holders.get(&self.owner) => {
+                return Err(NamespaceError::AlreadyClaimed {
+                    namespace: namespace.into_owned(),
+                    owner,
+                });
+            }
+            Some(_) => return Ok(self),
+            None => self.namespaces.holder_id(self.owner),
+        };
+
+        /* keeps the holder even if none of its series are accepted, see Staged::commit */
+        self.staged.claimed = true;
+        self.namespaces
+            .claims
+            .push(NamespaceClaim { namespace, owner });
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{IntCounter, PromMetricRegistry};
+
+    use super::NamespaceError;
+
+    #[derive(Default)]
+    struct Lib {
+        requests: IntCounter,
+        errors: IntCounter,
+    }
+
+    #[test]
+    fn two_libraries_test() {
+        let auth = Arc::new(Lib::default());
+        let billing = Arc::new(Lib::default());
+
+        let mut reg = PromMetricRegistry::empty();
+        reg.require_namespaces();
+
+        reg.register_fn(&auth, |m, reg| {
+            reg.claim_namespace("authlib").unwrap();
+            reg.name_prefix("authlib");
+            reg.count("requests_total", &m.requests);
+            reg.count("errors_total", &m.errors);
+        });
+        reg.register_fn(&billing, |m, reg| {
+            reg.claim_namespace("billing").unwrap();
+            reg.count("billing_requests_total", &m.requests);
+            reg.count("billing", &m.errors);
+        });
+
+        auth.requests.inc();
+        billing.requests.inc_by(2);
+
+        assert_eq!(
+            reg.to_string(),
+            "# HELP arc_metrics_namespace_rejected_total\n\
+             # TYPE arc_metrics_namespace_rejected_total counter\n\
+             arc_metrics_namespace_rejected_total 0\n\
+             # HELP authlib_errors_total\n\
+             # TYPE authlib_errors_total counter\n\
+             authlib_errors_total 0\n\
+             # HELP authlib_requests_total\n\
+             # TYPE authlib_requests_total counter\n\
+             authlib_requests_total 1\n\
+             # HELP billing\n\
+             # TYPE billing counter\n\
+             billing 0\n\
+             # HELP billing_requests_total\n\
+             # TYPE billing_requests_total counter\n\
+             billing_requests_total 2\n"
+        );
+
+        let claims = reg.namespace_claims();
+        assert_eq!(claims.len(), 2);
+        assert_eq!(claims[0].namespace, "authlib");
+        assert_eq!(Some(claims[0].owner), reg.holder_id(&auth));
+        assert_eq!(claims[1].namespace, "billing");
+        assert_eq!(Some(claims[1].owner), reg.holder_id(&billing));
+        assert_ne!(claims[0].owner, claims[1].owner);
+    }
+
+    #[test]
+    fn rejection_test() {
+        let auth = Arc::new(Lib::default());
+        let other = Arc::new(Lib::default());
+
+        let mut reg = PromMetricRegistry::empty();
+        reg.require_namespaces();
+
+        reg.register_fn(&auth, |m, reg| {
+            reg.claim_namespace("authlib").unwrap();
+            reg.claim_namespace("authlib").unwrap();
+            reg.count("requests_total", &m.requests);
+            reg.count("authlibrary_total", &m.errors);
+        });
+
+        let auth_id = reg.holder_id(&auth).unwrap();
+        reg.register_fn(&other, |m, reg| {
+            let claimed = reg.claim_namespace("authlib").err();
+            assert_eq!(
+                claimed,
+                Some(NamespaceError::AlreadyClaimed {
+                    namespace: "authlib".to_string(),
+                    owner: auth_id,
+                })
+            );
+            assert_eq!(
+                claimed.unwrap().to_string(),
+                format!("namespace \"authlib\" already claimed by {}", auth_id)
+            );
+            assert!(matches!(
+                reg.claim_namespace("arc_metrics_x"),
+                Err(NamespaceError::Reserved { .. })
+            ));
+            assert!(matches!(
+                reg.claim_namespace("bad-name"),
+                Err(NamespaceError::Invalid { .. })
+            ));
+            reg.count("authlib_requests_total", &m.requests);
+            /* only the registry's own series are exempt, not its prefix */
+            reg.count("arc_metrics_fake_total", &m.errors);
+        });
+
+        assert_eq!(
+            reg.to_string(),
+            "# HELP arc_metrics_namespace_rejected_total\n\
+             # TYPE arc_metrics_namespace_rejected_total counter\n\
+             arc_metrics_namespace_rejected_total 4\n"
+        );
+        assert_eq!(reg.namespace_claims().len(), 1);
+        assert_eq!(reg.holder_id(&other), None);
+    }
+
+    #[test]
+    fn release_test() {
+        let mut reg = PromMetricRegistry::empty();
+        reg.require_namespaces();
+
+        /* a claim keeps the holder even when every series is rejected */
+        let lib = Arc::new(Lib::default());
+        reg.register_fn(&lib, |m, reg| {
+            reg.claim_namespace("lib").unwrap();
+            reg.count("unclaimed_total", &m.requests);
+        });
+        assert_eq!(Arc::strong_count(&lib), 2);
+        assert!(reg.holder_id(&lib).is_some());
+
+        let weak = Arc::new(Lib::default());
+        reg.register_weak_fn(&weak, |m, reg| {
+            reg.claim_namespace("weak").unwrap();
+            reg.count("weak_total", &m.requests);
+        });
+        assert_eq!(reg.namespace_claims().len(), 2);
+
+        drop(weak);
+        reg.prune();
+        assert_eq!(reg.namespace_claims().len(), 1);
+        assert_eq!(reg.namespace_claims()[0].namespace, "lib");
+    }
+
+    #[test]
+    fn not_enforced_test() {
+        let lib = Arc::new(Lib::default());
+        let mut reg = PromMetricRegistry::empty();
+
+        reg.register_fn(&lib, |m, reg| {
+            reg.claim_namespace("lib").unwrap();
+            reg.count("requests_total", &m.requests);
+        });
+
+        assert_eq!(reg.namespace_claims().len(), 1);
+        assert!(reg.to_string().contains("requests_total 0\n"));
+    }
+}
@@ -0,0 +1,342 @@
+/*
+ * OpenMetrics 1.0 text. Counter families are named without _total and their samples with
+ * it, ExemplarCounters carry their latest exemplar (see exemplar.rs) and the output ends
+ * with # EOF.
+ * Appended text (bridge sources, the registry's own families) is converted line by line.
+ */
+use std::{
+    fmt::Write,
+    time::{Instant, UNIX_EPOCH},
+};
+
+use crate::{
+    created_base, escape, exemplar::Exemplar, write_created, write_float_series, write_quantiles,
+    write_sample, write_series, Digits, FamilyView, MetricType, PromMetricRegistry, Reading,
+};
+
+impl PromMetricRegistry {
+    pub fn encode_openmetrics(&self, f: &mut dyn Write) -> std::fmt::Result {
+        self.encode_openmetrics_filtered(f, &|_| true)
+    }
+
+    pub(crate) fn encode_openmetrics_filtered(
+        &self,
+        f: &mut dyn Write,
+        filter: &dyn Fn(&str) -> bool,
+    ) -> std::fmt::Result {
+        let started = Instant::now();
+        let mut deprecated = Vec::new();
+
+        self.for_each_family(filter, false, |family| {
+            self.rendering(&family, &mut deprecated);
+            write_family(f, &family)
+        })?;
+
+        let mut appended = String::new();
+        self.encode_appended(&mut appended, filter, deprecated)?;
+        convert_text(f, &appended)?;
+        f.write_str("# EOF\n")?;
+
+        if let Some(metrics) = self.self_metrics {
+            metrics.rendered(self.scrape_elapsed(started));
+        }
+        Ok(())
+    }
+}
+
+fn write_family(f: &mut dyn Write, family: &FamilyView) -> std::fmt::Result {
+    let first = family.first();
+    let name = created_base(&first.name, first.metric_type);
+    let suffix = match first.metric_type {
+        MetricType::IntCounter => "_total",
+        _ => "",
+    };
+    let created = format!("{}_created", name);
+    writeln!(f, "# TYPE {} {}", name, first.metric_type)?;
+    if let Some(unit) = first.unit {
+        writeln!(f, "# UNIT {} {}", name, unit)?;
+    }
+    if let Some(help) = family.help() {
+        writeln!(f, "# HELP {} {}", name, escape::help(&help))?;
+    }
+
+    for (metric, reading) in family.metrics.iter().zip(family.readings) {
+        let attrs = metric.labels();
+        match reading {
+            Reading::Skipped => {}
+            Reading::Value(value) => {
+                write_series(f, name, suffix, attrs, None, *value)?;
+                if let Some(exemplar) = metric.exemplar.and_then(|slot| slot.latest()) {
+                    write_exemplar(f, &exemplar)?;
+                }
+                f.write_str("\n")?;
+
+                if metric.metric_type == MetricType::IntCounter {
+                    write_created(f, &created, attrs, metric.created)?;
+                }
+            }
+            Reading::Float(value) => {
+                write_float_series(f, name, suffix, attrs, *value)?;
+                f.write_str("\n")?;
+
+                if metric.metric_type == MetricType::IntCounter {
+                    write_created(f, &created, attrs, metric.created)?;
+                }
+            }
+            Reading::Histogram { counts, sum } => {
+                for (bound, count) in metric.bounds().iter().zip(counts) {
+                    let bound = Digits::new(*bound);
+                    let le = Some(("le", bound.as_str()));
+                    write_sample(f, name, "_bucket", attrs, le, *count)?;
+                }
+
+                let total = counts[counts.len() - 1];
+                write_sample(f, name, "_bucket", attrs, Some(("le", "+Inf")), total)?;
+                write_sample(f, name, "_sum", attrs, None, *sum)?;
+                write_sample(f, name, "_count", attrs, None, total)?;
+                write_created(f, &created, attrs, metric.created)?;
+            }
+            Reading::Summary { values, sum, count } => {
+                write_quantiles(f, name, attrs, metric.quantiles(), values)?;
+                write_sample(f, name, "_sum", attrs, None, *sum)?;
+                write_sample(f, name, "_count", attrs, None, *count)?;
+                write_created(f, &created, attrs, metric.created)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/* ` # {trace_id="abc"} 1 1700000000.123` */
+fn write_exemplar(f: &mut dyn Write, exemplar: &Exemplar) -> std::fmt::Result {
+    f.write_str(" # {")?;
+    for (i, (key, value)) in exemplar.labels.iter().enumerate() {
+        if i != 0 {
+            f.write_str(",")?;
+        }
+        write!(f, "{}=\"{}\"", key, escape::label_value(value))?;
+    }
+
+    let since = exemplar
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    write!(
+        f,
+        "}} {} {}.{:03}",
+        exemplar.value,
+        since.as_secs(),
+        since.subsec_millis()
+    )
+}
+
+/*
+ * text exposition lines to OpenMetrics: TYPE lines of counters lose _total and their
+ * samples gain it, HELP moves after TYPE, untyped becomes unknown and other comments
+ * are dropped
+ */
+fn convert_text(f: &mut dyn Write, text: &str) -> std::fmt::Result {
+    let mut help = None;
+    let mut counter = None;
+
+    for line in text.lines() {
+        if let Some(rest) = line.strip_prefix("# HELP ") {
+            help = rest.split_once(' ').filter(|(_, text)| !text.is_empty());
+            continue;
+        }
+
+        if let Some(rest) = line.strip_prefix("# TYPE ") {
+            let Some((name, metric_type)) = rest.split_once(' ') else {
+                continue;
+            };
+            let (family, metric_type) = match metric_type {
+                "counter" => (name.strip_suffix("_total").unwrap_or(name), "counter"),
+                "untyped" => (name, "unknown"),
+                metric_type => (name, metric_type),
+            };
+
+            writeln!(f, "# TYPE {} {}", family, metric_type)?;
+            if let Some((_, text)) = help.take().filter(|(help, _)| *help == name) {
+                writeln!(f, "# HELP {} {}", family, text)?;
+            }
+            counter = (metric_type == "counter").then_some(family);
+            continue;
+        }
+
+        if line.starts_with('#') {
+            continue;
+        }
+
+        match counter.and_then(|family| Some((family, line.strip_prefix(family)?))) {
+            Some((family, rest)) if !rest.starts_with("_total") => {
+                writeln!(f, "{}_total{}", family, rest)?;
+            }
+            _ => writeln!(f, "{}", line)?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::Arc,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use crate::{
+        exemplar::ExemplarCounter,
+        scrape::{ScrapeFormat, ScrapeOptions},
+        IntCounter, IntGauge, IntHistogram, PromMetricRegistry,
+    };
+
+    #[derive(Default)]
+    struct Met {
+        requests: ExemplarCounter,
+        errors: IntCounter,
+        queue: IntGauge,
+        latency: IntHistogram,
+    }
+
+    fn registry() -> (Arc<Met>, PromMetricRegistry) {
+        let met = Arc::new(Met {
+            latency: IntHistogram::new([10]),
+            ..Default::default()
+        });
+        let mut reg = PromMetricRegistry::empty();
+        reg.created_clock(|| UNIX_EPOCH + Duration::from_millis(1_600_000_000_250));
+        reg.register_fn(&met, |m, reg| {
+            reg.exemplar_count("requests_total", &m.requests)
+                .attr("path", "/");
+            reg.count("errors", &m.errors);
+            reg.gauge("queue", &m.queue);
+            reg.histogram("latency", &m.latency);
+        });
+        (met, reg)
+    }
+
+    #[test]
+    fn exemplar_test() {
+        let (met, reg) = registry();
+        let at = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        met.requests
+            .inc_with_exemplar_at(&[("trace_id", "abc")], at);
+        met.requests.inc();
+        met.errors.inc_by(2);
+        met.queue.set(3);
+        met.latency.observe(4);
+
+        let mut out = String::new();
+        reg.encode_openmetrics(&mut out).unwrap();
+        assert_eq!(
+            out,
+            "# TYPE errors counter\n\
+             errors_total 2\n\
+             errors_created 1600000000.250\n\
+             # TYPE latency histogram\n\
+             latency_bucket{le=\"10\"} 1\n\
+             latency_bucket{le=\"+Inf\"} 1\n\
+             latency_sum 4\n\
+             latency_count 1\n\
+             latency_created 1600000000.250\n\
+             # TYPE queue gauge\n\
+             queue 3\n\
+             # TYPE requests counter\n\
+             requests_total{path=\"/\"} 2 # {trace_id=\"abc\"} 1 1700000000.123\n\
+             requests_created{path=\"/\"} 1600000000.250\n\
+             # EOF\n"
+        );
+
+        /* the 0.0.4 text format has no exemplars */
+        assert!(!reg.to_string().contains("trace_id"));
+    }
+
+    #[test]
+    fn test_mode_test() {
+        let render = || {
+            let met = Arc::new(Met::default());
+            let mut reg = PromMetricRegistry::empty().test_mode();
+            reg.register_fn(&met, |m, reg| {
+                reg.count("errors", &m.errors);
+            });
+
+            let mut out = String::new();
+            reg.encode_openmetrics(&mut out).unwrap();
+            out
+        };
+
+        let first = render();
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(first, render());
+        assert_eq!(
+            first,
+            "# TYPE errors counter\n\
+             errors_total{program=\"test\",pkg_version=\"0.0.0\"} 0\n\
+             errors_created{program=\"test\",pkg_version=\"0.0.0\"} 0.000\n\
+             # EOF\n"
+        );
+    }
+
+    #[test]
+    fn scrape_test() {
+        let (_met, mut reg) = registry();
+        reg.track_deprecated_renders();
+        reg.register_fn(&Arc::new(IntCounter::new()), |c, reg| {
+            reg.count("old_total", c).deprecated("1.0", "use new");
+        });
+
+        let accept = "application/openmetrics-text;version=1.0.0,text/plain;version=0.0.4;q=0.5";
+        assert_eq!(
+            ScrapeFormat::negotiate(Some(accept)),
+            ScrapeFormat::OpenMetrics
+        );
+        assert_eq!(
+            ScrapeFormat::negotiate(Some("application/openmetrics-text; q=0, text/plain")),
+            ScrapeFormat::PrometheusText
+        );
+
+        let output = reg
+            .scrape(&ScrapeOptions::from_accept(Some(accept)))
+            .unwrap();
+        assert_eq!(
+            output.content_type,
+            ScrapeFormat::OpenMetrics.content_type()
+        );
+        assert!(output.body.contains(
+            "# TYPE old counter\n\
+             # HELP old (DEPRECATED since 1.0: use new)\n\
+             old_total 0\n\
+             old_created 1600000000.250\n"
+        ));
+        assert!(output.body.ends_with(
+            "# TYPE arc_metrics_deprecated_family_rendered counter\n\
+             arc_metrics_deprecated_family_rendered_total{family=\"old_total\"} 1\n\
+             # EOF\n"
+        ));
+    }
+
+    #[test]
+    fn convert_text_test() {
+        let mut out = String::new();
+        super::convert_text(
+            &mut out,
+            "# HELP jobs Jobs run\n\
+             # TYPE jobs counter\n\
+             jobs{kind=\"a\"} 3\n\
+             # TYPE temp untyped\n\
+             temp 5\n\
+             # a comment\n",
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "# TYPE jobs counter\n\
+             # HELP jobs Jobs run\n\
+             jobs_total{kind=\"a\"} 3\n\
+             # TYPE temp unknown\n\
+             temp 5\n"
+        );
+    }
+}
@@ -0,0 +1,801 @@
+/*
+ * Converts registry samples into the OpenTelemetry metrics data model so they can be handed
+ * to an OTel SDK exporter: counters become monotonic sums, gauges stay gauges,
+ * histograms keep their explicit bounds and summaries stay cumulative (OTLP has no delta
+ * summaries). FloatCounter / FloatGauge series are exported as separate double valued
+ * FloatSum / FloatGauge metrics. Base attributes are reported as resource
+ * attributes and removed from the data points. The types mirror OTLP so mapping them onto
+ * opentelemetry_sdk::metrics::data is a field by field copy in the export callback.
+ */
+use std::{
+    borrow::Cow,
+    collections::{hash_map::Entry, HashMap},
+    sync::{mpsc, Arc, RwLock},
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    export::{ExportPolicy, ExportSchedule},
+    FloatValue, MetricType, PromMetricRegistry, Quantile, SampleValue,
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Temporality {
+    #[default]
+    Cumulative,
+    /* change since the series was last exported, resets report the new value */
+    Delta,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyValue {
+    pub key: Cow<'static, str>,
+    pub value: Cow<'static, str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceMetrics {
+    pub resource: Vec<KeyValue>,
+    pub metrics: Vec<Metric>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metric {
+    pub name: Cow<'static, str>,
+    pub data: MetricData,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetricData {
+    Sum {
+        monotonic: bool,
+        temporality: Temporality,
+        points: Vec<DataPoint>,
+    },
+    Gauge {
+        points: Vec<DataPoint>,
+    },
+    Histogram {
+        temporality: Temporality,
+        points: Vec<HistogramPoint>,
+    },
+    Summary {
+        points: Vec<SummaryPoint>,
+    },
+    FloatSum {
+        monotonic: bool,
+        temporality: Temporality,
+        points: Vec<FloatDataPoint>,
+    },
+    FloatGauge {
+        points: Vec<FloatDataPoint>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataPoint {
+    pub attributes: Vec<KeyValue>,
+    pub start_time: SystemTime,
+    pub time: SystemTime,
+    pub value: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FloatDataPoint {
+    pub attributes: Vec<KeyValue>,
+    pub start_time: SystemTime,
+    pub time: SystemTime,
+    pub value: FloatValue,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramPoint {
+    pub attributes: Vec<KeyValue>,
+    pub start_time: SystemTime,
+    pub time: SystemTime,
+    pub bounds: Vec<u64>,
+    /* per bucket, not cumulative, the last one counts values above every bound */
+    pub bucket_counts: Vec<u64>,
+    pub sum: u64,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SummaryPoint {
+    pub attributes: Vec<KeyValue>,
+    pub start_time: SystemTime,
+    pub time: SystemTime,
+    pub quantiles: Vec<(Quantile, u64)>,
+    pub sum: u64,
+    pub count: u64,
+}
+
+pub struct OtelBridge {
+    temporality: Temporality,
+    suppress_zero_deltas: bool,
+    last_collect: SystemTime,
+    /* counters and histograms seen by the last collect, others are evicted */
+    series: HashMap<SeriesKey, Series>,
+    generation: u64,
+}
+
+type SeriesKey = (Cow<'static, str>, Vec<[Cow<'static, str>; 2]>);
+
+struct Series {
+    /* start of the current cumulative run, moved up when a reset is seen */
+    start: SystemTime,
+    /* last collect that exported the series */
+    last: SystemTime,
+    /* cumulative readings, a counter's value or a histogram's bucket counts, count then sum */
+    values: Vec<u64>,
+    generation: u64,
+}
+
+impl OtelBridge {
+    pub fn new(temporality: Temporality) -> Self {
+        OtelBridge {
+            temporality,
+            suppress_zero_deltas: false,
+            last_collect: SystemTime::now(),
+            series: HashMap::new(),
+            generation: 0,
+        }
+    }
+
+    /* with delta temporality, leave out points of series that didn't change since the last collect */
+    pub fn suppress_zero_deltas(mut self, suppress: bool) -> Self {
+        self.suppress_zero_deltas = suppress;
+        self
+    }
+
+    pub fn collect(&mut self, registry: &PromMetricRegistry) -> ResourceMetrics {
+        let base = registry.base_attrs();
+        let now = SystemTime::now();
+        self.generation += 1;
+
+        let mut metrics = Vec::new();
+        for family in registry.gather() {
+            let mut points = Vec::new();
+            let mut histograms = Vec::new();
+            let mut summaries = Vec::new();
+            let mut floats = Vec::new();
+
+            for sample in family.samples {
+                let attributes = sample
+                    .attributes
+                    .iter()
+                    .filter(|attr| !base.contains(attr))
+                    .map(|[key, value]| KeyValue {
+                        key: key.clone(),
+                        value: value.clone(),
+                    })
+                    .collect();
+                let key = (family.name.clone(), sample.attributes);
+
+                match sample.value {
+                    SampleValue::Value(value) if family.metric_type == MetricType::IntGauge => {
+                        points.push(DataPoint {
+                            attributes,
+                            start_time: self.last_collect,
+                            time: now,
+                            value,
+                        });
+                    }
+                    SampleValue::Value(value) => {
+                        if let Some((start_time, values)) = self.advance(key, vec![value], sub, now)
+                        {
+                            points.push(DataPoint {
+                                attributes,
+                                start_time,
+                                time: now,
+                                value: values[0],
+                            });
+                        }
+                    }
+                    SampleValue::Histogram {
+                        buckets,
+                        sum,
+                        count,
+                    } => {
+                        let (bounds, mut values): (Vec<_>, Vec<_>) = buckets.into_iter().unzip();
+                        /* bounds only list finite buckets, the +Inf one is the total count */
+                        values.push(count);
+                        values.push(sum);
+
+                        let Some((start_time, mut values)) = self.advance(key, values, sub, now)
+                        else {
+                            continue;
+                        };
+                        let sum = values.pop().unwrap_or(0);
+                        let bucket_counts = per_bucket(&values);
+                        histograms.push(HistogramPoint {
+                            attributes,
+                            start_time,
+                            time: now,
+                            bounds,
+                            count: values.last().copied().unwrap_or(0),
+                            bucket_counts,
+                            sum,
+                        });
+                    }
+                    SampleValue::Summary {
+                        quantiles,
+                        sum,
+                        count,
+                    } => {
+                        /* tracked like a counter for its start time, exported as is */
+                        if self
+                            .advance(key.clone(), vec![count, sum], sub, now)
+                            .is_none()
+                        {
+                            continue;
+                        }
+                        summaries.push(SummaryPoint {
+                            attributes,
+                            start_time: self.series[&key].start,
+                            time: now,
+                            quantiles,
+                            sum,
+                            count,
+                        });
+                    }
+                    SampleValue::Float(value) if family.metric_type == MetricType::IntGauge => {
+                        floats.push(FloatDataPoint {
+                            attributes,
+                            start_time: self.last_collect,
+                            time: now,
+                            value,
+                        });
+                    }
+                    SampleValue::Float(value) => {
+                        /* non-negative f64 bits order like the values, resets are seen the same */
+                        let bits = vec![value.get().to_bits()];
+                        if let Some((start_time, values)) = self.advance(key, bits, float_sub, now)
+                        {
+                            floats.push(FloatDataPoint {
+                                attributes,
+                                start_time,
+                                time: now,
+                                value: FloatValue::new(f64::from_bits(values[0])),
+                            });
+                        }
+                    }
+                }
+            }
+
+            /* a family of only float series has no integer metric */
+            let integers = floats.is_empty() || !points.is_empty();
+            let floats = match family.metric_type {
+                _ if floats.is_empty() => None,
+                MetricType::IntGauge => Some(MetricData::FloatGauge { points: floats }),
+                _ => Some(MetricData::FloatSum {
+                    monotonic: true,
+                    temporality: self.temporality,
+                    points: floats,
+                }),
+            };
+
+            let data = match family.metric_type {
+                MetricType::IntCounter => MetricData::Sum {
+                    monotonic: true,
+                    temporality: self.temporality,
+                    points,
+                },
+                MetricType::IntGauge => MetricData::Gauge { points },
+                MetricType::IntHistogram => MetricData::Histogram {
+                    temporality: self.temporality,
+                    points: histograms,
+                },
+                MetricType::Summary => MetricData::Summary { points: summaries },
+            };
+            if integers {
+                metrics.push(Metric {
+                    name: family.name.clone(),
+                    data,
+                });
+            }
+            if let Some(data) = floats {
+                metrics.push(Metric {
+                    name: family.name,
+                    data,
+                });
+            }
+        }
+
+        /* series that disappeared get no final point, they start over if they come back */
+        let generation = self.generation;
+        self.series
+            .retain(|_, series| series.generation == generation);
+
+        self.last_collect = now;
+        ResourceMetrics {
+            resource: base
+                .iter()
+                .map(|[key, value]| KeyValue {
+                    key: key.clone(),
+                    value: value.clone(),
+                })
+                .collect(),
+            metrics,
+        }
+    }
+
+    /*
+     * start time and values to export for cumulative readings, None for a suppressed zero
+     * delta. Any reading going backwards is a reset: the delta is the new value and the
+     * run restarts at the previous collect, the latest time the old run was known to last.
+     */
+    fn advance(
+        &mut self,
+        key: SeriesKey,
+        values: Vec<u64>,
+        sub: fn(u64, u64) -> u64,
+        now: SystemTime,
+    ) -> Option<(SystemTime, Vec<u64>)> {
+        let generation = self.generation;
+        let series = match self.series.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            /* appeared since the last collect, the first delta is the whole value */
+            Entry::Vacant(entry) => entry.insert(Series {
+                start: self.last_collect,
+                last: self.last_collect,
+                values: Vec::new(),
+                generation,
+            }),
+        };
+
+        let reset = !series.values.is_empty()
+            && (series.values.len() != values.len()
+                || values
+                    .iter()
+                    .zip(&series.values)
+                    .any(|(now, before)| now < before));
+        if reset {
+            series.start = series.last;
+            series.values.clear();
+        }
+
+        let (start_time, export) = match self.temporality {
+            Temporality::Cumulative => (series.start, values.clone()),
+            Temporality::Delta => {
+                let delta = match series.values.is_empty() {
+                    true => values.clone(),
+                    false => values
+                        .iter()
+                        .zip(&series.values)
+                        .map(|(now, before)| sub(*now, *before))
+                        .collect(),
+                };
+                (series.last, delta)
+            }
+        };
+
+        let unchanged = !series.values.is_empty() && series.values == values;
+        series.last = now;
+        series.values = values;
+        series.generation = generation;
+
+        let suppress = self.suppress_zero_deltas && self.temporality == Temporality::Delta;
+        match suppress && unchanged {
+            true => None,
+            false => Some((start_time, export)),
+        }
+    }
+
+    pub fn spawn_interval<F>(
+        self,
+        registry: Arc<RwLock<PromMetricRegistry>>,
+        period: Duration,
+        export: F,
+    ) -> OtelHandle
+    where
+        F: FnMut(ResourceMetrics) + Send + 'static,
+    {
+        self.spawn_with_policy(registry, ExportPolicy::every(period), export)
+    }
+
+    /* checks the policy every min_interval, stopping always performs a final export */
+    pub fn spawn_with_policy<F>(
+        mut self,
+        registry: Arc<RwLock<PromMetricRegistry>>,
+        policy: ExportPolicy,
+        mut export: F,
+    ) -> OtelHandle
+    where
+        F: FnMut(ResourceMetrics) + Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let mut schedule = ExportSchedule::new(policy);
+        schedule.exported(Instant::now());
+
+        let thread = std::thread::spawn(move || loop {
+            let stop = !matches!(
+                stopped.recv_timeout(policy.min_interval),
+                Err(mpsc::RecvTimeoutError::Timeout)
+            );
+
+            let metrics = {
+                let registry = registry.read().unwrap();
+                let now = Instant::now();
+                let changed = schedule.changed_series(&registry);
+                if stop || schedule.should_export(now, changed) {
+                    schedule.exported(now);
+                    Some(self.collect(&registry))
+                } else {
+                    None
+                }
+            };
+
+            /* exporters may block on the network, the registry lock is released by now */
+            if let Some(metrics) = metrics {
+                export(metrics);
+            }
+
+            if stop {
+                break;
+            }
+        });
+
+        OtelHandle { stop, thread }
+    }
+}
+
+fn sub(now: u64, before: u64) -> u64 {
+    now - before
+}
+
+/* sub() of two f64 bits */
+fn float_sub(now: u64, before: u64) -> u64 {
+    (f64::from_bits(now) - f64::from_bits(before)).to_bits()
+}
+
+/* cumulative bucket counts to per bucket counts */
+fn per_bucket(cumulative: &[u64]) -> Vec<u64> {
+    let mut below = 0;
+    cumulative
+        .iter()
+        .map(|total| {
+            let count = total - below;
+            below = *total;
+            count
+        })
+        .collect()
+}
+
+pub struct OtelHandle {
+    stop: mpsc::Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl OtelHandle {
+    /* performs a final export before returning */
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.thread.join();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{mpsc, Arc, RwLock},
+        time::Duration,
+    };
+
+    use crate::{
+        FloatCounter, IntCounter, IntGauge, IntHistogram, PromMetricRegistry, Quantile, Summary,
+    };
+
+    use super::{DataPoint, KeyValue, MetricData, OtelBridge, ResourceMetrics, Temporality};
+
+    #[derive(Default)]
+    struct Met {
+        requests: IntCounter,
+        active: IntGauge,
+        latency: IntHistogram,
+    }
+
+    fn setup() -> (Arc<Met>, PromMetricRegistry) {
+        let met = Arc::new(Met {
+            latency: IntHistogram::new([10, 100]),
+            ..Default::default()
+        });
+        let mut reg = PromMetricRegistry::empty().with_base_attrs([("service", "api")]);
+        reg.register_fn(&met, |m, reg| {
+            reg.count("requests", &m.requests).attr("method", "get");
+            reg.gauge("active", &m.active);
+            reg.histogram("latency", &m.latency);
+        });
+        (met, reg)
+    }
+
+    fn kv(key: &'static str, value: &'static str) -> KeyValue {
+        KeyValue {
+            key: key.into(),
+            value: value.into(),
+        }
+    }
+
+    type Point<'a> = (&'a str, Vec<KeyValue>, u64);
+
+    /* (name, attributes, value) of every sum and gauge point, (buckets, sum) of histograms */
+    fn values(metrics: &ResourceMetrics) -> (Vec<Point<'_>>, Vec<(Vec<u64>, u64)>) {
+        let mut points = Vec::new();
+        let mut histograms = Vec::new();
+        for metric in &metrics.metrics {
+            match &metric.data {
+                MetricData::Sum { points: p, .. } | MetricData::Gauge { points: p } => {
+                    for point in p {
+                        points.push((&*metric.name, point.attributes.clone(), point.value));
+                    }
+                }
+                MetricData::Histogram { points: p, .. } => {
+                    for point in p {
+                        assert_eq!(point.bounds, [10, 100]);
+                        assert_eq!(point.count, point.bucket_counts.iter().sum::<u64>());
+                        histograms.push((point.bucket_counts.clone(), point.sum));
+                    }
+                }
+                MetricData::Summary { .. }
+                | MetricData::FloatSum { .. }
+                | MetricData::FloatGauge { .. } => {}
+            }
+        }
+        (points, histograms)
+    }
+
+    #[test]
+    fn cumulative_test() {
+        let (met, reg) = setup();
+        let mut bridge = OtelBridge::new(Temporality::Cumulative);
+
+        met.requests.inc_by(3);
+        met.active.set(2);
+        met.latency.observe(5);
+        met.latency.observe(500);
+        bridge.collect(&reg);
+        met.requests.inc();
+
+        let metrics = bridge.collect(&reg);
+        assert_eq!(metrics.resource, [kv("service", "api")]);
+        assert!(matches!(
+            metrics.metrics[2].data,
+            MetricData::Sum {
+                monotonic: true,
+                temporality: Temporality::Cumulative,
+                ..
+            }
+        ));
+        assert_eq!(
+            values(&metrics),
+            (
+                vec![
+                    ("active", vec![], 2),
+                    ("requests", vec![kv("method", "get")], 4),
+                ],
+                vec![(vec![1, 0, 1], 505)],
+            )
+        );
+    }
+
+    #[test]
+    fn delta_test() {
+        let (met, reg) = setup();
+        let mut bridge = OtelBridge::new(Temporality::Delta);
+
+        met.requests.inc_by(3);
+        met.active.set(2);
+        met.latency.observe(5);
+        bridge.collect(&reg);
+
+        met.requests.inc();
+        met.latency.observe(50);
+        let metrics = bridge.collect(&reg);
+        assert_eq!(
+            values(&metrics),
+            (
+                vec![
+                    ("active", vec![], 2),
+                    ("requests", vec![kv("method", "get")], 1),
+                ],
+                vec![(vec![0, 1, 0], 50)],
+            )
+        );
+
+        let MetricData::Sum { points, .. } = &metrics.metrics[2].data else {
+            panic!("requests is a sum");
+        };
+        assert!(points[0].start_time <= points[0].time);
+
+        met.requests.take();
+        met.requests.inc();
+        assert_eq!(values(&bridge.collect(&reg)).0[1].2, 1);
+    }
+
+    /* the requests point of a collect */
+    fn requests(metrics: &ResourceMetrics) -> Option<&DataPoint> {
+        metrics
+            .metrics
+            .iter()
+            .find_map(|metric| match &metric.data {
+                MetricData::Sum { points, .. } if metric.name == "requests" => points.first(),
+                _ => None,
+            })
+    }
+
+    #[test]
+    fn reset_test() {
+        for temporality in [Temporality::Delta, Temporality::Cumulative] {
+            let (met, reg) = setup();
+            let mut bridge = OtelBridge::new(temporality);
+
+            met.requests.inc_by(5);
+            let first = requests(&bridge.collect(&reg)).unwrap().clone();
+            met.requests.inc_by(2);
+            let second = requests(&bridge.collect(&reg)).unwrap().clone();
+            assert_eq!(
+                second.start_time,
+                match temporality {
+                    Temporality::Delta => first.time,
+                    Temporality::Cumulative => first.start_time,
+                }
+            );
+
+            met.requests.take();
+            met.requests.inc();
+            let reset = requests(&bridge.collect(&reg)).unwrap().clone();
+            assert_eq!(reset.value, 1);
+            assert_eq!(reset.start_time, second.time);
+
+            met.requests.inc();
+            let after = requests(&bridge.collect(&reg)).unwrap().clone();
+            match temporality {
+                Temporality::Delta => assert_eq!((after.value, after.start_time), (1, reset.time)),
+                Temporality::Cumulative => {
+                    assert_eq!((after.value, after.start_time), (2, second.time))
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn appear_disappear_test() {
+        let (_met, mut reg) = setup();
+        let mut bridge = OtelBridge::new(Temporality::Delta);
+        let earlier = bridge.collect(&reg);
+        let series = bridge.series.len();
+
+        let worker = Arc::new(IntCounter::new());
+        reg.register_fn(&worker, |counter, reg| {
+            reg.count("worker", counter);
+        });
+        worker.inc_by(7);
+
+        let find = |metrics: &ResourceMetrics| {
+            metrics
+                .metrics
+                .iter()
+                .find_map(|metric| match &metric.data {
+                    MetricData::Sum { points, .. } if metric.name == "worker" => {
+                        points.first().cloned()
+                    }
+                    _ => None,
+                })
+        };
+
+        /* the first delta is the whole value, starting at the previous collect */
+        let appeared = find(&bridge.collect(&reg)).unwrap();
+        assert_eq!(appeared.value, 7);
+        assert_eq!(appeared.start_time, requests(&earlier).unwrap().time);
+
+        /* no final point, the state is evicted */
+        reg.unregister_holder(&worker);
+        assert_eq!(find(&bridge.collect(&reg)), None);
+        assert_eq!(bridge.series.len(), series);
+
+        /* coming back below the old value is a new series, not a reset */
+        let worker = Arc::new(IntCounter::new());
+        reg.register_fn(&worker, |counter, reg| {
+            reg.count("worker", counter);
+        });
+        worker.inc_by(4);
+        assert_eq!(find(&bridge.collect(&reg)).unwrap().value, 4);
+    }
+
+    #[test]
+    fn zero_delta_test() {
+        let (met, reg) = setup();
+        met.requests.inc();
+        met.latency.observe(5);
+
+        let mut bridge = OtelBridge::new(Temporality::Delta);
+        bridge.collect(&reg);
+        let metrics = bridge.collect(&reg);
+        assert_eq!(requests(&metrics).unwrap().value, 0);
+        assert_eq!(values(&metrics).1, [(vec![0, 0, 0], 0)]);
+
+        let mut bridge = OtelBridge::new(Temporality::Delta).suppress_zero_deltas(true);
+        bridge.collect(&reg);
+        let metrics = bridge.collect(&reg);
+        assert_eq!(requests(&metrics), None);
+        assert!(values(&metrics).1.is_empty());
+        /* gauges are always reported */
+        assert_eq!(values(&metrics).0, [("active", vec![], 0)]);
+
+        met.requests.inc();
+        assert_eq!(requests(&bridge.collect(&reg)).unwrap().value, 1);
+    }
+
+    #[test]
+    fn summary_test() {
+        let summary = Arc::new(Summary::new(&[0.5]));
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&summary, |summary, reg| {
+            reg.summary("latency", summary);
+        });
+        summary.observe(4);
+        summary.observe(8);
+
+        /* summaries stay cumulative with delta temporality */
+        let mut bridge = OtelBridge::new(Temporality::Delta);
+        let first = bridge.collect(&reg);
+        summary.observe(6);
+        let second = bridge.collect(&reg);
+
+        let point = |metrics: &ResourceMetrics| match &metrics.metrics[0].data {
+            MetricData::Summary { points } => points[0].clone(),
+            other => panic!("not a summary: {:?}", other),
+        };
+        let (first, second) = (point(&first), point(&second));
+        assert_eq!((second.count, second.sum), (3, 18));
+        assert_eq!(second.quantiles, [(Quantile::new(0.5).unwrap(), 6)]);
+        assert_eq!(second.start_time, first.start_time);
+    }
+
+    #[test]
+    fn float_test() {
+        let counter = Arc::new(FloatCounter::new());
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&counter, |counter, reg| {
+            reg.float_count("cpu_seconds", counter);
+        });
+        counter.inc_by(1.5);
+
+        let mut bridge = OtelBridge::new(Temporality::Delta);
+        let value = |metrics: &ResourceMetrics| match &metrics.metrics[..] {
+            [metric] => match &metric.data {
+                MetricData::FloatSum { points, .. } => points[0].value.get(),
+                other => panic!("not a float sum: {:?}", other),
+            },
+            metrics => panic!("expected one metric: {:?}", metrics),
+        };
+        assert_eq!(value(&bridge.collect(&reg)), 1.5);
+        counter.inc_by(0.25);
+        assert_eq!(value(&bridge.collect(&reg)), 0.25);
+
+        /* a reset reports the new value as the delta */
+        counter.take();
+        counter.inc_by(0.5);
+        assert_eq!(value(&bridge.collect(&reg)), 0.5);
+    }
+
+    #[test]
+    fn spawn_interval_test() {
+        let (met, reg) = setup();
+        let (sender, exported) = mpsc::channel();
+
+        met.requests.inc();
+        let handle = OtelBridge::new(Temporality::Cumulative).spawn_interval(
+            Arc::new(RwLock::new(reg)),
+            Duration::from_millis(10),
+            move |metrics| {
+                let _ = sender.send(metrics);
+            },
+        );
+
+        let metrics = exported.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(values(&metrics).0[1].2, 1);
+        handle.stop();
+    }
+}
@@ -0,0 +1,241 @@
+/*
+ * The opposite direction of the otel exporter: every family becomes an observable OTel
+ * instrument whose callback reads the registry on each collection. Counters are
+ * registered as observable counters so the SDK keeps them monotonic, gauges as observable
+ * gauges and histograms as their _sum and _count counters (OTel has no observable
+ * histogram). ObservableMeter is implemented over opentelemetry::metrics::Meter by the
+ * application, each method is a single u64_observable_* builder call. FloatCounter and
+ * FloatGauge values go through Observer::observe_f64.
+ */
+use std::sync::{Arc, RwLock};
+
+use crate::{otel::KeyValue, MetricType, PromMetricRegistry, SampleValue};
+
+pub trait Observer {
+    fn observe(&mut self, value: u64, attributes: &[KeyValue]);
+
+    /* rounds down to observe() by default (NaN as 0), override to keep the fraction */
+    fn observe_f64(&mut self, value: f64, attributes: &[KeyValue]) {
+        self.observe(value as u64, attributes);
+    }
+}
+
+pub type Callback = Box<dyn Fn(&mut dyn Observer) + Send + Sync>;
+
+pub trait ObservableMeter {
+    fn observable_counter(&self, name: String, callback: Callback);
+
+    fn observable_gauge(&self, name: String, callback: Callback);
+}
+
+pub struct ObservableBridge {
+    name_mapper: Arc<dyn Fn(&str) -> String + Send + Sync>,
+}
+
+impl Default for ObservableBridge {
+    fn default() -> Self {
+        ObservableBridge {
+            name_mapper: Arc::new(str::to_string),
+        }
+    }
+}
+
+impl ObservableBridge {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /* maps registry names to instrument names, see dotted_name */
+    pub fn with_name_mapper<F: Fn(&str) -> String + Send + Sync + 'static>(
+        mut self,
+        mapper: F,
+    ) -> Self {
+        self.name_mapper = Arc::new(mapper);
+        self
+    }
+
+    /* creates instruments for the families registered now, later registrations are not picked up */
+    pub fn register<M: ObservableMeter>(
+        &self,
+        meter: &M,
+        registry: Arc<RwLock<PromMetricRegistry>>,
+    ) {
+        let families = registry.read().unwrap().gather();
+
+        for family in families {
+            let name = family.name.to_string();
+            match family.metric_type {
+                MetricType::IntCounter => meter.observable_counter(
+                    (self.name_mapper)(&name),
+                    observe(&registry, name, Field::Value),
+                ),
+                MetricType::IntGauge => meter.observable_gauge(
+                    (self.name_mapper)(&name),
+                    observe(&registry, name, Field::Value),
+                ),
+                MetricType::IntHistogram | MetricType::Summary => {
+                    meter.observable_counter(
+                        (self.name_mapper)(&format!("{}_sum", name)),
+                        observe(&registry, name.clone(), Field::Sum),
+                    );
+                    meter.observable_counter(
+                        (self.name_mapper)(&format!("{}_count", name)),
+                        observe(&registry, name, Field::Count),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/* process_cpu_seconds_total -> process.cpu.seconds.total */
+pub fn dotted_name(name: &str) -> String {
+    name.replace('_', ".")
+}
+
+#[derive(Clone, Copy)]
+enum Field {
+    Value,
+    Sum,
+    Count,
+}
+
+fn observe(registry: &Arc<RwLock<PromMetricRegistry>>, name: String, field: Field) -> Callback {
+    let registry = Arc::clone(registry);
+
+    Box::new(move |observer| {
+        let registry = registry.read().unwrap();
+        for family in registry.collect_families(&|family| family == name, false) {
+            for sample in family.samples {
+                let attributes = sample
+                    .attributes
+                    .into_iter()
+                    .map(|[key, value]| KeyValue { key, value })
+                    .collect::<Vec<_>>();
+
+                let value = match (sample.value, field) {
+                    (SampleValue::Value(value), _) => value,
+                    (SampleValue::Float(value), _) => {
+                        observer.observe_f64(value.get(), &attributes);
+                        continue;
+                    }
+                    (SampleValue::Histogram { sum, .. }, Field::Sum)
+                    | (SampleValue::Summary { sum, .. }, Field::Sum) => sum,
+                    (SampleValue::Histogram { count, .. }, _)
+                    | (SampleValue::Summary { count, .. }, _) => count,
+                };
+                observer.observe(value, &attributes);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex, RwLock};
+
+    use crate::{otel::KeyValue, IntCounter, IntGauge, IntHistogram, PromMetricRegistry};
+
+    use super::{dotted_name, Callback, ObservableBridge, ObservableMeter, Observer};
+
+    /* stands in for an SDK meter with an in-memory exporter */
+    #[derive(Default)]
+    struct InMemoryMeter {
+        instruments: Mutex<Vec<(String, bool, Callback)>>,
+    }
+
+    impl ObservableMeter for InMemoryMeter {
+        fn observable_counter(&self, name: String, callback: Callback) {
+            self.instruments
+                .lock()
+                .unwrap()
+                .push((name, true, callback));
+        }
+
+        fn observable_gauge(&self, name: String, callback: Callback) {
+            self.instruments
+                .lock()
+                .unwrap()
+                .push((name, false, callback));
+        }
+    }
+
+    impl Observer for Vec<(u64, Vec<KeyValue>)> {
+        fn observe(&mut self, value: u64, attributes: &[KeyValue]) {
+            self.push((value, attributes.to_vec()));
+        }
+    }
+
+    impl InMemoryMeter {
+        /* (name, monotonic, value, attributes as key=value) */
+        fn collect(&self) -> Vec<(String, bool, u64, Vec<String>)> {
+            let mut out = Vec::new();
+            for (name, monotonic, callback) in self.instruments.lock().unwrap().iter() {
+                let mut points = Vec::new();
+                callback(&mut points);
+                for (value, attributes) in points {
+                    let attributes = attributes
+                        .iter()
+                        .map(|kv| format!("{}={}", kv.key, kv.value))
+                        .collect();
+                    out.push((name.clone(), *monotonic, value, attributes));
+                }
+            }
+            out
+        }
+    }
+
+    #[derive(Default)]
+    struct Met {
+        requests: IntCounter,
+        active: IntGauge,
+        latency: IntHistogram,
+    }
+
+    #[test]
+    fn register_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty().with_base_attrs([("service", "api")]);
+        reg.register_fn(&met, |m, reg| {
+            reg.count("http_requests", &m.requests)
+                .attr("method", "get");
+            reg.gauge("active", &m.active);
+            reg.histogram("latency", &m.latency);
+        });
+
+        let meter = InMemoryMeter::default();
+        ObservableBridge::new()
+            .with_name_mapper(dotted_name)
+            .register(&meter, Arc::new(RwLock::new(reg)));
+
+        met.requests.inc_by(3);
+        met.active.set(7);
+        met.latency.observe(20);
+        met.latency.observe(30);
+
+        let attrs = |attrs: &[&str]| attrs.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            meter.collect(),
+            [
+                ("active".to_string(), false, 7, attrs(&["service=api"])),
+                (
+                    "http.requests".to_string(),
+                    true,
+                    3,
+                    attrs(&["service=api", "method=get"])
+                ),
+                ("latency.sum".to_string(), true, 50, attrs(&["service=api"])),
+                (
+                    "latency.count".to_string(),
+                    true,
+                    2,
+                    attrs(&["service=api"])
+                ),
+            ]
+        );
+
+        /* callbacks read the registry on every collection */
+        met.requests.inc();
+        assert_eq!(meter.collect()[1].2, 4);
+    }
+}
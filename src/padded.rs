@@ -0,0 +1,109 @@
+/*
+ * a value on its own cache line, ex. counters in one struct bumped from different
+ * threads. 128 bytes like the ShardedCounter shards, as adjacent line prefetching pairs
+ * 64 byte lines. Derefs to the inner metric so registration and helpers take it as is.
+ */
+use core::ops::Deref;
+
+#[cfg(feature = "std")]
+use crate::{helpers::RegisterableMetric, RegisterAction};
+use crate::{IntCounter, IntGauge, Observe};
+
+#[derive(Debug, Default)]
+#[repr(align(128))]
+pub struct CachePadded<T>(T);
+
+pub type PaddedIntCounter = CachePadded<IntCounter>;
+pub type PaddedIntGauge = CachePadded<IntGauge>;
+
+impl<T> CachePadded<T> {
+    pub const fn new(value: T) -> Self {
+        CachePadded(value)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Observe> Observe for CachePadded<T> {
+    #[inline]
+    fn observe(&self, value: u64) {
+        self.0.observe(value);
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: RegisterableMetric> RegisterableMetric for CachePadded<T> {
+    fn register(&'static self, register: &mut RegisterAction) {
+        self.0.register(register);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{
+        helpers::{ActiveGauge, DurationIncMs},
+        IntCounter, IntGauge, PromMetricRegistry,
+    };
+
+    use super::{CachePadded, PaddedIntCounter, PaddedIntGauge};
+
+    #[derive(Default)]
+    struct Met {
+        requests: PaddedIntCounter,
+        errors: PaddedIntCounter,
+        active: PaddedIntGauge,
+        latency_ms: PaddedIntCounter,
+    }
+
+    #[test]
+    fn padded_test() {
+        assert_eq!(std::mem::align_of::<PaddedIntCounter>(), 128);
+        assert_eq!(std::mem::size_of::<Met>(), 4 * 128);
+
+        static STATIC: PaddedIntGauge = CachePadded::new(IntGauge::with_value(2));
+
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&met, |m, reg| {
+            reg.count("requests_total", &m.requests);
+            reg.count("errors_total", &m.errors);
+            reg.gauge("active", &m.active);
+        });
+        reg.register_static_gauge("static", &STATIC);
+
+        met.requests.inc();
+        met.errors.inc_by(2);
+        {
+            let _active = ActiveGauge::new(&met, |m| &m.active);
+            let _timer = DurationIncMs::new(&met, |m| &m.latency_ms);
+            assert_eq!(met.active.load(), 1);
+        }
+
+        assert_eq!(
+            reg.to_string(),
+            "# HELP active\n# TYPE active gauge\nactive 0\n\
+             # HELP errors_total\n# TYPE errors_total counter\nerrors_total 2\n\
+             # HELP requests_total\n# TYPE requests_total counter\nrequests_total 1\n\
+             # HELP static\n# TYPE static gauge\nstatic 2\n"
+        );
+        assert_eq!(
+            CachePadded::new(IntCounter::with_value(3))
+                .into_inner()
+                .load(),
+            3
+        );
+    }
+}
@@ -0,0 +1,808 @@
+/*
+ * How registration handles rule violations. Panic variants are for catching bugs in
+ * tests, Error skips the series and keeps it in PromMetricRegistry::violations(), Warn
+ * keeps it there but registers the series, Ignore registers the series anyway.
+ */
+use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashMap},
+    fmt::Display,
+    panic::Location,
+    sync::Mutex,
+};
+
+use crate::{escape, MetricType, PromMetricRegistry, RegisterAction};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnViolation {
+    /* Panic with debug assertions, Error otherwise */
+    PanicInDebug,
+    Panic,
+    Error,
+    Warn,
+    Ignore,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Convention {
+    #[default]
+    None,
+    /* counters end in _total, gauges don't, no uppercase in names */
+    Prometheus,
+    /* Prometheus, appending _total to counters without it instead of reporting them */
+    PrometheusAppendTotal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lint {
+    CounterWithoutTotal,
+    GaugeWithTotal,
+    Uppercase,
+}
+
+impl Convention {
+    pub fn lints(&self, name: &str, metric_type: MetricType) -> Vec<Lint> {
+        let mut lints = Vec::new();
+        if *self == Convention::None {
+            return lints;
+        }
+
+        let total = name.ends_with("_total");
+        match metric_type {
+            MetricType::IntCounter if !total && *self == Convention::Prometheus => {
+                lints.push(Lint::CounterWithoutTotal)
+            }
+            MetricType::IntGauge if total => lints.push(Lint::GaugeWithTotal),
+            _ => {}
+        }
+        if name.chars().any(|c| c.is_ascii_uppercase()) {
+            lints.push(Lint::Uppercase);
+        }
+        lints
+    }
+
+    /* the name a series is registered under */
+    pub(crate) fn apply(
+        &self,
+        name: Cow<'static, str>,
+        metric_type: MetricType,
+    ) -> Cow<'static, str> {
+        match (self, metric_type) {
+            (Convention::PrometheusAppendTotal, MetricType::IntCounter)
+                if !name.ends_with("_total") =>
+            {
+                Cow::Owned(format!("{}_total", name))
+            }
+            _ => name,
+        }
+    }
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Lint::CounterWithoutTotal => write!(f, "counter name doesn't end in _total"),
+            Lint::GaugeWithTotal => write!(f, "gauge name ends in _total"),
+            Lint::Uppercase => write!(f, "name has uppercase letters"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintWarning {
+    pub lint: Lint,
+    pub name: Cow<'static, str>,
+    pub location: Option<&'static Location<'static>>,
+}
+
+impl Display for LintWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.name, self.lint)?;
+        match self.location {
+            Some(location) => write!(f, " at {}", location),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Policy {
+    /* metric name outside [a-zA-Z_:][a-zA-Z0-9_:]* or label name rejected by check_label_name */
+    pub on_invalid_name: OnViolation,
+    /* label names passed to attr / base_attr go through escape::sanitize_label_name */
+    pub sanitize_label_names: bool,
+    /* same name, type and attributes as an already registered series */
+    pub on_duplicate: OnViolation,
+    /* name already registered with another type, Prometheus rejects the scrape */
+    pub on_type_conflict: OnViolation,
+    /* registration past max_series */
+    pub on_cardinality_exceeded: OnViolation,
+    /* name outside the namespaces claimed by its holder, see require_namespaces */
+    pub on_misuse: OnViolation,
+    /* label from escape::RESERVED_LABELS set on a series */
+    pub on_reserved_label: OnViolation,
+    /* series with more than max_labels labels, base attributes included */
+    pub on_too_many_labels: OnViolation,
+    pub max_labels: usize,
+    /* naming rules checked at registration */
+    pub convention: Convention,
+    /* series breaking the convention */
+    pub on_naming: OnViolation,
+}
+
+/* matches the behavior from before policies existed, apart from type conflicts and label checks */
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            on_invalid_name: OnViolation::Ignore,
+            sanitize_label_names: false,
+            on_duplicate: OnViolation::Ignore,
+            on_type_conflict: OnViolation::PanicInDebug,
+            on_cardinality_exceeded: OnViolation::Error,
+            on_misuse: OnViolation::Error,
+            on_reserved_label: OnViolation::Error,
+            on_too_many_labels: OnViolation::Error,
+            max_labels: Self::DEFAULT_MAX_LABELS,
+            convention: Convention::None,
+            on_naming: OnViolation::Warn,
+        }
+    }
+}
+
+impl Policy {
+    pub const DEFAULT_MAX_LABELS: usize = 20;
+
+    /* every check set to the same behavior, label names are not sanitized, no convention */
+    pub fn all(on_violation: OnViolation) -> Self {
+        Policy {
+            on_invalid_name: on_violation,
+            sanitize_label_names: false,
+            on_duplicate: on_violation,
+            on_type_conflict: on_violation,
+            on_cardinality_exceeded: on_violation,
+            on_misuse: on_violation,
+            on_reserved_label: on_violation,
+            on_too_many_labels: on_violation,
+            max_labels: Self::DEFAULT_MAX_LABELS,
+            convention: Convention::None,
+            on_naming: on_violation,
+        }
+    }
+
+    pub(crate) fn label_key(&self, key: Cow<'static, str>) -> Cow<'static, str> {
+        match self.sanitize_label_names {
+            true => escape::sanitize_label_name(key),
+            false => key,
+        }
+    }
+
+    pub(crate) fn on(&self, kind: ViolationKind) -> OnViolation {
+        match kind {
+            ViolationKind::InvalidName => self.on_invalid_name,
+            ViolationKind::Duplicate => self.on_duplicate,
+            ViolationKind::TypeConflict { .. } => self.on_type_conflict,
+            ViolationKind::CardinalityExceeded => self.on_cardinality_exceeded,
+            ViolationKind::Misuse => self.on_misuse,
+            ViolationKind::ReservedLabel { .. } => self.on_reserved_label,
+            ViolationKind::TooManyLabels { .. } => self.on_too_many_labels,
+            ViolationKind::Naming { .. } => self.on_naming,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViolationKind {
+    InvalidName,
+    Duplicate,
+    TypeConflict {
+        registered: MetricType,
+        existing: MetricType,
+    },
+    CardinalityExceeded,
+    Misuse,
+    ReservedLabel {
+        label: &'static str,
+    },
+    TooManyLabels {
+        labels: usize,
+        max: usize,
+    },
+    Naming {
+        lint: Lint,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub kind: ViolationKind,
+    pub name: Cow<'static, str>,
+    /* registration call site, only captured in debug builds or with the diagnostics feature */
+    pub location: Option<&'static Location<'static>>,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            ViolationKind::InvalidName => write!(f, "invalid name in series {:?}", self.name),
+            ViolationKind::Duplicate => write!(f, "series {:?} already registered", self.name),
+            ViolationKind::TypeConflict {
+                registered,
+                existing,
+            } => write!(
+                f,
+                "{:?} registered as {} but already registered as {}",
+                self.name, registered, existing
+            ),
+            ViolationKind::CardinalityExceeded => {
+                write!(f, "series limit reached registering {:?}", self.name)
+            }
+            ViolationKind::Misuse => {
+                write!(f, "{:?} is outside the claimed namespaces", self.name)
+            }
+            ViolationKind::ReservedLabel { label } => {
+                write!(f, "series {:?} sets reserved label {:?}", self.name, label)
+            }
+            ViolationKind::TooManyLabels { labels, max } => write!(
+                f,
+                "series {:?} has {} labels, at most {} allowed",
+                self.name, labels, max
+            ),
+            ViolationKind::Naming { lint } => write!(f, "{:?}: {}", self.name, lint),
+        }?;
+
+        match self.location {
+            Some(location) => write!(f, " at {}", location),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for Violation {}
+
+#[derive(Default)]
+pub(crate) struct Violations {
+    recorded: Mutex<Vec<Violation>>,
+}
+
+impl Violations {
+    /* true when the series should still be registered */
+    pub(crate) fn check(
+        &self,
+        policy: &Policy,
+        kind: ViolationKind,
+        name: &str,
+        location: Option<&'static Location<'static>>,
+    ) -> bool {
+        let violation = || Violation {
+            kind,
+            name: Cow::Owned(name.to_string()),
+            location,
+        };
+
+        match policy.on(kind) {
+            OnViolation::Ignore => true,
+            OnViolation::Panic => panic!("{}", violation()),
+            OnViolation::PanicInDebug if cfg!(debug_assertions) => panic!("{}", violation()),
+            OnViolation::PanicInDebug | OnViolation::Error => {
+                self.recorded.lock().unwrap().push(violation());
+                false
+            }
+            OnViolation::Warn => {
+                self.recorded.lock().unwrap().push(violation());
+                true
+            }
+        }
+    }
+}
+
+/* reserved labels then the label count, the first reserved label is reported */
+pub(crate) fn label_violations(
+    attributes: &[[Cow<'static, str>; 2]],
+    max_labels: usize,
+) -> impl Iterator<Item = ViolationKind> {
+    let reserved = attributes
+        .iter()
+        .find_map(|[key, _]| escape::reserved_label(key))
+        .map(|label| ViolationKind::ReservedLabel { label });
+    let too_many = (max_labels < attributes.len()).then_some(ViolationKind::TooManyLabels {
+        labels: attributes.len(),
+        max: max_labels,
+    });
+    reserved.into_iter().chain(too_many)
+}
+
+impl PromMetricRegistry {
+    /* applies to registrations after this call */
+    pub fn set_policy(&mut self, policy: Policy) -> &mut Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn policy(&self) -> Policy {
+        self.policy
+    }
+
+    /* applies to registrations after this call, see Policy::on_naming */
+    pub fn naming_convention(&mut self, convention: Convention) -> &mut Self {
+        self.policy.convention = convention;
+        self
+    }
+
+    /* every registered series checked against Convention::Prometheus regardless of the policy */
+    pub fn lint(&self) -> Vec<LintWarning> {
+        let mut warnings = Vec::new();
+        let mut families = BTreeSet::new();
+
+        for metric in &self.metrics {
+            if !families.insert((&metric.name, metric.metric_type)) {
+                continue;
+            }
+            for lint in Convention::Prometheus.lints(&metric.name, metric.metric_type) {
+                warnings.push(LintWarning {
+                    lint,
+                    name: metric.name.clone(),
+                    location: metric.call_site.location(),
+                });
+            }
+        }
+        warnings
+    }
+
+    /*
+     * checks every registered series for invalid names, duplicates, type conflicts and label
+     * rules (against the policy's max_labels) regardless of the policy, ex. to assert validity
+     * at startup. With the bridge feature
+     * families that appended text sources also define are reported as duplicates.
+     */
+    pub fn validate(&self) -> Result<(), Vec<Violation>> {
+        let mut violations = Vec::new();
+        let mut types = HashMap::<&str, MetricType>::new();
+        let mut series = BTreeSet::new();
+
+        for metric in &self.metrics {
+            let violation = |kind| Violation {
+                kind,
+                name: metric.name.clone(),
+                location: metric.call_site.location(),
+            };
+
+            let valid = escape::is_legacy_name(&metric.name)
+                && metric
+                    .attributes
+                    .iter()
+                    .all(|[key, _]| escape::check_label_name(key).is_ok());
+            if !valid {
+                violations.push(violation(ViolationKind::InvalidName));
+            }
+            violations.extend(
+                label_violations(&metric.attributes, self.policy.max_labels).map(violation),
+            );
+
+            let existing = *types.entry(&metric.name).or_insert(metric.metric_type);
+            if existing != metric.metric_type {
+                violations.push(violation(ViolationKind::TypeConflict {
+                    registered: metric.metric_type,
+                    existing,
+                }));
+            } else if !series.insert(metric.sort_key()) {
+                violations.push(violation(ViolationKind::Duplicate));
+            }
+        }
+
+        #[cfg(feature = "bridge")]
+        violations.extend(self.text_sources.conflicts(self));
+
+        match violations.is_empty() {
+            true => Ok(()),
+            false => Err(violations),
+        }
+    }
+
+    /* series skipped by an Error policy, oldest first */
+    pub fn violations(&self) -> Vec<Violation> {
+        self.violations.recorded.lock().unwrap().clone()
+    }
+}
+
+impl RegisterAction<'_> {
+    /* overrides the registry policy for metrics registered through this action */
+    pub fn with_policy(&mut self, policy: Policy) -> &mut Self {
+        self.options.policy = policy;
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        panic::{catch_unwind, AssertUnwindSafe},
+        sync::Arc,
+    };
+
+    use crate::{IntCounter, IntGauge, MetricType, PromMetricRegistry, RegisterAction};
+
+    use super::{Convention, Lint, LintWarning, OnViolation, Policy, Violation, ViolationKind};
+
+    #[derive(Default)]
+    struct Lib {
+        a: IntCounter,
+        b: IntCounter,
+        c: IntGauge,
+    }
+
+    fn register(kind: ViolationKind, reg: &mut PromMetricRegistry) {
+        let lib = Arc::new(Lib::default());
+        let series = |m: &'static Lib, reg: &mut RegisterAction| match kind {
+            ViolationKind::InvalidName => {
+                reg.count("ok_total", &m.a);
+                reg.count("bad-name", &m.b);
+            }
+            ViolationKind::Duplicate => {
+                reg.count("ok_total", &m.a);
+                reg.count("ok_total", &m.b);
+            }
+            ViolationKind::TypeConflict { .. } => {
+                reg.count("ok_total", &m.a);
+                reg.gauge("ok_total", &m.c);
+            }
+            ViolationKind::CardinalityExceeded => {
+                reg.count("ok_total", &m.a);
+                reg.count("over_total", &m.b);
+            }
+            ViolationKind::Misuse => {
+                reg.claim_namespace("ok").unwrap();
+                reg.count("ok_total", &m.a);
+                reg.count("other_total", &m.b);
+            }
+            ViolationKind::ReservedLabel { label } => {
+                reg.count("ok_total", &m.a);
+                reg.count("other_total", &m.b).attr(label, "1");
+            }
+            ViolationKind::TooManyLabels { labels, .. } => {
+                reg.count("ok_total", &m.a);
+                let mut helper = reg.count("other_total", &m.b);
+                for i in 0..labels {
+                    helper.attr(format!("l{}", i), "v");
+                }
+            }
+            ViolationKind::Naming { .. } => {
+                reg.count("ok_total", &m.a);
+                reg.count("other", &m.b);
+            }
+        };
+
+        match kind {
+            ViolationKind::CardinalityExceeded => {
+                reg.max_series(1);
+            }
+            ViolationKind::Misuse => {
+                reg.require_namespaces();
+            }
+            ViolationKind::Naming { .. } => {
+                reg.naming_convention(Convention::Prometheus);
+            }
+            _ => {}
+        }
+        reg.register_fn(&lib, series);
+    }
+
+    #[test]
+    fn matrix_test() {
+        let kinds = [
+            ViolationKind::InvalidName,
+            ViolationKind::Duplicate,
+            ViolationKind::TypeConflict {
+                registered: MetricType::IntGauge,
+                existing: MetricType::IntCounter,
+            },
+            ViolationKind::CardinalityExceeded,
+            ViolationKind::Misuse,
+            ViolationKind::ReservedLabel { label: "le" },
+            ViolationKind::TooManyLabels {
+                labels: 21,
+                max: 20,
+            },
+            ViolationKind::Naming {
+                lint: Lint::CounterWithoutTotal,
+            },
+        ];
+
+        for kind in kinds {
+            for on_violation in [
+                OnViolation::PanicInDebug,
+                OnViolation::Panic,
+                OnViolation::Error,
+                OnViolation::Warn,
+                OnViolation::Ignore,
+            ] {
+                let mut reg = PromMetricRegistry::empty();
+                reg.set_policy(Policy::all(on_violation));
+
+                let panicked = catch_unwind(AssertUnwindSafe(|| register(kind, &mut reg)));
+                let expect_panic = on_violation == OnViolation::Panic
+                    || (on_violation == OnViolation::PanicInDebug && cfg!(debug_assertions));
+                assert_eq!(
+                    panicked.is_err(),
+                    expect_panic,
+                    "{:?} {:?}",
+                    kind,
+                    on_violation
+                );
+                if expect_panic {
+                    continue;
+                }
+
+                let violations = reg.violations();
+                let counters = reg
+                    .gather()
+                    .iter()
+                    .filter(|family| !family.name.starts_with("arc_metrics"))
+                    .map(|family| family.samples.len())
+                    .sum::<usize>();
+
+                if on_violation == OnViolation::Ignore {
+                    assert!(violations.is_empty());
+                    assert_eq!(counters, 2, "{:?}", kind);
+                } else if on_violation == OnViolation::Warn {
+                    assert_eq!(violations.len(), 1);
+                    assert_eq!(violations[0].kind, kind);
+                    assert_eq!(counters, 2, "{:?}", kind);
+                } else {
+                    assert_eq!(violations.len(), 1);
+                    assert_eq!(violations[0].kind, kind);
+                    assert_eq!(counters, 1, "{:?}", kind);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn scope_override_test() {
+        let lib = Arc::new(Lib::default());
+        let mut reg = PromMetricRegistry::empty();
+        reg.set_policy(Policy::all(OnViolation::Panic));
+
+        let mut line = 0;
+        reg.register_fn(&lib, |m, reg| {
+            reg.with_policy(Policy::all(OnViolation::Error));
+            reg.count("ok_total", &m.a);
+            line = line!() + 1;
+            reg.count("ok_total", &m.b);
+        });
+
+        assert_eq!(reg.policy(), Policy::all(OnViolation::Panic));
+        assert_eq!(reg.gather().len(), 1);
+
+        let violation = &reg.violations()[0];
+        let message = "series \"ok_total\" already registered";
+        assert_eq!(
+            violation.location.is_some(),
+            cfg!(any(debug_assertions, feature = "diagnostics"))
+        );
+        match violation.location {
+            Some(location) => {
+                assert_eq!((location.file(), location.line()), (file!(), line));
+                assert_eq!(
+                    violation.to_string(),
+                    format!("{} at {}", message, location)
+                );
+            }
+            None => assert_eq!(violation.to_string(), message),
+        }
+    }
+
+    #[test]
+    fn validate_test() {
+        let lib = Arc::new(Lib::default());
+        let mut reg = PromMetricRegistry::empty();
+        reg.set_policy(Policy::all(OnViolation::Ignore));
+
+        reg.register_fn(&lib, |m, reg| {
+            reg.count("requests", &m.a);
+            reg.gauge("requests", &m.c);
+            reg.count("errors", &m.a);
+            reg.count("errors", &m.b);
+            reg.count("bad-name", &m.b);
+        });
+        assert_eq!(reg.gather().len(), 4);
+
+        let errors = reg.validate().unwrap_err();
+        let errors = errors
+            .into_iter()
+            .map(|e| {
+                Violation {
+                    location: None,
+                    ..e
+                }
+                .to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                "invalid name in series \"bad-name\"",
+                "series \"errors\" already registered",
+                "\"requests\" registered as gauge but already registered as counter",
+            ]
+        );
+
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&lib, |m, reg| {
+            reg.count("requests", &m.a).attr("code", "200");
+            reg.count("requests", &m.b).attr("code", "500");
+        });
+        assert_eq!(reg.validate(), Ok(()));
+    }
+
+    #[test]
+    fn label_names_test() {
+        let lib = Arc::new(Lib::default());
+        let mut reg = PromMetricRegistry::empty();
+        reg.set_policy(Policy {
+            sanitize_label_names: true,
+            ..Policy::default()
+        });
+        reg.base_attr("1stkey", "a");
+
+        reg.register_fn(&lib, |m, reg| {
+            reg.base_attr("my-key", "b");
+            let mut helper = reg.count("requests", &m.a);
+            helper.attr("__name__", "c");
+            assert_eq!(
+                helper.try_attr("bad key", "d").err().map(|e| e.to_string()),
+                Some("label name \"bad key\" has an invalid character at byte 3".to_string())
+            );
+            helper.try_attr("ok", "e").unwrap();
+        });
+
+        assert_eq!(
+            reg.to_string(),
+            "# HELP requests\n\
+             # TYPE requests counter\n\
+             requests{_1stkey=\"a\",my_key=\"b\",_name__=\"c\",ok=\"e\"} 0\n"
+        );
+        assert_eq!(reg.validate(), Ok(()));
+
+        let mut reg = PromMetricRegistry::empty();
+        reg.set_policy(Policy::all(OnViolation::Error));
+        reg.register_fn(&lib, |m, reg| {
+            reg.count("requests", &m.a).attr("le-gacy", "x");
+        });
+        assert_eq!(reg.violations()[0].kind, ViolationKind::InvalidName);
+    }
+
+    #[test]
+    fn default_test() {
+        let lib = Arc::new(Lib::default());
+        let mut reg = PromMetricRegistry::empty();
+
+        reg.register_fn(&lib, |m, reg| {
+            reg.count("dup_total", &m.a);
+            reg.count("dup_total", &m.a);
+            reg.count("with.dot", &m.b);
+        });
+
+        assert!(reg.violations().is_empty());
+        assert_eq!(reg.gather()[0].samples.len(), 2);
+    }
+    #[test]
+    fn label_rules_test() {
+        let lib = Arc::new(Lib::default());
+
+        for reserved in crate::escape::RESERVED_LABELS {
+            let mut reg = PromMetricRegistry::empty();
+            reg.set_policy(Policy::default());
+            reg.register_fn(&lib, |m, reg| {
+                reg.count("requests", &m.a).attr(*reserved, "x");
+            });
+
+            let violations = reg.violations();
+            assert_eq!(
+                violations[0].kind,
+                ViolationKind::ReservedLabel { label: reserved }
+            );
+            assert!(violations[0].to_string().starts_with(&format!(
+                "series \"requests\" sets reserved label {:?}",
+                reserved
+            )));
+            assert!(reg.gather().is_empty());
+        }
+
+        /* base attributes count towards the limit */
+        let register = |max_labels: usize, labels: usize| {
+            let mut reg = PromMetricRegistry::empty().with_base_attrs([("service", "api")]);
+            reg.set_policy(Policy {
+                max_labels,
+                ..Policy::default()
+            });
+            reg.register_fn(&lib, |m, reg| {
+                let mut helper = reg.count("requests", &m.a);
+                for i in 1..labels {
+                    helper.attr(format!("l{}", i), "v");
+                }
+            });
+            reg
+        };
+
+        assert!(register(20, 20).violations().is_empty());
+        assert_eq!(
+            register(20, 21).violations()[0].kind,
+            ViolationKind::TooManyLabels {
+                labels: 21,
+                max: 20
+            }
+        );
+        assert!(register(2, 2).violations().is_empty());
+        assert_eq!(register(2, 3).violations().len(), 1);
+
+        /* ignored at registration, still reported by validate */
+        let mut reg = PromMetricRegistry::empty();
+        reg.set_policy(Policy::all(OnViolation::Ignore));
+        reg.register_fn(&lib, |m, reg| {
+            reg.count("requests", &m.a).attr("quantile", "0.5");
+        });
+        assert_eq!(
+            reg.validate().unwrap_err()[0].kind,
+            ViolationKind::ReservedLabel { label: "quantile" }
+        );
+    }
+
+    #[test]
+    fn naming_test() {
+        let lib = Arc::new(Lib::default());
+        let register = |reg: &mut PromMetricRegistry| {
+            reg.register_fn(&lib, |m, reg| {
+                reg.count("requests", &m.a);
+                reg.count("errors_total", &m.b);
+                reg.gauge("inflight_total", &m.c);
+                reg.gauge("queueDepth", &m.c);
+            });
+        };
+
+        /* off by default, lint() still reports */
+        let mut reg = PromMetricRegistry::empty();
+        register(&mut reg);
+        assert!(reg.violations().is_empty());
+        let lints = reg
+            .lint()
+            .into_iter()
+            .map(|w| {
+                LintWarning {
+                    location: None,
+                    ..w
+                }
+                .to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lints,
+            [
+                "\"inflight_total\": gauge name ends in _total",
+                "\"queueDepth\": name has uppercase letters",
+                "\"requests\": counter name doesn't end in _total",
+            ]
+        );
+
+        /* warnings are recorded, the series still registered */
+        let mut reg = PromMetricRegistry::empty();
+        reg.naming_convention(Convention::Prometheus);
+        register(&mut reg);
+        assert_eq!(reg.violations().len(), 3);
+        assert_eq!(reg.gather().len(), 4);
+
+        let mut reg = PromMetricRegistry::empty();
+        reg.name_prefix("app");
+        reg.set_policy(Policy {
+            on_naming: OnViolation::Error,
+            ..Policy::default()
+        })
+        .naming_convention(Convention::PrometheusAppendTotal);
+        register(&mut reg);
+        assert_eq!(
+            reg.metric_names(),
+            ["app_errors_total", "app_requests_total"]
+        );
+        assert_eq!(reg.violations().len(), 2);
+        assert!(reg.lint().is_empty());
+    }
+}
@@ -0,0 +1,385 @@
+use std::{
+    error::Error,
+    fmt::{Display, Write as _},
+    time::Duration,
+};
+
+use crate::{
+    transport::{parse_response, split_http_url, TcpTransport, Transport},
+    PromMetricRegistry,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum PushError {
+    InvalidUrl(String),
+    Io(std::io::Error),
+    InvalidResponse,
+    Status { code: u16, body: String },
+}
+
+impl Display for PushError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidUrl(url) => write!(f, "invalid pushgateway url {:?}", url),
+            Self::Io(error) => write!(f, "pushgateway io error: {}", error),
+            Self::InvalidResponse => write!(f, "invalid response from pushgateway"),
+            Self::Status { code, body } => {
+                write!(f, "pushgateway responded with {}: {}", code, body.trim())
+            }
+        }
+    }
+}
+
+impl Error for PushError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for PushError {
+    fn from(error: std::io::Error) -> Self {
+        PushError::Io(error)
+    }
+}
+
+impl PromMetricRegistry {
+    /* replaces all metrics in the job's grouping */
+    pub fn push_to_gateway(
+        &self,
+        url: &str,
+        job: &str,
+        grouping: &[(&str, &str)],
+    ) -> Result<(), PushError> {
+        Pushgateway::new(url)?.push(self, job, grouping)
+    }
+
+    /* replaces only metrics with the same names in the job's grouping */
+    pub fn push_add(
+        &self,
+        url: &str,
+        job: &str,
+        grouping: &[(&str, &str)],
+    ) -> Result<(), PushError> {
+        Pushgateway::new(url)?.push_add(self, job, grouping)
+    }
+}
+
+pub struct Pushgateway {
+    host: String,
+    base_path: String,
+    transport: Box<dyn Transport>,
+}
+
+impl Pushgateway {
+    pub fn new(url: &str) -> Result<Self, PushError> {
+        let (host, _) = parse_url(url)?;
+        let addr = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:80", host)
+        };
+
+        Self::with_transport(url, TcpTransport::new(addr, TIMEOUT))
+    }
+
+    /* the url still provides the Host header and path, transport receives whole requests */
+    pub fn with_transport<T: Transport + 'static>(
+        url: &str,
+        transport: T,
+    ) -> Result<Self, PushError> {
+        let (host, base_path) = parse_url(url)?;
+
+        Ok(Pushgateway {
+            host: host.to_string(),
+            base_path: base_path.to_string(),
+            transport: Box::new(transport),
+        })
+    }
+
+    /* replaces all metrics in the job's grouping */
+    pub fn push(
+        &mut self,
+        registry: &PromMetricRegistry,
+        job: &str,
+        grouping: &[(&str, &str)],
+    ) -> Result<(), PushError> {
+        self.send(registry, "PUT", job, grouping)
+    }
+
+    /* replaces only metrics with the same names in the job's grouping */
+    pub fn push_add(
+        &mut self,
+        registry: &PromMetricRegistry,
+        job: &str,
+        grouping: &[(&str, &str)],
+    ) -> Result<(), PushError> {
+        self.send(registry, "POST", job, grouping)
+    }
+
+    fn send(
+        &mut self,
+        registry: &PromMetricRegistry,
+        method: &str,
+        job: &str,
+        grouping: &[(&str, &str)],
+    ) -> Result<(), PushError> {
+        let mut path = String::new();
+        for segment in self.base_path.split('/').filter(|s| !s.is_empty()) {
+            path.push('/');
+            path.push_str(segment);
+        }
+        path.push_str("/metrics");
+        push_label(&mut path, "job", job);
+        for (key, value) in grouping {
+            push_label(&mut path, key, value);
+        }
+
+        let body = registry.to_string();
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\n\
+            Host: {}\r\n\
+            Content-Type: text/plain; version=0.0.4\r\n\
+            Content-Length: {}\r\n\
+            Connection: close\r\n\r\n",
+            method,
+            path,
+            self.host,
+            body.len()
+        );
+        request.push_str(&body);
+
+        let response = self.transport.send(request.as_bytes())?;
+        let (code, body) = parse_response(&response).ok_or(PushError::InvalidResponse)?;
+
+        if (200..300).contains(&code) {
+            return Ok(());
+        }
+
+        Err(PushError::Status { code, body })
+    }
+}
+
+fn parse_url(url: &str) -> Result<(&str, &str), PushError> {
+    split_http_url(url).ok_or_else(|| PushError::InvalidUrl(url.to_string()))
+}
+
+/*
+ * pushgateway cannot represent an empty path segment and rejects an encoded '/', values
+ * with either use the key@base64 form
+ */
+fn push_label(path: &mut String, key: &str, value: &str) {
+    if value.is_empty() || value.contains('/') {
+        let _ = write!(path, "/{}@base64/", key);
+        base64_url(path, value.as_bytes());
+        return;
+    }
+
+    let _ = write!(path, "/{}/", key);
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            path.push(byte as char);
+        } else {
+            let _ = write!(path, "%{:02X}", byte);
+        }
+    }
+}
+
+/* RFC 4648 url safe alphabet with padding, an empty value is a lone "=" */
+fn base64_url(out: &mut String, bytes: &[u8]) {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+    if bytes.is_empty() {
+        out.push('=');
+        return;
+    }
+
+    for chunk in bytes.chunks(3) {
+        let mut group = [0u8; 3];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let bits = u32::from_be_bytes([0, group[0], group[1], group[2]]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        sync::Arc,
+        thread::JoinHandle,
+    };
+
+    use crate::{IntCounter, PromMetricRegistry};
+
+    use super::PushError;
+
+    fn gateway(status: &'static str) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 1024];
+
+            loop {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("Content-Length: "))
+                        .unwrap()
+                        .parse::<usize>()
+                        .unwrap();
+
+                    if body.len() == length {
+                        break;
+                    }
+                }
+            }
+
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: 4\r\n\r\nnope",
+                status
+            )
+            .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        (url, handle)
+    }
+
+    fn registry() -> PromMetricRegistry {
+        let counter = Arc::new(IntCounter::default());
+        counter.inc_by(3);
+
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&counter, |c, reg| {
+            reg.count("jobs_done", c);
+        });
+        reg
+    }
+
+    #[test]
+    fn push_to_gateway_test() {
+        let (url, handle) = gateway("200 OK");
+        registry()
+            .push_to_gateway(
+                &url,
+                "batch",
+                &[("instance", "a/b c"), ("zone", "eu west"), ("empty", "")],
+            )
+            .unwrap();
+
+        let request = handle.join().unwrap();
+        assert!(request.starts_with(
+            "PUT /metrics/job/batch/instance@base64/YS9iIGM=/zone/eu%20west/empty@base64/= \
+             HTTP/1.1\r\n"
+        ));
+        assert!(
+            request.ends_with("\r\n\r\n# HELP jobs_done\n# TYPE jobs_done counter\njobs_done 3\n")
+        );
+    }
+
+    #[test]
+    fn base64_url_test() {
+        let encoded = |value: &str| {
+            let mut out = String::new();
+            super::base64_url(&mut out, value.as_bytes());
+            out
+        };
+        assert_eq!(encoded(""), "=");
+        assert_eq!(encoded("f"), "Zg==");
+        assert_eq!(encoded("fo"), "Zm8=");
+        assert_eq!(encoded("foo"), "Zm9v");
+        assert_eq!(encoded("/path/to?"), "L3BhdGgvdG8_");
+        assert_eq!(encoded("\u{fb}\u{ff}"), "w7vDvw==");
+    }
+
+    #[test]
+    fn push_add_error_test() {
+        let (url, handle) = gateway("500 Internal Server Error");
+        let error = registry().push_add(&url, "batch", &[]).unwrap_err();
+        assert!(handle
+            .join()
+            .unwrap()
+            .starts_with("POST /metrics/job/batch HTTP/1.1\r\n"));
+
+        match error {
+            PushError::Status { code, body } => {
+                assert_eq!(code, 500);
+                assert_eq!(body, "nope");
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+
+        assert!(matches!(
+            registry().push_add("https://gateway", "batch", &[]),
+            Err(PushError::InvalidUrl(_))
+        ));
+    }
+
+    #[test]
+    fn push_connection_refused_test() {
+        let url = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+
+        assert!(matches!(
+            registry().push_to_gateway(&url, "batch", &[]),
+            Err(PushError::Io(_))
+        ));
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn push_fault_injection_test() {
+        use crate::transport::{FaultInjector, TcpTransport};
+
+        use super::Pushgateway;
+
+        let (url, handle) = gateway("200 OK");
+        let addr = url.trim_start_matches("http://").trim_end_matches('/');
+        let faults = FaultInjector::new(TcpTransport::new(addr, super::TIMEOUT));
+        let mut gateway = Pushgateway::with_transport(&url, faults.clone()).unwrap();
+
+        faults.fail_next(2);
+        for _ in 0..2 {
+            assert!(matches!(
+                gateway.push(&registry(), "batch", &[]),
+                Err(PushError::Io(_))
+            ));
+        }
+        gateway.push(&registry(), "batch", &[]).unwrap();
+        assert!(handle
+            .join()
+            .unwrap()
+            .starts_with("PUT /metrics/job/batch "));
+        assert_eq!((faults.attempts(), faults.failures()), (3, 2));
+
+        faults.respond_with_status(Some(503));
+        match gateway.push_add(&registry(), "batch", &[]) {
+            Err(PushError::Status { code: 503, body }) => assert_eq!(body, "injected"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(faults.attempts(), 4);
+    }
+}
@@ -0,0 +1,3979 @@
+/* PromMetricRegistry, registration and the text exposition format, needs std */
+use std::{
+    any::Any,
+    borrow::Cow,
+    collections::{hash_map::RandomState, HashMap, HashSet},
+    fmt::{Display, Write as _},
+    hash::{BuildHasher, Hash, Hasher},
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, OnceLock, Weak,
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(feature = "bridge")]
+use crate::bridge;
+use crate::{
+    attributes::Attributes, escape, exemplar, flat, helpers, helpers::RegisterableMetric, labels,
+    lost, namespace, policy, render_cache, scrape, self_metrics, units, FloatCounter, FloatGauge,
+    FloatValue, IntCounter, IntGauge, IntHistogram, Quantile, ShardedCounter, Summary,
+};
+
+pub struct PromMetricRegistry {
+    /* note: keep reference to Arc to ensure it doesn't drop */
+    metric_holders: Vec<Arc<dyn Any>>,
+    /* holders from register_weak, referenced by RegisteredMetric::holder */
+    weak_holders: Vec<WeakHolder>,
+    pub(crate) metrics: Vec<RegisteredMetric>,
+    pub(crate) staged: Staged,
+    /* series the registry registered about itself, they don't count towards max_series */
+    own_series: usize,
+    pub(crate) base_attributes: Vec<[Cow<'static, str>; 2]>,
+    pub(crate) series_limit: Option<SeriesLimit>,
+    ordering: MetricOrdering,
+    pub(crate) track_deprecated_renders: bool,
+    /* _created series in the text format, OpenMetrics always has them */
+    pub(crate) render_created: bool,
+    pub(crate) clock: fn() -> SystemTime,
+    pub(crate) track_dropped: bool,
+    label_cache: LabelCache,
+    pub(crate) scrape_clients: scrape::ScrapeClients,
+    pub(crate) offload_threshold: usize,
+    pub(crate) namespaces: namespace::Namespaces,
+    pub(crate) test_mode: bool,
+    pub(crate) render_cache: Option<Mutex<render_cache::RenderCache>>,
+    stale_zero: bool,
+    /* see set_skip_zero() */
+    skip_zero: bool,
+    skip_zero_gauges: bool,
+    sort_labels: bool,
+    pub(crate) name_prefix: Option<String>,
+    pub(crate) policy: policy::Policy,
+    pub(crate) violations: policy::Violations,
+    pub(crate) self_metrics: Option<&'static self_metrics::SelfMetrics>,
+    pub(crate) counter_check: Option<&'static self_metrics::CounterCheck>,
+    #[cfg(feature = "bridge")]
+    pub(crate) text_sources: bridge::TextSources,
+}
+
+struct WeakHolder {
+    pub(crate) holder: Weak<dyn Any>,
+    /* set once a scrape exported the zeros of a dropped holder, see stale_zero() */
+    pub(crate) zeroed: AtomicBool,
+}
+
+/*
+ * Converts borrowed strings into 'static label values. Each distinct string is leaked
+ * exactly once and lives for the rest of the process (not just the registry), so only
+ * use it for bounded sets of values like config entries.
+ */
+#[derive(Default)]
+pub struct LabelCache {
+    pub(crate) values: Mutex<HashSet<&'static str>>,
+}
+
+impl LabelCache {
+    pub fn intern(&self, value: &str) -> &'static str {
+        let mut values = self.values.lock().unwrap();
+        if let Some(interned) = values.get(value) {
+            return interned;
+        }
+
+        let interned: &'static str = Box::leak(value.to_string().into_boxed_str());
+        values.insert(interned);
+        interned
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MetricOrdering {
+    /* by name, type then attributes so output is independent of registration order */
+    #[default]
+    Sorted,
+    /* registration order, series of an existing family are placed after it */
+    Insertion,
+}
+
+/* registry settings carried into RegisterAction / RegisterHelper */
+#[derive(Clone, Copy)]
+pub(crate) struct RegisterOptions {
+    pub(crate) series_limit: Option<SeriesLimit>,
+    ordering: MetricOrdering,
+    pub(crate) holder: Option<usize>,
+    sort_labels: bool,
+    pub(crate) policy: policy::Policy,
+    pub(crate) self_metrics: Option<&'static self_metrics::SelfMetrics>,
+    pub(crate) clock: fn() -> SystemTime,
+    /* registered by the registry about itself, see register_own_fn */
+    pub(crate) own: bool,
+    own_series: usize,
+}
+
+/*
+ * series registered since the last commit, appended unsorted after metrics[..settled] and
+ * put in place at once when the registration ends instead of with an insert per series
+ */
+#[derive(Default)]
+pub(crate) struct Staged {
+    settled: usize,
+    hasher: RandomState,
+    /* hash of the sort key to the first staged series with it */
+    keys: HashMap<u64, usize>,
+    /* hash of name and type to the first staged series of the family */
+    families: HashMap<u64, usize>,
+    /* hash of the name to the first staged series of each type with it */
+    names: HashMap<u64, Vec<usize>>,
+    /* the registration's Arc, only held once one of its series is accepted */
+    pub(crate) holder: Option<Arc<dyn Any>>,
+    /* the registration claimed a namespace, its holder is kept either way */
+    pub(crate) claimed: bool,
+}
+
+impl Staged {
+    pub(crate) fn start(&mut self, settled: usize) {
+        self.settled = settled;
+        self.claimed = false;
+        self.keys.clear();
+        self.families.clear();
+        self.names.clear();
+    }
+
+    fn hash(&self, value: impl Hash) -> u64 {
+        let mut hasher = self.hasher.build_hasher();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn settled<'a>(&self, metrics: &'a [RegisteredMetric]) -> &'a [RegisteredMetric] {
+        &metrics[..self.settled]
+    }
+
+    fn push(&mut self, metrics: &mut Vec<RegisteredMetric>, reg: RegisteredMetric) {
+        let index = metrics.len();
+        self.keys.entry(self.hash(reg.sort_key())).or_insert(index);
+        self.families
+            .entry(self.hash((&*reg.name, reg.metric_type)))
+            .or_insert(index);
+
+        let types = self.names.entry(self.hash(&*reg.name)).or_default();
+        if !types
+            .iter()
+            .any(|first| metrics[*first].metric_type == reg.metric_type)
+        {
+            types.push(index);
+        }
+        metrics.push(reg);
+    }
+
+    /* hash hits are compared, a collision falls back to scanning the staged series */
+    fn duplicate(&self, metrics: &[RegisteredMetric], reg: &RegisteredMetric) -> bool {
+        let key = reg.sort_key();
+        match self.keys.get(&self.hash(&key)) {
+            Some(index) if metrics[*index].sort_key() == key => true,
+            Some(_) => metrics[self.settled..]
+                .iter()
+                .any(|item| item.sort_key() == key),
+            None => false,
+        }
+    }
+
+    fn family(&self, metrics: &[RegisteredMetric], reg: &RegisteredMetric) -> Option<usize> {
+        let same =
+            |item: &RegisteredMetric| item.name == reg.name && item.metric_type == reg.metric_type;
+        match self.families.get(&self.hash((&*reg.name, reg.metric_type))) {
+            Some(index) if same(&metrics[*index]) => Some(*index),
+            Some(_) => (self.settled..metrics.len()).find(|index| same(&metrics[*index])),
+            None => None,
+        }
+    }
+
+    fn existing_type(
+        &self,
+        metrics: &[RegisteredMetric],
+        reg: &RegisteredMetric,
+    ) -> Option<MetricType> {
+        let conflict = |item: &RegisteredMetric| {
+            (item.name == reg.name && item.metric_type != reg.metric_type)
+                .then_some(item.metric_type)
+        };
+        let types = self.names.get(&self.hash(&*reg.name))?;
+        types
+            .iter()
+            .find_map(|index| conflict(&metrics[*index]))
+            .or_else(|| {
+                /* hash collision with another name, scan */
+                types
+                    .iter()
+                    .any(|index| metrics[*index].name != reg.name)
+                    .then(|| metrics[self.settled..].iter().find_map(conflict))
+                    .flatten()
+            })
+    }
+
+    /*
+     * puts the staged series in place, a single one is inserted directly, more are sorted
+     * in at once: the settled part is one sorted run so this is a merge
+     */
+    pub(crate) fn commit(
+        &mut self,
+        metrics: &mut Vec<RegisteredMetric>,
+        holders: &mut Vec<Arc<dyn Any>>,
+        ordering: MetricOrdering,
+    ) {
+        /* nothing points into a holder whose series were all rejected */
+        if let Some(holder) = self.holder.take() {
+            if self.settled < metrics.len() || self.claimed {
+                holders.push(holder);
+            }
+        }
+
+        match metrics.len().saturating_sub(self.settled) {
+            0 => {}
+            1 => {
+                let reg = metrics.pop().unwrap();
+                let index = insert_index(metrics, ordering, &reg);
+                metrics.insert(index, reg);
+            }
+            _ => match ordering {
+                MetricOrdering::Sorted => metrics.sort_by(|a, b| a.sort_key().cmp(&b.sort_key())),
+                MetricOrdering::Insertion => group_families(metrics),
+            },
+        }
+        self.start(metrics.len());
+    }
+}
+
+/* stable, every series moves right after the first series of its family */
+fn group_families(metrics: &mut Vec<RegisteredMetric>) {
+    let ranks = {
+        let mut first = HashMap::new();
+        metrics
+            .iter()
+            .enumerate()
+            .map(|(index, item)| {
+                *first
+                    .entry((&*item.name, item.metric_type))
+                    .or_insert(index)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut ranked = ranks.into_iter().zip(metrics.drain(..)).collect::<Vec<_>>();
+    ranked.sort_by_key(|(rank, _)| *rank);
+    metrics.extend(ranked.into_iter().map(|(_, item)| item));
+}
+
+/* series of reg's family among sorted or grouped series */
+fn family_range(
+    metrics: &[RegisteredMetric],
+    ordering: MetricOrdering,
+    reg: &RegisteredMetric,
+) -> Range<usize> {
+    let family = (&*reg.name, reg.metric_type);
+    let start = match ordering {
+        MetricOrdering::Sorted => {
+            metrics.partition_point(|item| (&*item.name, item.metric_type) < family)
+        }
+        MetricOrdering::Insertion => metrics
+            .iter()
+            .position(|item| (&*item.name, item.metric_type) == family)
+            .unwrap_or(metrics.len()),
+    };
+    let len = metrics[start..]
+        .iter()
+        .take_while(|item| (&*item.name, item.metric_type) == family)
+        .count();
+    start..start + len
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct SeriesLimit {
+    pub(crate) max_series: usize,
+    pub(crate) rejected: &'static IntCounter,
+}
+
+impl Default for PromMetricRegistry {
+    fn default() -> Self {
+        let base_attributes = if let Some(details) = pkg_details::try_get() {
+            vec![
+                [Cow::Borrowed("program"), Cow::Borrowed(details.pkg_name)],
+                [
+                    Cow::Borrowed("pkg_version"),
+                    Cow::Borrowed(details.pkg_version),
+                ],
+            ]
+        } else {
+            Vec::new()
+        };
+
+        PromMetricRegistry {
+            metric_holders: Vec::new(),
+            own_series: 0,
+            weak_holders: Vec::new(),
+            metrics: Vec::new(),
+            staged: Staged::default(),
+            base_attributes,
+            series_limit: None,
+            ordering: MetricOrdering::Sorted,
+            track_deprecated_renders: false,
+            render_created: false,
+            clock: SystemTime::now,
+            track_dropped: false,
+            label_cache: LabelCache::default(),
+            scrape_clients: scrape::ScrapeClients::default(),
+            offload_threshold: scrape::ScrapeFuture::DEFAULT_OFFLOAD_THRESHOLD,
+            namespaces: namespace::Namespaces::default(),
+            test_mode: false,
+            render_cache: None,
+            stale_zero: false,
+            skip_zero: false,
+            skip_zero_gauges: false,
+            sort_labels: false,
+            name_prefix: None,
+            policy: policy::Policy::default(),
+            violations: policy::Violations::default(),
+            self_metrics: None,
+            counter_check: None,
+            #[cfg(feature = "bridge")]
+            text_sources: bridge::TextSources::default(),
+        }
+    }
+}
+
+unsafe impl Send for PromMetricRegistry {}
+unsafe impl Sync for PromMetricRegistry {}
+
+pub(crate) struct RegisteredMetric {
+    pub(crate) metric_type: MetricType,
+    pub(crate) name: Cow<'static, str>,
+    pub(crate) value: MetricValue,
+    pub(crate) attributes: Attributes,
+    skip_zero: bool,
+    /* registered through RegisterHelper::sparse() */
+    sparse: bool,
+    deprecation: Option<Arc<Deprecation>>,
+    #[cfg(feature = "blackbox")]
+    pub(crate) blackbox: bool,
+    pub(crate) holder: Option<usize>,
+    /* address of the metrics holder, see unregister_holder */
+    pub(crate) owner: usize,
+    /* escaped {key="value",..} joined on first render, see labels() */
+    pub(crate) labels: OnceLock<Box<str>>,
+    pub(crate) call_site: CallSite,
+    /* when the series was added to the registry, rendered as _created */
+    pub(crate) created: SystemTime,
+    /* CounterVec child with a ttl, hidden while expired */
+    pub(crate) expiry: Option<labels::Expiry>,
+    /* latest exemplar of an ExemplarCounter, rendered by OpenMetrics */
+    pub(crate) exemplar: Option<&'static exemplar::Slot>,
+    /* one of the registry's own series, see register_own_fn */
+    pub(crate) own: bool,
+    /* set by the units gauges, the name ends with _<unit> */
+    pub(crate) unit: Option<&'static str>,
+}
+
+/*
+ * where a series was registered, named in violations. Only kept in debug builds or with
+ * the diagnostics feature, otherwise this is zero sized.
+ */
+#[derive(Clone, Copy)]
+pub(crate) struct CallSite {
+    #[cfg(any(debug_assertions, feature = "diagnostics"))]
+    pub(crate) location: &'static std::panic::Location<'static>,
+}
+
+impl CallSite {
+    #[track_caller]
+    pub(crate) fn here() -> Self {
+        CallSite {
+            #[cfg(any(debug_assertions, feature = "diagnostics"))]
+            location: std::panic::Location::caller(),
+        }
+    }
+
+    pub(crate) fn location(self) -> Option<&'static std::panic::Location<'static>> {
+        #[cfg(any(debug_assertions, feature = "diagnostics"))]
+        return Some(self.location);
+
+        #[cfg(not(any(debug_assertions, feature = "diagnostics")))]
+        None
+    }
+}
+
+pub(crate) struct Deprecation {
+    pub(crate) since: Cow<'static, str>,
+    note: Cow<'static, str>,
+    pub(crate) rendered: IntCounter,
+}
+
+/* families rendered with a deprecation, for arc_metrics_deprecated_family_rendered_total */
+type Deprecated<'s> = Vec<(&'s Cow<'static, str>, &'s Arc<Deprecation>)>;
+
+#[derive(Clone, Copy)]
+pub(crate) enum MetricValue {
+    Atomic(&'static AtomicU64),
+    /* bucket counts, sum and count are multiplied by the scale when rendered */
+    Histogram(&'static IntHistogram, u64),
+    Sharded(&'static ShardedCounter),
+    /* fixed value owned by the registry, ex. build info */
+    Const(u64),
+    Summary(&'static Summary),
+    /* f64 bits of a FloatCounter / FloatGauge */
+    Float(&'static AtomicU64),
+}
+
+impl MetricValue {
+    fn is_zero(&self) -> bool {
+        match self {
+            Self::Atomic(value) => value.load(Ordering::Relaxed) == 0,
+            Self::Histogram(histogram, _) => histogram.count() == 0,
+            Self::Sharded(counter) => counter.load() == 0,
+            Self::Const(value) => *value == 0,
+            Self::Summary(summary) => summary.count() == 0,
+            Self::Float(value) => f64::from_bits(value.load(Ordering::Relaxed)) == 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MetricType {
+    #[cfg_attr(feature = "serde", serde(rename = "counter"))]
+    IntCounter,
+    #[cfg_attr(feature = "serde", serde(rename = "gauge"))]
+    IntGauge,
+    #[cfg_attr(feature = "serde", serde(rename = "histogram"))]
+    IntHistogram,
+    #[cfg_attr(feature = "serde", serde(rename = "summary"))]
+    Summary,
+}
+
+impl Display for MetricType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IntCounter => write!(f, "counter"),
+            Self::IntGauge => write!(f, "gauge"),
+            Self::IntHistogram => write!(f, "histogram"),
+            Self::Summary => write!(f, "summary"),
+        }
+    }
+}
+
+/* series sharing a name and type, HELP / TYPE are written once per family */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetricFamily {
+    pub name: Cow<'static, str>,
+    pub metric_type: MetricType,
+    /* unescaped, None when the HELP line only carries the name */
+    pub help: Option<String>,
+    /* ex. seconds for duration_gauge, rendered as the OpenMetrics UNIT */
+    pub unit: Option<&'static str>,
+    pub samples: Vec<Sample>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sample {
+    pub attributes: Vec<[Cow<'static, str>; 2]>,
+    pub value: SampleValue,
+}
+
+/* a series borrowed from the registry, see PromMetricRegistry::find */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SampleView<'a> {
+    pub metric_type: MetricType,
+    pub attributes: &'a [[Cow<'static, str>; 2]],
+    pub value: SampleValue,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SampleValue {
+    Value(u64),
+    /* (upper bound, cumulative count) per bucket, count is the +Inf bucket */
+    Histogram {
+        buckets: Vec<(u64, u64)>,
+        sum: u64,
+        count: u64,
+    },
+    /* estimate per quantile, sum and count are of every observation */
+    Summary {
+        quantiles: Vec<(Quantile, u64)>,
+        sum: u64,
+        count: u64,
+    },
+    /* FloatCounter / FloatGauge */
+    Float(FloatValue),
+}
+
+fn with_unit_suffix(name: Cow<'static, str>, suffix: &str) -> Cow<'static, str> {
+    if name.ends_with(suffix) {
+        return name;
+    }
+    Cow::Owned(format!("{}{}", name, suffix))
+}
+
+/* replaces the value of an existing key so a sample never has duplicate labels */
+pub(crate) fn set_attr(
+    attributes: &mut Vec<[Cow<'static, str>; 2]>,
+    key: Cow<'static, str>,
+    value: Cow<'static, str>,
+) {
+    match attributes.iter_mut().find(|[k, _]| *k == key) {
+        Some(existing) => existing[1] = value,
+        None => attributes.push([key, value]),
+    }
+}
+
+pub(crate) fn write_sample(
+    f: &mut dyn std::fmt::Write,
+    name: &str,
+    suffix: &str,
+    /* from join_labels */
+    labels: &str,
+    /* value must already be escaped */
+    extra: Option<(&str, &str)>,
+    value: u64,
+) -> std::fmt::Result {
+    write_series(f, name, suffix, labels, extra, value)?;
+    f.write_str("\n")
+}
+
+/* write_sample without the line break */
+pub(crate) fn write_series(
+    f: &mut dyn std::fmt::Write,
+    name: &str,
+    suffix: &str,
+    labels: &str,
+    extra: Option<(&str, &str)>,
+    value: u64,
+) -> std::fmt::Result {
+    write_series_name(f, name, suffix, labels, extra)?;
+    f.write_str(" ")?;
+    f.write_str(Digits::new(value).as_str())
+}
+
+/* write_series of a float value, see FloatValue's Display */
+pub(crate) fn write_float_series(
+    f: &mut dyn std::fmt::Write,
+    name: &str,
+    suffix: &str,
+    labels: &str,
+    value: FloatValue,
+) -> std::fmt::Result {
+    write_series_name(f, name, suffix, labels, None)?;
+    write!(f, " {}", value)
+}
+
+/* name, suffix and labels with the extra label appended */
+fn write_series_name(
+    f: &mut dyn std::fmt::Write,
+    name: &str,
+    suffix: &str,
+    labels: &str,
+    extra: Option<(&str, &str)>,
+) -> std::fmt::Result {
+    f.write_str(name)?;
+    f.write_str(suffix)?;
+
+    match extra {
+        None => f.write_str(labels)?,
+        Some((key, value)) => {
+            match labels.strip_suffix('}') {
+                Some(labels) => {
+                    f.write_str(labels)?;
+                    f.write_str(",")?;
+                }
+                None => f.write_str("{")?,
+            }
+            f.write_str(key)?;
+            f.write_str("=\"")?;
+            f.write_str(value)?;
+            f.write_str("\"}")?;
+        }
+    }
+
+    Ok(())
+}
+
+/* {key="value",..} with escaped values, empty without attributes */
+pub(crate) fn join_labels(attributes: &[[Cow<'static, str>; 2]]) -> String {
+    let mut labels = String::new();
+    let mut sep = '{';
+    for [key, value] in attributes {
+        labels.push(sep);
+        labels.push_str(key);
+        labels.push_str("=\"");
+        let _ = write!(labels, "{}", escape::label_value(value));
+        labels.push('"');
+        sep = ',';
+    }
+    if sep == ',' {
+        labels.push('}');
+    }
+    labels
+}
+
+/* decimal digits of a u64 on the stack, avoids the fmt machinery when rendering */
+pub(crate) struct Digits {
+    pub(crate) buf: [u8; 20],
+    pub(crate) start: usize,
+}
+
+impl Digits {
+    const PAIRS: &'static [u8; 200] = b"\
+        0001020304050607080910111213141516171819\
+        2021222324252627282930313233343536373839\
+        4041424344454647484950515253545556575859\
+        6061626364656667686970717273747576777879\
+        8081828384858687888990919293949596979899";
+
+    pub(crate) fn new(mut value: u64) -> Self {
+        let mut buf = [0u8; 20];
+        let mut start = buf.len();
+
+        while 100 <= value {
+            let pair = (value % 100) as usize * 2;
+            value /= 100;
+            start -= 2;
+            buf[start..start + 2].copy_from_slice(&Self::PAIRS[pair..pair + 2]);
+        }
+        if 10 <= value {
+            let pair = value as usize * 2;
+            start -= 2;
+            buf[start..start + 2].copy_from_slice(&Self::PAIRS[pair..pair + 2]);
+        } else {
+            start -= 1;
+            buf[start] = b'0' + value as u8;
+        }
+
+        Digits { buf, start }
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        /* only ascii digits are written */
+        unsafe { std::str::from_utf8_unchecked(&self.buf[self.start..]) }
+    }
+}
+
+/* values of a series read once per render, scaled and compared for the render cache */
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) enum Reading {
+    Skipped,
+    Value(u64),
+    Histogram {
+        counts: Vec<u64>,
+        sum: u64,
+    },
+    Summary {
+        values: Vec<u64>,
+        sum: u64,
+        count: u64,
+    },
+    Float(FloatValue),
+}
+
+impl Reading {
+    fn is_zero(&self) -> bool {
+        match self {
+            Reading::Skipped => false,
+            Reading::Value(value) => *value == 0,
+            Reading::Histogram { counts, .. } => counts.last().map_or(true, |count| *count == 0),
+            Reading::Summary { count, .. } => *count == 0,
+            Reading::Float(value) => value.get() == 0.0,
+        }
+    }
+}
+
+impl RegisteredMetric {
+    pub(crate) fn labels(&self) -> &str {
+        self.labels
+            .get_or_init(|| join_labels(&self.attributes).into_boxed_str())
+    }
+
+    pub(crate) fn bounds(&self) -> &[u64] {
+        match self.value {
+            MetricValue::Histogram(histogram, _) => histogram.bounds(),
+            _ => &[],
+        }
+    }
+
+    pub(crate) fn quantiles(&self) -> &[Quantile] {
+        match self.value {
+            MetricValue::Summary(summary) => summary.quantiles(),
+            _ => &[],
+        }
+    }
+
+    /* reset zeroes counters and histograms as they are read, gauges are left alone */
+    fn sample_value(&self, reading: &Reading) -> Option<SampleValue> {
+        Some(match reading {
+            Reading::Skipped => return None,
+            Reading::Value(value) => SampleValue::Value(*value),
+            Reading::Float(value) => SampleValue::Float(*value),
+            Reading::Histogram { counts, sum } => SampleValue::Histogram {
+                buckets: self.bounds().iter().copied().zip(counts.clone()).collect(),
+                sum: *sum,
+                count: counts[counts.len() - 1],
+            },
+            Reading::Summary { values, sum, count } => SampleValue::Summary {
+                quantiles: self
+                    .quantiles()
+                    .iter()
+                    .copied()
+                    .zip(values.clone())
+                    .collect(),
+                sum: *sum,
+                count: *count,
+            },
+        })
+    }
+
+    pub(crate) fn read(&self, reset: bool) -> Reading {
+        if self.expiry.is_some_and(|expiry| !expiry.is_live()) {
+            return Reading::Skipped;
+        }
+        if self.skip_zero && self.value.is_zero() {
+            return Reading::Skipped;
+        }
+
+        let reset = reset && self.metric_type != MetricType::IntGauge;
+        match self.value {
+            MetricValue::Atomic(value) if reset => Reading::Value(value.swap(0, Ordering::AcqRel)),
+            MetricValue::Atomic(value) => Reading::Value(value.load(Ordering::Relaxed)),
+            MetricValue::Sharded(counter) if reset => Reading::Value(counter.take()),
+            MetricValue::Sharded(counter) => Reading::Value(counter.load()),
+            MetricValue::Const(value) => Reading::Value(value),
+            /* 0.0 is all zero bits */
+            MetricValue::Float(bits) if reset => {
+                Reading::Float(FloatValue::from_bits(bits.swap(0, Ordering::AcqRel)))
+            }
+            MetricValue::Float(bits) => {
+                Reading::Float(FloatValue::from_bits(bits.load(Ordering::Acquire)))
+            }
+            MetricValue::Histogram(histogram, scale) => {
+                let (counts, sum) = match reset {
+                    true => histogram.take_cumulative(),
+                    false => (histogram.cumulative_counts(), histogram.sum()),
+                };
+                Reading::Histogram {
+                    counts: counts
+                        .into_iter()
+                        .map(|count| count.saturating_mul(scale))
+                        .collect(),
+                    sum: sum.saturating_mul(scale),
+                }
+            }
+            MetricValue::Summary(summary) => {
+                let (values, sum, count) = match reset {
+                    true => summary.take(),
+                    false => (summary.values(), summary.sum(), summary.count()),
+                };
+                Reading::Summary { values, sum, count }
+            }
+        }
+    }
+}
+
+/* series of one family with values read once, weak holders are alive while it exists */
+pub(crate) struct FamilyView<'m, 'r> {
+    /* position of the first series in the registry, keys the render cache */
+    pub(crate) index: usize,
+    pub(crate) metrics: &'m [RegisteredMetric],
+    pub(crate) readings: &'r [Reading],
+    /* first series that isn't skipped, HELP / TYPE are taken from it */
+    pub(crate) visible: usize,
+}
+
+impl<'m> FamilyView<'m, '_> {
+    pub(crate) fn first(&self) -> &'m RegisteredMetric {
+        &self.metrics[self.visible]
+    }
+
+    pub(crate) fn help(&self) -> Option<String> {
+        let deprecation = self.first().deprecation.as_ref()?;
+        Some(format!(
+            "(DEPRECATED since {}: {})",
+            deprecation.since, deprecation.note
+        ))
+    }
+
+    fn to_family(&self) -> MetricFamily {
+        let first = self.first();
+        let samples = self
+            .metrics
+            .iter()
+            .zip(self.readings)
+            .filter_map(|(metric, reading)| {
+                Some(Sample {
+                    attributes: metric.attributes.to_vec(),
+                    value: metric.sample_value(reading)?,
+                })
+            })
+            .collect();
+
+        MetricFamily {
+            name: first.name.clone(),
+            metric_type: first.metric_type,
+            help: self.help(),
+            unit: first.unit,
+            samples,
+        }
+    }
+}
+
+/*
+ * HELP / TYPE from the first visible series, then every series that isn't skipped. With
+ * created, counters and histograms are followed by a <name>_created gauge family.
+ */
+pub(crate) fn write_family(
+    f: &mut dyn std::fmt::Write,
+    family: &FamilyView,
+    created: bool,
+) -> std::fmt::Result {
+    let first = family.first();
+    match family.help() {
+        Some(help) => writeln!(f, "# HELP {} {}", first.name, escape::help(&help))?,
+        None => writeln!(f, "# HELP {}", first.name)?,
+    }
+    writeln!(f, "# TYPE {} {}", first.name, first.metric_type)?;
+
+    for (metric, reading) in family.metrics.iter().zip(family.readings) {
+        let attrs = metric.labels();
+        match reading {
+            Reading::Skipped => {}
+            Reading::Value(value) => {
+                write_sample(f, &metric.name, "", attrs, None, *value)?;
+            }
+            Reading::Float(value) => {
+                write_float_series(f, &metric.name, "", attrs, *value)?;
+                f.write_str("\n")?;
+            }
+            Reading::Histogram { counts, sum } => {
+                for (bound, count) in metric.bounds().iter().zip(counts) {
+                    let bound = Digits::new(*bound);
+                    let le = Some(("le", bound.as_str()));
+                    write_sample(f, &metric.name, "_bucket", attrs, le, *count)?;
+                }
+
+                let total = counts[counts.len() - 1];
+                let le = Some(("le", "+Inf"));
+                write_sample(f, &metric.name, "_bucket", attrs, le, total)?;
+                write_sample(f, &metric.name, "_sum", attrs, None, *sum)?;
+                write_sample(f, &metric.name, "_count", attrs, None, total)?;
+            }
+            Reading::Summary { values, sum, count } => {
+                write_quantiles(f, &metric.name, attrs, metric.quantiles(), values)?;
+                write_sample(f, &metric.name, "_sum", attrs, None, *sum)?;
+                write_sample(f, &metric.name, "_count", attrs, None, *count)?;
+            }
+        }
+    }
+
+    if !created || first.metric_type == MetricType::IntGauge {
+        return Ok(());
+    }
+
+    let name = format!("{}_created", created_base(&first.name, first.metric_type));
+    writeln!(f, "# HELP {}", name)?;
+    writeln!(f, "# TYPE {} {}", name, MetricType::IntGauge)?;
+    for (metric, reading) in family.metrics.iter().zip(family.readings) {
+        if *reading != Reading::Skipped {
+            write_created(f, &name, metric.labels(), metric.created)?;
+        }
+    }
+
+    Ok(())
+}
+
+/* name{..,quantile="0.5"} value per quantile of a summary */
+pub(crate) fn write_quantiles(
+    f: &mut dyn std::fmt::Write,
+    name: &str,
+    attrs: &str,
+    quantiles: &[Quantile],
+    values: &[u64],
+) -> std::fmt::Result {
+    let mut label = String::new();
+    for (quantile, value) in quantiles.iter().zip(values) {
+        label.clear();
+        write!(label, "{}", quantile)?;
+        write_sample(f, name, "", attrs, Some(("quantile", &label)), *value)?;
+    }
+    Ok(())
+}
+
+/* counters drop _total, foo_total is created as foo_created */
+pub(crate) fn created_base(name: &str, metric_type: MetricType) -> &str {
+    match metric_type {
+        MetricType::IntCounter => name.strip_suffix("_total").unwrap_or(name),
+        _ => name,
+    }
+}
+
+/* name already ends in _created, the value is unix seconds with milliseconds */
+pub(crate) fn write_created(
+    f: &mut dyn std::fmt::Write,
+    name: &str,
+    labels: &str,
+    created: SystemTime,
+) -> std::fmt::Result {
+    let since = created.duration_since(UNIX_EPOCH).unwrap_or_default();
+    writeln!(
+        f,
+        "{}{} {}.{:03}",
+        name,
+        labels,
+        since.as_secs(),
+        since.subsec_millis()
+    )
+}
+
+/* {} is the text exposition format, {:#} an aligned table for terminals (see flat.rs) */
+impl Display for PromMetricRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            return flat::fmt_table(self, f, f.sign_plus());
+        }
+        self.encode(f, &|_| true)
+    }
+}
+
+impl PromMetricRegistry {
+    /*
+     * groups series into families and reads their values once, the text encoder and
+     * gather() are both built on this
+     */
+    pub(crate) fn for_each_family<'s>(
+        &'s self,
+        filter: &dyn Fn(&str) -> bool,
+        reset: bool,
+        mut each: impl FnMut(FamilyView<'s, '_>) -> std::fmt::Result,
+    ) -> std::fmt::Result {
+        let mut readings = Vec::new();
+        let mut holders = Vec::new();
+        let now = labels::unix_secs(SystemTime::now());
+
+        let mut start = 0;
+        while start < self.metrics.len() {
+            let index = start;
+            let first = &self.metrics[index];
+            let len = self.metrics[index..]
+                .iter()
+                .take_while(|m| m.name == first.name && m.metric_type == first.metric_type)
+                .count();
+            let family = &self.metrics[index..index + len];
+            start += len;
+
+            if !filter(&family[0].name) {
+                continue;
+            }
+
+            /* weak holders stay alive until the family is handled */
+            holders.clear();
+            readings.clear();
+            readings.reserve(len);
+            for metric in family {
+                match self.hold(metric) {
+                    Some(holder) => {
+                        holders.extend(holder);
+                        if let Some(expiry) = metric.expiry {
+                            expiry.prune(now);
+                        }
+                        let reading = metric.read(reset);
+                        readings.push(match reading.is_zero() && self.omits_zero(metric) {
+                            true => Reading::Skipped,
+                            false => reading,
+                        });
+                    }
+                    None => readings.push(self.stale_reading(metric)),
+                }
+            }
+
+            let Some(visible) = readings.iter().position(|r| *r != Reading::Skipped) else {
+                continue;
+            };
+
+            each(FamilyView {
+                index,
+                metrics: family,
+                readings: &readings,
+                visible,
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /* renders families whose name passes the filter in the text exposition format */
+    pub(crate) fn encode(
+        &self,
+        f: &mut dyn std::fmt::Write,
+        filter: &dyn Fn(&str) -> bool,
+    ) -> std::fmt::Result {
+        let started = Instant::now();
+        let mut deprecated = Vec::new();
+        let mut cache = self.render_cache.as_ref().map(|cache| match cache.lock() {
+            Ok(cache) => cache,
+            Err(poisoned) => poisoned.into_inner(),
+        });
+
+        self.for_each_family(filter, false, |family| {
+            self.rendering(&family, &mut deprecated);
+
+            let Some(cache) = &mut cache else {
+                return write_family(f, &family, self.render_created);
+            };
+
+            if let Some(text) = cache.lookup(family.index, family.readings) {
+                return f.write_str(text);
+            }
+
+            let mut text = String::new();
+            write_family(&mut text, &family, self.render_created)?;
+            f.write_str(&text)?;
+            cache.store(family.index, family.readings, text);
+            Ok(())
+        })?;
+
+        self.encode_appended(f, filter, deprecated)?;
+
+        if let Some(metrics) = self.self_metrics {
+            metrics.rendered(self.scrape_elapsed(started));
+        }
+
+        Ok(())
+    }
+
+    /* bookkeeping for a family about to be written, by any text encoder */
+    pub(crate) fn rendering<'s>(
+        &'s self,
+        family: &FamilyView<'s, '_>,
+        deprecated: &mut Deprecated<'s>,
+    ) {
+        if let Some(check) = self.counter_check {
+            check.check(family);
+        }
+
+        let first = family.first();
+        if let Some(deprecation) = &first.deprecation {
+            if self.track_deprecated_renders {
+                deprecation.rendered.inc();
+                deprecated.push((&first.name, deprecation));
+            }
+        }
+    }
+
+    /* text sources and the families written by the registry itself, after the series */
+    pub(crate) fn encode_appended(
+        &self,
+        f: &mut dyn std::fmt::Write,
+        filter: &dyn Fn(&str) -> bool,
+        deprecated: Deprecated<'_>,
+    ) -> std::fmt::Result {
+        #[cfg(feature = "bridge")]
+        self.text_sources.encode(f, filter)?;
+        #[cfg(not(feature = "bridge"))]
+        let _ = filter;
+
+        if !deprecated.is_empty() {
+            let name = "arc_metrics_deprecated_family_rendered_total";
+            writeln!(f, "# HELP {}", name)?;
+            writeln!(f, "# TYPE {} {}", name, MetricType::IntCounter)?;
+
+            let attrs = join_labels(&self.base_attributes);
+            for (family, deprecation) in deprecated {
+                let value = deprecation.rendered.load();
+                let family = escape::label_value(family).to_string();
+                write_sample(f, name, "", &attrs, Some(("family", &family)), value)?;
+            }
+        }
+
+        self.scrape_clients.encode(f, &self.base_attributes)?;
+        self.encode_dropped(f)?;
+
+        if let Some(limit) = &self.series_limit {
+            let rejected = limit.rejected.load();
+            if rejected != 0 {
+                writeln!(
+                    f,
+                    "# series limit of {} reached, {} series rejected",
+                    limit.max_series, rejected
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl PromMetricRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /* registry without the automatic program / pkg_version attributes */
+    pub fn empty() -> Self {
+        PromMetricRegistry {
+            base_attributes: Vec::new(),
+            ..Self::default()
+        }
+    }
+
+    /*
+     * deterministic output for golden tests: program / pkg_version are always set to
+     * placeholders, scrape durations are recorded as 0 and _created is the epoch. Call
+     * before registering.
+     */
+    pub fn test_mode(mut self) -> Self {
+        self.base_attr("program", "test");
+        self.base_attr("pkg_version", "0.0.0");
+        self.clock = || UNIX_EPOCH;
+        self.test_mode = true;
+        self
+    }
+
+    pub fn with_base_attrs<K, V, I>(mut self, attrs: I) -> Self
+    where
+        K: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        for (key, value) in attrs {
+            self.base_attr(key, value);
+        }
+        self
+    }
+
+    /* only applies to metrics registered after this call */
+    pub fn base_attr<K: Into<Cow<'static, str>>, V: Into<Cow<'static, str>>>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> &mut Self {
+        let key = self.policy.label_key(key.into());
+        set_attr(&mut self.base_attributes, key, value.into());
+        self
+    }
+
+    pub fn base_attrs(&self) -> &[[Cow<'static, str>; 2]] {
+        &self.base_attributes
+    }
+
+    /*
+     * caps the number of exported series, registrations and merged series past the cap are
+     * dropped and counted. The registry's own series don't count.
+     */
+    pub fn max_series(&mut self, limit: usize) -> &mut Self {
+        if let Some(series_limit) = &mut self.series_limit {
+            series_limit.max_series = limit;
+            return self;
+        }
+
+        let rejected = Arc::new(IntCounter::default());
+        self.register_own_fn(&rejected, |counter, reg| {
+            reg.count("arc_metrics_series_rejected_total", counter);
+        });
+
+        self.series_limit = Some(SeriesLimit {
+            max_series: limit,
+            rejected: unsafe { std::mem::transmute::<&IntCounter, &'static IntCounter>(&rejected) },
+        });
+
+        self
+    }
+
+    /* exports arc_metrics_deprecated_family_rendered_total, counting deprecated family renders */
+    pub fn track_deprecated_renders(&mut self) -> &mut Self {
+        self.track_deprecated_renders = true;
+        self
+    }
+
+    /* <name>_created gauges for counters and histograms in the text format */
+    pub fn render_created(&mut self) -> &mut Self {
+        self.render_created = true;
+        self.invalidate_render_cache();
+        self
+    }
+
+    /* source of _created timestamps for registrations after this call */
+    pub fn created_clock(&mut self, clock: fn() -> SystemTime) -> &mut Self {
+        self.clock = clock;
+        self
+    }
+
+    /*
+     * name{version="..", ..} 1 for joining build metadata onto other series. version comes
+     * from pkg_details unless given in labels, ex. a commit hash from the caller's build.rs.
+     * Values are interned in the label cache.
+     */
+    pub fn register_build_info<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        labels: &[(&str, &str)],
+    ) -> &mut Self {
+        let mut attrs = Vec::with_capacity(labels.len() + 1);
+        if let Some(details) = pkg_details::try_get() {
+            attrs.push([Cow::Borrowed("version"), Cow::Borrowed(details.pkg_version)]);
+        }
+        for (key, value) in labels {
+            let key = self.label_value(key);
+            let value = self.label_value(value);
+            set_attr(&mut attrs, key, value);
+        }
+
+        self.register_static_fn(&(), |_, reg| {
+            let mut helper = reg.constant(name, 1);
+            for [key, value] in attrs {
+                helper.attr(key, value);
+            }
+        });
+        self
+    }
+
+    pub fn label_value(&self, value: &str) -> Cow<'static, str> {
+        Cow::Borrowed(self.label_cache.intern(value))
+    }
+
+    pub fn label_cache(&self) -> &LabelCache {
+        &self.label_cache
+    }
+
+    /*
+     * the text exposition format into a reused buffer, byte identical to to_string().
+     * Once every series has rendered once, only the per scrape reading vectors allocate.
+     */
+    pub fn encode_into(&self, buf: &mut String) {
+        buf.clear();
+        let _ = self.encode(buf, &|_| true);
+    }
+
+    /* every family with values read once, skipped series are left out */
+    pub fn gather(&self) -> Vec<MetricFamily> {
+        self.collect_families(&|_| true, false)
+    }
+
+    /*
+     * like gather() while zeroing counters and histograms (gauges are untouched).
+     * Increments racing with the reset are not lost, they are included in the next snapshot.
+     */
+    pub fn snapshot_and_reset(&self) -> Vec<MetricFamily> {
+        self.collect_families(&|_| true, true)
+    }
+
+    /* registered series, including ones a scrape currently skips */
+    pub fn len(&self) -> usize {
+        self.metrics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.metrics.is_empty()
+    }
+
+    /* sorted and deduplicated */
+    pub fn metric_names(&self) -> Vec<&str> {
+        let mut names = self
+            .metrics
+            .iter()
+            .map(|metric| metric.name.as_ref())
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    /* every series of name with its current value, series a scrape would skip are left out */
+    pub fn find(&self, name: &str) -> Vec<SampleView<'_>> {
+        self.metrics
+            .iter()
+            .filter(|metric| metric.name == name)
+            .filter_map(|metric| {
+                let _holder = self.hold(metric)?;
+                Some(SampleView {
+                    metric_type: metric.metric_type,
+                    attributes: &metric.attributes,
+                    value: metric.sample_value(&metric.read(false))?,
+                })
+            })
+            .collect()
+    }
+
+    pub(crate) fn collect_families(
+        &self,
+        filter: &dyn Fn(&str) -> bool,
+        reset: bool,
+    ) -> Vec<MetricFamily> {
+        let mut families = Vec::new();
+        let _ = self.for_each_family(filter, reset, |family| {
+            families.push(family.to_family());
+            Ok(())
+        });
+        families
+    }
+
+    /* prefixes metrics registered after this call, joined with _ like groups */
+    pub fn name_prefix<P: Into<String>>(&mut self, prefix: P) -> &mut Self {
+        self.name_prefix = Some(prefix.into());
+        self
+    }
+
+    /*
+     * orders labels by key instead of base, group then per metric attributes. Done once
+     * per series at registration, applies to registrations after this call.
+     */
+    pub fn sort_labels(&mut self, enabled: bool) -> &mut Self {
+        self.sort_labels = enabled;
+        self
+    }
+
+    /* switching to Sorted also sorts already registered metrics */
+    pub fn set_ordering(&mut self, ordering: MetricOrdering) -> &mut Self {
+        self.invalidate_render_cache();
+        self.ordering = ordering;
+        if ordering == MetricOrdering::Sorted {
+            self.metrics.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+        }
+        self
+    }
+
+    pub fn register<M: RegisterableMetric + 'static>(&mut self, metrics: &Arc<M>) {
+        self.register_fn(metrics, |m, reg| {
+            m.register(reg);
+        });
+    }
+
+    pub fn register_fn<T: 'static>(
+        &mut self,
+        metrics: &Arc<T>,
+        register: impl FnOnce(&'static T, &mut RegisterAction<'_>),
+    ) {
+        let metric_ref = self.hold_arc(metrics);
+        self.register_static_fn(metric_ref, register);
+    }
+
+    pub fn register_static<M: RegisterableMetric>(&mut self, metrics: &'static M) {
+        self.register_static_fn(metrics, |m, reg| {
+            m.register(reg);
+        });
+    }
+
+    /* no holder needed as the reference already lives forever */
+    pub fn register_static_fn<T: 'static>(
+        &mut self,
+        metrics: &'static T,
+        register: impl FnOnce(&'static T, &mut RegisterAction<'_>),
+    ) {
+        self.register_with_holder(metrics, None, register);
+    }
+
+    pub fn register_weak<M: RegisterableMetric + 'static>(&mut self, metrics: &Arc<M>) {
+        self.register_weak_fn(metrics, |m, reg| {
+            m.register(reg);
+        });
+    }
+
+    /* metrics stop rendering once every Arc is dropped, prune() removes them */
+    pub fn register_weak_fn<T: 'static>(
+        &mut self,
+        metrics: &Arc<T>,
+        register: impl FnOnce(&'static T, &mut RegisterAction<'_>),
+    ) {
+        self.weak_holders.push(WeakHolder {
+            holder: Arc::downgrade(metrics) as Weak<dyn Any>,
+            zeroed: AtomicBool::new(false),
+        });
+        let holder = self.weak_holders.len() - 1;
+
+        /* only read after upgrading the holder, see hold() */
+        let metric_ref = unsafe { std::mem::transmute::<&T, &'static T>(metrics) };
+        self.register_with_holder(metric_ref, Some(holder), register);
+    }
+
+    /*
+     * Prometheus keeps showing the last value of a series that disappears from scrapes for
+     * up to 5 minutes. With stale_zero the series of dropped weak holders are exported as
+     * 0 by the next scrape and only removed by a prune() after that, so dashboards see the
+     * drop instead of a frozen value. Counters resetting to 0 look like a restart to rate().
+     * Histograms and skip_zero series of dropped holders are still hidden right away.
+     */
+    pub fn stale_zero(&mut self, enabled: bool) -> &mut Self {
+        self.stale_zero = enabled;
+        self
+    }
+
+    /*
+     * leaves counters and histograms out of the output while they are 0, families with
+     * every series at 0 are left out entirely. Applies to series registered before and
+     * after this call, RegisterHelper::sparse() does the same for one group.
+     */
+    pub fn set_skip_zero(&mut self, enabled: bool) -> &mut Self {
+        self.skip_zero = enabled;
+        self
+    }
+
+    /* 0 is a meaningful gauge value, they are only left out with this set as well */
+    pub fn skip_zero_gauges(&mut self, enabled: bool) -> &mut Self {
+        self.skip_zero_gauges = enabled;
+        self
+    }
+
+    fn omits_zero(&self, metric: &RegisteredMetric) -> bool {
+        (self.skip_zero || metric.sparse)
+            && (metric.metric_type != MetricType::IntGauge || self.skip_zero_gauges)
+    }
+
+    /* drops metrics of weak holders that are gone and compacts the holder list */
+    pub fn prune(&mut self) {
+        self.invalidate_render_cache();
+
+        let alive = self
+            .weak_holders
+            .iter()
+            .map(|weak| {
+                weak.holder.strong_count() != 0
+                    || (self.stale_zero && !weak.zeroed.load(Ordering::Relaxed))
+            })
+            .collect::<Vec<_>>();
+
+        let mut remap = Vec::with_capacity(alive.len());
+        let mut next = 0;
+        for alive in &alive {
+            remap.push(next);
+            next += *alive as usize;
+        }
+
+        self.metrics.retain_mut(|metric| match metric.holder {
+            Some(holder) if !alive[holder] => false,
+            Some(holder) => {
+                metric.holder = Some(remap[holder]);
+                true
+            }
+            None => true,
+        });
+
+        /* the dead holders' addresses may be reused once their Weak is gone */
+        for (weak, _) in self
+            .weak_holders
+            .iter()
+            .zip(&alive)
+            .filter(|(_, alive)| !**alive)
+        {
+            self.namespaces
+                .release(Weak::as_ptr(&weak.holder) as *const () as usize);
+        }
+
+        let mut alive = alive.into_iter();
+        self.weak_holders.retain(|_| alive.next().unwrap());
+        self.update_series_gauge();
+    }
+
+    /*
+     * removes every series registered for this holder, ex. a stopped worker's metrics, and
+     * releases the registry's Arc and namespace claims. Returns the number of series removed.
+     */
+    pub fn unregister_holder<T: 'static>(&mut self, holder: &Arc<T>) -> usize {
+        let owner = Arc::as_ptr(holder) as usize;
+        self.invalidate_render_cache();
+
+        let registered = self.metrics.len();
+        self.metrics.retain(|metric| metric.owner != owner);
+        let removed = registered - self.metrics.len();
+        self.update_series_gauge();
+
+        /* only once no series points into it */
+        self.metric_holders.retain(|held| {
+            !held
+                .downcast_ref::<T>()
+                .is_some_and(|held| std::ptr::eq(held, Arc::as_ptr(holder)))
+        });
+        self.namespaces.release(owner);
+        removed
+    }
+
+    /*
+     * moves the holders and series of another registry into this one, ex. a library's own
+     * registry into the binary's. Series keep the attributes they were registered with,
+     * other settings like policy and limits of the merged registry are dropped. Nothing is
+     * merged if a series conflicts in type or duplicates one already registered here, other
+     * is then left as it was. On success other is left with only its own series (self
+     * metrics, rejected counters), those past this registry's max_series are dropped and
+     * counted.
+     */
+    pub fn merge(&mut self, other: &mut PromMetricRegistry) -> Result<(), Vec<policy::Violation>> {
+        self.merge_with(other, None, &[])
+    }
+
+    /* like merge, with prefix joined onto every merged name with _ */
+    pub fn merge_with_prefix(
+        &mut self,
+        other: &mut PromMetricRegistry,
+        prefix: &str,
+    ) -> Result<(), Vec<policy::Violation>> {
+        self.merge_with(other, Some(prefix), &[])
+    }
+
+    /* like merge, setting attrs on every merged series. Values are interned in the label cache. */
+    pub fn merge_with_attrs(
+        &mut self,
+        other: &mut PromMetricRegistry,
+        attrs: &[(&str, &str)],
+    ) -> Result<(), Vec<policy::Violation>> {
+        self.merge_with(other, None, attrs)
+    }
+
+    fn merge_with(
+        &mut self,
+        other: &mut PromMetricRegistry,
+        prefix: Option<&str>,
+        attrs: &[(&str, &str)],
+    ) -> Result<(), Vec<policy::Violation>> {
+        /* names and attributes as merged, swapped in for the checks and back on conflict */
+        let mut rewritten = Vec::with_capacity(other.metrics.len());
+        for metric in &other.metrics {
+            if metric.own {
+                rewritten.push((metric.name.clone(), None));
+                continue;
+            }
+            let name = match prefix {
+                Some(prefix) => Cow::Owned(format!("{}_{}", prefix, metric.name)),
+                None => metric.name.clone(),
+            };
+
+            let attributes = (!attrs.is_empty()).then(|| {
+                let mut attributes = metric.attributes.to_vec();
+                for (key, value) in attrs {
+                    let key = self.policy.label_key(self.label_value(key));
+                    set_attr(&mut attributes, key, self.label_value(value));
+                }
+                if self.sort_labels {
+                    attributes.sort_by(|[a, _], [b, _]| a.cmp(b));
+                }
+                Attributes::from(&attributes[..])
+            });
+            rewritten.push((name, attributes));
+        }
+
+        let swap = |metrics: &mut [RegisteredMetric], rewritten: &mut [(_, Option<_>)]| {
+            for (metric, (name, attributes)) in metrics.iter_mut().zip(rewritten) {
+                std::mem::swap(&mut metric.name, name);
+                if let Some(attributes) = attributes {
+                    std::mem::swap(&mut metric.attributes, attributes);
+                    metric.labels = OnceLock::new();
+                }
+            }
+        };
+        swap(&mut other.metrics, &mut rewritten);
+
+        let mut conflicts = Vec::new();
+        for metric in other.metrics.iter().filter(|metric| !metric.own) {
+            let kind = match existing_type(&self.metrics, self.ordering, metric) {
+                Some(existing) => policy::ViolationKind::TypeConflict {
+                    registered: metric.metric_type,
+                    existing,
+                },
+                None if contains_series(&self.metrics, self.ordering, metric) => {
+                    policy::ViolationKind::Duplicate
+                }
+                None => continue,
+            };
+
+            conflicts.push(policy::Violation {
+                kind,
+                name: metric.name.clone(),
+                location: metric.call_site.location(),
+            });
+        }
+
+        if !conflicts.is_empty() {
+            swap(&mut other.metrics, &mut rewritten);
+            return Err(conflicts);
+        }
+
+        self.invalidate_render_cache();
+        other.invalidate_render_cache();
+        let holder_offset = self.weak_holders.len();
+        self.weak_holders.append(&mut other.weak_holders);
+
+        let mut room = self
+            .series_limit
+            .map(|limit| (limit.max_series + self.own_series).saturating_sub(self.metrics.len()));
+        let mut rejected = HashSet::new();
+        self.staged.start(self.metrics.len());
+        /* own series stay with other, its limit and self metrics keep references into them */
+        for mut metric in std::mem::take(&mut other.metrics) {
+            if metric.own {
+                other.metrics.push(metric);
+                continue;
+            }
+            if let (Some(limit), Some(room)) = (self.series_limit, &mut room) {
+                if *room == 0 {
+                    let kind = policy::ViolationKind::CardinalityExceeded;
+                    let location = metric.call_site.location();
+                    if !self
+                        .violations
+                        .check(&self.policy, kind, &metric.name, location)
+                    {
+                        limit.rejected.inc();
+                        lost::record(lost::DropReason::Cardinality, 1);
+                        rejected.insert(metric.owner);
+                        continue;
+                    }
+                }
+                *room = room.saturating_sub(1);
+            }
+            metric.holder = metric.holder.map(|holder| holder + holder_offset);
+            self.metrics.push(metric);
+        }
+
+        /* holders whose series were all past the cap are dropped, the rest of other's stay */
+        let owners = self.metrics[self.staged.settled..]
+            .iter()
+            .map(|metric| metric.owner)
+            .collect::<HashSet<_>>();
+        for held in std::mem::take(&mut other.metric_holders) {
+            let address = Arc::as_ptr(&held) as *const () as usize;
+            if owners.contains(&address) {
+                self.metric_holders.push(held);
+            } else if !rejected.contains(&address) {
+                other.metric_holders.push(held);
+            }
+        }
+        self.staged
+            .commit(&mut self.metrics, &mut self.metric_holders, self.ordering);
+        other.update_series_gauge();
+        self.update_series_gauge();
+
+        Ok(())
+    }
+
+    /* keeps a weak holder alive while its values are read, None once it's dropped */
+    pub(crate) fn hold(&self, metric: &RegisteredMetric) -> Option<Option<Arc<dyn Any>>> {
+        match metric.holder {
+            Some(holder) => self.weak_holders[holder].holder.upgrade().map(Some),
+            None => Some(None),
+        }
+    }
+
+    /* reading for a series whose weak holder is gone, its value must not be dereferenced */
+    fn stale_reading(&self, metric: &RegisteredMetric) -> Reading {
+        let Some(holder) = metric.holder.filter(|_| self.stale_zero) else {
+            return Reading::Skipped;
+        };
+
+        self.weak_holders[holder]
+            .zeroed
+            .store(true, Ordering::Relaxed);
+
+        /* histogram bounds and summary quantiles lived in the dropped holder */
+        match metric.value {
+            MetricValue::Histogram(..) | MetricValue::Summary(..) => Reading::Skipped,
+            _ if metric.skip_zero => Reading::Skipped,
+            MetricValue::Float(..) => Reading::Float(FloatValue::new(0.0)),
+            _ => Reading::Value(0),
+        }
+    }
+
+    fn register_with_holder<T: 'static>(
+        &mut self,
+        metrics: &'static T,
+        holder: Option<usize>,
+        register: impl FnOnce(&'static T, &mut RegisterAction<'_>),
+    ) {
+        let mut action = self.action(metrics, holder);
+        register(metrics, &mut action);
+        self.staged
+            .commit(&mut self.metrics, &mut self.metric_holders, self.ordering);
+    }
+
+    /*
+     * the registry's own series, ex. arc_metrics_series_rejected_total. Always held as
+     * the registry keeps references into them and exempt from max_series.
+     */
+    pub(crate) fn register_own_fn<T: 'static>(
+        &mut self,
+        metrics: &Arc<T>,
+        register: impl FnOnce(&'static T, &mut RegisterAction<'_>),
+    ) {
+        self.metric_holders
+            .push(Arc::clone(metrics) as Arc<dyn Any>);
+        let metric_ref = unsafe { std::mem::transmute::<&T, &'static T>(metrics) };
+
+        let registered = self.metrics.len();
+        let mut action = self.action(metric_ref, None);
+        action.options.own = true;
+        register(metric_ref, &mut action);
+        self.staged
+            .commit(&mut self.metrics, &mut self.metric_holders, self.ordering);
+        self.own_series += self.metrics.len() - registered;
+    }
+
+    /* a single counter without a holder struct, attrs are set on the returned helper */
+    #[track_caller]
+    pub fn register_counter<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        counter: &Arc<IntCounter>,
+    ) -> RegisterHelper<'_> {
+        let counter = self.hold_arc(counter);
+        let mut helper = self.action(counter, None).into_helper();
+        helper.count(name, counter);
+        helper
+    }
+
+    #[track_caller]
+    pub fn register_gauge<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        gauge: &Arc<IntGauge>,
+    ) -> RegisterHelper<'_> {
+        let gauge = self.hold_arc(gauge);
+        let mut helper = self.action(gauge, None).into_helper();
+        helper.gauge(name, gauge);
+        helper
+    }
+
+    /* like register_counter for a `static`, nothing is held */
+    #[track_caller]
+    pub fn register_static_counter<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        counter: &'static IntCounter,
+    ) -> RegisterHelper<'_> {
+        let mut helper = self.action(counter, None).into_helper();
+        helper.count(name, counter);
+        helper
+    }
+
+    #[track_caller]
+    pub fn register_static_gauge<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        gauge: &'static IntGauge,
+    ) -> RegisterHelper<'_> {
+        let mut helper = self.action(gauge, None).into_helper();
+        helper.gauge(name, gauge);
+        helper
+    }
+
+    /*
+     * allows us to keep static references as we own an Arc copy. Held by the commit ending
+     * the registration, unless every series was rejected.
+     */
+    fn hold_arc<T: 'static>(&mut self, metric: &Arc<T>) -> &'static T {
+        self.staged.holder = Some(Arc::clone(metric) as Arc<dyn Any>);
+        unsafe { std::mem::transmute::<&T, &'static T>(metric) }
+    }
+
+    pub(crate) fn action<T: 'static>(
+        &mut self,
+        metrics: &'static T,
+        holder: Option<usize>,
+    ) -> RegisterAction<'_> {
+        self.invalidate_render_cache();
+        self.staged.start(self.metrics.len());
+
+        RegisterAction {
+            name_prefix: self.name_prefix.clone(),
+            metrics: &mut self.metrics,
+            staged: &mut self.staged,
+            holders: &mut self.metric_holders,
+            base_attributes: self.base_attributes.clone(),
+            options: RegisterOptions {
+                series_limit: self.series_limit,
+                ordering: self.ordering,
+                holder,
+                sort_labels: self.sort_labels,
+                policy: self.policy,
+                self_metrics: self.self_metrics,
+                clock: self.clock,
+                own: false,
+                own_series: self.own_series,
+            },
+            namespaces: &mut self.namespaces,
+            violations: &self.violations,
+            owner: metrics as *const T as usize,
+        }
+    }
+}
+
+pub struct RegisterAction<'a> {
+    pub(crate) metrics: &'a mut Vec<RegisteredMetric>,
+    pub(crate) staged: &'a mut Staged,
+    pub(crate) holders: &'a mut Vec<Arc<dyn Any>>,
+    pub(crate) name_prefix: Option<String>,
+    pub(crate) base_attributes: Vec<[Cow<'static, str>; 2]>,
+    pub(crate) options: RegisterOptions,
+    pub(crate) namespaces: &'a mut namespace::Namespaces,
+    pub(crate) violations: &'a policy::Violations,
+    /* address of the metrics holder, namespace claims are found through it */
+    pub(crate) owner: usize,
+}
+
+impl<'a> RegisterAction<'a> {
+    pub fn child(&mut self) -> RegisterAction<'_> {
+        RegisterAction {
+            metrics: self.metrics,
+            staged: self.staged,
+            holders: self.holders,
+            name_prefix: self.name_prefix.clone(),
+            base_attributes: self.base_attributes.clone(),
+            options: self.options,
+            namespaces: self.namespaces,
+            violations: self.violations,
+            owner: self.owner,
+        }
+    }
+
+    pub fn name_prefix<S: Into<String>>(&mut self, prefix: S) -> &mut Self {
+        self.name_prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn base_attr<K: Into<Cow<'static, str>>, V: Into<Cow<'static, str>>>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> &mut Self {
+        let key = self.options.policy.label_key(key.into());
+        let value = value.into();
+        set_attr(&mut self.base_attributes, key, value);
+        self
+    }
+
+    /* drops an inherited attribute for metrics registered through this action */
+    pub fn remove_attr(&mut self, key: &str) -> &mut Self {
+        self.base_attributes.retain(|[k, _]| k != key);
+        self
+    }
+
+    #[track_caller]
+    pub fn count<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        count: &'static IntCounter,
+    ) -> RegisterHelper<'_> {
+        self.metric(name, &count.0, MetricType::IntCounter)
+    }
+
+    #[track_caller]
+    pub fn gauge<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        gauge: &'static IntGauge,
+    ) -> RegisterHelper<'_> {
+        self.metric(name, &gauge.0, MetricType::IntGauge)
+    }
+
+    #[track_caller]
+    pub fn float_count<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        count: &'static FloatCounter,
+    ) -> RegisterHelper<'_> {
+        let mut helper = self.empty();
+        helper.float_count(name, count);
+        helper
+    }
+
+    #[track_caller]
+    pub fn float_gauge<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        gauge: &'static FloatGauge,
+    ) -> RegisterHelper<'_> {
+        let mut helper = self.empty();
+        helper.float_gauge(name, gauge);
+        helper
+    }
+
+    #[track_caller]
+    pub fn histogram<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        histogram: &'static IntHistogram,
+    ) -> RegisterHelper<'_> {
+        let mut helper = self.empty();
+        helper.histogram(name, histogram);
+        helper
+    }
+
+    #[track_caller]
+    pub fn summary<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        summary: &'static Summary,
+    ) -> RegisterHelper<'_> {
+        let mut helper = self.empty();
+        helper.summary(name, summary);
+        helper
+    }
+
+    #[track_caller]
+    pub fn duration_gauge<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        gauge: &'static units::Seconds<IntGauge>,
+    ) -> RegisterHelper<'_> {
+        let mut helper = self.empty();
+        helper.duration_gauge(name, gauge);
+        helper
+    }
+
+    #[track_caller]
+    pub fn bytes_gauge<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        gauge: &'static units::Bytes<IntGauge>,
+    ) -> RegisterHelper<'_> {
+        let mut helper = self.empty();
+        helper.bytes_gauge(name, gauge);
+        helper
+    }
+
+    #[track_caller]
+    pub fn sharded_count<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        count: &'static ShardedCounter,
+    ) -> RegisterHelper<'_> {
+        let mut helper = self.empty();
+        helper.sharded_count(name, count);
+        helper
+    }
+
+    #[track_caller]
+    pub fn sampled_histogram<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        sampled: &'static helpers::Sampled<IntHistogram>,
+    ) -> RegisterHelper<'_> {
+        let mut helper = self.empty();
+        helper.sampled_histogram(name, sampled);
+        helper
+    }
+
+    #[track_caller]
+    pub fn constant<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        value: u64,
+    ) -> RegisterHelper<'_> {
+        let mut helper = self.empty();
+        helper.constant(name, value);
+        helper
+    }
+
+    #[track_caller]
+    pub(crate) fn metric<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        value: &'static AtomicU64,
+        metric_type: MetricType,
+    ) -> RegisterHelper<'_> {
+        let mut helper = self.empty();
+        helper.metric(name, value, metric_type);
+        helper
+    }
+
+    pub fn group<N: Into<Cow<'static, str>>>(&mut self, prefix: N) -> RegisterHelper<'_> {
+        self.start(Some(prefix))
+    }
+
+    pub fn empty(&mut self) -> RegisterHelper<'_> {
+        self.start::<String>(None)
+    }
+
+    /* metrics added inside the closure are committed when it returns */
+    pub fn scope<N, F>(&mut self, prefix: N, register: F) -> &mut Self
+    where
+        N: Into<Cow<'static, str>>,
+        F: FnOnce(&mut RegisterScope<'_>),
+    {
+        register(&mut RegisterScope {
+            helper: self.group(prefix),
+        });
+        self
+    }
+
+    /* registers a nested metrics struct with prefix appended to this action's prefix */
+    pub fn nested<N: Into<Cow<'static, str>>, M: RegisterableMetric>(
+        &mut self,
+        prefix: N,
+        metrics: &'static M,
+    ) -> &mut Self {
+        let prefix = prefix.into();
+        let mut child = self.child();
+        child.name_prefix = Some(match child.name_prefix.take() {
+            Some(parent) => format!("{}_{}", parent, prefix),
+            None => prefix.into_owned(),
+        });
+        metrics.register(&mut child);
+        self
+    }
+
+    /* like empty() for an action that isn't needed afterwards */
+    fn into_helper(self) -> RegisterHelper<'a> {
+        RegisterHelper {
+            metrics: self.metrics,
+            staged: self.staged,
+            holders: self.holders,
+            commit: true,
+            name_prefix: self.name_prefix.map(Cow::Owned),
+            attributes: self.base_attributes,
+            registered: Vec::new(),
+            options: self.options,
+            deprecation: None,
+            #[cfg(feature = "blackbox")]
+            blackbox: false,
+            sparse: false,
+            namespaces: self.namespaces,
+            violations: self.violations,
+            owner: self.owner,
+        }
+    }
+
+    pub(crate) fn start<N: Into<Cow<'static, str>>>(
+        &mut self,
+        prefix: Option<N>,
+    ) -> RegisterHelper<'_> {
+        let attributes = self.base_attributes.clone();
+
+        let name_prefix = match (&self.name_prefix, prefix) {
+            (Some(prefix), None) => Some(Cow::Owned(prefix.clone())),
+            (None, Some(prefix)) => Some(prefix.into()),
+            (Some(a), Some(b)) => {
+                let b = b.into();
+                Some(Cow::Owned(format!("{}_{}", a, b)))
+            }
+            (None, None) => None,
+        };
+
+        RegisterHelper {
+            metrics: self.metrics,
+            staged: self.staged,
+            holders: self.holders,
+            commit: false,
+            name_prefix,
+            attributes,
+            registered: Vec::new(),
+            options: self.options,
+            deprecation: None,
+            #[cfg(feature = "blackbox")]
+            blackbox: false,
+            sparse: false,
+            namespaces: self.namespaces,
+            violations: self.violations,
+            owner: self.owner,
+        }
+    }
+}
+
+/* closure based registration, see RegisterAction::scope */
+pub struct RegisterScope<'a> {
+    pub(crate) helper: RegisterHelper<'a>,
+}
+
+impl RegisterScope<'_> {
+    #[track_caller]
+    pub fn count<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        count: &'static IntCounter,
+    ) -> &mut Self {
+        self.helper.count(name, count);
+        self
+    }
+
+    #[track_caller]
+    pub fn gauge<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        gauge: &'static IntGauge,
+    ) -> &mut Self {
+        self.helper.gauge(name, gauge);
+        self
+    }
+
+    #[track_caller]
+    pub fn float_count<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        count: &'static FloatCounter,
+    ) -> &mut Self {
+        self.helper.float_count(name, count);
+        self
+    }
+
+    #[track_caller]
+    pub fn float_gauge<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        gauge: &'static FloatGauge,
+    ) -> &mut Self {
+        self.helper.float_gauge(name, gauge);
+        self
+    }
+
+    #[track_caller]
+    pub fn histogram<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        histogram: &'static IntHistogram,
+    ) -> &mut Self {
+        self.helper.histogram(name, histogram);
+        self
+    }
+
+    #[track_caller]
+    pub fn summary<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        summary: &'static Summary,
+    ) -> &mut Self {
+        self.helper.summary(name, summary);
+        self
+    }
+
+    /* nested scope with the prefixes joined by _ */
+    pub fn scope<N, F>(&mut self, prefix: N, register: F) -> &mut Self
+    where
+        N: Into<Cow<'static, str>>,
+        F: FnOnce(&mut RegisterScope<'_>),
+    {
+        register(&mut RegisterScope {
+            helper: self.helper.nested(Some(prefix.into())),
+        });
+        self
+    }
+
+    /* the attribute only applies to metrics added inside the closure */
+    pub fn with_attr<K, V, F>(&mut self, key: K, value: V, register: F) -> &mut Self
+    where
+        K: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+        F: FnOnce(&mut RegisterScope<'_>),
+    {
+        let mut helper = self.helper.nested(None);
+        helper.attr(key, value);
+        register(&mut RegisterScope { helper });
+        self
+    }
+}
+
+pub struct RegisterHelper<'a> {
+    pub(crate) name_prefix: Option<Cow<'static, str>>,
+    pub(crate) metrics: &'a mut Vec<RegisteredMetric>,
+    pub(crate) staged: &'a mut Staged,
+    pub(crate) holders: &'a mut Vec<Arc<dyn Any>>,
+    /* set for a helper outside of a register_fn, its drop ends the registration */
+    pub(crate) commit: bool,
+    pub(crate) attributes: Vec<[Cow<'static, str>; 2]>,
+    pub(crate) registered: Vec<RegisteredMetric>,
+    pub(crate) options: RegisterOptions,
+    deprecation: Option<Arc<Deprecation>>,
+    #[cfg(feature = "blackbox")]
+    pub(crate) blackbox: bool,
+    sparse: bool,
+    pub(crate) namespaces: &'a namespace::Namespaces,
+    pub(crate) violations: &'a policy::Violations,
+    pub(crate) owner: usize,
+}
+
+impl RegisterHelper<'_> {
+    /*
+     * nested group with the prefixes joined by _, starts with the attributes and
+     * deprecation set so far; later changes to this helper don't reach the child
+     */
+    pub fn group<N: Into<Cow<'static, str>>>(&mut self, prefix: N) -> RegisterHelper<'_> {
+        self.nested(Some(prefix.into()))
+    }
+
+    pub(crate) fn nested(&mut self, prefix: Option<Cow<'static, str>>) -> RegisterHelper<'_> {
+        let name_prefix = match (&self.name_prefix, prefix) {
+            (Some(parent), Some(prefix)) => Some(Cow::Owned(format!("{}_{}", parent, prefix))),
+            (parent, prefix) => prefix.or_else(|| parent.clone()),
+        };
+
+        RegisterHelper {
+            metrics: self.metrics,
+            staged: self.staged,
+            holders: self.holders,
+            commit: false,
+            name_prefix,
+            attributes: self.attributes.clone(),
+            registered: Vec::new(),
+            options: self.options,
+            deprecation: self.deprecation.clone(),
+            #[cfg(feature = "blackbox")]
+            blackbox: self.blackbox,
+            sparse: self.sparse,
+            namespaces: self.namespaces,
+            violations: self.violations,
+            owner: self.owner,
+        }
+    }
+
+    /* records every series of this group in a blackbox::Recorder */
+    #[cfg(feature = "blackbox")]
+    pub fn blackbox(&mut self) -> &mut Self {
+        self.blackbox = true;
+        self
+    }
+
+    /* set_skip_zero() for the series of this group, ex. error kinds that are mostly 0 */
+    pub fn sparse(&mut self) -> &mut Self {
+        self.sparse = true;
+        self
+    }
+
+    /* marks every family in this group as deprecated in its HELP text */
+    pub fn deprecated<S: Into<Cow<'static, str>>, N: Into<Cow<'static, str>>>(
+        &mut self,
+        since: S,
+        note: N,
+    ) -> &mut Self {
+        self.deprecation = Some(Arc::new(Deprecation {
+            since: since.into(),
+            note: note.into(),
+            rendered: IntCounter::default(),
+        }));
+        self
+    }
+
+    pub fn attr<K: Into<Cow<'static, str>>, V: Into<Cow<'static, str>>>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> &mut Self {
+        let key = self.options.policy.label_key(key.into());
+        let value = value.into();
+        set_attr(&mut self.attributes, key, value);
+        self
+    }
+
+    /* attr() for every label of a typed label set */
+    pub fn attrs<L: labels::EncodeLabels + ?Sized>(&mut self, labels: &L) -> &mut Self {
+        for (key, value) in labels.labels() {
+            self.attr(key, value);
+        }
+        self
+    }
+
+    /* attr that rejects label names failing escape::check_label_name */
+    pub fn try_attr<K: Into<Cow<'static, str>>, V: Into<Cow<'static, str>>>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> Result<&mut Self, escape::LabelNameError> {
+        let key = key.into();
+        escape::check_label_name(&key)?;
+        Ok(self.attr(key, value))
+    }
+
+    /* CounterVec children are pruned by the render path, see CounterVec::set_ttl */
+    pub(crate) fn expires(&mut self, expiry: labels::Expiry) -> &mut Self {
+        for reg in &mut self.registered {
+            reg.expiry = Some(expiry);
+        }
+        self
+    }
+
+    pub fn remove_attr(&mut self, key: &str) -> &mut Self {
+        self.attributes.retain(|[k, _]| k != key);
+        self
+    }
+
+    #[track_caller]
+    pub fn count<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        count: &'static IntCounter,
+    ) -> &mut Self {
+        self.metric(name, &count.0, MetricType::IntCounter)
+    }
+
+    #[track_caller]
+    pub fn gauge<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        gauge: &'static IntGauge,
+    ) -> &mut Self {
+        self.metric(name, &gauge.0, MetricType::IntGauge)
+    }
+
+    /* rendered as a counter, the value is written as a float */
+    #[track_caller]
+    pub fn float_count<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        count: &'static FloatCounter,
+    ) -> &mut Self {
+        self.push(
+            name,
+            MetricValue::Float(&count.0),
+            MetricType::IntCounter,
+            false,
+        )
+    }
+
+    #[track_caller]
+    pub fn float_gauge<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        gauge: &'static FloatGauge,
+    ) -> &mut Self {
+        self.push(
+            name,
+            MetricValue::Float(&gauge.0),
+            MetricType::IntGauge,
+            false,
+        )
+    }
+
+    #[track_caller]
+    pub fn histogram<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        histogram: &'static IntHistogram,
+    ) -> &mut Self {
+        self.push(
+            name,
+            MetricValue::Histogram(histogram, 1),
+            MetricType::IntHistogram,
+            false,
+        )
+    }
+
+    /* quantiles rendered with a quantile label, plus _sum and _count */
+    #[track_caller]
+    pub fn summary<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        summary: &'static Summary,
+    ) -> &mut Self {
+        self.push(
+            name,
+            MetricValue::Summary(summary),
+            MetricType::Summary,
+            false,
+        )
+    }
+
+    /* _seconds is appended when the name doesn't already end with it */
+    #[track_caller]
+    pub fn duration_gauge<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        gauge: &'static units::Seconds<IntGauge>,
+    ) -> &mut Self {
+        let name = with_unit_suffix(name.into(), "_seconds");
+        self.gauge(name, gauge.gauge()).unit("seconds")
+    }
+
+    /* _bytes is appended when the name doesn't already end with it */
+    #[track_caller]
+    pub fn bytes_gauge<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        gauge: &'static units::Bytes<IntGauge>,
+    ) -> &mut Self {
+        let name = with_unit_suffix(name.into(), "_bytes");
+        self.gauge(name, gauge.gauge()).unit("bytes")
+    }
+
+    fn unit(&mut self, unit: &'static str) -> &mut Self {
+        if let Some(reg) = self.registered.last_mut() {
+            reg.unit = Some(unit);
+        }
+        self
+    }
+
+    #[track_caller]
+    pub fn sharded_count<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        count: &'static ShardedCounter,
+    ) -> &mut Self {
+        self.push(
+            name,
+            MetricValue::Sharded(count),
+            MetricType::IntCounter,
+            false,
+        )
+    }
+
+    /* rendered scaled by the sampling factor, which is exported as <name>_sampling_factor */
+    #[track_caller]
+    pub fn sampled_histogram<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        sampled: &'static helpers::Sampled<IntHistogram>,
+    ) -> &mut Self {
+        let name = name.into();
+        let factor = sampled.factor_gauge();
+
+        self.push(
+            format!("{}_sampling_factor", name),
+            MetricValue::Atomic(&factor.0),
+            MetricType::IntGauge,
+            false,
+        );
+        self.push(
+            name,
+            MetricValue::Histogram(sampled.inner(), factor.load()),
+            MetricType::IntHistogram,
+            false,
+        )
+    }
+
+    /* attrs only apply to this metric and take precedence over the group's attr() */
+    #[track_caller]
+    pub fn count_with_attrs<N, I, K, V>(
+        &mut self,
+        name: N,
+        count: &'static IntCounter,
+        attrs: I,
+    ) -> &mut Self
+    where
+        N: Into<Cow<'static, str>>,
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+    {
+        self.count(name, count).last_attrs(attrs)
+    }
+
+    #[track_caller]
+    pub fn gauge_with_attrs<N, I, K, V>(
+        &mut self,
+        name: N,
+        gauge: &'static IntGauge,
+        attrs: I,
+    ) -> &mut Self
+    where
+        N: Into<Cow<'static, str>>,
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+    {
+        self.gauge(name, gauge).last_attrs(attrs)
+    }
+
+    #[track_caller]
+    pub fn histogram_with_attrs<N, I, K, V>(
+        &mut self,
+        name: N,
+        histogram: &'static IntHistogram,
+        attrs: I,
+    ) -> &mut Self
+    where
+        N: Into<Cow<'static, str>>,
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+    {
+        self.histogram(name, histogram).last_attrs(attrs)
+    }
+
+    fn last_attrs<I, K, V>(&mut self, attrs: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+    {
+        let mut own = Vec::new();
+        for (key, value) in attrs {
+            let key = self.options.policy.label_key(key.into());
+            set_attr(&mut own, key, value.into());
+        }
+
+        if let Some(last) = self.registered.last_mut() {
+            last.attributes = Attributes::from(&own[..]);
+        }
+        self
+    }
+
+    #[track_caller]
+    pub fn metric<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        value: &'static AtomicU64,
+        metric_type: MetricType,
+    ) -> &mut Self {
+        self.metric_opt(name, value, metric_type, false)
+    }
+
+    #[track_caller]
+    pub fn metric_opt<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        value: &'static AtomicU64,
+        metric_type: MetricType,
+        skip_zero: bool,
+    ) -> &mut Self {
+        self.push(name, MetricValue::Atomic(value), metric_type, skip_zero)
+    }
+
+    /* gauge that always reports value */
+    #[track_caller]
+    pub fn constant<N: Into<Cow<'static, str>>>(&mut self, name: N, value: u64) -> &mut Self {
+        self.push(name, MetricValue::Const(value), MetricType::IntGauge, false)
+    }
+
+    #[track_caller]
+    pub(crate) fn push<N: Into<Cow<'static, str>>>(
+        &mut self,
+        name: N,
+        value: MetricValue,
+        metric_type: MetricType,
+        skip_zero: bool,
+    ) -> &mut Self {
+        let name = match &self.name_prefix {
+            Some(prefix) => Cow::Owned(format!("{}_{}", prefix, name.into())),
+            None => name.into(),
+        };
+
+        self.registered.push(RegisteredMetric {
+            metric_type,
+            name,
+            value,
+            attributes: Attributes::default(),
+            skip_zero,
+            sparse: false,
+            deprecation: None,
+            #[cfg(feature = "blackbox")]
+            blackbox: false,
+            holder: self.options.holder,
+            owner: self.owner,
+            labels: OnceLock::new(),
+            call_site: CallSite::here(),
+            created: UNIX_EPOCH,
+            expiry: None,
+            exemplar: None,
+            own: false,
+            unit: None,
+        });
+
+        self
+    }
+}
+
+/* type of an already registered series with the same name but another type */
+fn existing_type(
+    metrics: &[RegisteredMetric],
+    ordering: MetricOrdering,
+    reg: &RegisteredMetric,
+) -> Option<MetricType> {
+    let conflict = |item: &RegisteredMetric| {
+        (item.name == reg.name && item.metric_type != reg.metric_type).then_some(item.metric_type)
+    };
+
+    match ordering {
+        MetricOrdering::Sorted => {
+            let start = metrics.partition_point(|item| *item.name < *reg.name);
+            metrics[start..]
+                .iter()
+                .take_while(|item| item.name == reg.name)
+                .find_map(conflict)
+        }
+        MetricOrdering::Insertion => metrics.iter().find_map(conflict),
+    }
+}
+
+/* whether a series with reg's name, type and attributes is registered */
+fn contains_series(
+    metrics: &[RegisteredMetric],
+    ordering: MetricOrdering,
+    reg: &RegisteredMetric,
+) -> bool {
+    let key = reg.sort_key();
+    match ordering {
+        MetricOrdering::Sorted => {
+            let index = metrics.partition_point(|item| item.sort_key() < key);
+            metrics
+                .get(index)
+                .is_some_and(|item| item.sort_key() == key)
+        }
+        MetricOrdering::Insertion => metrics.iter().any(|item| item.sort_key() == key),
+    }
+}
+
+/* insert in place rather than re-sorting everything per registration */
+fn insert_index(
+    metrics: &[RegisteredMetric],
+    ordering: MetricOrdering,
+    reg: &RegisteredMetric,
+) -> usize {
+    match ordering {
+        MetricOrdering::Sorted => {
+            let key = reg.sort_key();
+            metrics.partition_point(|item| item.sort_key() <= key)
+        }
+        MetricOrdering::Insertion => {
+            let same = |item: &RegisteredMetric| {
+                item.name == reg.name && item.metric_type == reg.metric_type
+            };
+            match metrics.iter().rposition(same) {
+                Some(last) => last + 1,
+                None => metrics.len(),
+            }
+        }
+    }
+}
+
+impl Drop for RegisterHelper<'_> {
+    fn drop(&mut self) {
+        let policy = self.options.policy;
+        let ordering = self.options.ordering;
+        let created = (self.options.clock)();
+        let check = |kind, reg: &RegisteredMetric| {
+            let location = reg.call_site.location();
+            self.violations.check(&policy, kind, &reg.name, location)
+        };
+
+        for mut reg in self.registered.drain(..) {
+            reg.name = policy
+                .convention
+                .apply(std::mem::take(&mut reg.name), reg.metric_type);
+
+            if !self.options.own
+                && !self.namespaces.allows(self.owner, &reg.name)
+                && !check(policy::ViolationKind::Misuse, &reg)
+            {
+                self.namespaces.reject();
+                continue;
+            }
+
+            /* per metric attributes were stashed in reg.attributes until now */
+            reg.attributes = if reg.attributes.is_empty() && !self.options.sort_labels {
+                Attributes::from(&self.attributes[..])
+            } else {
+                let mut attributes = self.attributes.clone();
+                for [key, value] in reg.attributes.iter() {
+                    set_attr(&mut attributes, key.clone(), value.clone());
+                }
+                if self.options.sort_labels {
+                    attributes.sort_by(|[a, _], [b, _]| a.cmp(b));
+                    attributes.dedup();
+                }
+                Attributes::from(&attributes[..])
+            };
+
+            if !policy::label_violations(&reg.attributes, policy.max_labels)
+                .all(|kind| check(kind, &reg))
+            {
+                continue;
+            }
+
+            if !policy
+                .convention
+                .lints(&reg.name, reg.metric_type)
+                .into_iter()
+                .all(|lint| check(policy::ViolationKind::Naming { lint }, &reg))
+            {
+                continue;
+            }
+
+            if policy.on_invalid_name != policy::OnViolation::Ignore {
+                let valid = escape::is_legacy_name(&reg.name)
+                    && reg
+                        .attributes
+                        .iter()
+                        .all(|[key, _]| escape::check_label_name(key).is_ok());
+                if !valid && !check(policy::ViolationKind::InvalidName, &reg) {
+                    continue;
+                }
+            }
+
+            let settled = self.staged.settled(self.metrics);
+            if policy.on_duplicate != policy::OnViolation::Ignore {
+                let duplicate = contains_series(settled, ordering, &reg)
+                    || self.staged.duplicate(self.metrics, &reg);
+                if duplicate && !check(policy::ViolationKind::Duplicate, &reg) {
+                    continue;
+                }
+            }
+
+            if policy.on_type_conflict != policy::OnViolation::Ignore {
+                if let Some(existing) = existing_type(settled, ordering, &reg)
+                    .or_else(|| self.staged.existing_type(self.metrics, &reg))
+                {
+                    let kind = policy::ViolationKind::TypeConflict {
+                        registered: reg.metric_type,
+                        existing,
+                    };
+                    if !check(kind, &reg) {
+                        continue;
+                    }
+                }
+            }
+
+            if let Some(limit) = self.options.series_limit.filter(|_| !self.options.own) {
+                if limit.max_series <= self.metrics.len() - self.options.own_series
+                    && !check(policy::ViolationKind::CardinalityExceeded, &reg)
+                {
+                    limit.rejected.inc();
+                    lost::record(lost::DropReason::Cardinality, 1);
+                    continue;
+                }
+            }
+            reg.deprecation = self.deprecation.clone();
+            #[cfg(feature = "blackbox")]
+            {
+                reg.blackbox = self.blackbox;
+            }
+            reg.sparse = self.sparse;
+
+            /* deprecation applies to the whole family */
+            let range = family_range(settled, ordering, &reg);
+            let staged = self.staged.family(self.metrics, &reg);
+            if let Some(deprecation) = &reg.deprecation {
+                let family = |item: &&mut RegisteredMetric| {
+                    item.name == reg.name && item.metric_type == reg.metric_type
+                };
+                for item in self.metrics[range].iter_mut() {
+                    item.deprecation = Some(deprecation.clone());
+                }
+                if let Some(first) = staged {
+                    for item in self.metrics[first..].iter_mut().filter(family) {
+                        item.deprecation = Some(deprecation.clone());
+                    }
+                }
+            } else if let Some(index) = range.clone().next().or(staged) {
+                reg.deprecation = self.metrics[index].deprecation.clone();
+            }
+
+            reg.created = created;
+            reg.own = self.options.own;
+            self.staged.push(self.metrics, reg);
+        }
+
+        if self.commit {
+            self.staged.commit(self.metrics, self.holders, ordering);
+        }
+
+        if let Some(metrics) = self.options.self_metrics {
+            metrics.set_series(self.metrics.len());
+        }
+    }
+}
+
+/* includes attributes so output doesn't depend on registration order */
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct SortKey<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) metric: MetricType,
+    pub(crate) attributes: &'a [[Cow<'static, str>; 2]],
+}
+
+impl RegisteredMetric {
+    pub(crate) fn sort_key(&self) -> SortKey<'_> {
+        SortKey {
+            name: &self.name,
+            metric: self.metric_type,
+            attributes: &self.attributes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        borrow::Cow,
+        sync::Arc,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use crate::{
+        helpers::RegisterableMetric, scrape::ScrapeContext, IntCounter, IntGauge, IntHistogram,
+        MetricFamily, MetricOrdering, MetricType, PromMetricRegistry, RegisterAction, Sample,
+        SampleValue,
+    };
+
+    use super::{CallSite, Digits};
+
+    #[derive(Debug, Default)]
+    struct Met {
+        a: IntCounter,
+        b: IntCounter,
+        c: IntGauge,
+    }
+
+    #[test]
+    fn metrics_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::new();
+        reg.base_attributes.push(["prefix".into(), "set".into()]);
+
+        reg.register_fn(&met, |m, reg| {
+            reg.name_prefix("base_prefix");
+
+            reg.group("prefix")
+                .count("a", &m.a)
+                .metric_opt("b", &m.b.0, crate::MetricType::IntCounter, true)
+                .attr("test", "2");
+
+            reg.gauge("c", &m.c);
+        });
+
+        println!("{}", reg);
+
+        met.b.inc();
+        println!("{}", reg);
+    }
+
+    #[test]
+    fn max_series_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+        reg.max_series(3);
+
+        reg.register_fn(&met, |m, reg| {
+            reg.count("a", &m.a);
+            reg.count("b", &m.b);
+            reg.gauge("c", &m.c);
+        });
+
+        for _ in 0..4 {
+            let dynamic = Arc::new(Met::default());
+            reg.register_fn(&dynamic, |m, reg| {
+                reg.count("dynamic", &m.a);
+            });
+        }
+
+        /* the rejected counter is the registry's own and doesn't take a slot */
+        assert_eq!(reg.metrics.len(), 4);
+        assert_eq!(reg.series_limit.unwrap().rejected.load(), 4);
+        /* the holders of rejected registrations aren't kept */
+        assert_eq!(reg.metric_holders.len(), 2);
+
+        let out = reg.to_string();
+        assert!(out.contains("arc_metrics_series_rejected_total 4\n"));
+        assert!(out.ends_with("# series limit of 3 reached, 4 series rejected\n"));
+        assert!(!out.contains("dynamic"));
+
+        /* later self-metrics aren't rejected either */
+        reg.enable_self_metrics();
+        assert_eq!(reg.metrics.len(), 7);
+        assert_eq!(reg.series_limit.unwrap().rejected.load(), 4);
+    }
+
+    #[test]
+    fn max_series_merge_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+        reg.max_series(2);
+        reg.register_fn(&met, |m, reg| {
+            reg.count("a", &m.a);
+        });
+
+        let mut other = PromMetricRegistry::empty();
+        let merged = [Arc::new(Met::default()), Arc::new(Met::default())];
+        for (i, met) in merged.iter().enumerate() {
+            other.register_fn(met, |m, reg| {
+                reg.count("merged", &m.a).attr("i", i.to_string());
+            });
+        }
+        reg.merge(&mut other).unwrap();
+
+        assert_eq!(reg.metrics.len(), 3);
+        assert_eq!(reg.series_limit.unwrap().rejected.load(), 1);
+        assert!(reg.to_string().contains("merged{i=\"0\"} 0\n"));
+        assert_eq!(reg.metric_holders.len(), 3);
+        assert_eq!(Arc::strong_count(&merged[1]), 1);
+    }
+
+    #[test]
+    fn merge_own_series_test() {
+        let mut reg = PromMetricRegistry::empty();
+        reg.max_series(4);
+        reg.enable_self_metrics();
+
+        let met = Arc::new(Met::default());
+        let mut other = PromMetricRegistry::empty();
+        other.max_series(1);
+        other.enable_self_metrics();
+        other.register_fn(&met, |m, reg| {
+            reg.count("a", &m.a);
+        });
+
+        /* both have their own rejected counter and self metrics, those aren't merged */
+        reg.merge(&mut other).unwrap();
+        assert!(reg.to_string().contains("a 0\n"));
+        assert_eq!(other.metrics.len(), 4);
+        assert!(other.metrics.iter().all(|metric| metric.own));
+        drop(reg);
+
+        for _ in 0..3 {
+            let dynamic = Arc::new(Met::default());
+            other.register_fn(&dynamic, |m, reg| {
+                reg.count("dynamic", &m.a);
+            });
+        }
+        assert_eq!(other.series_limit.unwrap().rejected.load(), 2);
+        assert!(other
+            .to_string()
+            .contains("arc_metrics_series_rejected_total 2\n"));
+    }
+
+    #[test]
+    fn histogram_test() {
+        let histogram = Arc::new(IntHistogram::new([10, 100]));
+        let mut reg = PromMetricRegistry::empty();
+
+        reg.register_fn(&histogram, |h, reg| {
+            reg.histogram("latency", h).attr("path", "/");
+        });
+
+        histogram.observe(5);
+        histogram.observe(10);
+        histogram.observe(50);
+        histogram.observe(500);
+
+        assert_eq!(
+            reg.to_string(),
+            "# HELP latency\n\
+            # TYPE latency histogram\n\
+            latency_bucket{path=\"/\",le=\"10\"} 2\n\
+            latency_bucket{path=\"/\",le=\"100\"} 3\n\
+            latency_bucket{path=\"/\",le=\"+Inf\"} 4\n\
+            latency_sum{path=\"/\"} 565\n\
+            latency_count{path=\"/\"} 4\n"
+        );
+    }
+
+    #[test]
+    fn gather_test() {
+        let met = Arc::new(Met::default());
+        let histogram = Arc::new(IntHistogram::new([10, 100]));
+        let mut reg = PromMetricRegistry::empty();
+
+        reg.register_fn(&met, |m, reg| {
+            reg.count("old", &m.a).attr("kind", "a");
+            reg.count("old", &m.b)
+                .attr("kind", "b")
+                .deprecated("0.2", "use new");
+            reg.group("skipped")
+                .metric_opt("gauge", &m.c.0, MetricType::IntGauge, true);
+        });
+        reg.register_fn(&histogram, |h, reg| {
+            reg.histogram("latency", h);
+        });
+
+        met.a.inc_by(2);
+        histogram.observe(5);
+        histogram.observe(500);
+
+        let attrs = |kind: &'static str| vec![["kind".into(), kind.into()]];
+        let families = reg.snapshot_and_reset();
+        assert_eq!(
+            families,
+            [
+                MetricFamily {
+                    name: "latency".into(),
+                    metric_type: MetricType::IntHistogram,
+                    help: None,
+                    unit: None,
+                    samples: vec![Sample {
+                        attributes: vec![],
+                        value: SampleValue::Histogram {
+                            buckets: vec![(10, 1), (100, 1)],
+                            sum: 505,
+                            count: 2,
+                        },
+                    }],
+                },
+                MetricFamily {
+                    name: "old".into(),
+                    metric_type: MetricType::IntCounter,
+                    help: Some("(DEPRECATED since 0.2: use new)".into()),
+                    unit: None,
+                    samples: vec![
+                        Sample {
+                            attributes: attrs("a"),
+                            value: SampleValue::Value(2),
+                        },
+                        Sample {
+                            attributes: attrs("b"),
+                            value: SampleValue::Value(0),
+                        },
+                    ],
+                },
+            ]
+        );
+
+        assert_eq!(histogram.count(), 0);
+        assert_eq!(reg.gather()[1].samples[0].value, SampleValue::Value(0));
+    }
+
+    #[test]
+    fn deprecated_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+        reg.track_deprecated_renders();
+
+        reg.register_fn(&met, |m, reg| {
+            reg.count("old", &m.a).attr("kind", "a");
+            reg.count("old", &m.b)
+                .attr("kind", "b")
+                .deprecated("0.2", "use new");
+            reg.gauge("new", &m.c);
+        });
+
+        let expected = "# HELP new\n\
+            # TYPE new gauge\n\
+            new 0\n\
+            # HELP old (DEPRECATED since 0.2: use new)\n\
+            # TYPE old counter\n\
+            old{kind=\"a\"} 0\n\
+            old{kind=\"b\"} 0\n\
+            # HELP arc_metrics_deprecated_family_rendered_total\n\
+            # TYPE arc_metrics_deprecated_family_rendered_total counter\n";
+
+        assert_eq!(
+            reg.to_string(),
+            format!(
+                "{}arc_metrics_deprecated_family_rendered_total{{family=\"old\"}} 1\n",
+                expected
+            )
+        );
+        assert!(reg.to_string().ends_with("_total{family=\"old\"} 2\n"));
+    }
+
+    #[test]
+    fn label_value_test() {
+        let reg = PromMetricRegistry::new();
+        let config = [String::from("eu-west"), String::from("eu-west")];
+
+        let a = reg.label_value(&config[0]);
+        let b = reg.label_value(&config[1]);
+        let c = reg.label_value("us-east");
+
+        assert_eq!(a, "eu-west");
+        assert!(std::ptr::eq(a.as_ptr(), b.as_ptr()));
+        assert!(!std::ptr::eq(a.as_ptr(), c.as_ptr()));
+        assert_eq!(reg.label_cache().len(), 2);
+    }
+
+    #[test]
+    fn register_static_test() {
+        struct StaticMet {
+            hits: IntCounter,
+            size: IntGauge,
+        }
+
+        impl RegisterableMetric for StaticMet {
+            fn register(&'static self, register: &mut RegisterAction) {
+                register.count("hits", &self.hits);
+                register.gauge("size", &self.size);
+            }
+        }
+
+        static METRICS: StaticMet = StaticMet {
+            hits: IntCounter::new(),
+            size: IntGauge::new(),
+        };
+        static EXTRA: IntCounter = IntCounter::new();
+
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_static(&METRICS);
+        reg.register_static_fn(&EXTRA, |c, reg| {
+            reg.count("extra", c);
+        });
+
+        METRICS.hits.inc();
+        EXTRA.inc_by(2);
+
+        assert!(reg.metric_holders.is_empty());
+        assert_eq!(
+            reg.to_string(),
+            "# HELP extra\n# TYPE extra counter\nextra 2\n\
+            # HELP hits\n# TYPE hits counter\nhits 1\n\
+            # HELP size\n# TYPE size gauge\nsize 0\n"
+        );
+    }
+
+    #[test]
+    fn static_initial_value_test() {
+        static REQUESTS: IntCounter = IntCounter::with_value(5);
+        static CAPACITY: IntGauge = IntGauge::with_value(32);
+
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_static_counter("requests", &REQUESTS)
+            .attr("path", "/");
+        reg.register_static_gauge("capacity", &CAPACITY);
+
+        REQUESTS.inc();
+        CAPACITY.dec();
+        assert!(reg.metric_holders.is_empty());
+        assert_eq!(
+            reg.to_string(),
+            "# HELP capacity\n# TYPE capacity gauge\ncapacity 31\n\
+            # HELP requests\n# TYPE requests counter\nrequests{path=\"/\"} 6\n"
+        );
+
+        assert_eq!(IntCounter::default().load(), IntCounter::new().load());
+        assert_eq!(IntGauge::default().load(), 0);
+    }
+
+    #[test]
+    fn base_attrs_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty().with_base_attrs([("env", "prod")]);
+        assert_eq!(reg.base_attrs(), [["env", "prod"]]);
+
+        reg.register_fn(&met, |m, reg| {
+            reg.count("before", &m.a);
+        });
+
+        reg.base_attr("region", "eu");
+        reg.register_fn(&met, |m, reg| {
+            reg.count("after", &m.b);
+        });
+
+        assert_eq!(
+            reg.to_string(),
+            "# HELP after\n# TYPE after counter\nafter{env=\"prod\",region=\"eu\"} 0\n\
+            # HELP before\n# TYPE before counter\nbefore{env=\"prod\"} 0\n"
+        );
+    }
+
+    #[test]
+    fn registration_order_test() {
+        #[derive(Default)]
+        struct Fixture {
+            get: IntCounter,
+            post: IntCounter,
+            errors: IntCounter,
+            active: IntGauge,
+        }
+
+        let met = Arc::new(Fixture::default());
+        met.get.inc_by(1);
+        met.post.inc_by(2);
+        met.errors.inc_by(3);
+        met.active.set(4);
+
+        let register = |reg: &mut PromMetricRegistry, item: usize| {
+            reg.register_fn(&met, |m, reg| match item {
+                0 => {
+                    reg.count("requests", &m.get).attr("method", "get");
+                }
+                1 => {
+                    reg.count("requests", &m.post).attr("method", "post");
+                }
+                2 => {
+                    reg.count("requests", &m.errors)
+                        .attr("method", "get")
+                        .attr("error", "true");
+                }
+                _ => {
+                    reg.gauge("active", &m.active);
+                }
+            });
+        };
+
+        /* every permutation of the four registrations */
+        let mut orders = vec![vec![]];
+        for _ in 0..4 {
+            let mut next = Vec::new();
+            for order in &orders {
+                for i in (0..4).filter(|i| !order.contains(i)) {
+                    let mut order: Vec<usize> = order.clone();
+                    order.push(i);
+                    next.push(order);
+                }
+            }
+            orders = next;
+        }
+        assert_eq!(orders.len(), 24);
+
+        let renders = orders
+            .iter()
+            .map(|order| {
+                let mut reg = PromMetricRegistry::empty();
+                for item in order {
+                    register(&mut reg, *item);
+                }
+                reg.to_string()
+            })
+            .collect::<Vec<_>>();
+
+        assert!(renders.iter().all(|render| render == &renders[0]));
+
+        /* the same with every series of an order in a single registration */
+        for order in &orders {
+            let mut reg = PromMetricRegistry::empty();
+            reg.register_fn(&met, |m, reg| {
+                for item in order {
+                    match item {
+                        0 => {
+                            reg.count("requests", &m.get).attr("method", "get");
+                        }
+                        1 => {
+                            reg.count("requests", &m.post).attr("method", "post");
+                        }
+                        2 => {
+                            reg.count("requests", &m.errors)
+                                .attr("method", "get")
+                                .attr("error", "true");
+                        }
+                        _ => {
+                            reg.gauge("active", &m.active);
+                        }
+                    }
+                }
+            });
+            assert_eq!(reg.to_string(), renders[0], "{:?}", order);
+        }
+
+        assert_eq!(
+            renders[0],
+            "# HELP active\n# TYPE active gauge\nactive 4\n\
+            # HELP requests\n# TYPE requests counter\n\
+            requests{method=\"get\"} 1\n\
+            requests{method=\"get\",error=\"true\"} 3\n\
+            requests{method=\"post\"} 2\n"
+        );
+    }
+
+    #[test]
+    fn override_attrs_test() {
+        let met = Arc::new(Met::default());
+        let mut reg =
+            PromMetricRegistry::empty().with_base_attrs([("component", "server"), ("env", "prod")]);
+
+        reg.register_fn(&met, |m, reg| {
+            reg.base_attr("component", "replicator");
+            reg.count("action", &m.a);
+
+            reg.group("helper")
+                .count("b", &m.b)
+                .attr("component", "helper")
+                .attr("kind", "x")
+                .attr("kind", "y");
+
+            let mut child = reg.child();
+            child.remove_attr("env");
+            child.count("removed", &m.a).remove_attr("component");
+        });
+
+        assert_eq!(
+            reg.to_string(),
+            "# HELP action\n# TYPE action counter\n\
+            action{component=\"replicator\",env=\"prod\"} 0\n\
+            # HELP helper_b\n# TYPE helper_b counter\n\
+            helper_b{component=\"helper\",env=\"prod\",kind=\"y\"} 0\n\
+            # HELP removed\n# TYPE removed counter\n\
+            removed 0\n"
+        );
+    }
+
+    #[test]
+    fn insertion_ordering_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+        reg.set_ordering(MetricOrdering::Insertion);
+
+        reg.register_fn(&met, |m, reg| {
+            reg.count("requests", &m.a).attr("method", "post");
+            reg.count("errors", &m.b);
+            reg.count("requests", &m.b).attr("method", "get");
+            reg.gauge("active", &m.c);
+        });
+
+        let insertion = "# HELP requests\n# TYPE requests counter\n\
+            requests{method=\"post\"} 0\n\
+            requests{method=\"get\"} 0\n\
+            # HELP errors\n# TYPE errors counter\nerrors 0\n\
+            # HELP active\n# TYPE active gauge\nactive 0\n";
+        assert_eq!(reg.to_string(), insertion);
+
+        reg.set_ordering(MetricOrdering::Sorted);
+        assert_eq!(
+            reg.to_string(),
+            "# HELP active\n# TYPE active gauge\nactive 0\n\
+            # HELP errors\n# TYPE errors counter\nerrors 0\n\
+            # HELP requests\n# TYPE requests counter\n\
+            requests{method=\"get\"} 0\n\
+            requests{method=\"post\"} 0\n"
+        );
+    }
+
+    #[test]
+    fn snapshot_and_reset_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&met, |m, reg| {
+            reg.count("a", &m.a);
+            reg.gauge("c", &m.c);
+        });
+
+        met.c.set(7);
+        assert_eq!(met.c.swap(5), 7);
+
+        let threads = (0..8)
+            .map(|_| {
+                let met = met.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100_000 {
+                        met.a.inc();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let value = |family: &MetricFamily| match family.samples[..] {
+            [Sample {
+                value: SampleValue::Value(value),
+                ..
+            }] => value,
+            _ => panic!("expected a single value in {:?}", family),
+        };
+
+        let mut total = 0;
+        while threads.iter().any(|thread| !thread.is_finished()) {
+            let families = reg.snapshot_and_reset();
+            assert_eq!(value(&families[1]), 5);
+            total += value(&families[0]);
+        }
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let families = reg.snapshot_and_reset();
+        total += value(&families[0]);
+        assert_eq!(total, 800_000);
+        assert_eq!(families[0].name, "a");
+        assert_eq!(met.a.take(), 0);
+        assert_eq!(met.c.load(), 5);
+    }
+
+    #[test]
+    fn escaped_label_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty().with_base_attrs([("path", "C:\\tmp\n\"x\"")]);
+        reg.register_fn(&met, |m, reg| {
+            reg.count("a", &m.a).deprecated("0.1", "multi\nline");
+        });
+
+        assert_eq!(
+            reg.to_string(),
+            "# HELP a (DEPRECATED since 0.1: multi\\nline)\n\
+            # TYPE a counter\n\
+            a{path=\"C:\\\\tmp\\n\\\"x\\\"\"} 0\n"
+        );
+    }
+
+    #[test]
+    fn register_weak_test() {
+        let kept = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+
+        reg.register_fn(&kept, |m, reg| {
+            reg.count("a", &m.a).attr("conn", "static");
+        });
+        let connections = (0..3)
+            .map(|i| {
+                let conn = Arc::new(Met::default());
+                reg.register_weak_fn(&conn, |m, reg| {
+                    reg.count("a", &m.a).attr("conn", i.to_string());
+                });
+                conn
+            })
+            .collect::<Vec<_>>();
+
+        let [first, second, third] = <[_; 3]>::try_from(connections).unwrap();
+        drop(first);
+        second.a.inc();
+
+        let expected = "# HELP a\n\
+             # TYPE a counter\n\
+             a{conn=\"1\"} 1\n\
+             a{conn=\"2\"} 0\n\
+             a{conn=\"static\"} 0\n";
+        assert_eq!(reg.to_string(), expected);
+        assert_eq!(reg.snapshot_and_reset()[0].samples.len(), 3);
+
+        reg.prune();
+        assert_eq!(reg.metrics.len(), 3);
+        assert_eq!(reg.weak_holders.len(), 2);
+
+        drop(third);
+        reg.prune();
+        assert_eq!(reg.weak_holders.len(), 1);
+        second.a.inc_by(5);
+        assert_eq!(
+            reg.to_string(),
+            "# HELP a\n\
+             # TYPE a counter\n\
+             a{conn=\"1\"} 5\n\
+             a{conn=\"static\"} 0\n"
+        );
+    }
+
+    #[test]
+    fn register_counter_test() {
+        let mut reg = PromMetricRegistry::empty();
+        let counter = Arc::new(IntCounter::new());
+        let gauge = Arc::new(IntGauge::new());
+
+        reg.register_counter("jobs_total", &counter)
+            .attr("queue", "default");
+        reg.register_gauge("workers", &gauge);
+        assert_eq!(Arc::strong_count(&counter), 2);
+
+        let weak = Arc::downgrade(&counter);
+        counter.inc_by(3);
+        drop(counter);
+        gauge.set(4);
+
+        assert!(weak.upgrade().is_some());
+        assert_eq!(
+            reg.to_string(),
+            "# HELP jobs_total\n\
+             # TYPE jobs_total counter\n\
+             jobs_total{queue=\"default\"} 3\n\
+             # HELP workers\n\
+             # TYPE workers gauge\n\
+             workers 4\n"
+        );
+
+        assert_eq!(reg.unregister_holder(&gauge), 1);
+        assert_eq!(Arc::strong_count(&gauge), 1);
+    }
+
+    #[test]
+    fn introspection_test() {
+        #[derive(Default)]
+        struct Met {
+            get: IntCounter,
+            post: IntCounter,
+            latency: IntHistogram,
+        }
+
+        let met = Arc::new(Met {
+            latency: IntHistogram::new([10]),
+            ..Default::default()
+        });
+        let mut reg = PromMetricRegistry::empty().with_base_attrs([("service", "api")]);
+        assert!(reg.is_empty());
+        reg.register_fn(&met, |m, reg| {
+            reg.count("requests", &m.get).attr("method", "get");
+            reg.count("requests", &m.post).attr("method", "post");
+            reg.histogram("latency", &m.latency);
+        });
+        met.post.inc_by(2);
+        met.latency.observe(4);
+
+        assert_eq!(reg.len(), 3);
+        assert_eq!(reg.metric_names(), ["latency", "requests"]);
+
+        let attrs = |method: &'static str| {
+            vec![
+                [Cow::Borrowed("service"), Cow::Borrowed("api")],
+                [Cow::Borrowed("method"), Cow::Borrowed(method)],
+            ]
+        };
+        let found = reg.find("requests");
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].metric_type, MetricType::IntCounter);
+        assert_eq!(found[0].attributes, &attrs("get")[..]);
+        assert_eq!(found[0].value, SampleValue::Value(0));
+        assert_eq!(found[1].attributes, &attrs("post")[..]);
+        assert_eq!(found[1].value, SampleValue::Value(2));
+
+        assert_eq!(
+            reg.find("latency")[0].value,
+            SampleValue::Histogram {
+                buckets: vec![(10, 1)],
+                sum: 4,
+                count: 1,
+            }
+        );
+        assert!(reg.find("missing").is_empty());
+    }
+
+    #[test]
+    fn unregister_holder_test() {
+        let mut reg = PromMetricRegistry::empty();
+        reg.require_namespaces();
+
+        let workers = [Arc::new(Met::default()), Arc::new(Met::default())];
+        for (i, worker) in workers.iter().enumerate() {
+            reg.register_fn(worker, |m, reg| {
+                reg.claim_namespace(format!("worker{}", i)).unwrap();
+                reg.count(format!("worker{}_a", i), &m.a);
+                reg.gauge(format!("worker{}_c", i), &m.c);
+            });
+        }
+        workers[1].a.inc();
+
+        assert_eq!(reg.unregister_holder(&workers[0]), 2);
+        assert_eq!(Arc::strong_count(&workers[0]), 1);
+        assert_eq!(reg.namespace_claims().len(), 1);
+        assert_eq!(reg.unregister_holder(&workers[0]), 0);
+        assert_eq!(reg.unregister_holder(&Arc::new(Met::default())), 0);
+
+        let rendered = reg.to_string();
+        assert!(!rendered.contains("worker0"));
+        assert!(rendered.contains("worker1_a 1\n"));
+
+        /* the address can be reused without inheriting claims or series */
+        drop(workers);
+        let next = Arc::new(Met::default());
+        reg.register_fn(&next, |m, reg| {
+            reg.count("worker0_a", &m.a);
+        });
+        assert!(!reg.to_string().contains("worker0"));
+    }
+
+    #[test]
+    fn skip_zero_test() {
+        let met = Arc::new(Met::default());
+        let histogram = Arc::new(IntHistogram::new([10]));
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&met, |m, reg| {
+            reg.count("errors", &m.a).attr("kind", "timeout");
+            reg.count("errors", &m.b).attr("kind", "reset");
+            reg.gauge("c", &m.c);
+        });
+        reg.register_fn(&histogram, |h, reg| {
+            reg.histogram("latency", h);
+        });
+        reg.set_skip_zero(true);
+
+        /* zero gauges stay, all zero families leave no HELP / TYPE */
+        assert_eq!(reg.to_string(), "# HELP c\n# TYPE c gauge\nc 0\n");
+
+        met.b.inc();
+        assert_eq!(
+            reg.to_string(),
+            "# HELP c\n\
+             # TYPE c gauge\n\
+             c 0\n\
+             # HELP errors\n\
+             # TYPE errors counter\n\
+             errors{kind=\"reset\"} 1\n"
+        );
+
+        reg.skip_zero_gauges(true);
+        histogram.observe(5);
+        let text = reg.to_string();
+        assert!(!text.contains("# TYPE c"));
+        assert!(text.contains("latency_count 1\n"));
+
+        reg.set_skip_zero(false);
+        assert!(reg.to_string().contains("errors{kind=\"timeout\"} 0\n"));
+    }
+
+    #[test]
+    fn sparse_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&met, |m, reg| {
+            reg.count("a", &m.a);
+            let mut errors = reg.empty();
+            errors.sparse();
+            errors.count("b", &m.b);
+            errors.gauge("c", &m.c);
+        });
+
+        assert_eq!(
+            reg.to_string(),
+            "# HELP a\n\
+             # TYPE a counter\n\
+             a 0\n\
+             # HELP c\n\
+             # TYPE c gauge\n\
+             c 0\n"
+        );
+
+        reg.skip_zero_gauges(true);
+        met.b.inc();
+        assert_eq!(
+            reg.to_string(),
+            "# HELP a\n\
+             # TYPE a counter\n\
+             a 0\n\
+             # HELP b\n\
+             # TYPE b counter\n\
+             b 1\n"
+        );
+    }
+
+    #[test]
+    fn stale_zero_test() {
+        let mut reg = PromMetricRegistry::empty();
+        reg.stale_zero(true);
+
+        let conn = Arc::new(Met::default());
+        reg.register_weak_fn(&conn, |m, reg| {
+            reg.count("a", &m.a).attr("conn", "1");
+            reg.gauge("c", &m.c).attr("conn", "1");
+        });
+        conn.a.inc_by(3);
+        conn.c.set(7);
+        drop(conn);
+
+        /* pruning before a scrape exported the zeros keeps the series */
+        reg.prune();
+        assert_eq!(reg.weak_holders.len(), 1);
+
+        let expected = "# HELP a\n\
+             # TYPE a counter\n\
+             a{conn=\"1\"} 0\n\
+             # HELP c\n\
+             # TYPE c gauge\n\
+             c{conn=\"1\"} 0\n";
+        assert_eq!(reg.to_string(), expected);
+        assert_eq!(reg.to_string(), expected);
+        assert_eq!(reg.gather().len(), 2);
+
+        reg.prune();
+        assert!(reg.weak_holders.is_empty());
+        assert!(reg.metrics.is_empty());
+        assert_eq!(reg.to_string(), "");
+    }
+
+    #[test]
+    fn build_info_test() {
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_build_info(
+            "app_build_info",
+            &[
+                ("version", "1.2.3"),
+                ("commit", "abc123"),
+                ("rustc", "1.77"),
+            ],
+        );
+
+        let expected = "# HELP app_build_info\n\
+             # TYPE app_build_info gauge\n\
+             app_build_info{version=\"1.2.3\",commit=\"abc123\",rustc=\"1.77\"} 1\n";
+        assert_eq!(reg.to_string(), expected);
+        assert_eq!(
+            reg.snapshot_and_reset()[0].samples[0].value,
+            SampleValue::Value(1)
+        );
+        assert_eq!(reg.to_string(), expected);
+    }
+
+    #[test]
+    fn call_site_size_test() {
+        let expected = match cfg!(any(debug_assertions, feature = "diagnostics")) {
+            true => std::mem::size_of::<usize>(),
+            false => 0,
+        };
+        assert_eq!(std::mem::size_of::<CallSite>(), expected);
+    }
+
+    #[test]
+    fn digits_test() {
+        let mut values = vec![0, 9, 10, 99, 100, 101, 999, 1000, u64::MAX, u64::MAX - 1];
+        values.extend((0..64).map(|shift| 1u64 << shift));
+        values.extend((1..20).map(|exp| 10u64.pow(exp) - 1));
+
+        for value in values {
+            assert_eq!(Digits::new(value).as_str(), value.to_string());
+        }
+    }
+
+    #[test]
+    fn sort_labels_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty().with_base_attrs([("zone", "eu"), ("app", "x")]);
+        reg.sort_labels(true);
+
+        reg.register_fn(&met, |m, reg| {
+            let mut group = reg.group("http");
+            group.attr("method", "get").attr("app", "x");
+            group.count_with_attrs("requests", &m.a, [("code", "200"), ("zone", "eu")]);
+            group.count("errors", &m.b);
+        });
+
+        assert_eq!(
+            reg.to_string(),
+            "# HELP http_errors\n\
+             # TYPE http_errors counter\n\
+             http_errors{app=\"x\",method=\"get\",zone=\"eu\"} 0\n\
+             # HELP http_requests\n\
+             # TYPE http_requests counter\n\
+             http_requests{app=\"x\",code=\"200\",method=\"get\",zone=\"eu\"} 0\n"
+        );
+    }
+
+    #[test]
+    fn merge_test() {
+        let (app_met, lib_met) = (Arc::new(Met::default()), Arc::new(Met::default()));
+        let mut app = PromMetricRegistry::empty().with_base_attrs([("app", "x"), ("zone", "eu")]);
+        let mut lib = PromMetricRegistry::empty().with_base_attrs([("app", "x"), ("lib", "foo")]);
+
+        app.register_fn(&app_met, |m, reg| {
+            reg.count("requests", &m.a);
+        });
+        lib.register_weak_fn(&lib_met, |m, reg| {
+            reg.count("requests", &m.a);
+            reg.gauge("open", &m.c);
+        });
+
+        app_met.a.inc();
+        lib_met.a.inc_by(2);
+        lib_met.c.set(3);
+        app.merge(&mut lib).unwrap();
+
+        assert_eq!(
+            app.to_string(),
+            "# HELP open\n\
+             # TYPE open gauge\n\
+             open{app=\"x\",lib=\"foo\"} 3\n\
+             # HELP requests\n\
+             # TYPE requests counter\n\
+             requests{app=\"x\",lib=\"foo\"} 2\n\
+             requests{app=\"x\",zone=\"eu\"} 1\n"
+        );
+
+        /* merged weak holders still stop rendering once dropped */
+        drop(lib_met);
+        app.prune();
+        assert_eq!(
+            app.to_string(),
+            "# HELP requests\n# TYPE requests counter\nrequests{app=\"x\",zone=\"eu\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn merge_with_test() {
+        let met = Arc::new(Met::default());
+        let registry = || {
+            let mut reg = PromMetricRegistry::empty().with_base_attrs([("app", "x")]);
+            reg.register_fn(&met, |m, reg| {
+                reg.count("requests", &m.a);
+            });
+            reg
+        };
+
+        let mut app = registry();
+        app.merge_with_prefix(&mut registry(), "libfoo").unwrap();
+        app.merge_with_attrs(&mut registry(), &[("lib", "bar")])
+            .unwrap();
+        assert_eq!(
+            app.to_string(),
+            "# HELP libfoo_requests\n\
+             # TYPE libfoo_requests counter\n\
+             libfoo_requests{app=\"x\"} 0\n\
+             # HELP requests\n\
+             # TYPE requests counter\n\
+             requests{app=\"x\"} 0\n\
+             requests{app=\"x\",lib=\"bar\"} 0\n"
+        );
+
+        let rendered = app.to_string();
+        let mut lib = registry();
+        let duplicate = app.merge_with_attrs(&mut lib, &[]).unwrap_err();
+        assert_eq!(duplicate.len(), 1);
+        assert_eq!(duplicate[0].kind, crate::policy::ViolationKind::Duplicate);
+
+        let mut gauges = PromMetricRegistry::empty().with_base_attrs([("app", "y")]);
+        gauges.register_fn(&met, |m, reg| {
+            reg.gauge("requests", &m.c);
+        });
+        let conflict = app.merge(&mut gauges).unwrap_err();
+        assert_eq!(
+            conflict[0].kind,
+            crate::policy::ViolationKind::TypeConflict {
+                registered: crate::MetricType::IntGauge,
+                existing: crate::MetricType::IntCounter,
+            }
+        );
+        assert_eq!(app.to_string(), rendered);
+
+        /* the registries that failed to merge are left as they were */
+        assert_eq!(
+            lib.to_string(),
+            "# HELP requests\n# TYPE requests counter\nrequests{app=\"x\"} 0\n"
+        );
+        assert_eq!(
+            gauges.to_string(),
+            "# HELP requests\n# TYPE requests gauge\nrequests{app=\"y\"} 0\n"
+        );
+        let mut prefixed = registry();
+        assert!(app.merge_with_prefix(&mut prefixed, "libfoo").is_err());
+        assert!(prefixed.to_string().contains("\nrequests{app=\"x\"} 0\n"));
+
+        let mut other = registry();
+        app.merge_with_prefix(&mut other, "libbaz").unwrap();
+        assert_eq!(other.to_string(), "");
+    }
+
+    #[test]
+    fn per_metric_attrs_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+
+        reg.register_fn(&met, |m, reg| {
+            reg.group("conn")
+                .attr("pool", "main")
+                .count_with_attrs("bytes", &m.a, [("direction", "tx")])
+                .count_with_attrs("bytes", &m.b, [("direction", "rx"), ("pool", "spare")])
+                .gauge("open", &m.c)
+                .attr("region", "eu");
+        });
+
+        assert_eq!(
+            reg.to_string(),
+            "# HELP conn_bytes\n\
+             # TYPE conn_bytes counter\n\
+             conn_bytes{pool=\"main\",region=\"eu\",direction=\"tx\"} 0\n\
+             conn_bytes{pool=\"spare\",region=\"eu\",direction=\"rx\"} 0\n\
+             # HELP conn_open\n\
+             # TYPE conn_open gauge\n\
+             conn_open{pool=\"main\",region=\"eu\"} 0\n"
+        );
+    }
+
+    #[test]
+    fn nested_group_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+
+        reg.register_fn(&met, |m, reg| {
+            let mut server = reg.group("server");
+            server.attr("instance", "a").gauge("up", &m.c);
+            {
+                let mut http = server.group("http");
+                http.attr("proto", "h2");
+                http.group("requests")
+                    .attr("method", "get")
+                    .count("total", &m.a);
+                http.count("errors", &m.b);
+            }
+            server.attr("zone", "eu");
+        });
+
+        assert_eq!(
+            reg.to_string(),
+            "# HELP server_http_errors\n\
+             # TYPE server_http_errors counter\n\
+             server_http_errors{instance=\"a\",proto=\"h2\"} 0\n\
+             # HELP server_http_requests_total\n\
+             # TYPE server_http_requests_total counter\n\
+             server_http_requests_total{instance=\"a\",proto=\"h2\",method=\"get\"} 0\n\
+             # HELP server_up\n\
+             # TYPE server_up gauge\n\
+             server_up{instance=\"a\",zone=\"eu\"} 0\n"
+        );
+    }
+
+    #[test]
+    fn test_mode_test() {
+        let render = |program: &'static str, version: &'static str| {
+            let met = Arc::new(Met::default());
+            let mut reg = PromMetricRegistry::empty()
+                .with_base_attrs([("program", program), ("pkg_version", version)])
+                .test_mode();
+            reg.register_fn(&met, |m, reg| {
+                reg.count("a", &m.a);
+            });
+
+            reg.render_with_context(ScrapeContext::client("prometheus"));
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            reg.render_with_context(ScrapeContext::client("prometheus"))
+        };
+
+        let first = render("service-a", "1.0.0");
+        assert_eq!(first, render("service-b", "2.3.4"));
+        assert_eq!(
+            first,
+            "# HELP a\n\
+             # TYPE a counter\n\
+             a{program=\"test\",pkg_version=\"0.0.0\"} 0\n\
+             # HELP arc_metrics_scrape_duration_us_total\n\
+             # TYPE arc_metrics_scrape_duration_us_total counter\n\
+             arc_metrics_scrape_duration_us_total{program=\"test\",pkg_version=\"0.0.0\",client=\"prometheus\"} 0\n\
+             # HELP arc_metrics_scrapes_total\n\
+             # TYPE arc_metrics_scrapes_total counter\n\
+             arc_metrics_scrapes_total{program=\"test\",pkg_version=\"0.0.0\",client=\"prometheus\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn scope_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+
+        reg.register_fn(&met, |m, reg| {
+            reg.count("plain", &m.a).attr("style", "builder");
+
+            reg.scope("db", |scope| {
+                scope.count("queries", &m.a);
+                scope.with_attr("kind", "write", |scope| {
+                    scope.count("queries", &m.b);
+                });
+                scope.scope("pool", |scope| {
+                    scope.gauge("size", &m.c);
+                });
+            });
+
+            reg.group("db")
+                .count("errors", &m.b)
+                .attr("style", "builder");
+        });
+
+        assert_eq!(
+            reg.to_string(),
+            "# HELP db_errors\n\
+             # TYPE db_errors counter\n\
+             db_errors{style=\"builder\"} 0\n\
+             # HELP db_pool_size\n\
+             # TYPE db_pool_size gauge\n\
+             db_pool_size 0\n\
+             # HELP db_queries\n\
+             # TYPE db_queries counter\n\
+             db_queries 0\n\
+             db_queries{kind=\"write\"} 0\n\
+             # HELP plain\n\
+             # TYPE plain counter\n\
+             plain{style=\"builder\"} 0\n"
+        );
+    }
+
+    #[test]
+    fn created_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+        reg.created_clock(|| UNIX_EPOCH + Duration::from_secs(1_600_000_000));
+        reg.register_fn(&met, |m, reg| {
+            reg.count("requests_total", &m.a).attr("path", "/");
+            reg.gauge("active", &m.c);
+        });
+        assert!(!reg.to_string().contains("_created"));
+
+        reg.render_created();
+        assert_eq!(
+            reg.to_string(),
+            "# HELP active\n# TYPE active gauge\nactive 0\n\
+             # HELP requests_total\n# TYPE requests_total counter\nrequests_total{path=\"/\"} 0\n\
+             # HELP requests_created\n# TYPE requests_created gauge\n\
+             requests_created{path=\"/\"} 1600000000.000\n"
+        );
+
+        /* a later registration gets its own timestamp */
+        reg.created_clock(|| UNIX_EPOCH + Duration::from_millis(1_700_000_000_500));
+        reg.register_fn(&met, |m, reg| {
+            reg.count("requests_total", &m.b).attr("path", "/b");
+        });
+        assert!(reg.to_string().ends_with(
+            "{path=\"/\"} 1600000000.000\nrequests_created{path=\"/b\"} 1700000000.500\n"
+        ));
+    }
+}
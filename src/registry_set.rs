@@ -0,0 +1,272 @@
+/*
+ * several registries rendered back to back as one scrape, ex. subsystems that own their
+ * registry and are set up at different times. Series of a family spread over registries are
+ * grouped under the first HELP / TYPE. A family registered with another type than in an
+ * earlier registry is dropped and named in a comment (text format only).
+ */
+use std::{
+    collections::HashMap,
+    fmt::{Display, Write},
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    scrape::{
+        scrape_fn, RenderError, ScrapeFormat, ScrapeFuture, ScrapeOptions, ScrapeOutput,
+        ScrapeSource,
+    },
+    PromMetricRegistry,
+};
+
+#[derive(Default, Clone)]
+pub struct RegistrySet {
+    registries: Vec<Arc<RwLock<PromMetricRegistry>>>,
+}
+
+impl RegistrySet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /* rendered after the registries added before it */
+    pub fn add(&mut self, registry: Arc<RwLock<PromMetricRegistry>>) -> &mut Self {
+        self.registries.push(registry);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.registries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.registries.is_empty()
+    }
+
+    pub fn as_scrape_fn(
+        &self,
+    ) -> impl Fn(ScrapeOptions) -> Result<ScrapeOutput, RenderError> + Clone + Send + Sync {
+        scrape_fn(self.clone())
+    }
+
+    pub fn scrape_async(&self, options: ScrapeOptions) -> ScrapeFuture {
+        ScrapeFuture::start(self.clone(), options)
+    }
+}
+
+impl ScrapeSource for RegistrySet {
+    fn scrape_output(&self, options: &ScrapeOptions) -> Result<ScrapeOutput, RenderError> {
+        let bodies = self
+            .registries
+            .iter()
+            .map(|registry| Ok(registry.scrape_output(options)?.body))
+            .collect::<Result<Vec<_>, RenderError>>()?;
+
+        let mut body = String::new();
+        merge(&mut body, &bodies, options.format).expect("writing to String cannot fail");
+        Ok(ScrapeOutput {
+            body,
+            content_type: options.format.content_type(),
+        })
+    }
+
+    fn should_offload(&self) -> bool {
+        self.registries
+            .iter()
+            .any(|registry| registry.should_offload())
+    }
+}
+
+impl Display for RegistrySet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bodies = self
+            .registries
+            .iter()
+            .map(|registry| match registry.read() {
+                Ok(registry) => registry.to_string(),
+                Err(poisoned) => poisoned.into_inner().to_string(),
+            })
+            .collect::<Vec<_>>();
+
+        merge(f, &bodies, ScrapeFormat::PrometheusText)
+    }
+}
+
+/* HELP / TYPE lines and the samples after them, up to the next family */
+struct Family<'a> {
+    name: &'a str,
+    metric_type: &'a str,
+    header: Vec<&'a str>,
+    samples: Vec<&'a str>,
+}
+
+fn merge(f: &mut dyn Write, bodies: &[String], format: ScrapeFormat) -> std::fmt::Result {
+    let mut families: Vec<Family> = Vec::new();
+    let mut by_name = HashMap::new();
+    let mut comments = Vec::new();
+    let mut conflicts = Vec::new();
+
+    for (source, body) in bodies.iter().enumerate() {
+        let mut blocks: Vec<Family> = Vec::new();
+        for line in body.lines() {
+            let header = line
+                .strip_prefix("# HELP ")
+                .map(|rest| (rest, false))
+                .or_else(|| line.strip_prefix("# TYPE ").map(|rest| (rest, true)));
+
+            match header {
+                Some((rest, is_type)) => {
+                    let (name, text) = rest.split_once(' ').unwrap_or((rest, ""));
+                    if blocks.last().map_or(true, |block| block.name != name) {
+                        blocks.push(Family {
+                            name,
+                            metric_type: "",
+                            header: Vec::new(),
+                            samples: Vec::new(),
+                        });
+                    }
+
+                    let block = blocks.last_mut().unwrap();
+                    block.header.push(line);
+                    if is_type {
+                        block.metric_type = text;
+                    }
+                }
+                None if line == "# EOF" => {}
+                None => match blocks.last_mut() {
+                    Some(block) if !line.starts_with('#') => block.samples.push(line),
+                    _ => comments.push(line),
+                },
+            }
+        }
+
+        for block in blocks {
+            match by_name.get(block.name) {
+                None => {
+                    by_name.insert(block.name, families.len());
+                    families.push(block);
+                }
+                Some(&index) if families[index].metric_type == block.metric_type => {
+                    families[index].samples.extend(block.samples);
+                }
+                Some(&index) => conflicts.push((
+                    block.name,
+                    block.metric_type,
+                    source,
+                    families[index].metric_type,
+                )),
+            }
+        }
+    }
+
+    for family in &families {
+        for line in family.header.iter().chain(&family.samples) {
+            writeln!(f, "{}", line)?;
+        }
+    }
+    for line in comments {
+        writeln!(f, "{}", line)?;
+    }
+
+    match format {
+        ScrapeFormat::PrometheusText => {
+            for (name, metric_type, source, first) in conflicts {
+                writeln!(
+                    f,
+                    "# {} {} of registry {} dropped, already a {}",
+                    metric_type, name, source, first
+                )?;
+            }
+        }
+        ScrapeFormat::OpenMetrics => f.write_str("# EOF\n")?,
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, RwLock};
+
+    use crate::{
+        scrape::{ScrapeFormat, ScrapeOptions, ScrapeSource},
+        IntCounter, IntGauge, PromMetricRegistry,
+    };
+
+    use super::RegistrySet;
+
+    #[derive(Default)]
+    struct Met {
+        requests: IntCounter,
+        depth: IntGauge,
+    }
+
+    fn registry(worker: &'static str, value: u64) -> Arc<RwLock<PromMetricRegistry>> {
+        let met = Arc::new(Met::default());
+        met.requests.inc_by(value);
+        met.depth.set(value);
+
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&met, move |met, reg| {
+            reg.count("requests_total", &met.requests)
+                .attr("worker", worker);
+            reg.gauge(format!("{}_depth", worker), &met.depth);
+        });
+        Arc::new(RwLock::new(reg))
+    }
+
+    #[test]
+    fn registry_set_test() {
+        let mut set = RegistrySet::new();
+        set.add(registry("a", 1)).add(registry("b", 2));
+
+        assert_eq!(
+            set.to_string(),
+            "# HELP a_depth\n\
+             # TYPE a_depth gauge\n\
+             a_depth 1\n\
+             # HELP requests_total\n\
+             # TYPE requests_total counter\n\
+             requests_total{worker=\"a\"} 1\n\
+             requests_total{worker=\"b\"} 2\n\
+             # HELP b_depth\n\
+             # TYPE b_depth gauge\n\
+             b_depth 2\n"
+        );
+
+        let output = set
+            .scrape_output(&ScrapeOptions {
+                format: ScrapeFormat::OpenMetrics,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(output.body.matches("# TYPE requests counter").count(), 1);
+        assert_eq!(output.body.matches("# EOF").count(), 1);
+        assert!(output.body.contains("requests_total{worker=\"b\"} 2\n"));
+        assert!(output.body.ends_with("b_depth 2\n# EOF\n"));
+    }
+
+    #[test]
+    fn conflict_test() {
+        let counter = Arc::new(IntCounter::new());
+        let gauge = Arc::new(IntGauge::new());
+        let mut first = PromMetricRegistry::empty();
+        first.register_fn(&counter, |counter, reg| {
+            reg.count("jobs", counter);
+        });
+        let mut second = PromMetricRegistry::empty();
+        second.register_fn(&gauge, |gauge, reg| {
+            reg.gauge("jobs", gauge);
+        });
+
+        let mut set = RegistrySet::new();
+        set.add(Arc::new(RwLock::new(first)))
+            .add(Arc::new(RwLock::new(second)));
+        assert_eq!(
+            set.to_string(),
+            "# HELP jobs\n\
+             # TYPE jobs counter\n\
+             jobs 0\n\
+             # gauge jobs of registry 1 dropped, already a counter\n"
+        );
+    }
+}
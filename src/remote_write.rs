@@ -0,0 +1,890 @@
+/*
+ * Prometheus remote_write 0.1: every push is a snappy compressed protobuf WriteRequest
+ * with one sample per series, stamped with the wall clock. Counters stay cumulative and
+ * histograms are sent as the classic _bucket / _sum / _count series. Series that were in
+ * the last successful push but are gone now get a staleness marker so receivers end them
+ * right away. Failed pushes count in arc_metrics_remote_write_failures_total and the
+ * spawned pusher retries with backoff.
+ */
+use std::{
+    collections::HashSet,
+    error::Error,
+    fmt::Display,
+    sync::{mpsc, Arc, RwLock},
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    export::{ExportPolicy, ExportSchedule},
+    transport::{parse_response, split_http_url, TcpTransport, Transport},
+    IntCounter, MetricFamily, PromMetricRegistry, SampleValue,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/* the NaN Prometheus reserves to mark a series stale */
+const STALE_NAN: u64 = 0x7ff0_0000_0000_0002;
+
+#[derive(Debug)]
+pub enum RemoteWriteError {
+    InvalidUrl(String),
+    Io(std::io::Error),
+    InvalidResponse,
+    Status { code: u16, body: String },
+}
+
+impl Display for RemoteWriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidUrl(url) => write!(f, "invalid remote_write url {:?}", url),
+            Self::Io(error) => write!(f, "remote_write io error: {}", error),
+            Self::InvalidResponse => write!(f, "invalid response from remote_write endpoint"),
+            Self::Status { code, body } => {
+                write!(
+                    f,
+                    "remote_write endpoint responded with {}: {}",
+                    code,
+                    body.trim()
+                )
+            }
+        }
+    }
+}
+
+impl Error for RemoteWriteError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for RemoteWriteError {
+    fn from(error: std::io::Error) -> Self {
+        RemoteWriteError::Io(error)
+    }
+}
+
+pub struct RemoteWritePusher {
+    host: String,
+    path: String,
+    authorization: Option<String>,
+    transport: Box<dyn Transport>,
+    /* first and largest wait before retrying a failed push */
+    backoff: (Duration, Duration),
+    failures: Arc<IntCounter>,
+    /* encoded label sets of the last successful push */
+    previous: HashSet<Vec<u8>>,
+}
+
+impl RemoteWritePusher {
+    pub fn new(url: &str) -> Result<Self, RemoteWriteError> {
+        let (host, _) = parse_url(url)?;
+        let addr = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:80", host)
+        };
+
+        Self::with_transport(url, TcpTransport::new(addr, TIMEOUT))
+    }
+
+    /* the url still provides the Host header and path, transport receives whole requests */
+    pub fn with_transport<T: Transport + 'static>(
+        url: &str,
+        transport: T,
+    ) -> Result<Self, RemoteWriteError> {
+        let (host, path) = parse_url(url)?;
+
+        Ok(RemoteWritePusher {
+            host: host.to_string(),
+            path: format!("/{}", path),
+            authorization: None,
+            transport: Box::new(transport),
+            backoff: (Duration::from_millis(500), Duration::from_secs(30)),
+            failures: Arc::new(IntCounter::new()),
+            previous: HashSet::new(),
+        })
+    }
+
+    /* value of the Authorization header, ex. "Bearer <token>" */
+    pub fn authorization(mut self, value: &str) -> Self {
+        self.authorization = Some(value.to_string());
+        self
+    }
+
+    /* the wait after a failure doubles from min up to max until a push succeeds */
+    pub fn retry_backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.backoff = (min, max.max(min));
+        self
+    }
+
+    /* failed pushes, registered as arc_metrics_remote_write_failures_total by spawn_interval */
+    pub fn failures(&self) -> &Arc<IntCounter> {
+        &self.failures
+    }
+
+    pub fn push(&mut self, registry: &PromMetricRegistry) -> Result<(), RemoteWriteError> {
+        let (body, current) = write_request(&registry.gather(), SystemTime::now(), &self.previous);
+        self.send(&body)?;
+        self.previous = current;
+        Ok(())
+    }
+
+    /* pushes every period, after a failure the next attempt follows the retry backoff */
+    pub fn spawn_interval(
+        self,
+        registry: Arc<RwLock<PromMetricRegistry>>,
+        period: Duration,
+    ) -> RemoteWriteHandle {
+        self.spawn_with_policy(registry, ExportPolicy::every(period))
+    }
+
+    /*
+     * checks the policy every min_interval, a failed push is retried after the backoff
+     * whatever the policy says. Stopping always performs a final push.
+     */
+    pub fn spawn_with_policy(
+        mut self,
+        registry: Arc<RwLock<PromMetricRegistry>>,
+        policy: ExportPolicy,
+    ) -> RemoteWriteHandle {
+        if let Ok(mut registry) = registry.write() {
+            registry.register_own_fn(&self.failures, |failures, reg| {
+                reg.count("arc_metrics_remote_write_failures_total", failures);
+            });
+        }
+
+        let (stop, stopped) = mpsc::channel::<()>();
+        let mut schedule = ExportSchedule::new(policy);
+        schedule.exported(Instant::now());
+
+        let thread = std::thread::spawn(move || {
+            let mut wait = policy.min_interval;
+            let mut backoff = self.backoff.0;
+            let mut failing = false;
+
+            loop {
+                let stop = !matches!(
+                    stopped.recv_timeout(wait),
+                    Err(mpsc::RecvTimeoutError::Timeout)
+                );
+
+                /* encoded under the lock, sent after releasing it */
+                let encoded = match registry.read() {
+                    Ok(registry) => {
+                        let now = Instant::now();
+                        let changed = schedule.changed_series(&registry);
+                        (stop || failing || schedule.should_export(now, changed)).then(|| {
+                            schedule.exported(now);
+                            Some(write_request(
+                                &registry.gather(),
+                                SystemTime::now(),
+                                &self.previous,
+                            ))
+                        })
+                    }
+                    Err(_) => Some(None),
+                };
+
+                if let Some(encoded) = encoded {
+                    let sent = match encoded {
+                        Some((body, current)) => {
+                            self.send(&body).map(|()| self.previous = current).is_ok()
+                        }
+                        /* a writer panicked holding the lock, counted like a failed push */
+                        None => {
+                            self.failures.inc();
+                            false
+                        }
+                    };
+
+                    if sent {
+                        wait = policy.min_interval;
+                        backoff = self.backoff.0;
+                        failing = false;
+                    } else {
+                        wait = backoff;
+                        backoff = (backoff * 2).min(self.backoff.1);
+                        failing = true;
+                    }
+                }
+
+                if stop {
+                    break;
+                }
+            }
+        });
+
+        RemoteWriteHandle { stop, thread }
+    }
+
+    fn send(&mut self, body: &[u8]) -> Result<(), RemoteWriteError> {
+        let result = self.try_send(body);
+        if result.is_err() {
+            self.failures.inc();
+        }
+        result
+    }
+
+    fn try_send(&mut self, body: &[u8]) -> Result<(), RemoteWriteError> {
+        let body = snappy::compress(body);
+
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\n\
+            Host: {}\r\n\
+            Content-Type: application/x-protobuf\r\n\
+            Content-Encoding: snappy\r\n\
+            X-Prometheus-Remote-Write-Version: 0.1.0\r\n\
+            User-Agent: arc-metrics/{}\r\n",
+            self.path,
+            self.host,
+            env!("CARGO_PKG_VERSION")
+        );
+        if let Some(authorization) = &self.authorization {
+            request.push_str(&format!("Authorization: {}\r\n", authorization));
+        }
+        request.push_str(&format!(
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        ));
+
+        let mut request = request.into_bytes();
+        request.extend_from_slice(&body);
+
+        let response = self.transport.send(&request)?;
+        let (code, body) = parse_response(&response).ok_or(RemoteWriteError::InvalidResponse)?;
+
+        if (200..300).contains(&code) {
+            return Ok(());
+        }
+
+        Err(RemoteWriteError::Status { code, body })
+    }
+}
+
+pub struct RemoteWriteHandle {
+    stop: mpsc::Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl RemoteWriteHandle {
+    /* performs a final push before returning */
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.thread.join();
+    }
+}
+
+fn parse_url(url: &str) -> Result<(&str, &str), RemoteWriteError> {
+    split_http_url(url).ok_or_else(|| RemoteWriteError::InvalidUrl(url.to_string()))
+}
+
+/*
+ * uncompressed WriteRequest, labels of each series sorted by name as receivers require.
+ * Series of previous missing now get a stale marker, returns the request and its series.
+ */
+fn write_request(
+    families: &[MetricFamily],
+    time: SystemTime,
+    previous: &HashSet<Vec<u8>>,
+) -> (Vec<u8>, HashSet<Vec<u8>>) {
+    let timestamp = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as i64);
+
+    let mut request = Vec::new();
+    let mut current = HashSet::new();
+    let mut series = Vec::new();
+    let add_sample = |request: &mut Vec<u8>, series: &mut Vec<u8>, value: f64| {
+        let mut sample = Vec::new();
+        proto::double(&mut sample, 1, value);
+        proto::varint_field(&mut sample, 2, timestamp as u64);
+        proto::bytes(series, 2, &sample);
+
+        proto::bytes(request, 1, series);
+    };
+    let mut add = |name: String, labels: Vec<(&str, &str)>, value: f64| {
+        let mut labels = labels;
+        labels.push(("__name__", &name));
+        labels.sort_unstable_by_key(|(key, _)| *key);
+
+        series.clear();
+        for (key, value) in labels {
+            let mut label = Vec::new();
+            proto::bytes(&mut label, 1, key.as_bytes());
+            proto::bytes(&mut label, 2, value.as_bytes());
+            proto::bytes(&mut series, 1, &label);
+        }
+        current.insert(series.clone());
+
+        add_sample(&mut request, &mut series, value);
+    };
+
+    for family in families {
+        for sample in &family.samples {
+            let labels = sample
+                .attributes
+                .iter()
+                .map(|[key, value]| (key.as_ref(), value.as_ref()))
+                .collect::<Vec<_>>();
+
+            match &sample.value {
+                SampleValue::Value(value) => {
+                    add(family.name.to_string(), labels, *value as f64);
+                }
+                SampleValue::Float(value) => {
+                    add(family.name.to_string(), labels, value.get());
+                }
+                SampleValue::Histogram {
+                    buckets,
+                    sum,
+                    count,
+                } => {
+                    let bounds = buckets
+                        .iter()
+                        .map(|(le, count)| (le.to_string(), *count))
+                        .chain(std::iter::once(("+Inf".to_string(), *count)))
+                        .collect::<Vec<_>>();
+                    for (le, count) in &bounds {
+                        let mut labels = labels.clone();
+                        labels.push(("le", le));
+                        add(format!("{}_bucket", family.name), labels, *count as f64);
+                    }
+                    add(format!("{}_sum", family.name), labels.clone(), *sum as f64);
+                    add(format!("{}_count", family.name), labels, *count as f64);
+                }
+                SampleValue::Summary {
+                    quantiles,
+                    sum,
+                    count,
+                } => {
+                    for (quantile, value) in quantiles {
+                        let quantile = quantile.to_string();
+                        let mut labels = labels.clone();
+                        labels.push(("quantile", &quantile));
+                        add(family.name.to_string(), labels, *value as f64);
+                    }
+                    add(format!("{}_sum", family.name), labels.clone(), *sum as f64);
+                    add(format!("{}_count", family.name), labels, *count as f64);
+                }
+            }
+        }
+    }
+
+    for labels in previous.difference(&current) {
+        series.clone_from(labels);
+        add_sample(&mut request, &mut series, f64::from_bits(STALE_NAN));
+    }
+
+    (request, current)
+}
+
+mod proto {
+    pub fn varint(out: &mut Vec<u8>, mut value: u64) {
+        while 0x80 <= value {
+            out.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    pub fn varint_field(out: &mut Vec<u8>, field: u32, value: u64) {
+        varint(out, (field as u64) << 3);
+        varint(out, value);
+    }
+
+    pub fn double(out: &mut Vec<u8>, field: u32, value: f64) {
+        varint(out, (field as u64) << 3 | 1);
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(out: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+        varint(out, (field as u64) << 3 | 2);
+        varint(out, bytes.len() as u64);
+        out.extend_from_slice(bytes);
+    }
+}
+
+/* snappy block format, greedy matching against the last position of each 4 byte hash */
+mod snappy {
+    use super::proto::varint;
+
+    /* copies with a 2 byte offset reach this far back */
+    const MAX_OFFSET: usize = u16::MAX as usize;
+
+    pub fn compress(input: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(input.len() / 2 + 16);
+        varint(&mut out, input.len() as u64);
+
+        /* position + 1 of the last occurrence, 0 for none */
+        let mut table = vec![0usize; 1 << 14];
+        let mut literal = 0;
+        let mut pos = 0;
+
+        while pos + 4 <= input.len() {
+            let key =
+                u32::from_le_bytes([input[pos], input[pos + 1], input[pos + 2], input[pos + 3]]);
+            let slot = (key.wrapping_mul(0x1e35_a7bd) >> 18) as usize;
+            let candidate = std::mem::replace(&mut table[slot], pos + 1);
+
+            let matched = candidate
+                .checked_sub(1)
+                .filter(|&start| pos - start <= MAX_OFFSET)
+                .filter(|&start| input[start..start + 4] == input[pos..pos + 4]);
+
+            let Some(start) = matched else {
+                pos += 1;
+                continue;
+            };
+
+            /* may run past pos, the decoder copies byte by byte */
+            let mut len = 4;
+            while pos + len < input.len() && input[start + len] == input[pos + len] {
+                len += 1;
+            }
+
+            emit_literal(&mut out, &input[literal..pos]);
+            emit_copy(&mut out, pos - start, len);
+            pos += len;
+            literal = pos;
+        }
+
+        emit_literal(&mut out, &input[literal..]);
+        out
+    }
+
+    fn emit_literal(out: &mut Vec<u8>, literal: &[u8]) {
+        for chunk in literal.chunks(1 << 16) {
+            let n = chunk.len() - 1;
+            if n < 60 {
+                out.push((n as u8) << 2);
+            } else if n < 1 << 8 {
+                out.push(60 << 2);
+                out.push(n as u8);
+            } else {
+                out.push(61 << 2);
+                out.extend_from_slice(&(n as u16).to_le_bytes());
+            }
+            out.extend_from_slice(chunk);
+        }
+    }
+
+    fn emit_copy(out: &mut Vec<u8>, offset: usize, mut len: usize) {
+        while 0 < len {
+            /* a copy is 1..=64 bytes, never leave a remainder under 4 */
+            let chunk = match len {
+                65..=67 => 60,
+                _ => len.min(64),
+            };
+            out.push(((chunk - 1) as u8) << 2 | 2);
+            out.extend_from_slice(&(offset as u16).to_le_bytes());
+            len -= chunk;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashSet,
+        io::{Read, Write},
+        net::TcpListener,
+        sync::{Arc, RwLock},
+        thread::JoinHandle,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    use crate::{export::ExportPolicy, IntCounter, IntGauge, IntHistogram, PromMetricRegistry};
+
+    use super::{snappy, write_request, RemoteWriteError, RemoteWritePusher, STALE_NAN};
+
+    type Series = (Vec<(String, String)>, f64, i64);
+
+    fn decompress(input: &[u8]) -> Vec<u8> {
+        let (len, mut pos) = read_varint(input, 0);
+        let mut out = Vec::with_capacity(len as usize);
+
+        while pos < input.len() {
+            let tag = input[pos];
+            pos += 1;
+            match tag & 3 {
+                0 => {
+                    let len = match tag >> 2 {
+                        n @ 0..=59 => n as usize + 1,
+                        n => {
+                            let bytes = n as usize - 59;
+                            let mut len = 0;
+                            for i in 0..bytes {
+                                len |= (input[pos + i] as usize) << (8 * i);
+                            }
+                            pos += bytes;
+                            len + 1
+                        }
+                    };
+                    out.extend_from_slice(&input[pos..pos + len]);
+                    pos += len;
+                }
+                kind => {
+                    let (len, offset) = match kind {
+                        1 => {
+                            let offset = ((tag as usize >> 5) << 8) | input[pos] as usize;
+                            pos += 1;
+                            (((tag >> 2) & 7) as usize + 4, offset)
+                        }
+                        2 => {
+                            let offset = u16::from_le_bytes([input[pos], input[pos + 1]]);
+                            pos += 2;
+                            ((tag >> 2) as usize + 1, offset as usize)
+                        }
+                        _ => {
+                            let mut offset = [0u8; 4];
+                            offset.copy_from_slice(&input[pos..pos + 4]);
+                            pos += 4;
+                            ((tag >> 2) as usize + 1, u32::from_le_bytes(offset) as usize)
+                        }
+                    };
+                    for _ in 0..len {
+                        out.push(out[out.len() - offset]);
+                    }
+                }
+            }
+        }
+
+        assert_eq!(out.len() as u64, len);
+        out
+    }
+
+    fn read_varint(input: &[u8], mut pos: usize) -> (u64, usize) {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = input[pos];
+            pos += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+            if byte < 0x80 {
+                return (value, pos);
+            }
+        }
+    }
+
+    /* (field, varint or fixed64 value, length delimited bytes) */
+    fn fields(input: &[u8]) -> Vec<(u64, u64, &[u8])> {
+        let mut fields = Vec::new();
+        let mut pos = 0;
+        while pos < input.len() {
+            let (key, next) = read_varint(input, pos);
+            pos = next;
+            match key & 7 {
+                0 => {
+                    let (value, next) = read_varint(input, pos);
+                    pos = next;
+                    fields.push((key >> 3, value, &input[..0]));
+                }
+                1 => {
+                    let mut value = [0u8; 8];
+                    value.copy_from_slice(&input[pos..pos + 8]);
+                    pos += 8;
+                    fields.push((key >> 3, u64::from_le_bytes(value), &input[..0]));
+                }
+                2 => {
+                    let (len, next) = read_varint(input, pos);
+                    let end = next + len as usize;
+                    fields.push((key >> 3, 0, &input[next..end]));
+                    pos = end;
+                }
+                other => panic!("unexpected wire type {}", other),
+            }
+        }
+        fields
+    }
+
+    fn decode(request: &[u8]) -> Vec<Series> {
+        fields(request)
+            .into_iter()
+            .map(|(field, _, series)| {
+                assert_eq!(field, 1);
+                let mut labels = Vec::new();
+                let mut sample = (0.0, 0);
+                for (field, _, bytes) in fields(series) {
+                    let values = fields(bytes);
+                    match field {
+                        1 => labels.push((
+                            String::from_utf8(values[0].2.to_vec()).unwrap(),
+                            String::from_utf8(values[1].2.to_vec()).unwrap(),
+                        )),
+                        _ => sample = (f64::from_bits(values[0].1), values[1].1 as i64),
+                    }
+                }
+                (labels, sample.0, sample.1)
+            })
+            .collect()
+    }
+
+    fn series(labels: &[(&str, &str)], value: f64) -> Series {
+        (
+            labels
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            value,
+            1_700_000_000_123,
+        )
+    }
+
+    #[derive(Default)]
+    struct Met {
+        requests: IntCounter,
+        queue: IntGauge,
+        latency: IntHistogram,
+    }
+
+    fn registry() -> (Arc<Met>, PromMetricRegistry) {
+        let met = Arc::new(Met {
+            latency: IntHistogram::new([10]),
+            ..Default::default()
+        });
+        let mut reg = PromMetricRegistry::empty().with_base_attrs([("service", "api")]);
+        reg.register_fn(&met, |m, reg| {
+            reg.count("requests", &m.requests).attr("method", "get");
+            reg.gauge("queue", &m.queue);
+            reg.histogram("latency", &m.latency);
+        });
+        (met, reg)
+    }
+
+    /* answers each request with the next status, returns the requests */
+    fn endpoint(statuses: &'static [&'static str]) -> (String, JoinHandle<Vec<Vec<u8>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api/v1/write", listener.local_addr().unwrap());
+
+        let handle = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for status in statuses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+
+                loop {
+                    let read = stream.read(&mut buffer).unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+
+                    let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") else {
+                        continue;
+                    };
+                    let head = String::from_utf8_lossy(&request[..end]).to_string();
+                    let length = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("Content-Length: "))
+                        .unwrap()
+                        .parse::<usize>()
+                        .unwrap();
+
+                    if request.len() == end + 4 + length {
+                        break;
+                    }
+                }
+
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: 4\r\n\r\nnope",
+                    status
+                )
+                .unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+
+        (url, handle)
+    }
+
+    fn split(request: &[u8]) -> (String, Vec<u8>) {
+        let end = request.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        (
+            String::from_utf8_lossy(&request[..end]).to_string(),
+            request[end + 4..].to_vec(),
+        )
+    }
+
+    #[test]
+    fn snappy_test() {
+        let input = "requests{method=\"get\",service=\"api\"} 1\n".repeat(50);
+        let compressed = snappy::compress(input.as_bytes());
+        assert!(compressed.len() < input.len() / 10);
+        assert_eq!(decompress(&compressed), input.as_bytes());
+
+        /* long literals and no matches */
+        let noise = (0..70_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect::<Vec<_>>();
+        assert_eq!(decompress(&snappy::compress(&noise)), noise);
+        assert_eq!(decompress(&snappy::compress(b"")), b"");
+        assert_eq!(decompress(&snappy::compress(b"abc")), b"abc");
+    }
+
+    #[test]
+    fn write_request_test() {
+        let (met, reg) = registry();
+        met.requests.inc_by(3);
+        met.queue.set(7);
+        met.latency.observe(5);
+        met.latency.observe(50);
+
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        assert_eq!(
+            decode(&write_request(&reg.gather(), time, &HashSet::new()).0),
+            [
+                series(
+                    &[
+                        ("__name__", "latency_bucket"),
+                        ("le", "10"),
+                        ("service", "api")
+                    ],
+                    1.0
+                ),
+                series(
+                    &[
+                        ("__name__", "latency_bucket"),
+                        ("le", "+Inf"),
+                        ("service", "api")
+                    ],
+                    2.0
+                ),
+                series(&[("__name__", "latency_sum"), ("service", "api")], 55.0),
+                series(&[("__name__", "latency_count"), ("service", "api")], 2.0),
+                series(&[("__name__", "queue"), ("service", "api")], 7.0),
+                series(
+                    &[
+                        ("__name__", "requests"),
+                        ("method", "get"),
+                        ("service", "api")
+                    ],
+                    3.0
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn stale_test() {
+        let (met, mut reg) = registry();
+        let gone = Arc::new(IntGauge::new());
+        reg.register_weak_fn(&gone, |gauge, reg| {
+            reg.gauge("workers", gauge);
+        });
+
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let (_, first) = write_request(&reg.gather(), time, &HashSet::new());
+        assert_eq!(first.len(), 7);
+
+        drop(gone);
+        reg.prune();
+        met.queue.set(1);
+        let (request, second) = write_request(&reg.gather(), time, &first);
+        assert_eq!(second.len(), 6);
+
+        let decoded = decode(&request);
+        assert_eq!(decoded.len(), 7);
+        let (labels, value, _) = decoded.last().unwrap();
+        assert_eq!(
+            labels[..],
+            [
+                ("__name__".to_string(), "workers".to_string()),
+                ("service".to_string(), "api".to_string())
+            ]
+        );
+        assert_eq!(value.to_bits(), STALE_NAN);
+
+        /* marked once, the next push no longer has it */
+        let (request, _) = write_request(&reg.gather(), time, &second);
+        assert_eq!(decode(&request).len(), 6);
+    }
+
+    #[test]
+    fn push_test() {
+        let (met, reg) = registry();
+        met.requests.inc_by(3);
+
+        let (url, handle) = endpoint(&["204 No Content"]);
+        let mut pusher = RemoteWritePusher::new(&url)
+            .unwrap()
+            .authorization("Bearer secret");
+        pusher.push(&reg).unwrap();
+
+        let requests = handle.join().unwrap();
+        let (head, body) = split(&requests[0]);
+        assert!(head.starts_with("POST /api/v1/write HTTP/1.1\r\n"));
+        assert!(head.contains("\r\nContent-Encoding: snappy\r\n"));
+        assert!(head.contains("\r\nContent-Type: application/x-protobuf\r\n"));
+        assert!(head.contains("\r\nX-Prometheus-Remote-Write-Version: 0.1.0\r\n"));
+        assert!(head.contains("\r\nAuthorization: Bearer secret\r\n"));
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+        let decoded = decode(&decompress(&body));
+        let (labels, value, timestamp) = decoded.last().unwrap();
+        assert_eq!(labels[0], ("__name__".to_string(), "requests".to_string()));
+        assert_eq!(*value, 3.0);
+        assert!((now - 60_000..=now).contains(timestamp));
+        assert_eq!(pusher.failures().load(), 0);
+
+        assert!(matches!(
+            RemoteWritePusher::new("https://example.com/api/v1/write"),
+            Err(RemoteWriteError::InvalidUrl(_))
+        ));
+    }
+
+    #[test]
+    fn retry_test() {
+        let (met, reg) = registry();
+        met.requests.inc();
+        let registry = Arc::new(RwLock::new(reg));
+
+        let (url, handle) = endpoint(&["503 Service Unavailable", "500 Internal", "200 OK"]);
+        let pusher = RemoteWritePusher::new(&url)
+            .unwrap()
+            .retry_backoff(Duration::from_millis(10), Duration::from_millis(20))
+            .spawn_interval(registry.clone(), Duration::from_millis(20));
+
+        /* two failures retried, then the third push succeeds */
+        let requests = handle.join().unwrap();
+        pusher.stop();
+        assert_eq!(requests.len(), 3);
+
+        /* the failure counter is registered and pushed too */
+        let (_, body) = split(&requests[2]);
+        assert!(decode(&decompress(&body)).iter().any(|(labels, value, _)| {
+            labels[0].1 == "arc_metrics_remote_write_failures_total" && *value == 2.0
+        }));
+    }
+
+    #[test]
+    fn policy_test() {
+        let (_met, reg) = registry();
+        let registry = Arc::new(RwLock::new(reg));
+
+        /* the first check sends everything, nothing changes after that until the stop */
+        let (url, handle) = endpoint(&["204 No Content", "204 No Content"]);
+        let pusher = RemoteWritePusher::new(&url).unwrap();
+        let failures = pusher.failures().clone();
+        let pusher = pusher.spawn_with_policy(
+            registry.clone(),
+            ExportPolicy {
+                min_interval: Duration::from_millis(10),
+                max_interval: Duration::from_secs(3600),
+                push_on_change_threshold: 1,
+            },
+        );
+        std::thread::sleep(Duration::from_millis(100));
+        pusher.stop();
+
+        assert_eq!(handle.join().unwrap().len(), 2);
+        assert_eq!(failures.load(), 0);
+    }
+}
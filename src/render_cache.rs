@@ -0,0 +1,132 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::{PromMetricRegistry, Reading};
+
+/*
+ * last rendered text per family keyed by the index of its first series. Readings are
+ * compared exactly rather than hashed so a collision can never serve stale text, memory
+ * is the previous render plus its readings. Cleared whenever the metric list changes.
+ */
+#[derive(Default)]
+pub(crate) struct RenderCache {
+    families: HashMap<usize, CachedFamily>,
+    pub(crate) hits: usize,
+    pub(crate) misses: usize,
+}
+
+struct CachedFamily {
+    readings: Vec<Reading>,
+    text: String,
+}
+
+impl RenderCache {
+    pub(crate) fn lookup(&mut self, index: usize, readings: &[Reading]) -> Option<&str> {
+        match self.families.get(&index) {
+            Some(cached) if cached.readings == readings => {
+                self.hits += 1;
+                Some(&cached.text)
+            }
+            _ => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub(crate) fn store(&mut self, index: usize, readings: &[Reading], text: String) {
+        let readings = readings.to_vec();
+        self.families.insert(index, CachedFamily { readings, text });
+    }
+}
+
+impl PromMetricRegistry {
+    /* reuses the text of families whose values didn't change since the last render */
+    pub fn render_cache(&mut self, enabled: bool) -> &mut Self {
+        self.render_cache = enabled.then(|| Mutex::new(RenderCache::default()));
+        self
+    }
+
+    pub(crate) fn invalidate_render_cache(&mut self) {
+        if let Some(cache) = &mut self.render_cache {
+            *cache = Mutex::new(RenderCache::default());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{IntCounter, IntGauge, IntHistogram, PromMetricRegistry};
+
+    struct Met {
+        series: Vec<IntGauge>,
+        hits: IntCounter,
+        latency: IntHistogram,
+    }
+
+    fn setup() -> (Arc<Met>, PromMetricRegistry) {
+        let met = Arc::new(Met {
+            series: (0..1000).map(|_| IntGauge::new()).collect(),
+            hits: IntCounter::new(),
+            latency: IntHistogram::new([10, 100]),
+        });
+
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&met, |m, reg| {
+            for (i, gauge) in m.series.iter().enumerate() {
+                reg.gauge("connections", gauge).attr("id", i.to_string());
+            }
+            reg.count("hits", &m.hits);
+            reg.histogram("latency", &m.latency);
+        });
+
+        (met, reg)
+    }
+
+    fn stats(reg: &PromMetricRegistry) -> (usize, usize) {
+        let cache = reg.render_cache.as_ref().unwrap().lock().unwrap();
+        (cache.hits, cache.misses)
+    }
+
+    #[test]
+    fn invalidation_test() {
+        let (met, mut reg) = setup();
+        let (_, uncached) = setup();
+        reg.render_cache(true);
+
+        assert_eq!(reg.to_string(), uncached.to_string());
+        assert_eq!(stats(&reg), (0, 3));
+
+        assert_eq!(reg.to_string(), uncached.to_string());
+        assert_eq!(stats(&reg), (3, 3));
+
+        /* one series of the large family only invalidates that family */
+        met.series[517].set(9);
+        let rendered = reg.to_string();
+        assert_eq!(stats(&reg), (5, 4));
+        assert!(rendered.contains("connections{id=\"517\"} 9\n"));
+
+        met.latency.observe(50);
+        let rendered = reg.to_string();
+        assert_eq!(stats(&reg), (7, 5));
+        assert!(rendered.contains("latency_bucket{le=\"100\"} 1\n"));
+    }
+
+    #[test]
+    fn registration_clears_test() {
+        let (_, mut reg) = setup();
+        reg.render_cache(true);
+        reg.to_string();
+
+        let extra = Arc::new(IntCounter::new());
+        reg.register_fn(&extra, |counter, reg| {
+            reg.count("hits", counter).attr("extra", "1");
+        });
+        extra.inc();
+
+        let rendered = reg.to_string();
+        assert_eq!(stats(&reg), (0, 3));
+        assert!(rendered.contains("hits{extra=\"1\"} 1\n"));
+    }
+}
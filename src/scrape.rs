@@ -0,0 +1,793 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, VecDeque},
+    error::Error,
+    fmt::Display,
+    future::Future,
+    panic::{catch_unwind, AssertUnwindSafe},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, OnceLock, RwLock, TryLockError,
+    },
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use crate::{escape, join_labels, write_sample, MetricType, PromMetricRegistry};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ScrapeFormat {
+    #[default]
+    PrometheusText,
+    /* the only format with exemplars */
+    OpenMetrics,
+}
+
+impl ScrapeFormat {
+    /* picks the best supported format for an Accept header, falls back to text */
+    pub fn negotiate(accept: Option<&str>) -> Self {
+        let openmetrics = accept.is_some_and(|accept| {
+            accept.split(',').any(|range| {
+                let mut params = range.split(';').map(str::trim);
+                let media = params.next().unwrap_or_default();
+                media.eq_ignore_ascii_case("application/openmetrics-text")
+                    && !params.any(|param| param.replace(' ', "") == "q=0")
+            })
+        });
+
+        match openmetrics {
+            true => ScrapeFormat::OpenMetrics,
+            false => ScrapeFormat::PrometheusText,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::PrometheusText => "text/plain; version=0.0.4; charset=utf-8",
+            Self::OpenMetrics => "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ScrapeOptions {
+    pub format: ScrapeFormat,
+    /* metric name prefixes to include, empty includes everything */
+    pub modules: Vec<String>,
+    pub deadline: Option<Instant>,
+    pub context: ScrapeContext,
+}
+
+/* describes who is scraping, only ever attached to the scrape self-metrics */
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ScrapeContext {
+    pub client: Option<String>,
+}
+
+impl ScrapeContext {
+    pub fn client<S: Into<String>>(client: S) -> Self {
+        ScrapeContext {
+            client: Some(client.into()),
+        }
+    }
+}
+
+pub(crate) struct ScrapeClients {
+    pub(crate) limit: usize,
+    clients: Mutex<Clients>,
+}
+
+#[derive(Default)]
+struct Clients {
+    named: BTreeMap<String, ClientStats>,
+    /* clients past the limit, kept apart so no client name can land in it */
+    overflow: Option<ClientStats>,
+}
+
+#[derive(Default, Clone, Copy)]
+struct ClientStats {
+    scrapes: u64,
+    duration_us: u64,
+}
+
+impl ScrapeClients {
+    pub const DEFAULT_LIMIT: usize = 8;
+    /* clients beyond the limit are rendered with this label set to true instead of client */
+    pub const OVERFLOW_LABEL: &'static str = "client_overflow";
+
+    fn record(&self, context: &ScrapeContext, elapsed: Duration) {
+        let Some(client) = &context.client else {
+            return;
+        };
+
+        let mut clients = self.clients.lock().unwrap();
+        let clients = &mut *clients;
+        let stats = if clients.named.contains_key(client) || clients.named.len() < self.limit {
+            clients.named.entry(client.clone()).or_default()
+        } else {
+            clients.overflow.get_or_insert_with(ClientStats::default)
+        };
+        stats.scrapes += 1;
+        stats.duration_us += elapsed.as_micros() as u64;
+    }
+
+    pub(crate) fn encode(
+        &self,
+        f: &mut dyn std::fmt::Write,
+        attrs: &[[Cow<'static, str>; 2]],
+    ) -> std::fmt::Result {
+        let clients = self.clients.lock().unwrap();
+        if clients.named.is_empty() && clients.overflow.is_none() {
+            return Ok(());
+        }
+
+        let attrs = join_labels(attrs);
+        let families = [
+            ("arc_metrics_scrape_duration_us_total", true),
+            ("arc_metrics_scrapes_total", false),
+        ];
+
+        for (name, duration) in families {
+            writeln!(f, "# HELP {}", name)?;
+            writeln!(f, "# TYPE {} {}", name, MetricType::IntCounter)?;
+
+            let value = |stats: &ClientStats| match duration {
+                true => stats.duration_us,
+                false => stats.scrapes,
+            };
+            for (client, stats) in clients.named.iter() {
+                let client = escape::label_value(client).to_string();
+                write_sample(f, name, "", &attrs, Some(("client", &client)), value(stats))?;
+            }
+            if let Some(stats) = &clients.overflow {
+                let label = Some((Self::OVERFLOW_LABEL, "true"));
+                write_sample(f, name, "", &attrs, label, value(stats))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for ScrapeClients {
+    fn default() -> Self {
+        ScrapeClients {
+            limit: Self::DEFAULT_LIMIT,
+            clients: Mutex::default(),
+        }
+    }
+}
+
+impl ScrapeOptions {
+    pub fn from_accept(accept: Option<&str>) -> Self {
+        ScrapeOptions {
+            format: ScrapeFormat::negotiate(accept),
+            ..Default::default()
+        }
+    }
+
+    pub fn module<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.modules.push(prefix.into());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.deadline = Some(Instant::now() + timeout);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScrapeOutput {
+    pub body: String,
+    pub content_type: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderError {
+    DeadlineExceeded,
+    Poisoned,
+    /* an offloaded render panicked, inline renders propagate the panic */
+    Panicked,
+}
+
+impl Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DeadlineExceeded => write!(f, "scrape deadline exceeded"),
+            Self::Poisoned => write!(f, "metric registry lock poisoned"),
+            Self::Panicked => write!(f, "scrape render panicked"),
+        }
+    }
+}
+
+impl Error for RenderError {}
+
+impl PromMetricRegistry {
+    /* zero in test mode so rendered self-metrics are deterministic */
+    pub(crate) fn scrape_elapsed(&self, start: Instant) -> Duration {
+        if self.test_mode {
+            return Duration::ZERO;
+        }
+        start.elapsed()
+    }
+
+    /*
+     * renders all metrics and records the scrape against the context's client, the
+     * per client self-metrics show previous scrapes as this one is still being timed
+     */
+    pub fn render_with_context(&self, context: ScrapeContext) -> String {
+        let start = Instant::now();
+        let output = self.to_string();
+        self.scrape_clients
+            .record(&context, self.scrape_elapsed(start));
+        output
+    }
+
+    /* clients past the limit are counted together, labelled client_overflow="true" */
+    pub fn max_scrape_clients(&mut self, limit: usize) -> &mut Self {
+        self.scrape_clients.limit = limit;
+        self
+    }
+
+    pub fn scrape(&self, options: &ScrapeOptions) -> Result<ScrapeOutput, RenderError> {
+        let start = Instant::now();
+        let mut body = String::new();
+        let filter = |name: &str| {
+            options.modules.is_empty()
+                || options
+                    .modules
+                    .iter()
+                    .any(|module| name.starts_with(module.as_str()))
+        };
+
+        match options.format {
+            ScrapeFormat::PrometheusText => {
+                self.encode(&mut body, &filter)
+                    .expect("writing to String cannot fail");
+            }
+            ScrapeFormat::OpenMetrics => {
+                self.encode_openmetrics_filtered(&mut body, &filter)
+                    .expect("writing to String cannot fail");
+            }
+        }
+
+        self.scrape_clients
+            .record(&options.context, self.scrape_elapsed(start));
+
+        if options
+            .deadline
+            .is_some_and(|deadline| deadline <= Instant::now())
+        {
+            return Err(RenderError::DeadlineExceeded);
+        }
+
+        Ok(ScrapeOutput {
+            body,
+            content_type: options.format.content_type(),
+        })
+    }
+
+    /* single entry point for HTTP integrations, late registrations are picked up */
+    pub fn as_scrape_fn(
+        registry: Arc<RwLock<Self>>,
+    ) -> impl Fn(ScrapeOptions) -> Result<ScrapeOutput, RenderError> + Clone + Send + Sync {
+        scrape_fn(registry)
+    }
+
+    /* waits for the read lock until the deadline, a render never blocks other readers */
+    pub(crate) fn scrape_locked(
+        registry: &RwLock<Self>,
+        options: &ScrapeOptions,
+    ) -> Result<ScrapeOutput, RenderError> {
+        let registry = loop {
+            match registry.try_read() {
+                Ok(registry) => break registry,
+                Err(TryLockError::Poisoned(_)) => return Err(RenderError::Poisoned),
+                Err(TryLockError::WouldBlock) => {}
+            }
+
+            match options.deadline {
+                Some(deadline) if deadline <= Instant::now() => {
+                    return Err(RenderError::DeadlineExceeded)
+                }
+                Some(_) => std::thread::sleep(Duration::from_millis(1)),
+                None => break registry.read().map_err(|_| RenderError::Poisoned)?,
+            }
+        };
+
+        registry.scrape(options)
+    }
+
+    /* registries with more series than this render on a thread in scrape_async */
+    pub fn offload_threshold(&mut self, series: usize) -> &mut Self {
+        self.offload_threshold = series;
+        self
+    }
+
+    /*
+     * scrape for async handlers that doesn't need a runtime, nothing renders before the
+     * first poll. Small registries render in that poll, above offload_threshold (or while
+     * a writer holds the lock) the render is queued on a few shared threads so the
+     * executor stays free. Dropping the future abandons the result, a render that hasn't
+     * started is skipped.
+     */
+    pub fn scrape_async(registry: Arc<RwLock<Self>>, options: ScrapeOptions) -> ScrapeFuture {
+        ScrapeFuture::start(registry, options)
+    }
+}
+
+/* what HTTP integrations serve, a shared registry or a RegistrySet */
+pub trait ScrapeSource: Clone + Send + Sync + 'static {
+    fn scrape_output(&self, options: &ScrapeOptions) -> Result<ScrapeOutput, RenderError>;
+
+    /* whether scrape_async renders on an offload thread */
+    fn should_offload(&self) -> bool;
+}
+
+impl ScrapeSource for Arc<RwLock<PromMetricRegistry>> {
+    fn scrape_output(&self, options: &ScrapeOptions) -> Result<ScrapeOutput, RenderError> {
+        PromMetricRegistry::scrape_locked(self, options)
+    }
+
+    fn should_offload(&self) -> bool {
+        match self.try_read() {
+            Ok(reg) => reg.offload_threshold < reg.metrics.len(),
+            Err(TryLockError::Poisoned(_)) => false,
+            Err(TryLockError::WouldBlock) => true,
+        }
+    }
+}
+
+/* PromMetricRegistry::as_scrape_fn for any source */
+pub fn scrape_fn<S: ScrapeSource>(
+    source: S,
+) -> impl Fn(ScrapeOptions) -> Result<ScrapeOutput, RenderError> + Clone + Send + Sync {
+    move |options| source.scrape_output(&options)
+}
+
+pub struct ScrapeFuture {
+    /* taken by the first poll, which renders inline or hands it to the offload threads */
+    render: Option<Render>,
+    offload: Option<Arc<Offload>>,
+}
+
+type Render = Box<dyn FnOnce() -> Result<ScrapeOutput, RenderError> + Send>;
+
+#[derive(Default)]
+struct Offload {
+    cancelled: AtomicBool,
+    state: Mutex<OffloadState>,
+}
+
+#[derive(Default)]
+struct OffloadState {
+    result: Option<Result<ScrapeOutput, RenderError>>,
+    waker: Option<Waker>,
+}
+
+impl ScrapeFuture {
+    pub const DEFAULT_OFFLOAD_THRESHOLD: usize = 10_000;
+
+    /* PromMetricRegistry::scrape_async for any source */
+    pub fn start<S: ScrapeSource>(source: S, options: ScrapeOptions) -> Self {
+        let offload = source.should_offload().then(Arc::default);
+        ScrapeFuture {
+            render: Some(Box::new(move || source.scrape_output(&options))),
+            offload,
+        }
+    }
+
+    /* whether the render runs on an offload thread */
+    pub fn is_offloaded(&self) -> bool {
+        self.offload.is_some()
+    }
+}
+
+impl Future for ScrapeFuture {
+    type Output = Result<ScrapeOutput, RenderError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let Some(shared) = &this.offload else {
+            let render = this
+                .render
+                .take()
+                .expect("ScrapeFuture polled after completion");
+            return Poll::Ready(render());
+        };
+
+        let mut state = shared.state.lock().unwrap();
+        if let Some(result) = state.result.take() {
+            return Poll::Ready(result);
+        }
+
+        /* the waker is in place before the render can finish */
+        state.waker = Some(cx.waker().clone());
+        drop(state);
+        if let Some(render) = this.render.take() {
+            offload_pool().submit(OffloadJob {
+                shared: shared.clone(),
+                render,
+            });
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for ScrapeFuture {
+    fn drop(&mut self) {
+        if let Some(shared) = &self.offload {
+            shared.cancelled.store(true, Ordering::Release);
+        }
+    }
+}
+
+/* at most this many offloaded renders run at once, later ones queue */
+const OFFLOAD_THREADS: usize = 4;
+
+struct OffloadJob {
+    shared: Arc<Offload>,
+    render: Render,
+}
+
+impl OffloadJob {
+    fn run(self) {
+        if self.shared.cancelled.load(Ordering::Acquire) {
+            return;
+        }
+
+        let result =
+            catch_unwind(AssertUnwindSafe(self.render)).unwrap_or(Err(RenderError::Panicked));
+        let mut state = self.shared.state.lock().unwrap();
+        if self.shared.cancelled.load(Ordering::Acquire) {
+            return;
+        }
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/* threads are started as renders queue up and kept for later scrapes */
+#[derive(Default)]
+struct OffloadPool {
+    state: Mutex<PoolState>,
+    queued: Condvar,
+}
+
+#[derive(Default)]
+struct PoolState {
+    jobs: VecDeque<OffloadJob>,
+    threads: usize,
+    idle: usize,
+}
+
+fn offload_pool() -> &'static OffloadPool {
+    static POOL: OnceLock<OffloadPool> = OnceLock::new();
+    POOL.get_or_init(OffloadPool::default)
+}
+
+impl OffloadPool {
+    fn submit(&'static self, job: OffloadJob) {
+        let mut state = self.state.lock().unwrap();
+        state.jobs.push_back(job);
+        if state.idle != 0 || OFFLOAD_THREADS <= state.threads {
+            self.queued.notify_one();
+            return;
+        }
+
+        state.threads += 1;
+        let spawned = std::thread::Builder::new()
+            .name("arc-metrics-scrape".into())
+            .spawn(move || self.work());
+        /* without a thread the caller renders, as a queued job would wait forever */
+        if spawned.is_err() {
+            state.threads -= 1;
+            let job = state.jobs.pop_back();
+            drop(state);
+            job.into_iter().for_each(OffloadJob::run);
+        }
+    }
+
+    fn work(&self) {
+        /* a waker that panics takes its thread down, the pool may start another */
+        struct Exit<'a>(&'a OffloadPool);
+        impl Drop for Exit<'_> {
+            fn drop(&mut self) {
+                if let Ok(mut state) = self.0.state.lock() {
+                    state.threads -= 1;
+                }
+            }
+        }
+        let _exit = Exit(self);
+
+        let mut state = self.state.lock().unwrap();
+        loop {
+            match state.jobs.pop_front() {
+                Some(job) => {
+                    drop(state);
+                    job.run();
+                    state = self.state.lock().unwrap();
+                }
+                None => {
+                    state.idle += 1;
+                    state = self.queued.wait(state).unwrap();
+                    state.idle -= 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        future::Future,
+        pin::Pin,
+        sync::{Arc, RwLock},
+        task::{Context, Poll, Waker},
+        time::{Duration, Instant},
+    };
+
+    use crate::{IntCounter, PromMetricRegistry};
+
+    use super::{
+        RenderError, ScrapeContext, ScrapeFormat, ScrapeFuture, ScrapeOptions, ScrapeOutput,
+        ScrapeSource,
+    };
+
+    #[derive(Default)]
+    struct Met {
+        http_requests: IntCounter,
+        db_queries: IntCounter,
+    }
+
+    fn registry() -> Arc<RwLock<PromMetricRegistry>> {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+
+        reg.register_fn(&met, |m, reg| {
+            reg.count("http_requests", &m.http_requests);
+            reg.count("db_queries", &m.db_queries);
+        });
+
+        Arc::new(RwLock::new(reg))
+    }
+
+    #[test]
+    fn scrape_fn_test() {
+        let registry = registry();
+        let scrape = PromMetricRegistry::as_scrape_fn(registry.clone());
+
+        let all = scrape(ScrapeOptions::from_accept(Some("text/plain"))).unwrap();
+        assert_eq!(
+            all.content_type,
+            ScrapeFormat::PrometheusText.content_type()
+        );
+        assert_eq!(all.body, registry.read().unwrap().to_string());
+
+        let http = scrape(ScrapeOptions::default().module("http_")).unwrap();
+        assert_eq!(
+            http.body,
+            "# HELP http_requests\n# TYPE http_requests counter\nhttp_requests 0\n"
+        );
+
+        let both = scrape(
+            ScrapeOptions::default()
+                .module("http_")
+                .module("db_")
+                .timeout(Duration::from_secs(5)),
+        )
+        .unwrap();
+        assert_eq!(both.body, all.body);
+
+        let scrape_clone = scrape.clone();
+        let threaded = std::thread::spawn(move || scrape_clone(ScrapeOptions::default()))
+            .join()
+            .unwrap()
+            .unwrap();
+        assert_eq!(threaded, all);
+    }
+
+    #[test]
+    fn scrape_deadline_test() {
+        let registry = registry();
+        let scrape = PromMetricRegistry::as_scrape_fn(registry.clone());
+
+        let expired = ScrapeOptions {
+            deadline: Some(Instant::now()),
+            ..Default::default()
+        };
+        assert_eq!(scrape(expired), Err(RenderError::DeadlineExceeded));
+
+        let _write = registry.write().unwrap();
+        assert_eq!(
+            scrape(ScrapeOptions::default().timeout(Duration::from_millis(5))),
+            Err(RenderError::DeadlineExceeded)
+        );
+    }
+
+    #[test]
+    fn scrape_client_test() {
+        let mut reg = PromMetricRegistry::empty();
+        reg.max_scrape_clients(2);
+
+        /* a client may be called anything, including what the overflow used to be */
+        reg.render_with_context(ScrapeContext::client("prometheus-a"));
+        reg.render_with_context(ScrapeContext::client("other"));
+        reg.render_with_context(ScrapeContext::client("prometheus-a"));
+        reg.render_with_context(ScrapeContext::client("curl"));
+        reg.render_with_context(ScrapeContext::client("wget"));
+        reg.render_with_context(ScrapeContext::default());
+
+        let output = reg.render_with_context(ScrapeContext::default());
+        let scrapes = output
+            .lines()
+            .filter(|line| line.starts_with("arc_metrics_scrapes_total"))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            scrapes,
+            [
+                "arc_metrics_scrapes_total{client=\"other\"} 1",
+                "arc_metrics_scrapes_total{client=\"prometheus-a\"} 2",
+                "arc_metrics_scrapes_total{client_overflow=\"true\"} 2",
+            ]
+        );
+        assert_eq!(
+            output
+                .lines()
+                .filter(|line| line.starts_with("arc_metrics_scrape_duration_us_total{"))
+                .count(),
+            3
+        );
+    }
+
+    /* minimal executor, parks the test thread until the waker fires */
+    struct ThreadWaker(std::thread::Thread);
+
+    impl std::task::Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn poll_once<F: Future + Unpin>(future: &mut F) -> Poll<F::Output> {
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        Pin::new(future).poll(&mut Context::from_waker(&waker))
+    }
+
+    fn block_on<F: Future + Unpin>(mut future: F) -> F::Output {
+        loop {
+            match poll_once(&mut future) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    fn inflated(series: usize) -> Arc<RwLock<PromMetricRegistry>> {
+        let counters = Arc::new((0..series).map(|_| IntCounter::new()).collect::<Vec<_>>());
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&counters, |counters, reg| {
+            for (i, counter) in counters.iter().enumerate() {
+                reg.count("inflated", counter).attr("id", i.to_string());
+            }
+        });
+        Arc::new(RwLock::new(reg))
+    }
+
+    #[test]
+    fn scrape_async_inline_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&met, |m, reg| {
+            reg.count("http_requests", &m.http_requests);
+        });
+        let registry = Arc::new(RwLock::new(reg));
+        let mut future = PromMetricRegistry::scrape_async(registry.clone(), Default::default());
+
+        /* nothing is rendered before the first poll */
+        met.http_requests.inc();
+        assert!(!future.is_offloaded());
+        let Poll::Ready(output) = poll_once(&mut future) else {
+            panic!("small registries render on the first poll");
+        };
+        assert!(output.unwrap().body.contains("http_requests 1\n"));
+    }
+
+    #[test]
+    fn scrape_async_offload_test() {
+        let big = inflated(20_000);
+        big.write().unwrap().offload_threshold(1_000);
+
+        let expected = big.read().unwrap().to_string();
+        let future = PromMetricRegistry::scrape_async(big.clone(), Default::default());
+        assert!(future.is_offloaded());
+        assert_eq!(block_on(future).unwrap().body, expected);
+
+        /* a writer keeps the offloaded render waiting, other scrapes still complete */
+        let writer = big.write().unwrap();
+        let mut slow = PromMetricRegistry::scrape_async(big.clone(), Default::default());
+        assert!(slow.is_offloaded());
+        assert!(poll_once(&mut slow).is_pending());
+
+        let small = PromMetricRegistry::scrape_async(registry(), Default::default());
+        assert!(block_on(small).is_ok());
+        assert!(poll_once(&mut slow).is_pending());
+
+        drop(writer);
+        assert_eq!(block_on(slow).unwrap().body, expected);
+    }
+
+    #[test]
+    fn scrape_async_pool_test() {
+        let big = inflated(100);
+        big.write().unwrap().offload_threshold(0);
+
+        /* renders queue behind the writer instead of each taking a thread */
+        let writer = big.write().unwrap();
+        let mut futures = (0..4 * super::OFFLOAD_THREADS)
+            .map(|_| PromMetricRegistry::scrape_async(big.clone(), Default::default()))
+            .collect::<Vec<_>>();
+        for future in &mut futures {
+            assert!(poll_once(future).is_pending());
+        }
+        assert!(super::offload_pool().state.lock().unwrap().threads <= super::OFFLOAD_THREADS);
+
+        drop(writer);
+        let expected = big.read().unwrap().to_string();
+        for future in futures {
+            assert_eq!(block_on(future).unwrap().body, expected);
+        }
+    }
+
+    #[derive(Clone)]
+    struct Panicking;
+
+    impl ScrapeSource for Panicking {
+        fn scrape_output(&self, _: &ScrapeOptions) -> Result<ScrapeOutput, RenderError> {
+            panic!("render failed")
+        }
+
+        fn should_offload(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn scrape_async_panic_test() {
+        /* the panic is reported to the future, the offload thread keeps serving */
+        let future = ScrapeFuture::start(Panicking, Default::default());
+        assert_eq!(block_on(future), Err(RenderError::Panicked));
+
+        let big = inflated(10);
+        big.write().unwrap().offload_threshold(0);
+        let future = PromMetricRegistry::scrape_async(big.clone(), Default::default());
+        assert_eq!(
+            block_on(future).unwrap().body,
+            big.read().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn scrape_async_cancel_test() {
+        let big = inflated(2_000);
+        big.write().unwrap().offload_threshold(0);
+
+        let writer = big.write().unwrap();
+        let future = PromMetricRegistry::scrape_async(big.clone(), Default::default());
+        drop(future);
+        drop(writer);
+
+        /* the abandoned task releases the registry once its render is done */
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Arc::strong_count(&big) != 1 {
+            assert!(Instant::now() < deadline);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
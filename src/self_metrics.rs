@@ -0,0 +1,219 @@
+/*
+ * the registry reporting on itself. A render is counted and timed when it finishes, so a
+ * scrape shows the renders before it and never its own.
+ */
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{FamilyView, IntCounter, IntGauge, MetricType, PromMetricRegistry, Reading};
+
+#[derive(Default)]
+pub(crate) struct SelfMetrics {
+    renders: IntCounter,
+    render_duration_us: IntCounter,
+    series: IntGauge,
+}
+
+impl SelfMetrics {
+    pub(crate) fn rendered(&self, elapsed: Duration) {
+        self.renders.inc();
+        self.render_duration_us.inc_by_micros(elapsed);
+    }
+
+    pub(crate) fn set_series(&self, series: usize) {
+        self.series.set(series as u64);
+    }
+}
+
+/*
+ * a counter (or histogram count) lower than at the previous render wrapped or was reset
+ * outside the registry. Series are keyed by name and labels, so unregistering and
+ * registering a series again with a lower value counts too. Like the render counters the
+ * total shows on the render after the one that found the decrease.
+ */
+#[derive(Default)]
+pub(crate) struct CounterCheck {
+    anomalies: IntCounter,
+    last: Mutex<HashMap<String, u64>>,
+}
+
+impl CounterCheck {
+    pub(crate) fn check(&self, family: &FamilyView) {
+        let mut last = match self.last.lock() {
+            Ok(last) => last,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        for (metric, reading) in family.metrics.iter().zip(family.readings) {
+            let value = match (metric.metric_type, reading) {
+                (MetricType::IntGauge, _) | (_, Reading::Skipped) => continue,
+                (_, Reading::Value(value)) => *value,
+                /* non-negative f64 bits order like the values */
+                (_, Reading::Float(value)) => value.get().to_bits(),
+                (_, Reading::Histogram { counts, .. }) => counts[counts.len() - 1],
+                (_, Reading::Summary { count, .. }) => *count,
+            };
+
+            let key = format!("{}{{{}}}", metric.name, metric.labels());
+            match last.insert(key, value) {
+                Some(previous) if value < previous => self.anomalies.inc(),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl PromMetricRegistry {
+    /*
+     * exports arc_metrics_renders_total, arc_metrics_render_duration_us_total,
+     * arc_metrics_registered_series (updated on every registration and removal) and
+     * arc_metrics_dropped_samples_total, see track_dropped
+     */
+    pub fn enable_self_metrics(&mut self) -> &mut Self {
+        if self.self_metrics.is_some() {
+            return self;
+        }
+
+        let metrics = Arc::new(SelfMetrics::default());
+        self.register_own_fn(&metrics, |m, reg| {
+            reg.count("arc_metrics_renders_total", &m.renders);
+            reg.count(
+                "arc_metrics_render_duration_us_total",
+                &m.render_duration_us,
+            );
+            reg.gauge("arc_metrics_registered_series", &m.series);
+        });
+
+        let metrics =
+            unsafe { std::mem::transmute::<&SelfMetrics, &'static SelfMetrics>(&metrics) };
+        metrics.set_series(self.metrics.len());
+        self.self_metrics = Some(metrics);
+        self.track_dropped()
+    }
+
+    /*
+     * debug mode comparing every counter against the previous render, decreases are
+     * counted in arc_metrics_counter_anomalies_total
+     */
+    pub fn enable_counter_check(&mut self) -> &mut Self {
+        if self.counter_check.is_some() {
+            return self;
+        }
+
+        let check = Arc::new(CounterCheck::default());
+        self.register_own_fn(&check, |check, reg| {
+            reg.count("arc_metrics_counter_anomalies_total", &check.anomalies);
+        });
+
+        let check = unsafe { std::mem::transmute::<&CounterCheck, &'static CounterCheck>(&check) };
+        self.counter_check = Some(check);
+        self
+    }
+
+    pub(crate) fn update_series_gauge(&self) {
+        if let Some(metrics) = self.self_metrics {
+            metrics.set_series(self.metrics.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{IntCounter, IntGauge, IntHistogram, PromMetricRegistry};
+
+    fn value(reg: &PromMetricRegistry, name: &str) -> u64 {
+        let text = reg.to_string();
+        let line = text
+            .lines()
+            .find(|line| line.starts_with(&format!("{} ", name)))
+            .unwrap();
+        line.rsplit(' ').next().unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn self_metrics_test() {
+        let mut reg = PromMetricRegistry::empty();
+        let requests = Arc::new(IntCounter::new());
+        reg.register_counter("requests", &requests);
+        reg.enable_self_metrics().enable_self_metrics();
+
+        /* the render reading the counter is not counted yet */
+        assert_eq!(value(&reg, "arc_metrics_renders_total"), 0);
+        assert_eq!(value(&reg, "arc_metrics_renders_total"), 1);
+        assert_eq!(value(&reg, "arc_metrics_registered_series"), 4);
+        assert!(reg
+            .to_string()
+            .contains("arc_metrics_dropped_samples_total{reason=\"cardinality\"} "));
+
+        let workers = Arc::new([IntCounter::new(), IntCounter::new()]);
+        reg.register_fn(&workers, |workers, reg| {
+            for (i, worker) in workers.iter().enumerate() {
+                reg.count("jobs", worker).attr("worker", i.to_string());
+            }
+        });
+        assert_eq!(value(&reg, "arc_metrics_registered_series"), 6);
+
+        reg.unregister_holder(&workers);
+        assert_eq!(value(&reg, "arc_metrics_registered_series"), 4);
+
+        /* gather() is not a render */
+        let renders = value(&reg, "arc_metrics_renders_total");
+        reg.gather();
+        assert_eq!(value(&reg, "arc_metrics_renders_total"), renders + 1);
+    }
+
+    #[test]
+    fn test_mode_test() {
+        let mut reg = PromMetricRegistry::empty().test_mode();
+        reg.enable_self_metrics();
+        for _ in 0..3 {
+            let mut sink = String::new();
+            reg.encode_openmetrics(&mut sink).unwrap();
+            reg.to_string();
+        }
+
+        assert!(reg.to_string().contains(
+            "arc_metrics_render_duration_us_total{program=\"test\",pkg_version=\"0.0.0\"} 0\n"
+        ));
+    }
+
+    #[test]
+    fn counter_check_test() {
+        let mut reg = PromMetricRegistry::empty();
+        let requests = Arc::new(IntCounter::new());
+        let latency = Arc::new(IntHistogram::new([10]));
+        let queue = Arc::new(IntGauge::new());
+        reg.register_counter("requests", &requests);
+        reg.register_fn(&latency, |latency, reg| {
+            reg.histogram("latency", latency);
+        });
+        reg.register_gauge("queue", &queue);
+        reg.enable_counter_check().enable_counter_check();
+
+        requests.inc_by(u64::MAX - 1);
+        latency.observe(5);
+        queue.set(10);
+        assert_eq!(value(&reg, "arc_metrics_counter_anomalies_total"), 0);
+
+        /* gauges may go down */
+        queue.set(1);
+        requests.inc();
+        assert_eq!(value(&reg, "arc_metrics_counter_anomalies_total"), 0);
+
+        assert!(requests.checked_inc_by(1).is_err());
+        assert_eq!(requests.load(), u64::MAX);
+
+        requests.inc_by(2);
+        reg.to_string();
+        assert_eq!(value(&reg, "arc_metrics_counter_anomalies_total"), 1);
+
+        latency.take();
+        reg.to_string();
+        assert_eq!(value(&reg, "arc_metrics_counter_anomalies_total"), 2);
+    }
+}
@@ -0,0 +1,135 @@
+/*
+ * /metrics over std::net for sidecars that don't want an HTTP stack. One connection at a
+ * time on the accept thread, each request gets a single response and the connection is
+ * closed. Only the request line and Accept header are looked at.
+ */
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crate::scrape::{scrape_fn, RenderError, ScrapeOptions, ScrapeSource};
+
+/* requests are small, anything past this is not a scrape */
+const MAX_REQUEST: usize = 8 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/* registry is an Arc<RwLock<PromMetricRegistry>> or a RegistrySet */
+pub fn serve_std<S: ScrapeSource>(registry: S, addr: SocketAddr) -> io::Result<ServerHandle> {
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+    let stopped = Arc::new(AtomicBool::new(false));
+
+    let scrape = scrape_fn(registry);
+    let thread = {
+        let stopped = Arc::clone(&stopped);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stopped.load(Ordering::Acquire) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    /* a client going away mid-request is its own problem */
+                    let _ = handle(stream, &scrape);
+                }
+            }
+        })
+    };
+
+    Ok(ServerHandle {
+        local_addr,
+        stopped,
+        thread,
+    })
+}
+
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    stopped: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl ServerHandle {
+    /* the bound address, ex. to find the port when binding port 0 */
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /* waits for an in-flight response, then stops accepting */
+    pub fn shutdown(self) {
+        self.stopped.store(true, Ordering::Release);
+
+        /* wake the blocking accept, unspecified addresses are reachable over loopback */
+        let mut wake = self.local_addr;
+        if wake.ip().is_unspecified() {
+            wake.set_ip(match wake {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        let _ = TcpStream::connect_timeout(&wake, TIMEOUT);
+        let _ = self.thread.join();
+    }
+}
+
+fn handle(
+    mut stream: TcpStream,
+    scrape: &impl Fn(ScrapeOptions) -> Result<crate::scrape::ScrapeOutput, RenderError>,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|end| end == b"\r\n\r\n") {
+        let read = stream.read(&mut buf)?;
+        if read == 0 || MAX_REQUEST < request.len() + read {
+            break;
+        }
+        request.extend_from_slice(&buf[..read]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut lines = request.split("\r\n");
+    let mut request_line = lines.next().unwrap_or("").split(' ');
+    let method = request_line.next().unwrap_or("");
+    let target = request_line.next().unwrap_or("");
+    let path = target.split('?').next().unwrap_or("");
+    let accept = lines.find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.eq_ignore_ascii_case("accept").then(|| value.trim())
+    });
+
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/metrics") => match scrape(ScrapeOptions::from_accept(accept).timeout(TIMEOUT)) {
+            Ok(output) => ("200 OK", output.content_type, output.body),
+            Err(error) => (
+                "503 Service Unavailable",
+                "text/plain; charset=utf-8",
+                format!("{}\n", error),
+            ),
+        },
+        _ => (
+            "404 Not Found",
+            "text/plain; charset=utf-8",
+            "not found\n".to_string(),
+        ),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+    stream.shutdown(Shutdown::Both)
+}
@@ -0,0 +1,111 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/* own cache line per shard so threads on different shards don't false share */
+#[derive(Debug, Default)]
+#[repr(align(128))]
+struct Shard(AtomicU64);
+
+/* counter spread over per thread shards, the value is the sum computed when read */
+#[derive(Debug)]
+pub struct ShardedCounter {
+    shards: Box<[Shard]>,
+}
+
+impl ShardedCounter {
+    pub fn new(shards: usize) -> Self {
+        assert!(0 < shards, "sharded counter needs at least one shard");
+        ShardedCounter {
+            shards: (0..shards).map(|_| Shard::default()).collect(),
+        }
+    }
+
+    #[inline]
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    #[inline]
+    pub fn inc_by(&self, amount: u64) {
+        let shard = thread_index() % self.shards.len();
+        self.shards[shard].0.fetch_add(amount, Ordering::Relaxed);
+    }
+
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /* sum over shards, not a snapshot of a single instant */
+    pub fn load(&self) -> u64 {
+        self.shards.iter().fold(0u64, |sum, shard| {
+            sum.wrapping_add(shard.0.load(Ordering::Acquire))
+        })
+    }
+
+    pub fn take(&self) -> u64 {
+        self.shards.iter().fold(0u64, |sum, shard| {
+            sum.wrapping_add(shard.0.swap(0, Ordering::AcqRel))
+        })
+    }
+}
+
+/* one shard per cpu */
+impl Default for ShardedCounter {
+    fn default() -> Self {
+        Self::new(std::thread::available_parallelism().map_or(8, |n| n.get()))
+    }
+}
+
+/* threads are numbered in start order so consecutive threads land on different shards */
+fn thread_index() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    thread_local! {
+        static INDEX: usize = NEXT.fetch_add(1, Ordering::Relaxed);
+    }
+
+    INDEX.with(|index| *index)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{PromMetricRegistry, SampleValue};
+
+    use super::ShardedCounter;
+
+    #[test]
+    fn sharded_counter_test() {
+        let counter = Arc::new(ShardedCounter::new(4));
+        assert_eq!(std::mem::align_of::<super::Shard>(), 128);
+
+        let threads = (0..8)
+            .map(|_| {
+                let counter = counter.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        counter.inc();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&counter, |counter, reg| {
+            reg.sharded_count("packets", counter);
+        });
+
+        assert_eq!(
+            reg.to_string(),
+            "# HELP packets\n# TYPE packets counter\npackets 8000\n"
+        );
+        assert_eq!(
+            reg.snapshot_and_reset()[0].samples[0].value,
+            SampleValue::Value(8000)
+        );
+        assert_eq!(counter.load(), 0);
+    }
+}
@@ -0,0 +1,208 @@
+/*
+ * owned, flat view of gathered families for structured logging. With the serde feature
+ * it serializes as {"timestamp_ms": .., "metrics": [{"name", "type", "labels", "value"}]}
+ * with type as counter / gauge / histogram / summary, labels as a map sorted by key and
+ * value as a number (a float for FloatCounter / FloatGauge), {"buckets": [{"le", "count"}], "sum", "count"} for histograms or
+ * {"quantiles": [{"quantile", "value"}], "sum", "count"} for summaries.
+ */
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{FloatValue, MetricFamily, MetricType, PromMetricRegistry, SampleValue};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot {
+    /* capture time in milliseconds since the unix epoch */
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub timestamp_ms: Option<u64>,
+    pub metrics: Vec<SnapshotSeries>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SnapshotSeries {
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(rename = "type"))]
+    pub metric_type: MetricType,
+    pub labels: BTreeMap<String, String>,
+    pub value: SnapshotValue,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
+pub enum SnapshotValue {
+    Number(u64),
+    Float(FloatValue),
+    /* cumulative counts like the exposition, count is the +Inf bucket */
+    Histogram {
+        buckets: Vec<SnapshotBucket>,
+        sum: u64,
+        count: u64,
+    },
+    Summary {
+        quantiles: Vec<SnapshotQuantile>,
+        sum: u64,
+        count: u64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SnapshotBucket {
+    pub le: u64,
+    pub count: u64,
+}
+
+/* quantile as rendered in its label, ex. "0.99" */
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SnapshotQuantile {
+    pub quantile: String,
+    pub value: u64,
+}
+
+impl Snapshot {
+    /* ex. from gather() or snapshot_and_reset() */
+    pub fn from_families(families: Vec<MetricFamily>, timestamp: Option<SystemTime>) -> Self {
+        let mut metrics = Vec::new();
+        for family in families {
+            for sample in family.samples {
+                let value = match sample.value {
+                    SampleValue::Value(value) => SnapshotValue::Number(value),
+                    SampleValue::Float(value) => SnapshotValue::Float(value),
+                    SampleValue::Histogram {
+                        buckets,
+                        sum,
+                        count,
+                    } => SnapshotValue::Histogram {
+                        buckets: buckets
+                            .into_iter()
+                            .map(|(le, count)| SnapshotBucket { le, count })
+                            .collect(),
+                        sum,
+                        count,
+                    },
+                    SampleValue::Summary {
+                        quantiles,
+                        sum,
+                        count,
+                    } => SnapshotValue::Summary {
+                        quantiles: quantiles
+                            .into_iter()
+                            .map(|(quantile, value)| SnapshotQuantile {
+                                quantile: quantile.to_string(),
+                                value,
+                            })
+                            .collect(),
+                        sum,
+                        count,
+                    },
+                };
+
+                metrics.push(SnapshotSeries {
+                    name: family.name.to_string(),
+                    metric_type: family.metric_type,
+                    labels: sample
+                        .attributes
+                        .into_iter()
+                        .map(|[key, value]| (key.into_owned(), value.into_owned()))
+                        .collect(),
+                    value,
+                });
+            }
+        }
+
+        Snapshot {
+            timestamp_ms: timestamp.map(|timestamp| {
+                timestamp
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |since| since.as_millis() as u64)
+            }),
+            metrics,
+        }
+    }
+}
+
+impl PromMetricRegistry {
+    /* gather() as a Snapshot stamped with the current time */
+    pub fn to_serializable(&self) -> Snapshot {
+        Snapshot::from_families(self.gather(), Some(SystemTime::now()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::Arc,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use crate::{IntCounter, IntHistogram, MetricType, PromMetricRegistry};
+
+    use super::{Snapshot, SnapshotBucket, SnapshotSeries, SnapshotValue};
+
+    #[derive(Default)]
+    struct Met {
+        requests: IntCounter,
+        latency: IntHistogram,
+    }
+
+    #[test]
+    fn from_families_test() {
+        let met = Arc::new(Met {
+            latency: IntHistogram::new([10]),
+            ..Default::default()
+        });
+        let mut reg = PromMetricRegistry::empty().with_base_attrs([("service", "api")]);
+        reg.register_fn(&met, |m, reg| {
+            reg.count("requests", &m.requests).attr("method", "get");
+            reg.histogram("latency", &m.latency);
+        });
+        met.requests.inc_by(2);
+        met.latency.observe(5);
+        met.latency.observe(50);
+
+        let timestamp = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let snapshot = Snapshot::from_families(reg.gather(), Some(timestamp));
+        let labels = |labels: &[(&str, &str)]| {
+            labels
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+
+        assert_eq!(
+            snapshot,
+            Snapshot {
+                timestamp_ms: Some(1_700_000_000_123),
+                metrics: vec![
+                    SnapshotSeries {
+                        name: "latency".to_string(),
+                        metric_type: MetricType::IntHistogram,
+                        labels: labels(&[("service", "api")]),
+                        value: SnapshotValue::Histogram {
+                            buckets: vec![SnapshotBucket { le: 10, count: 1 }],
+                            sum: 55,
+                            count: 2,
+                        },
+                    },
+                    SnapshotSeries {
+                        name: "requests".to_string(),
+                        metric_type: MetricType::IntCounter,
+                        labels: labels(&[("method", "get"), ("service", "api")]),
+                        value: SnapshotValue::Number(2),
+                    },
+                ],
+            }
+        );
+
+        assert!(reg.to_serializable().timestamp_ms.is_some());
+    }
+}
@@ -0,0 +1,426 @@
+use std::{
+    collections::HashMap,
+    fmt::{Display, Write as _},
+    net::SocketAddr,
+    sync::{atomic::AtomicU64, mpsc, Arc, RwLock},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    export::{ExportPolicy, ExportSchedule},
+    transport::{Transport, UdpTransport},
+    FloatValue, MetricType, MetricValue, PromMetricRegistry, RegisteredMetric, ShardedCounter,
+};
+
+pub struct StatsdExporter {
+    transport: Box<dyn Transport>,
+    prefix: Option<String>,
+    mtu: usize,
+    /* last flushed counter values (f64 bits of float counters) keyed by the address of their value */
+    previous: HashMap<usize, u64>,
+}
+
+impl StatsdExporter {
+    /* safe payload size for UDP over typical networks */
+    pub const DEFAULT_MTU: usize = 1432;
+
+    pub fn new(target: SocketAddr, prefix: Option<&str>) -> std::io::Result<Self> {
+        Ok(Self::with_transport(UdpTransport::new(target)?, prefix))
+    }
+
+    /* every packet is handed to transport as a single send */
+    pub fn with_transport<T: Transport + 'static>(transport: T, prefix: Option<&str>) -> Self {
+        StatsdExporter {
+            transport: Box::new(transport),
+            prefix: prefix.map(|prefix| prefix.to_string()),
+            mtu: Self::DEFAULT_MTU,
+            previous: HashMap::new(),
+        }
+    }
+
+    pub fn mtu(mut self, mtu: usize) -> Self {
+        self.mtu = mtu;
+        self
+    }
+
+    pub fn flush(&mut self, registry: &PromMetricRegistry) -> std::io::Result<()> {
+        let mut packet = String::new();
+        let mut line = String::new();
+
+        for metric in &registry.metrics {
+            let Some(_holder) = registry.hold(metric) else {
+                continue;
+            };
+
+            match metric.value {
+                MetricValue::Atomic(value) => {
+                    let kind = match metric.metric_type {
+                        MetricType::IntGauge => Kind::Gauge,
+                        _ => Kind::Counter,
+                    };
+                    self.line(&mut line, metric, "", read(value), 1, kind);
+                }
+                MetricValue::Histogram(histogram, scale) => {
+                    let (sum, count) = (read(&histogram.sum), read(&histogram.count));
+                    self.line(&mut line, metric, "_sum", sum, scale, Kind::Counter);
+                    self.send_line(&mut packet, &mut line)?;
+                    self.line(&mut line, metric, "_count", count, scale, Kind::Counter);
+                }
+                /* quantiles are left to the statsd server's own timers */
+                MetricValue::Summary(summary) => {
+                    let (sum, count) = (read(&summary.sum), read(&summary.count));
+                    self.line(&mut line, metric, "_sum", sum, 1, Kind::Counter);
+                    self.send_line(&mut packet, &mut line)?;
+                    self.line(&mut line, metric, "_count", count, 1, Kind::Counter);
+                }
+                MetricValue::Sharded(counter) => {
+                    let value = (counter as *const ShardedCounter as usize, counter.load());
+                    self.line(&mut line, metric, "", value, 1, Kind::Counter);
+                }
+                MetricValue::Const(value) => {
+                    self.line(&mut line, metric, "", (0, value), 1, Kind::Gauge);
+                }
+                MetricValue::Float(bits) => {
+                    let kind = match metric.metric_type {
+                        MetricType::IntGauge => Kind::Gauge,
+                        _ => Kind::Counter,
+                    };
+                    self.float_line(&mut line, metric, read(bits), kind);
+                }
+            }
+
+            self.send_line(&mut packet, &mut line)?;
+        }
+
+        if !packet.is_empty() {
+            self.transport.send(packet.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn spawn_interval(
+        self,
+        registry: Arc<RwLock<PromMetricRegistry>>,
+        period: Duration,
+    ) -> StatsdHandle {
+        self.spawn_with_policy(registry, ExportPolicy::every(period))
+    }
+
+    /* checks the policy every min_interval, stopping always performs a final flush */
+    pub fn spawn_with_policy(
+        mut self,
+        registry: Arc<RwLock<PromMetricRegistry>>,
+        policy: ExportPolicy,
+    ) -> StatsdHandle {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let mut schedule = ExportSchedule::new(policy);
+        schedule.exported(Instant::now());
+
+        let thread = std::thread::spawn(move || loop {
+            let stop = !matches!(
+                stopped.recv_timeout(policy.min_interval),
+                Err(mpsc::RecvTimeoutError::Timeout)
+            );
+
+            let registry = registry.read().unwrap();
+            let now = Instant::now();
+            let changed = schedule.changed_series(&registry);
+            if stop || schedule.should_export(now, changed) {
+                /* errors are transient for UDP, next interval retries */
+                let _ = self.flush(&registry);
+                schedule.exported(now);
+            }
+
+            if stop {
+                break;
+            }
+        });
+
+        StatsdHandle { stop, thread }
+    }
+
+    fn line(
+        &mut self,
+        line: &mut String,
+        metric: &RegisteredMetric,
+        suffix: &str,
+        /* address of the underlying value and its current reading */
+        (key, current): (usize, u64),
+        scale: u64,
+        kind: Kind,
+    ) {
+        line.clear();
+
+        let value = match kind {
+            Kind::Gauge => current,
+            Kind::Counter => {
+                let previous = self.previous.insert(key, current).unwrap_or(0);
+
+                /* counter went backwards, treat as reset */
+                let delta = current.checked_sub(previous).unwrap_or(current);
+                if delta == 0 {
+                    return;
+                }
+                delta.saturating_mul(scale)
+            }
+        };
+
+        self.write_line(line, metric, suffix, value, kind);
+    }
+
+    /* line() for the f64 bits of a FloatCounter / FloatGauge */
+    fn float_line(
+        &mut self,
+        line: &mut String,
+        metric: &RegisteredMetric,
+        (key, bits): (usize, u64),
+        kind: Kind,
+    ) {
+        line.clear();
+
+        let current = f64::from_bits(bits);
+        let value = match kind {
+            Kind::Gauge => current,
+            Kind::Counter => {
+                let previous = self.previous.insert(key, bits).map_or(0.0, f64::from_bits);
+
+                /* counter went backwards, treat as reset */
+                let delta = match current < previous {
+                    true => current,
+                    false => current - previous,
+                };
+                if delta == 0.0 {
+                    return;
+                }
+                delta
+            }
+        };
+
+        self.write_line(line, metric, "", FloatValue::new(value), kind);
+    }
+
+    fn write_line(
+        &self,
+        line: &mut String,
+        metric: &RegisteredMetric,
+        suffix: &str,
+        value: impl Display,
+        kind: Kind,
+    ) {
+        if let Some(prefix) = &self.prefix {
+            let _ = write!(line, "{}.", prefix);
+        }
+
+        let _ = write!(line, "{}{}:{}|{}", metric.name, suffix, value, kind.code());
+        for (i, [key, value]) in metric.attributes.iter().enumerate() {
+            let sep = if i == 0 { "|#" } else { "," };
+            let _ = write!(line, "{}{}:{}", sep, key, value);
+        }
+    }
+
+    fn send_line(&mut self, packet: &mut String, line: &mut String) -> std::io::Result<()> {
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        if !packet.is_empty() && self.mtu < packet.len() + 1 + line.len() {
+            self.transport.send(packet.as_bytes())?;
+            packet.clear();
+        }
+
+        if !packet.is_empty() {
+            packet.push('\n');
+        }
+        packet.push_str(line);
+        line.clear();
+
+        Ok(())
+    }
+}
+
+fn read(value: &AtomicU64) -> (usize, u64) {
+    (
+        value as *const AtomicU64 as usize,
+        value.load(std::sync::atomic::Ordering::Relaxed),
+    )
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Counter,
+    Gauge,
+}
+
+impl Kind {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Counter => "c",
+            Self::Gauge => "g",
+        }
+    }
+}
+
+pub struct StatsdHandle {
+    stop: mpsc::Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl StatsdHandle {
+    /* performs a final flush before returning */
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.thread.join();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::UdpSocket,
+        sync::{Arc, RwLock},
+        time::Duration,
+    };
+
+    use crate::{IntCounter, IntGauge, PromMetricRegistry};
+
+    use crate::export::ExportPolicy;
+
+    use super::StatsdExporter;
+
+    #[derive(Default)]
+    struct Met {
+        requests: IntCounter,
+        errors: IntCounter,
+        active: IntGauge,
+    }
+
+    fn setup() -> (Arc<Met>, PromMetricRegistry, UdpSocket) {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+
+        reg.register_fn(&met, |m, reg| {
+            reg.count("requests", &m.requests).attr("method", "get");
+            reg.count("errors", &m.errors);
+            reg.gauge("active", &m.active);
+        });
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        (met, reg, receiver)
+    }
+
+    fn recv(socket: &UdpSocket) -> String {
+        let mut buffer = [0u8; 2048];
+        let read = socket.recv(&mut buffer).unwrap();
+        String::from_utf8(buffer[..read].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn flush_test() {
+        let (met, reg, receiver) = setup();
+        let target = receiver.local_addr().unwrap();
+        let mut exporter = StatsdExporter::new(target, Some("app")).unwrap();
+
+        met.requests.inc_by(5);
+        met.errors.inc();
+        met.active.set(3);
+        exporter.flush(&reg).unwrap();
+        assert_eq!(
+            recv(&receiver),
+            "app.active:3|g\napp.errors:1|c\napp.requests:5|c|#method:get"
+        );
+
+        met.requests.inc_by(2);
+        exporter.flush(&reg).unwrap();
+        assert_eq!(
+            recv(&receiver),
+            "app.active:3|g\napp.requests:2|c|#method:get"
+        );
+    }
+
+    #[test]
+    fn mtu_test() {
+        let (met, reg, receiver) = setup();
+        let target = receiver.local_addr().unwrap();
+        let mut exporter = StatsdExporter::new(target, None).unwrap().mtu(24);
+
+        met.requests.inc();
+        met.errors.inc();
+        exporter.flush(&reg).unwrap();
+
+        assert_eq!(recv(&receiver), "active:0|g\nerrors:1|c");
+        assert_eq!(recv(&receiver), "requests:1|c|#method:get");
+    }
+
+    #[test]
+    fn spawn_interval_test() {
+        let (met, reg, receiver) = setup();
+        let target = receiver.local_addr().unwrap();
+        let exporter = StatsdExporter::new(target, None).unwrap();
+
+        met.errors.inc();
+        let handle = exporter.spawn_interval(Arc::new(RwLock::new(reg)), Duration::from_millis(10));
+        assert_eq!(recv(&receiver), "active:0|g\nerrors:1|c");
+        handle.stop();
+    }
+
+    #[test]
+    fn spawn_with_policy_test() {
+        let (met, reg, receiver) = setup();
+        let target = receiver.local_addr().unwrap();
+        let exporter = StatsdExporter::new(target, None).unwrap();
+
+        let policy = ExportPolicy {
+            min_interval: Duration::from_millis(10),
+            max_interval: Duration::from_secs(3600),
+            push_on_change_threshold: 2,
+        };
+        let handle = exporter.spawn_with_policy(Arc::new(RwLock::new(reg)), policy);
+
+        /* the first check sees every series as changed */
+        assert_eq!(recv(&receiver), "active:0|g");
+
+        met.errors.inc();
+        met.requests.inc();
+        assert_eq!(
+            recv(&receiver),
+            "active:0|g\nerrors:1|c\nrequests:1|c|#method:get"
+        );
+
+        met.errors.inc();
+        handle.stop();
+        assert_eq!(recv(&receiver), "active:0|g\nerrors:1|c");
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn flush_fault_injection_test() {
+        use std::time::Instant;
+
+        use crate::transport::{FaultInjector, UdpTransport};
+
+        let (met, reg, receiver) = setup();
+        let transport = UdpTransport::new(receiver.local_addr().unwrap()).unwrap();
+        let faults = FaultInjector::new(transport);
+        let mut exporter = StatsdExporter::with_transport(faults.clone(), None).mtu(24);
+
+        met.requests.inc();
+        met.errors.inc();
+        faults.fail_next(1);
+        assert!(exporter.flush(&reg).is_err());
+        assert_eq!((faults.attempts(), faults.failures()), (1, 1));
+
+        /* the failed packet's deltas were already taken, only the gauge is resent */
+        faults.delay(Some(Duration::from_millis(20)));
+        let start = Instant::now();
+        exporter.flush(&reg).unwrap();
+        assert!(Duration::from_millis(20) <= start.elapsed());
+        assert_eq!(recv(&receiver), "active:0|g");
+        assert_eq!(faults.attempts(), 2);
+    }
+}
@@ -0,0 +1,232 @@
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::Observe;
+
+/*
+ * quantile of a Summary in parts per million, so samples holding one stay Eq.
+ * Rendered as the shortest decimal, ex. 0.99.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Quantile(u32);
+
+impl Quantile {
+    /* None outside 0..=1 */
+    pub fn new(quantile: f64) -> Option<Self> {
+        (0.0..=1.0)
+            .contains(&quantile)
+            .then(|| Quantile((quantile * 1e6).round() as u32))
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        self.0 as f64 / 1e6
+    }
+}
+
+impl Display for Quantile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_f64())
+    }
+}
+
+/*
+ * count, sum and quantiles estimated from the most recent observations. Observing is a
+ * store into a ring of window slots plus the sum and count fetch_adds, the quantiles are
+ * computed by sorting a copy of the ring when the series is read. Values in the window
+ * can be a write behind under contention, the estimate is approximate either way.
+ */
+#[derive(Debug)]
+pub struct Summary {
+    quantiles: Box<[Quantile]>,
+    window: Box<[AtomicU64]>,
+    /* observations written to the window, the next slot is written % window.len() */
+    next: AtomicU64,
+    pub(crate) sum: AtomicU64,
+    pub(crate) count: AtomicU64,
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_QUANTILES)
+    }
+}
+
+impl Summary {
+    pub const DEFAULT_QUANTILES: &'static [f64] = &[0.5, 0.9, 0.99];
+    pub const DEFAULT_WINDOW: usize = 1024;
+
+    /* panics on quantiles outside 0..=1 */
+    pub fn new(quantiles: &[f64]) -> Self {
+        Self::with_window(quantiles, Self::DEFAULT_WINDOW)
+    }
+
+    /* quantiles over the last window observations */
+    pub fn with_window(quantiles: &[f64], window: usize) -> Self {
+        assert!(
+            0 < window,
+            "summary needs a window of at least one observation"
+        );
+        let mut quantiles = quantiles
+            .iter()
+            .map(|quantile| match Quantile::new(*quantile) {
+                Some(quantile) => quantile,
+                None => panic!("quantile {} is outside 0..=1", quantile),
+            })
+            .collect::<Vec<_>>();
+        quantiles.sort();
+        quantiles.dedup();
+
+        Summary {
+            quantiles: quantiles.into_boxed_slice(),
+            window: (0..window).map(|_| AtomicU64::new(0)).collect(),
+            next: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    #[inline]
+    pub fn observe(&self, value: u64) {
+        let slot = self.next.fetch_add(1, Ordering::Relaxed) % self.window.len() as u64;
+        self.window[slot as usize].store(value, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::AcqRel);
+        self.count.fetch_add(1, Ordering::AcqRel);
+    }
+
+    pub fn quantiles(&self) -> &[Quantile] {
+        &self.quantiles
+    }
+
+    /* nearest rank estimate per quantile over the window, 0 before the first observation */
+    pub fn values(&self) -> Vec<u64> {
+        let filled = self
+            .next
+            .load(Ordering::Acquire)
+            .min(self.window.len() as u64);
+        let mut window = self.window[..filled as usize]
+            .iter()
+            .map(|slot| slot.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        window.sort_unstable();
+
+        self.quantiles
+            .iter()
+            .map(|quantile| {
+                let rank = (quantile.as_f64() * window.len() as f64).ceil() as usize;
+                window
+                    .get(rank.saturating_sub(1).min(window.len().saturating_sub(1)))
+                    .copied()
+                    .unwrap_or(0)
+            })
+            .collect()
+    }
+
+    pub fn sum(&self) -> u64 {
+        self.sum.load(Ordering::Acquire)
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Acquire)
+    }
+
+    /* values, sum and count, then starts over with an empty window */
+    pub(crate) fn take(&self) -> (Vec<u64>, u64, u64) {
+        let values = self.values();
+        self.next.store(0, Ordering::Release);
+        let sum = self.sum.swap(0, Ordering::AcqRel);
+        let count = self.count.swap(0, Ordering::AcqRel);
+        (values, sum, count)
+    }
+}
+
+impl Observe for Summary {
+    fn observe(&self, value: u64) {
+        Summary::observe(self, value);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{PromMetricRegistry, SampleValue};
+
+    use super::{Quantile, Summary};
+
+    #[test]
+    fn quantile_test() {
+        assert_eq!(Quantile::new(0.99).unwrap().to_string(), "0.99");
+        assert_eq!(Quantile::new(0.5).unwrap().to_string(), "0.5");
+        assert_eq!(Quantile::new(1.0).unwrap().to_string(), "1");
+        assert_eq!(Quantile::new(1.5), None);
+        assert_eq!(Quantile::new(f64::NAN), None);
+    }
+
+    #[test]
+    fn summary_test() {
+        let summary = Summary::default();
+        assert_eq!(summary.values(), [0, 0, 0]);
+
+        for value in 1..=100 {
+            summary.observe(value);
+        }
+        assert_eq!(summary.values(), [50, 90, 99]);
+        assert_eq!((summary.sum(), summary.count()), (5050, 100));
+
+        /* only the last window observations count towards the quantiles */
+        let summary = Summary::with_window(&[0.99, 0.5, 0.5], 10);
+        assert_eq!(summary.quantiles().len(), 2);
+        for value in 1..=1000 {
+            summary.observe(value);
+        }
+        assert_eq!(summary.values(), [995, 1000]);
+        assert_eq!(summary.count(), 1000);
+
+        assert_eq!(summary.take(), (vec![995, 1000], 500500, 1000));
+        assert_eq!(summary.values(), [0, 0]);
+    }
+
+    #[test]
+    fn register_summary_test() {
+        let summary = Arc::new(Summary::new(&[0.5, 0.9]));
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_fn(&summary, |summary, reg| {
+            reg.summary("latency_ms", summary).attr("route", "/");
+        });
+        for value in [5, 10, 20, 40] {
+            summary.observe(value);
+        }
+
+        assert_eq!(
+            reg.to_string(),
+            "# HELP latency_ms\n\
+             # TYPE latency_ms summary\n\
+             latency_ms{route=\"/\",quantile=\"0.5\"} 10\n\
+             latency_ms{route=\"/\",quantile=\"0.9\"} 40\n\
+             latency_ms_sum{route=\"/\"} 75\n\
+             latency_ms_count{route=\"/\"} 4\n"
+        );
+
+        let samples = reg.find("latency_ms");
+        assert_eq!(
+            samples[0].value,
+            SampleValue::Summary {
+                quantiles: vec![
+                    (Quantile::new(0.5).unwrap(), 10),
+                    (Quantile::new(0.9).unwrap(), 40)
+                ],
+                sum: 75,
+                count: 4,
+            }
+        );
+
+        let mut open = String::new();
+        reg.encode_openmetrics(&mut open).unwrap();
+        assert!(open.contains("# TYPE latency_ms summary\n"));
+        assert!(open.contains("latency_ms{route=\"/\",quantile=\"0.9\"} 40\n"));
+        assert!(open.contains("latency_ms_count{route=\"/\"} 4\n"));
+        assert!(open.contains("latency_ms_created{route=\"/\"} "));
+    }
+}
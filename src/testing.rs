@@ -0,0 +1,394 @@
+/*
+ * parses rendered text back into samples so tests can assert on values instead of
+ * substrings. Reads both the text format and OpenMetrics: comments, # EOF and exemplars are
+ * skipped, quoted names and escaped label values are handled, values are floats.
+ */
+use std::{collections::BTreeMap, error::Error, fmt::Display};
+
+use crate::{escape, PromMetricRegistry};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedSample {
+    /* with suffixes, ex. latency_bucket */
+    pub name: String,
+    pub labels: BTreeMap<String, String>,
+    pub value: f64,
+    /* as written, milliseconds in the text format and seconds in OpenMetrics */
+    pub timestamp: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /* 1 based */
+    pub line: usize,
+    pub message: &'static str,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+pub fn parse_exposition(text: &str) -> Result<Vec<ParsedSample>, ParseError> {
+    let mut samples = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let error = |message| ParseError {
+            line: index + 1,
+            message,
+        };
+
+        let line = line.trim();
+        if let Some(comment) = line.strip_prefix('#') {
+            check_comment(comment.trim_start()).map_err(error)?;
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        samples.push(parse_sample(line).map_err(error)?);
+    }
+
+    Ok(samples)
+}
+
+fn check_comment(comment: &str) -> Result<(), &'static str> {
+    let Some((keyword, rest)) = comment.split_once(' ') else {
+        return Ok(());
+    };
+
+    match keyword {
+        "HELP" if rest.trim().is_empty() => Err("HELP without a metric name"),
+        "TYPE" => match rest.trim().rsplit_once(' ') {
+            Some((_, kind)) if TYPES.contains(&kind) => Ok(()),
+            Some(_) => Err("unknown metric type"),
+            None => Err("TYPE without a metric type"),
+        },
+        _ => Ok(()),
+    }
+}
+
+const TYPES: &[&str] = &[
+    "counter",
+    "gauge",
+    "histogram",
+    "gaugehistogram",
+    "summary",
+    "untyped",
+    "unknown",
+    "info",
+    "stateset",
+];
+
+fn parse_sample(line: &str) -> Result<ParsedSample, &'static str> {
+    let mut rest = line;
+    let mut name = String::new();
+    if !rest.starts_with('{') {
+        let end = rest.find(['{', ' ', '\t']).unwrap_or(rest.len());
+        name = rest[..end].to_string();
+        rest = &rest[end..];
+    }
+
+    let mut labels = BTreeMap::new();
+    if let Some(inner) = rest.strip_prefix('{') {
+        rest = parse_labels(inner, &mut name, &mut labels)?;
+    }
+    if name.is_empty() {
+        return Err("sample without a metric name");
+    }
+
+    /* OpenMetrics exemplar */
+    let rest = rest.split_once(" # ").map_or(rest, |(sample, _)| sample);
+    let mut fields = rest.split_whitespace();
+    let value = parse_float(fields.next().ok_or("sample without a value")?)?;
+    let timestamp = fields.next().map(parse_float).transpose()?;
+    if fields.next().is_some() {
+        return Err("unexpected text after the timestamp");
+    }
+
+    Ok(ParsedSample {
+        name,
+        labels,
+        value,
+        timestamp,
+    })
+}
+
+/* label pairs up to the closing brace, a quoted entry without = is the metric name */
+fn parse_labels<'a>(
+    mut rest: &'a str,
+    name: &mut String,
+    labels: &mut BTreeMap<String, String>,
+) -> Result<&'a str, &'static str> {
+    loop {
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix('}') {
+            return Ok(after);
+        }
+
+        let key;
+        if rest.starts_with('"') {
+            (key, rest) = quoted(rest)?;
+        } else {
+            let end = rest
+                .find(['=', ',', '}', ' '])
+                .ok_or("unterminated labels")?;
+            key = rest[..end].to_string();
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+
+        match rest.strip_prefix('=') {
+            Some(after) => {
+                let value;
+                (value, rest) = quoted(after.trim_start())?;
+                labels.insert(key, value);
+            }
+            None if name.is_empty() => *name = key,
+            None => return Err("label without a value"),
+        }
+
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after;
+        } else if !rest.starts_with('}') {
+            return Err("expected , or } after a label");
+        }
+    }
+}
+
+/* "..." with escapes, returns the unescaped text and what follows the closing quote */
+fn quoted(text: &str) -> Result<(String, &str), &'static str> {
+    let inner = text.strip_prefix('"').ok_or("expected a quoted string")?;
+    let mut escaped = false;
+    for (i, c) in inner.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => {
+                let value = escape::unescape(&inner[..i]).map_err(|_| "invalid escape")?;
+                return Ok((value, &inner[i + 1..]));
+            }
+            _ => {}
+        }
+    }
+    Err("unterminated quoted string")
+}
+
+fn parse_float(text: &str) -> Result<f64, &'static str> {
+    match text {
+        "+Inf" | "Inf" => Ok(f64::INFINITY),
+        "-Inf" => Ok(f64::NEG_INFINITY),
+        "NaN" => Ok(f64::NAN),
+        text => text.parse().map_err(|_| "invalid number"),
+    }
+}
+
+/* the sample with this name whose labels include the given pairs, None unless exactly one */
+pub fn find_sample<'a>(
+    samples: &'a [ParsedSample],
+    name: &str,
+    labels: &[(&str, &str)],
+) -> Option<&'a ParsedSample> {
+    let mut matching = samples.iter().filter(|sample| {
+        sample.name == name
+            && labels
+                .iter()
+                .all(|(key, value)| sample.labels.get(*key).map(String::as_str) == Some(*value))
+    });
+    let sample = matching.next()?;
+    matching.next().is_none().then_some(sample)
+}
+
+/* renders the registry and checks one series, see assert_metric! */
+#[track_caller]
+pub fn assert_metric(
+    registry: &PromMetricRegistry,
+    name: &str,
+    labels: &[(&str, &str)],
+    value: f64,
+) {
+    let text = registry.to_string();
+    let samples = match parse_exposition(&text) {
+        Ok(samples) => samples,
+        Err(error) => panic!("registry rendered unparsable text, {}:\n{}", error, text),
+    };
+
+    match find_sample(&samples, name, labels) {
+        Some(sample) => assert_eq!(
+            sample.value, value,
+            "{} {:?} is {}, expected {}",
+            name, labels, sample.value, value
+        ),
+        None => {
+            let candidates = samples
+                .iter()
+                .filter(|sample| sample.name == name)
+                .map(|sample| format!("  {:?} {}\n", sample.labels, sample.value))
+                .collect::<String>();
+            panic!(
+                "no single {} sample with labels {:?}, candidates:\n{}",
+                name, labels, candidates
+            );
+        }
+    }
+}
+
+/* assert_metric!(registry, "requests_total", labels = {"method" => "GET"}, value == 3) */
+#[macro_export]
+macro_rules! assert_metric {
+    ($registry:expr, $name:expr, labels = {$($key:expr => $value:expr),* $(,)?}, value == $expected:expr) => {
+        $crate::testing::assert_metric(&$registry, $name, &[$(($key, $value)),*], $expected as f64)
+    };
+    ($registry:expr, $name:expr, value == $expected:expr) => {
+        $crate::testing::assert_metric(&$registry, $name, &[], $expected as f64)
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, sync::Arc};
+
+    use crate::{IntCounter, IntGauge, IntHistogram, PromMetricRegistry};
+
+    use super::{find_sample, parse_exposition, ParseError, ParsedSample};
+
+    fn labels(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_test() {
+        let samples = parse_exposition(
+            "# HELP a Some \\\\ help\n\
+             # TYPE a counter\n\
+             a{x=\"1\",y=\"q\\\"uo\\nte\",} 3 1700000000000\n\
+             \n\
+             {\"my.metric\", z = \"a,b}\"} -1.5e3\n\
+             b_bucket{le=\"+Inf\"} +Inf\n\
+             c_total 2 # {trace_id=\"abc\"} 1 1700000000.123\n\
+             # EOF\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            samples,
+            [
+                ParsedSample {
+                    name: "a".into(),
+                    labels: labels(&[("x", "1"), ("y", "q\"uo\nte")]),
+                    value: 3.0,
+                    timestamp: Some(1_700_000_000_000.0),
+                },
+                ParsedSample {
+                    name: "my.metric".into(),
+                    labels: labels(&[("z", "a,b}")]),
+                    value: -1500.0,
+                    timestamp: None,
+                },
+                ParsedSample {
+                    name: "b_bucket".into(),
+                    labels: labels(&[("le", "+Inf")]),
+                    value: f64::INFINITY,
+                    timestamp: None,
+                },
+                ParsedSample {
+                    name: "c_total".into(),
+                    labels: BTreeMap::new(),
+                    value: 2.0,
+                    timestamp: None,
+                },
+            ]
+        );
+
+        assert_eq!(
+            find_sample(&samples, "a", &[("x", "1")]).unwrap().value,
+            3.0
+        );
+        assert!(find_sample(&samples, "a", &[("x", "2")]).is_none());
+    }
+
+    #[test]
+    fn parse_error_test() {
+        let error = |text| parse_exposition(text).unwrap_err();
+        assert_eq!(
+            error("ok 1\nbad{x=\"1} 2\n"),
+            ParseError {
+                line: 2,
+                message: "unterminated quoted string"
+            }
+        );
+        assert_eq!(error("a{x} 1").message, "label without a value");
+        assert_eq!(error("a one").message, "invalid number");
+        assert_eq!(error("a").message, "sample without a value");
+        assert_eq!(
+            error("a 1 2 3").message,
+            "unexpected text after the timestamp"
+        );
+        assert_eq!(error("# TYPE a widget").message, "unknown metric type");
+        assert_eq!(error("a{x=\"\\t\"} 1").message, "invalid escape");
+    }
+
+    #[test]
+    fn assert_metric_test() {
+        let requests = Arc::new(IntCounter::new());
+        let mut reg = PromMetricRegistry::empty().with_base_attrs([("service", "api")]);
+        reg.register_counter("requests_total", &requests)
+            .attr("method", "GET");
+        requests.inc_by(3);
+
+        crate::assert_metric!(reg, "requests_total", labels = {"method" => "GET"}, value == 3);
+        crate::assert_metric!(reg, "requests_total", value == 3);
+
+        let wrong = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            crate::assert_metric!(reg, "requests_total", labels = {"method" => "PUT"}, value == 3);
+        }));
+        assert!(wrong.is_err());
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let requests = Arc::new(IntCounter::new());
+        let queue = Arc::new(IntGauge::new());
+        let latency = Arc::new(IntHistogram::new([10, 100]));
+        let mut reg = PromMetricRegistry::empty().with_base_attrs([("service", "a \"b\"")]);
+        reg.register_counter("requests_total", &requests)
+            .attr("path", "C:\\tmp\nnext");
+        reg.register_gauge("queue", &queue);
+        reg.register_fn(&latency, |h, reg| {
+            reg.histogram("latency", h);
+        });
+        requests.inc_by(7);
+        queue.set(2);
+        latency.observe(50);
+        latency.observe(500);
+
+        let mut openmetrics = String::new();
+        reg.encode_openmetrics(&mut openmetrics).unwrap();
+
+        for text in [reg.to_string(), openmetrics] {
+            let samples = parse_exposition(&text).unwrap();
+            let value =
+                |name, labels: &[(&str, &str)]| find_sample(&samples, name, labels).unwrap().value;
+
+            let path = ("path", "C:\\tmp\nnext");
+            assert_eq!(
+                value("requests_total", &[("service", "a \"b\""), path]),
+                7.0
+            );
+            assert_eq!(value("queue", &[]), 2.0);
+            assert_eq!(value("latency_bucket", &[("le", "10")]), 0.0);
+            assert_eq!(value("latency_bucket", &[("le", "100")]), 1.0);
+            assert_eq!(value("latency_bucket", &[("le", "+Inf")]), 2.0);
+            assert_eq!(value("latency_sum", &[]), 550.0);
+            assert_eq!(value("latency_count", &[]), 2.0);
+        }
+    }
+}
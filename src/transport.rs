@@ -0,0 +1,223 @@
+/*
+ * How exporters deliver a payload. The push exporter sends a full HTTP request and reads
+ * the raw response, statsd sends one datagram per packet and gets an empty reply.
+ */
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
+    time::Duration,
+};
+
+pub trait Transport: Send {
+    fn send(&mut self, payload: &[u8]) -> std::io::Result<Vec<u8>>;
+}
+
+/* one connection per send, the request must ask the server to close it */
+pub struct TcpTransport {
+    /* host:port, resolved on every send */
+    addr: String,
+    timeout: Duration,
+}
+
+impl TcpTransport {
+    pub fn new<A: Into<String>>(addr: A, timeout: Duration) -> Self {
+        TcpTransport {
+            addr: addr.into(),
+            timeout,
+        }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send(&mut self, payload: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut last_error = None;
+        let mut stream = None;
+        for addr in self.addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.timeout) {
+                Ok(connected) => {
+                    stream = Some(connected);
+                    break;
+                }
+                Err(error) => last_error = Some(error),
+            }
+        }
+
+        let mut stream = match (stream, last_error) {
+            (Some(stream), _) => stream,
+            (None, Some(error)) => return Err(error),
+            (None, None) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("{} did not resolve", self.addr),
+                ))
+            }
+        };
+
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        stream.write_all(payload)?;
+        stream.flush()?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+        Ok(response)
+    }
+}
+
+pub struct UdpTransport {
+    socket: UdpSocket,
+    target: SocketAddr,
+}
+
+impl UdpTransport {
+    /* binds an ephemeral local port of the target's address family */
+    pub fn new(target: SocketAddr) -> std::io::Result<Self> {
+        let bind: SocketAddr = if target.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+
+        Ok(UdpTransport {
+            socket: UdpSocket::bind(bind)?,
+            target,
+        })
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send(&mut self, payload: &[u8]) -> std::io::Result<Vec<u8>> {
+        self.socket.send_to(payload, self.target)?;
+        Ok(Vec::new())
+    }
+}
+
+/* host and path of an http:// url */
+#[cfg(any(feature = "push", feature = "remote-write"))]
+pub(crate) fn split_http_url(url: &str) -> Option<(&str, &str)> {
+    url.strip_prefix("http://")
+        .map(|rest| rest.split_once('/').unwrap_or((rest, "")))
+        .filter(|(host, _)| !host.is_empty())
+}
+
+/* status code and body of a raw HTTP/1.x response */
+#[cfg(any(feature = "push", feature = "remote-write"))]
+pub(crate) fn parse_response(response: &[u8]) -> Option<(u16, String)> {
+    let response = String::from_utf8_lossy(response);
+    let code = response
+        .strip_prefix("HTTP/1.")
+        .and_then(|rest| rest.get(2..5))
+        .and_then(|code| code.parse::<u16>().ok())?;
+
+    let body = response
+        .split_once("\r\n\r\n")
+        .map(|(_, body)| body.to_string())
+        .unwrap_or_default();
+
+    Some((code, body))
+}
+
+#[cfg(feature = "test-util")]
+pub use fault::FaultInjector;
+
+#[cfg(feature = "test-util")]
+mod fault {
+    use std::{
+        io::ErrorKind,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::Transport;
+
+    /*
+     * Wraps a transport for exporter tests. Clones share state so a test can keep one
+     * to program failures while the exporter owns another.
+     */
+    #[derive(Clone)]
+    pub struct FaultInjector {
+        state: Arc<Mutex<State>>,
+    }
+
+    struct State {
+        inner: Box<dyn Transport>,
+        fail_next: usize,
+        delay: Option<Duration>,
+        status: Option<u16>,
+        attempts: usize,
+        failures: usize,
+    }
+
+    impl FaultInjector {
+        pub fn new<T: Transport + 'static>(inner: T) -> Self {
+            FaultInjector {
+                state: Arc::new(Mutex::new(State {
+                    inner: Box::new(inner),
+                    fail_next: 0,
+                    delay: None,
+                    status: None,
+                    attempts: 0,
+                    failures: 0,
+                })),
+            }
+        }
+
+        /* the next count sends fail with ConnectionRefused without reaching inner */
+        pub fn fail_next(&self, count: usize) -> &Self {
+            self.state.lock().unwrap().fail_next = count;
+            self
+        }
+
+        /* sleeps before every send */
+        pub fn delay(&self, delay: Option<Duration>) -> &Self {
+            self.state.lock().unwrap().delay = delay;
+            self
+        }
+
+        /* answers every send with an HTTP response of this status without reaching inner */
+        pub fn respond_with_status(&self, status: Option<u16>) -> &Self {
+            self.state.lock().unwrap().status = status;
+            self
+        }
+
+        /* sends attempted, including injected failures */
+        pub fn attempts(&self) -> usize {
+            self.state.lock().unwrap().attempts
+        }
+
+        /* sends failed by fail_next */
+        pub fn failures(&self) -> usize {
+            self.state.lock().unwrap().failures
+        }
+    }
+
+    impl Transport for FaultInjector {
+        fn send(&mut self, payload: &[u8]) -> std::io::Result<Vec<u8>> {
+            let mut state = self.state.lock().unwrap();
+            state.attempts += 1;
+
+            if let Some(delay) = state.delay {
+                std::thread::sleep(delay);
+            }
+
+            if 0 < state.fail_next {
+                state.fail_next -= 1;
+                state.failures += 1;
+                return Err(std::io::Error::new(
+                    ErrorKind::ConnectionRefused,
+                    "injected failure",
+                ));
+            }
+
+            if let Some(status) = state.status {
+                return Ok(format!(
+                    "HTTP/1.1 {} Injected\r\nContent-Length: 8\r\n\r\ninjected",
+                    status
+                )
+                .into_bytes());
+            }
+
+            state.inner.send(payload)
+        }
+    }
+}
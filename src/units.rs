@@ -0,0 +1,161 @@
+/*
+ * unit typed gauges, the setters only take values of their unit so a millisecond
+ * count can't end up in a _seconds gauge. Registered with duration_gauge / bytes_gauge,
+ * which name the unit in MetricFamily::unit and the OpenMetrics UNIT line.
+ */
+use std::time::Duration;
+
+use crate::IntGauge;
+
+#[derive(Debug, Default)]
+pub struct Seconds<G>(G);
+
+impl Seconds<IntGauge> {
+    pub const fn new() -> Self {
+        Seconds(IntGauge::new())
+    }
+
+    /* whole seconds, the fraction is truncated */
+    pub fn set(&self, duration: Duration) {
+        self.0.set(duration.as_secs());
+    }
+
+    pub fn get(&self) -> Duration {
+        Duration::from_secs(self.0.load())
+    }
+
+    pub(crate) fn gauge(&self) -> &IntGauge {
+        &self.0
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(u64);
+
+impl ByteSize {
+    pub const fn bytes(bytes: u64) -> Self {
+        ByteSize(bytes)
+    }
+
+    /* the multiples saturate at u64::MAX bytes */
+    pub const fn kib(kib: u64) -> Self {
+        ByteSize(kib.saturating_mul(1 << 10))
+    }
+
+    pub const fn mib(mib: u64) -> Self {
+        ByteSize(mib.saturating_mul(1 << 20))
+    }
+
+    pub const fn gib(gib: u64) -> Self {
+        ByteSize(gib.saturating_mul(1 << 30))
+    }
+
+    pub const fn as_bytes(&self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Bytes<G>(G);
+
+impl Bytes<IntGauge> {
+    pub const fn new() -> Self {
+        Bytes(IntGauge::new())
+    }
+
+    pub fn set(&self, size: ByteSize) {
+        self.0.set(size.0);
+    }
+
+    pub fn inc_by(&self, size: ByteSize) {
+        self.0.shared_inc_by(size.0);
+    }
+
+    pub fn dec_by(&self, size: ByteSize) {
+        self.0.shared_dec_by(size.0);
+    }
+
+    pub fn get(&self) -> ByteSize {
+        ByteSize(self.0.load())
+    }
+
+    pub(crate) fn gauge(&self) -> &IntGauge {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use crate::{IntGauge, PromMetricRegistry};
+
+    use super::{ByteSize, Bytes, Seconds};
+
+    #[derive(Default)]
+    struct Met {
+        uptime: Seconds<IntGauge>,
+        timeout: Seconds<IntGauge>,
+        buffered: Bytes<IntGauge>,
+    }
+
+    #[test]
+    fn conversion_test() {
+        let met = Met::default();
+
+        met.uptime.set(Duration::from_millis(90_500));
+        assert_eq!(met.uptime.get(), Duration::from_secs(90));
+
+        met.buffered.set(ByteSize::mib(2));
+        met.buffered.inc_by(ByteSize::kib(1));
+        met.buffered.dec_by(ByteSize::bytes(24));
+        assert_eq!(met.buffered.get().as_bytes(), 2 * 1024 * 1024 + 1000);
+        assert_eq!(ByteSize::gib(1), ByteSize::mib(1024));
+        assert_eq!(ByteSize::kib(u64::MAX).as_bytes(), u64::MAX);
+        assert_eq!(ByteSize::gib(1 << 34).as_bytes(), u64::MAX);
+        const LIMIT: ByteSize = ByteSize::gib(u64::MAX / 2);
+        assert_eq!(LIMIT.as_bytes(), u64::MAX);
+    }
+
+    #[test]
+    fn register_test() {
+        let met = Arc::new(Met::default());
+        let mut reg = PromMetricRegistry::empty();
+
+        reg.register_fn(&met, |m, reg| {
+            reg.duration_gauge("uptime", &m.uptime);
+            reg.duration_gauge("timeout_seconds", &m.timeout);
+            reg.bytes_gauge("buffered", &m.buffered);
+        });
+
+        met.uptime.set(Duration::from_secs(3));
+        met.buffered.set(ByteSize::kib(1));
+
+        assert_eq!(
+            reg.to_string(),
+            "# HELP buffered_bytes\n\
+             # TYPE buffered_bytes gauge\n\
+             buffered_bytes 1024\n\
+             # HELP timeout_seconds\n\
+             # TYPE timeout_seconds gauge\n\
+             timeout_seconds 0\n\
+             # HELP uptime_seconds\n\
+             # TYPE uptime_seconds gauge\n\
+             uptime_seconds 3\n"
+        );
+
+        let mut open = String::new();
+        reg.encode_openmetrics(&mut open).unwrap();
+        assert!(open.starts_with(
+            "# TYPE buffered_bytes gauge\n\
+             # UNIT buffered_bytes bytes\n\
+             buffered_bytes 1024\n"
+        ));
+        let units = reg
+            .gather()
+            .into_iter()
+            .map(|family| family.unit)
+            .collect::<Vec<_>>();
+        assert_eq!(units, [Some("bytes"), Some("seconds"), Some("seconds")]);
+    }
+}
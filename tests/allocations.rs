@@ -0,0 +1,96 @@
+#![cfg(feature = "std")]
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use arc_metrics::{IntCounter, PromMetricRegistry};
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/* tests run on parallel threads and share the counter */
+static SERIAL: Mutex<()> = Mutex::new(());
+
+#[test]
+fn attributes_inline_allocations() {
+    const SERIES: usize = 1000;
+    let _serial = SERIAL.lock().unwrap();
+
+    let counters = Arc::new((0..SERIES).map(|_| IntCounter::new()).collect::<Vec<_>>());
+    let mut reg = PromMetricRegistry::empty()
+        .with_base_attrs([("program", "fixture"), ("pkg_version", "1.0.0")]);
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    reg.register_fn(&counters, |counters, reg| {
+        let mut group = reg.empty();
+        for counter in counters {
+            group.count("requests", counter);
+        }
+        group.attr("method", "get");
+    });
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    /* three labels per series stay inline, only vec growth and sorting allocate */
+    assert!(
+        allocations < 64,
+        "{} allocations for {} series",
+        allocations,
+        SERIES
+    );
+}
+
+#[test]
+fn encode_into_allocations() {
+    let _serial = SERIAL.lock().unwrap();
+    let allocations = |series: usize| {
+        let counters = Arc::new((0..series).map(|_| IntCounter::new()).collect::<Vec<_>>());
+        let mut reg = PromMetricRegistry::empty().with_base_attrs([("program", "fixture")]);
+        reg.register_fn(&counters, |counters, reg| {
+            for (i, counter) in counters.iter().enumerate() {
+                counter.inc_by(i as u64);
+                reg.count(format!("family_{}_total", i % 10), counter)
+                    .attr("series", i.to_string());
+            }
+        });
+
+        /* the first render builds each series' label text and grows the buffer */
+        let mut buf = String::new();
+        reg.encode_into(&mut buf);
+        assert_eq!(buf, reg.to_string());
+
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        reg.encode_into(&mut buf);
+        ALLOCATIONS.load(Ordering::Relaxed) - before
+    };
+
+    /* the same handful of allocations however many series are written */
+    let small = allocations(10);
+    let large = allocations(10_000);
+    assert!(
+        large <= small,
+        "{} allocations for 10k series, {} for 10",
+        large,
+        small
+    );
+    assert!(small < 8, "{} allocations", small);
+}
@@ -0,0 +1,87 @@
+/*
+ * Checks that the hot path compiles to a single locked instruction with no calls. Needs
+ * a release build of examples/hot_path with asm output so it is opt-in:
+ * cargo test --release --test codegen -- --ignored
+ */
+#![cfg(target_arch = "x86_64")]
+
+use std::{path::PathBuf, process::Command};
+
+const FUNCTIONS: [&str; 3] = [
+    "hot_path_counter_inc",
+    "hot_path_gauge_dec",
+    "hot_path_child_inc",
+];
+
+fn emit_asm() -> String {
+    let manifest = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    /* separate target dir so the outer cargo's build lock isn't contended */
+    let target = manifest.join("target").join("codegen");
+
+    let status = Command::new(env!("CARGO"))
+        .current_dir(&manifest)
+        .args([
+            "rustc",
+            "--release",
+            "--example",
+            "hot_path",
+            "--target-dir",
+        ])
+        .arg(&target)
+        .args([
+            "--",
+            "--emit",
+            "asm",
+            "-C",
+            "llvm-args=-x86-asm-syntax=intel",
+        ])
+        .status()
+        .expect("failed to run cargo");
+    assert!(status.success());
+
+    let dir = target.join("release").join("examples");
+    let asm = std::fs::read_dir(&dir)
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.starts_with("hot_path-") && name.ends_with(".s")
+        })
+        .max_by_key(|entry| entry.metadata().and_then(|m| m.modified()).ok())
+        .expect("no asm output");
+
+    std::fs::read_to_string(asm.path()).unwrap()
+}
+
+/* instructions between the function's label and the end of its body */
+fn body<'a>(asm: &'a str, function: &str) -> Vec<&'a str> {
+    let label = format!("{}:", function);
+    asm.lines()
+        .skip_while(|line| line.trim() != label)
+        .skip(1)
+        .take_while(|line| !line.trim_start().starts_with(".Lfunc_end"))
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('.') && !line.starts_with('#'))
+        .collect()
+}
+
+#[test]
+#[ignore]
+fn hot_path_codegen() {
+    let asm = emit_asm();
+
+    for function in FUNCTIONS {
+        let body = body(&asm, function);
+        assert!(!body.is_empty(), "{} not found", function);
+
+        let locked = body.iter().filter(|line| line.starts_with("lock")).count();
+        let calls = body
+            .iter()
+            .filter(|line| line.starts_with("call") || line.starts_with("jmp"))
+            .count();
+
+        assert_eq!(locked, 1, "{}:\n{}", function, body.join("\n"));
+        assert_eq!(calls, 0, "{}:\n{}", function, body.join("\n"));
+    }
+}
@@ -0,0 +1,118 @@
+/*
+ * cargo check for every feature on its own and the combinations below, so a gate missing
+ * on one side of a feature pair fails here instead of for whoever enables that pair. The
+ * powerset is too big, combinations are the ones that share code.
+ */
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+};
+
+/* every feature on its own (with and without default) is checked in addition to these */
+const COMBINATIONS: &[&[&str]] = &[
+    /* otel-bridge reads the registry through otel */
+    &["otel-bridge", "push"],
+    &["otel", "serde"],
+    /* exporters together, they share scrape / push policy code */
+    &["graphite", "push", "remote-write", "statsd", "serve"],
+    &["bridge", "serve"],
+    /* registration paths with every optional RegisterHelper field */
+    &["blackbox", "diagnostics", "test-util"],
+    &["diagnostics", "modern"],
+    &["serde", "test-util"],
+];
+
+fn features(manifest: &Path) -> Vec<String> {
+    let manifest = std::fs::read_to_string(manifest).unwrap();
+    manifest
+        .lines()
+        .skip_while(|line| line.trim() != "[features]")
+        .skip(1)
+        .take_while(|line| !line.starts_with('['))
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| Some(line.split_once('=')?.0.trim().to_string()))
+        .filter(|feature| !feature.is_empty() && feature != "default")
+        .collect()
+}
+
+/* the error lines of a failed check, None if it passed */
+fn check(manifest: &Path, default: bool, features: &[&str]) -> Option<String> {
+    let mut command = Command::new(std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into()));
+    command
+        .arg("check")
+        .arg("--all-targets")
+        .arg("--message-format=short")
+        .arg("--manifest-path")
+        .arg(manifest)
+        /* the outer cargo holds the lock on the regular target dir */
+        .arg("--target-dir")
+        .arg(Path::new(env!("CARGO_TARGET_TMPDIR")).join("features"))
+        .env("RUSTFLAGS", "-D warnings")
+        .stdout(Stdio::null());
+    if !default {
+        command.arg("--no-default-features");
+    }
+    if !features.is_empty() {
+        command.arg("--features").arg(features.join(","));
+    }
+
+    let output = command.output().unwrap();
+    if output.status.success() {
+        return None;
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let errors = stderr
+        .lines()
+        .filter(|line| line.contains("error") || line.contains("warning"))
+        .map(|line| format!("    {}\n", line))
+        .collect::<String>();
+    Some(errors)
+}
+
+#[test]
+fn features_test() {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+    let all = features(&manifest);
+    for combination in COMBINATIONS {
+        for feature in combination.iter() {
+            assert!(
+                all.iter().any(|f| f == feature),
+                "unknown feature {}",
+                feature
+            );
+        }
+    }
+
+    let mut configs = vec![(false, vec![]), (true, vec![])];
+    for feature in &all {
+        configs.push((false, vec![feature.as_str()]));
+        configs.push((true, vec![feature.as_str()]));
+    }
+    for combination in COMBINATIONS {
+        configs.push((false, combination.to_vec()));
+    }
+    configs.push((true, all.iter().map(String::as_str).collect()));
+
+    let mut report = String::new();
+    for (default, features) in &configs {
+        if let Some(errors) = check(&manifest, *default, features) {
+            report.push_str(&format!(
+                "{}--features \"{}\"\n{}",
+                if *default {
+                    ""
+                } else {
+                    "--no-default-features "
+                },
+                features.join(","),
+                errors
+            ));
+        }
+    }
+
+    assert!(
+        report.is_empty(),
+        "feature combinations failed to check:\n{}",
+        report
+    );
+}
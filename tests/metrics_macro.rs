@@ -0,0 +1,100 @@
+/*
+ * metrics! from a downstream crate: used with nothing imported but the macro, and malformed
+ * declarations checked with cargo check on a scratch crate (like tests/features.rs) to
+ * fail with a useful error
+ */
+#![cfg(feature = "std")]
+
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+    sync::Arc,
+};
+
+arc_metrics::metrics! {
+    pub struct QueueMetrics prefix "queue" {
+        pushed_total: counter ["Items pushed"],
+        depth: gauge,
+    }
+}
+
+#[test]
+fn downstream_test() {
+    let metrics = Arc::new(QueueMetrics::default());
+    let mut reg = arc_metrics::PromMetricRegistry::empty();
+    reg.register(&metrics);
+    metrics.pushed_total.inc_by(2);
+    metrics.depth.set(5);
+
+    assert_eq!(
+        reg.to_string(),
+        "# HELP queue_depth\n\
+         # TYPE queue_depth gauge\n\
+         queue_depth 5\n\
+         # HELP queue_pushed_total\n\
+         # TYPE queue_pushed_total counter\n\
+         queue_pushed_total 2\n"
+    );
+}
+
+/* the check's stderr, None if it passed */
+fn check(dir: &Path, source: &str) -> Option<String> {
+    std::fs::write(dir.join("src/main.rs"), source).unwrap();
+    let output = Command::new(std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into()))
+        .arg("check")
+        .arg("--offline")
+        .arg("--message-format=short")
+        .arg("--manifest-path")
+        .arg(dir.join("Cargo.toml"))
+        .stdout(Stdio::null())
+        .output()
+        .unwrap();
+    (!output.status.success()).then(|| String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+#[test]
+fn compile_fail_test() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("metrics_macro");
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    std::fs::write(
+        dir.join("Cargo.toml"),
+        format!(
+            "[package]\nname = \"metrics-macro-check\"\nversion = \"0.0.0\"\nedition = \"2021\"\n\n\
+             [dependencies]\narc-metrics = {{ path = {:?} }}\n\n[workspace]\n",
+            env!("CARGO_MANIFEST_DIR")
+        ),
+    )
+    .unwrap();
+
+    let valid =
+        "arc_metrics::metrics! { struct M prefix \"m\" { a: counter [\"A\"] attr(x = \"1\") } }\n\
+        fn main() { let _ = M::default(); }\n";
+    if let Some(errors) = check(&dir, valid) {
+        panic!("valid declaration failed to check:\n{}", errors);
+    }
+
+    let cases = [
+        ("a: timer", "unknown metric kind `timer`"),
+        ("a", "no rules expected"),
+        ("a: counter [42]", "mismatched types"),
+        ("a: counter attr(unit = ms)", "no rules expected"),
+        ("a: counter, a: gauge", "field `a` is already declared"),
+    ];
+
+    for (fields, expected) in cases {
+        let source = format!(
+            "arc_metrics::metrics! {{ struct M {{ {} }} }}\nfn main() {{}}\n",
+            fields
+        );
+        match check(&dir, &source) {
+            None => panic!("`{}` compiled", fields),
+            Some(errors) => assert!(
+                errors.contains(expected),
+                "`{}` failed without `{}`:\n{}",
+                fields,
+                expected,
+                errors
+            ),
+        }
+    }
+}
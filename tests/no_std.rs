@@ -0,0 +1,74 @@
+/*
+ * the primitives and guards as a no_std + alloc component would use them, run with
+ * --no-default-features to check the crate builds without std
+ */
+#![no_std]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use arc_metrics::{
+    helpers::{ActiveGauge, Clock, DurationIncMs, DurationUnit, DurationWithCount, MaybeMetrics},
+    IntCounter, IntGauge, IntHistogram,
+};
+
+/* a monotonic tick counter standing in for a hardware timer, 1 tick = 1ms */
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy)]
+struct TickClock;
+
+impl Clock for TickClock {
+    type Instant = u64;
+
+    fn now(&self) -> u64 {
+        TICKS.load(Ordering::Relaxed)
+    }
+
+    fn elapsed(&self, since: &u64) -> Duration {
+        Duration::from_millis(self.now() - since)
+    }
+}
+
+#[derive(Default)]
+struct Met {
+    requests: IntCounter,
+    active: IntGauge,
+    latency_ms: IntCounter,
+    calls: IntCounter,
+    sizes: IntHistogram,
+}
+
+#[test]
+fn no_std_test() {
+    let met = Arc::new(Met::default());
+    met.requests.inc();
+    met.sizes.observe(300);
+
+    {
+        let _active = ActiveGauge::new(&met, |m| &m.active);
+        let _latency = DurationIncMs::with_clock(&met, |m| &m.latency_ms, TickClock);
+        let _timed = DurationWithCount::with_clock(
+            &met,
+            |m| (&m.latency_ms, &m.calls),
+            DurationUnit::Millis,
+            TickClock,
+        );
+        assert_eq!(met.active.load(), 1);
+        TICKS.fetch_add(25, Ordering::Relaxed);
+    }
+
+    assert_eq!(met.active.load(), 0);
+    assert_eq!(met.latency_ms.load(), 50);
+    assert_eq!(met.calls.load(), 1);
+
+    let maybe = MaybeMetrics::from(met.clone());
+    maybe.inc(|m| &m.requests);
+    assert_eq!(met.requests.load(), 2);
+    assert_eq!(met.sizes.count(), 1);
+}
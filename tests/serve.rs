@@ -0,0 +1,78 @@
+#![cfg(feature = "serve")]
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::{Arc, RwLock},
+};
+
+use arc_metrics::{serve_std, IntCounter, PromMetricRegistry, RegistrySet};
+
+fn get(addr: SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nAccept: text/plain\r\n\r\n",
+        path
+    )
+    .unwrap();
+
+    /* the server closes the connection after responding */
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn serve_std_test() {
+    let counter = Arc::new(IntCounter::new());
+    let mut reg = PromMetricRegistry::empty();
+    reg.register_counter("requests_total", &counter);
+    counter.inc_by(3);
+
+    let registry = Arc::new(RwLock::new(reg));
+    let server = serve_std(registry.clone(), "127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = server.local_addr();
+
+    let response = get(addr, "/metrics?debug=1");
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(head.contains("Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n"));
+    assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+    assert!(body.contains("requests_total 3\n"));
+
+    /* registrations after starting are served */
+    let late = Arc::new(IntCounter::new());
+    registry
+        .write()
+        .unwrap()
+        .register_counter("late_total", &late);
+    assert!(get(addr, "/metrics").contains("late_total 0\n"));
+
+    assert!(get(addr, "/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    assert!(get(addr, "/metricsx").starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    server.shutdown();
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn serve_registry_set_test() {
+    let registry = |name: &'static str| {
+        let counter = Arc::new(IntCounter::new());
+        counter.inc();
+        let mut reg = PromMetricRegistry::empty();
+        reg.register_counter(name, &counter);
+        Arc::new(RwLock::new(reg))
+    };
+
+    let mut set = RegistrySet::new();
+    set.add(registry("db_queries_total"))
+        .add(registry("http_requests_total"));
+    let server = serve_std(set, "127.0.0.1:0".parse().unwrap()).unwrap();
+
+    let response = get(server.local_addr(), "/metrics");
+    assert!(response.contains("\r\n\r\n# HELP db_queries_total\n"));
+    assert!(response.ends_with("http_requests_total 1\n"));
+    server.shutdown();
+}
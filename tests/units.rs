@@ -0,0 +1,89 @@
+/*
+ * unit mismatches in unit typed gauges are compile errors: a gauge of the wrong unit, or an
+ * untyped one, passed to duration_gauge / bytes_gauge, and setters given a bare number.
+ * Checked with cargo check on a scratch crate like tests/metrics_macro.rs.
+ */
+#![cfg(feature = "std")]
+
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+};
+
+/* the check's stderr, None if it passed */
+fn check(dir: &Path, source: &str) -> Option<String> {
+    std::fs::write(dir.join("src/main.rs"), source).unwrap();
+    let output = Command::new(std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into()))
+        .arg("check")
+        .arg("--offline")
+        .arg("--message-format=short")
+        .arg("--manifest-path")
+        .arg(dir.join("Cargo.toml"))
+        .stdout(Stdio::null())
+        .output()
+        .unwrap();
+    (!output.status.success()).then(|| String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+/* registers or sets inside a registration of M */
+fn source(statement: &str) -> String {
+    format!(
+        "use std::{{sync::Arc, time::Duration}};\n\
+         use arc_metrics::{{units::{{ByteSize, Bytes, Seconds}}, IntGauge, PromMetricRegistry}};\n\
+         #[derive(Default)]\n\
+         struct M {{ uptime: Seconds<IntGauge>, buffered: Bytes<IntGauge>, plain: IntGauge }}\n\
+         fn main() {{\n\
+             let _ = (Duration::ZERO, ByteSize::bytes(0));\n\
+             let mut reg = PromMetricRegistry::empty();\n\
+             reg.register_fn(&Arc::new(M::default()), |m, reg| {{ {}; }});\n\
+         }}\n",
+        statement
+    )
+}
+
+#[test]
+fn compile_fail_test() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("units");
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    std::fs::write(
+        dir.join("Cargo.toml"),
+        format!(
+            "[package]\nname = \"units-check\"\nversion = \"0.0.0\"\nedition = \"2021\"\n\n\
+             [dependencies]\narc-metrics = {{ path = {:?} }}\n\n[workspace]\n",
+            env!("CARGO_MANIFEST_DIR")
+        ),
+    )
+    .unwrap();
+
+    let valid = "reg.duration_gauge(\"uptime\", &m.uptime);\
+                 reg.bytes_gauge(\"buffered\", &m.buffered);\
+                 reg.gauge(\"plain\", &m.plain);\
+                 m.uptime.set(Duration::from_secs(1));\
+                 m.buffered.set(ByteSize::kib(1))";
+    if let Some(errors) = check(&dir, &source(valid)) {
+        panic!("matching units failed to check:\n{}", errors);
+    }
+
+    let cases = [
+        "reg.duration_gauge(\"uptime\", &m.buffered)",
+        "reg.duration_gauge(\"uptime\", &m.plain)",
+        "reg.bytes_gauge(\"buffered\", &m.uptime)",
+        "reg.bytes_gauge(\"buffered\", &m.plain)",
+        "m.uptime.set(1_000)",
+        "m.uptime.set(ByteSize::bytes(1))",
+        "m.buffered.set(1_024)",
+        "m.buffered.inc_by(Duration::from_secs(1))",
+    ];
+
+    for statement in cases {
+        match check(&dir, &source(statement)) {
+            None => panic!("`{}` compiled", statement),
+            Some(errors) => assert!(
+                errors.contains("mismatched types"),
+                "`{}` failed without a type mismatch:\n{}",
+                statement,
+                errors
+            ),
+        }
+    }
+}